@@ -0,0 +1,314 @@
+#![allow(clippy::upper_case_acronyms)]
+
+//! Score function discovery against a ground truth layout,
+//! like the SoK evaluation in `resources/evaluation/SoK`.
+//!
+//! The ground truth is a text file with lines like:
+//!
+//!     function: 0x140013638
+//!     basic block: 0x140013638
+//!     instruction: 0x140013638
+//!     instruction: 0x14001363c
+//!
+//! as emitted by `dump_ground_truth_report.py`, `dump_ida_layout.py`, or
+//! `dump_viv_layout.py`.
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use log::{debug, error, info};
+#[macro_use]
+extern crate clap;
+
+use lancelot::{
+    analysis::{
+        dis,
+        pe::{self, workspace, FunctionSource},
+    },
+    aspace::AddressSpace,
+    config::Config,
+    loader::pe::PE,
+    util, VA,
+};
+
+#[derive(Default)]
+struct Layout {
+    functions:    BTreeSet<VA>,
+    basic_blocks: BTreeSet<VA>,
+    instructions: BTreeSet<VA>,
+}
+
+fn parse_layout(s: &str) -> Result<Layout> {
+    let mut layout: Layout = Default::default();
+
+    for line in s.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (key, va) = match line.split_once(": ") {
+            Some((key, va)) => (key, va),
+            None => continue,
+        };
+
+        // python2 may render longs with a trailing `L`.
+        let va = va.trim_end_matches('L');
+        let va = VA::from_str_radix(va.trim_start_matches("0x"), 0x10)?;
+
+        match key {
+            "function" => layout.functions.insert(va),
+            "basic block" => layout.basic_blocks.insert(va),
+            "instruction" => layout.instructions.insert(va),
+            _ => return Err(anyhow::anyhow!("unexpected key: {}", key)),
+        };
+    }
+
+    Ok(layout)
+}
+
+struct Score {
+    found:     usize,
+    correct:   usize,
+    precision: f64,
+    recall:    f64,
+    f1:        f64,
+}
+
+fn score(found: &BTreeSet<VA>, wanted: &BTreeSet<VA>) -> Score {
+    let correct = found.intersection(wanted).count();
+
+    let precision = if found.is_empty() {
+        0.0
+    } else {
+        correct as f64 / found.len() as f64
+    };
+
+    let recall = if wanted.is_empty() {
+        0.0
+    } else {
+        correct as f64 / wanted.len() as f64
+    };
+
+    let f1 = if precision + recall == 0.0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    };
+
+    Score {
+        found: found.len(),
+        correct,
+        precision,
+        recall,
+        f1,
+    }
+}
+
+fn print_score(name: &str, score: &Score) {
+    println!(
+        "{:<24} {:>8} {:>8} {:>10.3} {:>10.3} {:>10.3}",
+        name, score.found, score.correct, score.precision, score.recall, score.f1
+    );
+}
+
+/// collect the basic blocks and instructions found in the CFGs.
+fn get_workspace_layout(pe: &PE, ws: &workspace::Workspace) -> Result<Layout> {
+    let decoder = dis::get_disassembler(&pe.module)?;
+    let mut layout: Layout = Default::default();
+
+    layout.functions.extend(ws.local_functions().map(|f| f.address));
+
+    for cfg in ws.cfgs.values() {
+        for bb in cfg.basic_blocks.values() {
+            if !layout.basic_blocks.insert(bb.address) {
+                continue;
+            }
+
+            let buf = pe.module.address_space.read_bytes(bb.address, bb.length as usize)?;
+            for (offset, insn) in dis::linear_disassemble(&decoder, &buf) {
+                if let Ok(Some(_)) = insn {
+                    layout.instructions.insert(bb.address + offset as VA);
+                }
+            }
+        }
+    }
+
+    Ok(layout)
+}
+
+fn _main() -> Result<()> {
+    better_panic::install();
+
+    let matches = clap::clap_app!(lancelot =>
+        (author: "Willi Ballenthin <william.ballenthin@mandiant.com>")
+        (about: "Score function discovery against a ground truth layout")
+        (@arg verbose: -v --verbose +multiple "log verbose messages")
+        (@arg quiet: -q --quiet "disable informational messages")
+        (@arg config: -c --config +takes_value "path to TOML configuration file")
+        (@arg min_precision: --("min-precision") +takes_value "fail if function precision is below this value")
+        (@arg min_recall: --("min-recall") +takes_value "fail if function recall is below this value")
+        (@arg truth: +required "path to ground truth layout")
+        (@arg input: +required "path to file to analyze"))
+    .get_matches();
+
+    // --quiet overrides --verbose
+    let log_level = if matches.is_present("quiet") {
+        log::LevelFilter::Error
+    } else {
+        match matches.occurrences_of("verbose") {
+            0 => log::LevelFilter::Info,
+            1 => log::LevelFilter::Debug,
+            2 => log::LevelFilter::Trace,
+            _ => log::LevelFilter::Trace,
+        }
+    };
+
+    fern::Dispatch::new()
+        .format(move |out, message, record| {
+            out.finish(format_args!(
+                "{} [{:5}] {} {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                if log_level == log::LevelFilter::Trace {
+                    record.target()
+                } else {
+                    ""
+                },
+                message
+            ))
+        })
+        .level(log_level)
+        .chain(std::io::stderr())
+        .filter(|metadata| !metadata.target().starts_with("goblin::pe"))
+        .apply()
+        .expect("failed to configure logging");
+
+    let config = match matches.value_of("config") {
+        Some(path) => {
+            debug!("config: {}", path);
+            Config::from_file(path)?
+        }
+        None => Default::default(),
+    };
+
+    let truth = matches.value_of("truth").unwrap();
+    debug!("truth: {}", truth);
+    let truth = parse_layout(&String::from_utf8(util::read_file(truth)?)?)?;
+    info!(
+        "ground truth: {} functions, {} basic blocks, {} instructions",
+        truth.functions.len(),
+        truth.basic_blocks.len(),
+        truth.instructions.len()
+    );
+
+    let filename = matches.value_of("input").unwrap();
+    debug!("input: {}", filename);
+
+    let buf = util::read_file(filename)?;
+    let pe = PE::from_bytes(&buf)?;
+
+    println!(
+        "{:<24} {:>8} {:>8} {:>10} {:>10} {:>10}",
+        "source", "found", "correct", "precision", "recall", "f1"
+    );
+
+    // the candidates from each analysis pass, before validation.
+    let candidates = pe::find_function_candidates_with_config(&pe, &config)?;
+    let mut by_source: BTreeMap<FunctionSource, BTreeSet<VA>> = Default::default();
+    for (&va, sources) in candidates.iter() {
+        for &source in sources.iter() {
+            by_source.entry(source).or_default().insert(va);
+        }
+    }
+    for (source, found) in by_source.iter() {
+        print_score(&source.to_string(), &score(found, &truth.functions));
+    }
+    print_score(
+        "(all candidates)",
+        &score(&candidates.keys().cloned().collect(), &truth.functions),
+    );
+
+    let functions: BTreeSet<VA> = pe::find_function_starts_with_config(&pe, &config)?
+        .into_iter()
+        .collect();
+    print_score("(functions)", &score(&functions, &truth.functions));
+
+    let ws = workspace::build_workspace_with_config(&pe, &config)?;
+    let layout = get_workspace_layout(&pe, &ws)?;
+    let functions = score(&layout.functions, &truth.functions);
+    print_score("(workspace)", &functions);
+    print_score("(basic blocks)", &score(&layout.basic_blocks, &truth.basic_blocks));
+    print_score("(instructions)", &score(&layout.instructions, &truth.instructions));
+
+    if let Some(min) = matches.value_of("min_precision") {
+        let min: f64 = min.parse()?;
+        if functions.precision < min {
+            return Err(anyhow::anyhow!(
+                "function precision {:.3} is below {:.3}",
+                functions.precision,
+                min
+            ));
+        }
+    }
+
+    if let Some(min) = matches.value_of("min_recall") {
+        let min: f64 = min.parse()?;
+        if functions.recall < min {
+            return Err(anyhow::anyhow!(
+                "function recall {:.3} is below {:.3}",
+                functions.recall,
+                min
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = _main() {
+        #[cfg(debug_assertions)]
+        error!("{:?}", e);
+        #[cfg(not(debug_assertions))]
+        error!("{:}", e);
+
+        // so that scripts can detect regressions.
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lancelot::rsrc::*;
+
+    #[test]
+    fn nop() -> Result<()> {
+        // nop.exe ships without symbols, so the layout was audited by hand.
+        // it starts from the entry point and the targets of direct calls,
+        // excluding import thunks, found by a linear sweep of `.text`:
+        //
+        // ```text
+        // objdump -d -M intel --section=.text nop.exe | grep -oP "\tcall\s+0x\K[0-9a-f]+"
+        // ```
+        //
+        // and adds the functions reached only by pointer (initializer tables,
+        // exception handlers), by tail jump, or not at all, until the functions
+        // cover all of `.text` besides padding, jump tables, the import thunk,
+        // and the `__except` filters and handlers of their parent functions.
+        let truth = format!("{}.layout", get_path(Rsrc::NOP));
+        let truth = parse_layout(&String::from_utf8(util::read_file(&truth)?)?)?;
+        assert_eq!(truth.functions.len(), 107);
+
+        let buf = get_buf(Rsrc::NOP);
+        let pe = PE::from_bytes(&buf)?;
+        let ws = workspace::build_workspace_with_config(&pe, &Default::default())?;
+        let layout = get_workspace_layout(&pe, &ws)?;
+
+        let functions = score(&layout.functions, &truth.functions);
+        assert!(functions.recall >= 0.98, "recall: {:.3}", functions.recall);
+        assert!(functions.precision >= 0.69, "precision: {:.3}", functions.precision);
+
+        Ok(())
+    }
+}
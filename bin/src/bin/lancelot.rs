@@ -7,11 +7,19 @@ extern crate clap;
 #[macro_use]
 extern crate anyhow;
 
-use lancelot::{analysis::dis, aspace::AddressSpace, loader::pe::PE, util, RVA, VA};
-
-fn handle_functions(pe: &PE) -> Result<()> {
-    let functions = lancelot::analysis::pe::find_function_starts(pe)?;
+use lancelot::{
+    analysis::{
+        cfg, dis,
+        pe::{db::Database, names, names::Names},
+        xrefs,
+    },
+    aspace::AddressSpace,
+    config::Config,
+    loader::pe::PE,
+    util, RVA, VA,
+};
 
+fn handle_functions(functions: &[VA]) -> Result<()> {
     info!("found {} functions", functions.len());
     for va in functions.iter() {
         println!("{:#x}", va);
@@ -49,8 +57,26 @@ fn render_insn(insn: &zydis::ffi::DecodedInstruction, va: VA) -> String {
     format!("{}", buffer)
 }
 
-fn handle_disassemble(pe: &PE, va: VA) -> Result<()> {
-    let cfg = lancelot::analysis::cfg::build_cfg(&pe.module, va)?;
+/// render the names of the addresses referenced by the instruction,
+/// such as the target of a call, like `; kernel32.dll!CreateFileW`.
+fn render_insn_names(pe: &PE, names: &Names, va: VA, insn: &zydis::ffi::DecodedInstruction) -> String {
+    let mut labels: Vec<&str> = vec![];
+    for xref in xrefs::get_insn_xrefs(&pe.module, va, insn).unwrap_or_default().iter() {
+        if let Some(name) = names.get(xref.dst) {
+            if !labels.contains(&name.name.as_str()) {
+                labels.push(&name.name);
+            }
+        }
+    }
+
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("  ; {}", labels.join(", "))
+    }
+}
+
+fn handle_disassemble(pe: &PE, cfg: &cfg::CFG, names: &Names) -> Result<()> {
     let decoder = dis::get_disassembler(&pe.module)?;
 
     info!("found {} basic blocks", cfg.basic_blocks.len());
@@ -80,11 +106,12 @@ fn handle_disassemble(pe: &PE, va: VA) -> Result<()> {
             if let Ok(Some(insn)) = insn {
                 let insn_buf = &buf[offset..offset + insn.length as usize];
                 println!(
-                    "{}:{:016x}  {:15}  {}",
+                    "{}:{:016x}  {:15}  {}{}",
                     name,
                     va,
                     render_insn_buf(insn_buf, 15),
-                    render_insn(&insn, va)
+                    render_insn(&insn, va),
+                    render_insn_names(pe, names, va, &insn)
                 );
             } else {
                 println!("  {}:{:#x}: INVALID", name, va);
@@ -97,6 +124,135 @@ fn handle_disassemble(pe: &PE, va: VA) -> Result<()> {
     Ok(())
 }
 
+fn handle_xrefs(db: &Database, va: VA) -> Result<()> {
+    let xrefs = &db.analysis.xrefs;
+
+    println!("xrefs to {:#x}:", va);
+    for xref in xrefs.xrefs_to(va).iter() {
+        println!("  {:#x} ({})", xref.src, xref.ty);
+    }
+
+    println!("xrefs from {:#x}:", va);
+    for xref in xrefs.xrefs_from(va).iter() {
+        println!("  {:#x} ({})", xref.dst, xref.ty);
+    }
+
+    Ok(())
+}
+
+fn handle_strings(db: &Database) -> Result<()> {
+    use lancelot::analysis::strings;
+
+    let strings = strings::find_strings(&db.pe.module, &db.analysis.cfgs, &db.analysis.xrefs)?;
+
+    info!("found {} strings", strings.len());
+    for s in strings.values() {
+        let functions = s
+            .functions
+            .iter()
+            .map(|f| format!("{:#x}", f))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        println!(
+            "{}:{:016x}  {:8}  {:?}  [{}]",
+            s.section,
+            s.address,
+            s.encoding.to_string(),
+            s.string,
+            functions
+        );
+    }
+
+    Ok(())
+}
+
+fn handle_stack_strings(db: &Database, config: &Config) -> Result<()> {
+    use lancelot::analysis::{calling_convention, stack_strings};
+
+    let prototypes = calling_convention::find_prototypes(&db.pe.module, &db.analysis.cfgs, &db.analysis.call_graph)?;
+
+    let mut count = 0;
+    for (&function, cfg) in db.analysis.cfgs.iter() {
+        if db.analysis.library_functions.contains(&function) {
+            debug!("skipping library function: {:#x}", function);
+            continue;
+        }
+
+        let mut strings = stack_strings::find_stack_strings(&db.pe.module, function, cfg)?;
+        strings.extend(stack_strings::find_decoded_strings_with_config(
+            &db.pe.module,
+            function,
+            cfg,
+            &prototypes,
+            config,
+        )?);
+
+        for s in strings.iter() {
+            let ty = match s.ty {
+                stack_strings::StackStringType::Stack => "stack",
+                stack_strings::StackStringType::Decoded => "decoded",
+            };
+
+            println!(
+                "{:016x}  {:016x}  {:7}  {:8}  {:?}",
+                s.function,
+                s.address,
+                ty,
+                s.encoding.to_string(),
+                s.string
+            );
+        }
+        count += strings.len();
+    }
+    info!("found {} stack strings", count);
+
+    Ok(())
+}
+
+fn handle_api_calls(db: &Database) -> Result<()> {
+    use lancelot::analysis::{calling_convention, pe::api_calls};
+
+    let prototypes = calling_convention::find_prototypes(&db.pe.module, &db.analysis.cfgs, &db.analysis.call_graph)?;
+    let calls = api_calls::find_api_calls(&db.pe, &db.analysis.cfgs, &prototypes)?;
+
+    info!("found {} API calls", calls.len());
+    for call in calls.iter() {
+        println!("{:016x}  {:016x}  {}", call.function, call.address, call);
+    }
+
+    Ok(())
+}
+
+fn handle_pat(db: &Database, output: Option<&str>) -> Result<()> {
+    use lancelot::analysis::pe::sigmake;
+
+    let sigs = sigmake::make_pe_signatures(&db.pe, &db.analysis)?;
+    info!("created {} signatures", sigs.len());
+
+    let pat = lancelot_flirt::pat::render(&sigs);
+    match output {
+        Some(path) => std::fs::write(path, pat)?,
+        None => print!("{}", pat),
+    }
+
+    Ok(())
+}
+
+/// load the analysis of the sample from the cache directory, if given,
+/// otherwise analyze it from scratch.
+fn load_database(filename: &str, config: &Config, cache: Option<&str>) -> Result<Database> {
+    let buf = util::read_file(filename)?;
+
+    match cache {
+        Some(dir) => {
+            debug!("cache: {}", dir);
+            Database::from_cache(dir, &buf, config)
+        }
+        None => Database::from_bytes(&buf, config),
+    }
+}
+
 fn parse_va(s: &str) -> Result<VA> {
     if s.starts_with("0x") {
         let without_prefix = s.trim_start_matches("0x");
@@ -114,13 +270,32 @@ fn _main() -> Result<()> {
         (about: "Binary analysis framework")
         (@arg verbose: -v --verbose +multiple "log verbose messages")
         (@arg quiet: -q --quiet "disable informational messages")
+        (@arg config: -c --config +takes_value "path to TOML configuration file")
+        (@arg cache: --cache +takes_value "directory of analysis databases, shared with pylancelot")
         (@subcommand functions =>
             (about: "find functions")
             (@arg input: +required "path to file to analyze"))
         (@subcommand disassemble =>
             (about: "disassemble function")
             (@arg input: +required "path to file to analyze")
-            (@arg va: +required "VA of function")))
+            (@arg va: +required "VA of function"))
+        (@subcommand strings =>
+            (about: "find strings and the functions that use them")
+            (@arg input: +required "path to file to analyze"))
+        (@subcommand stackstrings =>
+            (about: "find strings constructed on the stack or decoded at runtime")
+            (@arg input: +required "path to file to analyze"))
+        (@subcommand apicalls =>
+            (about: "find calls to well-known APIs and their arguments")
+            (@arg input: +required "path to file to analyze"))
+        (@subcommand xrefs =>
+            (about: "show cross-references to and from an address")
+            (@arg input: +required "path to file to analyze")
+            (@arg va: +required "VA of instruction or data"))
+        (@subcommand pat =>
+            (about: "create FLIRT signatures for the functions, as a .pat file")
+            (@arg input: +required "path to file to analyze")
+            (@arg output: -o --output +takes_value "path to .pat file to write, otherwise stdout")))
     .get_matches();
 
     // --quiet overrides --verbose
@@ -155,16 +330,32 @@ fn _main() -> Result<()> {
         .apply()
         .expect("failed to configure logging");
 
+    let config = match matches.value_of("config") {
+        Some(path) => {
+            debug!("config: {}", path);
+            Config::from_file(path)?
+        }
+        None => Default::default(),
+    };
+
+    let cache = matches.value_of("cache");
+
     if let Some(matches) = matches.subcommand_matches("functions") {
         debug!("mode: find functions");
 
         let filename = matches.value_of("input").unwrap();
         debug!("input: {}", filename);
 
-        let buf = util::read_file(filename)?;
-        let pe = PE::from_bytes(&buf)?;
+        match cache {
+            Some(_) => handle_functions(&load_database(filename, &config, cache)?.analysis.function_starts()),
+            // don't do the full analysis just to list the functions.
+            None => {
+                let buf = util::read_file(filename)?;
+                let pe = PE::from_bytes(&buf)?;
 
-        handle_functions(&pe)
+                handle_functions(&lancelot::analysis::pe::find_function_starts_with_config(&pe, &config)?)
+            }
+        }
     } else if let Some(matches) = matches.subcommand_matches("disassemble") {
         debug!("mode: disassemble");
 
@@ -173,10 +364,77 @@ fn _main() -> Result<()> {
 
         let va = parse_va(matches.value_of("va").unwrap())?;
 
-        let buf = util::read_file(filename)?;
-        let pe = PE::from_bytes(&buf)?;
+        match cache {
+            // reuse the CFG from the analysis database, when it has one.
+            Some(_) => {
+                let db = load_database(filename, &config, cache)?;
+                match db.analysis.cfgs.get(&va) {
+                    Some(cfg) => handle_disassemble(&db.pe, cfg, &db.analysis.names),
+                    None => handle_disassemble(
+                        &db.pe,
+                        &cfg::build_cfg_with_config(&db.pe.module, va, &config)?,
+                        &db.analysis.names,
+                    ),
+                }
+            }
+            None => {
+                let buf = util::read_file(filename)?;
+                let pe = PE::from_bytes(&buf)?;
+                // imports and thunks are found along with the functions.
+                let sigs = lancelot::analysis::pe::Signatures::from_config(&config)?;
+                let functions = lancelot::analysis::pe::find_functions_with_signatures(&pe, &config, &sigs)?;
+                let names = names::find_pe_names_with_signatures(&pe, &functions, &sigs)?;
+
+                handle_disassemble(&pe, &cfg::build_cfg_with_config(&pe.module, va, &config)?, &names)
+            }
+        }
+    } else if let Some(matches) = matches.subcommand_matches("strings") {
+        debug!("mode: strings");
+
+        let filename = matches.value_of("input").unwrap();
+        debug!("input: {}", filename);
+
+        let db = load_database(filename, &config, cache)?;
+
+        handle_strings(&db)
+    } else if let Some(matches) = matches.subcommand_matches("stackstrings") {
+        debug!("mode: stack strings");
+
+        let filename = matches.value_of("input").unwrap();
+        debug!("input: {}", filename);
+
+        let db = load_database(filename, &config, cache)?;
+
+        handle_stack_strings(&db, &config)
+    } else if let Some(matches) = matches.subcommand_matches("apicalls") {
+        debug!("mode: API calls");
+
+        let filename = matches.value_of("input").unwrap();
+        debug!("input: {}", filename);
+
+        let db = load_database(filename, &config, cache)?;
+
+        handle_api_calls(&db)
+    } else if let Some(matches) = matches.subcommand_matches("xrefs") {
+        debug!("mode: xrefs");
+
+        let filename = matches.value_of("input").unwrap();
+        debug!("input: {}", filename);
+
+        let va = parse_va(matches.value_of("va").unwrap())?;
+
+        let db = load_database(filename, &config, cache)?;
+
+        handle_xrefs(&db, va)
+    } else if let Some(matches) = matches.subcommand_matches("pat") {
+        debug!("mode: pat");
+
+        let filename = matches.value_of("input").unwrap();
+        debug!("input: {}", filename);
+
+        let db = load_database(filename, &config, cache)?;
 
-        handle_disassemble(&pe, va)
+        handle_pat(&db, matches.value_of("output"))
     } else {
         Err(anyhow!("SUBCOMMAND required"))
     }
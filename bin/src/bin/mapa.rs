@@ -10,7 +10,6 @@
 // TODO: resource data section
 // TODO: overlay
 // TODO: stack strings
-// TODO: function names
 // TODO: flirt function names
 
 use std::collections::BTreeMap;
@@ -577,12 +576,22 @@ fn insert_resource_ranges(ranges: &mut Ranges, pe: &PE) -> Result<()> {
 
 /// add a range for each basic block. these won't be rendered, though.
 /// add a range for each function, from its start through all contiguous basic
-/// blocks. only the function start address will be rendered.
+/// blocks. only the function start address and name will be rendered.
 fn insert_function_ranges(ranges: &mut Ranges, pe: &PE) -> Result<()> {
-    let functions = lancelot::analysis::pe::find_function_starts(pe)?;
+    let functions = lancelot::analysis::pe::find_functions(pe)?;
+    let names = lancelot::analysis::pe::names::find_pe_names(pe, &functions)?;
+
+    let functions: Vec<VA> = functions
+        .iter()
+        .filter_map(|f| match f {
+            lancelot::analysis::pe::Function::Local(f) => Some(f.address),
+            _ => None,
+        })
+        .collect();
+    let cfgs = lancelot::analysis::cfg::build_cfgs(&pe.module, &functions, &Default::default());
 
     for &function in functions.iter() {
-        if let Ok(cfg) = lancelot::analysis::cfg::build_cfg(&pe.module, function) {
+        if let Some(cfg) = cfgs.get(&function) {
             let mut end = function;
             for bb in cfg.basic_blocks.values() {
                 if bb.address != end {
@@ -591,9 +600,12 @@ fn insert_function_ranges(ranges: &mut Ranges, pe: &PE) -> Result<()> {
                 end += bb.length;
             }
 
-            // TODO get function name
+            let name = match names.get(function) {
+                Some(name) => name.name.clone(),
+                None => format!("sub_{:x}", function),
+            };
 
-            ranges.va_insert(pe, function, end, Structure::Function(format!("sub_{:x}", function)))?;
+            ranges.va_insert(pe, function, end, Structure::Function(name))?;
         } else {
             debug!("failed to compute build CFG at 0x{:#x}", function);
         }
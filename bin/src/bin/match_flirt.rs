@@ -1,13 +1,16 @@
 #![allow(clippy::upper_case_acronyms)]
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
 
 use anyhow::Result;
 use log::{debug, error, info};
 #[macro_use]
 extern crate clap;
 
-use lancelot::{loader::pe::PE, util, VA};
+use lancelot::{config::Config, loader::pe::PE, util, VA};
 use lancelot_flirt::*;
 
 fn _main() -> Result<()> {
@@ -18,8 +21,9 @@ fn _main() -> Result<()> {
         (about: "Binary analysis framework")
         (@arg verbose: -v --verbose +multiple "log verbose messages")
         (@arg quiet: -q --quiet "disable informational messages")
+        (@arg config: -c --config +takes_value "path to TOML configuration file with FLIRT signature paths")
         (@arg input: +required "path to file to analyze")
-        (@arg sig: +required +multiple "path to FLIRT sig/pat"))
+        (@arg sig: +multiple "path to FLIRT sig/pat"))
     .get_matches();
 
     // --quiet overrides --verbose
@@ -54,17 +58,22 @@ fn _main() -> Result<()> {
         .apply()
         .expect("failed to configure logging");
 
-    let mut sigs = vec![];
-    for sigpath in matches.values_of("sig").unwrap() {
-        if sigpath.ends_with(".pat") {
-            sigs.extend(pat::parse(&String::from_utf8(util::read_file(sigpath)?)?)?);
-        } else if sigpath.ends_with(".sig") {
-            sigs.extend(sig::parse(&util::read_file(sigpath)?)?);
-        } else {
-            return Err(anyhow::anyhow!("--sig must end with .pat or .sig"));
-        };
+    let config = match matches.value_of("config") {
+        Some(path) => {
+            debug!("config: {}", path);
+            Config::from_file(path)?
+        }
+        None => Default::default(),
+    };
+
+    let mut sigpaths: Vec<PathBuf> = config.flirt.signatures.clone();
+    if let Some(paths) = matches.values_of("sig") {
+        sigpaths.extend(paths.map(PathBuf::from));
+    }
+    if sigpaths.is_empty() {
+        return Err(anyhow::anyhow!("no FLIRT signatures provided"));
     }
-    let sigs = FlirtSignatureSet::with_signatures(sigs);
+    let sigs = lancelot::analysis::flirt::load_flirt_signatures(&sigpaths)?;
 
     let filename = matches.value_of("input").unwrap();
     debug!("input: {}", filename);
@@ -72,7 +81,7 @@ fn _main() -> Result<()> {
     let buf = util::read_file(filename)?;
     let pe = PE::from_bytes(&buf)?;
 
-    let mut functions = lancelot::analysis::pe::find_function_starts(&pe)?;
+    let mut functions = lancelot::analysis::pe::find_function_starts_with_config(&pe, &config)?;
     functions.sort_unstable();
     info!("found {} functions", functions.len());
 
@@ -93,11 +102,10 @@ fn _main() -> Result<()> {
 
     let mut names: BTreeMap<VA, BTreeSet<Name>> = Default::default();
 
-    for &va in functions.iter() {
-        for sig in lancelot::analysis::flirt::match_flirt(&pe.module, &sigs, va)
-            .unwrap_or_default()
-            .iter()
-        {
+    let matches = lancelot::analysis::flirt::match_flirt_module(&pe.module, &sigs, &functions);
+    info!("found {} library functions", matches.matches.len());
+    for (&va, sigs) in matches.matches.iter() {
+        for sig in sigs.iter() {
             for name in sig.names.iter() {
                 match name {
                     Symbol::Reference(_) => continue,
@@ -2,15 +2,18 @@ use std::collections::BTreeMap;
 
 use anyhow::Result;
 use log::debug;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    analysis::{cfg, dis},
+    analysis::{cfg, constants, constants::CallTarget, dis},
     aspace::AddressSpace,
-    module::Module,
+    module::{Module, Permissions},
     RVA, VA,
 };
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct CallGraph {
     // call instruction indexes...
     /// map from function start to the addresses that call here.
@@ -30,39 +33,77 @@ pub struct CallGraph {
     pub call_instruction_functions: BTreeMap<VA, Vec<VA>>,
 }
 
+/// find the call instructions in the CFG and their targets.
+///
+/// indirect calls, like `mov eax, offset foo; call eax`, are resolved via
+/// constant propagation.
+fn find_calls(module: &Module, function: VA, cfg: &cfg::CFG) -> Result<Vec<(VA, VA)>> {
+    let decoder = dis::get_disassembler(module)?;
+    let mut calls = vec![];
+
+    for basic_block in cfg.basic_blocks.values() {
+        let buf = module
+            .address_space
+            .read_bytes(basic_block.address, basic_block.length as usize)?;
+
+        for (offset, insn) in dis::linear_disassemble(&decoder, &buf) {
+            if let Ok(Some(insn)) = insn {
+                if matches!(insn.mnemonic, zydis::enums::Mnemonic::CALL) {
+                    let va = basic_block.address + offset as RVA;
+                    for flow in cfg::get_call_insn_flow(module, va, &insn)?.iter() {
+                        if let cfg::Flow::Call(target) = *flow {
+                            calls.push((va, target));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    for (va, target) in constants::find_indirect_call_targets(module, cfg, function, &Default::default())?.into_iter() {
+        let target = match target {
+            CallTarget::Address(target) => target,
+            CallTarget::Pointer(ptr) => match module.read_va_at_va(ptr) {
+                Ok(target) => target,
+                Err(_) => continue,
+            },
+        };
+
+        // like direct calls, only code within the module is interesting.
+        if module.probe_va(target, Permissions::X) {
+            calls.push((va, target));
+        }
+    }
+
+    Ok(calls)
+}
+
 pub fn build_call_graph(module: &Module, cfgs: &BTreeMap<VA, cfg::CFG>) -> Result<CallGraph> {
     debug!("call graph");
 
-    let mut cg: CallGraph = Default::default();
-    let decoder = dis::get_disassembler(module)?;
-
-    for (&function, cfg) in cfgs.iter() {
+    let find = |(&function, cfg): (&VA, &cfg::CFG)| -> Result<(VA, Vec<(VA, VA)>)> {
         debug!("call graph: {:#x}", function);
+        Ok((function, find_calls(module, function, cfg)?))
+    };
+
+    // disassembling each function is independent, so may be done in parallel,
+    // while indexing happens in order, so the results are the same.
+    #[cfg(feature = "parallel")]
+    let calls: Vec<(VA, Vec<(VA, VA)>)> = cfgs.par_iter().map(find).collect::<Result<_>>()?;
+    #[cfg(not(feature = "parallel"))]
+    let calls: Vec<(VA, Vec<(VA, VA)>)> = cfgs.iter().map(find).collect::<Result<_>>()?;
 
+    let mut cg: CallGraph = Default::default();
+    for (function, calls) in calls.into_iter() {
         // ensure there are at least (empty) entries for all the keys in `functions`
         cg.function_call_instructions.entry(function).or_default();
         cg.calls_to.entry(function).or_default();
 
-        for basic_block in cfg.basic_blocks.values() {
-            let buf = module
-                .address_space
-                .read_bytes(basic_block.address, basic_block.length as usize)?;
-
-            for (offset, insn) in dis::linear_disassemble(&decoder, &buf) {
-                if let Ok(Some(insn)) = insn {
-                    if matches!(insn.mnemonic, zydis::enums::Mnemonic::CALL) {
-                        let va = basic_block.address + offset as RVA;
-                        for flow in cfg::get_call_insn_flow(module, va, &insn)?.iter() {
-                            if let cfg::Flow::Call(target) = *flow {
-                                cg.calls_from.entry(va).or_default().push(target);
-                                cg.calls_to.entry(target).or_default().push(va);
-                                cg.function_call_instructions.entry(function).or_default().push(va);
-                                cg.call_instruction_functions.entry(va).or_default().push(function);
-                            }
-                        }
-                    }
-                }
-            }
+        for (va, target) in calls.into_iter() {
+            cg.calls_from.entry(va).or_default().push(target);
+            cg.calls_to.entry(target).or_default().push(va);
+            cg.function_call_instructions.entry(function).or_default().push(va);
+            cg.call_instruction_functions.entry(va).or_default().push(function);
         }
     }
 
@@ -74,6 +115,7 @@ mod tests {
     use crate::{
         analysis::{call_graph, cfg::CFG, pe},
         rsrc::*,
+        test::*,
         VA,
     };
     use anyhow::Result;
@@ -138,4 +180,24 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn indirect_call() -> Result<()> {
+        // 0:  b8 08 00 00 00          mov    eax, 0x8
+        // 5:  ff d0                   call   eax
+        // 7:  c3                      ret
+        // 8:  c3                      ret
+        let module = load_shellcode32(b"\xB8\x08\x00\x00\x00\xFF\xD0\xC3\xC3");
+
+        let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+        cfgs.insert(0x0, crate::analysis::cfg::build_cfg(&module, 0x0)?);
+
+        let cg = call_graph::build_call_graph(&module, &cfgs)?;
+
+        assert_eq!(cg.calls_from[&0x5], vec![0x8]);
+        assert_eq!(cg.calls_to[&0x8], vec![0x5]);
+        assert_eq!(cg.function_call_instructions[&0x0], vec![0x5]);
+
+        Ok(())
+    }
 }
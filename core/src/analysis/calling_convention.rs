@@ -0,0 +1,892 @@
+//! Infer the calling convention and argument count of local functions.
+//!
+//! We use a few signals that can be collected from a function's CFG:
+//!
+//!   1. registers that are read before they are written, such as `ecx` for
+//!      thiscall or `rcx`/`rdx`/`r8`/`r9` for the Microsoft x64 ABI,
+//!   2. the number of bytes popped by `ret N`, which indicates callee cleanup
+//!      (stdcall/fastcall/thiscall),
+//!   3. accesses to stack slots above the return address, relative to either
+//!      the stack pointer or a frame pointer established in the prologue, and
+//!   4. the stack adjustment made by callers after a call returns (`add esp,
+//!      N`), which indicates caller cleanup (cdecl).
+//!
+//! None of these are definitive on their own - for example, an optimizing
+//! compiler may never touch an argument - so the result is a best guess.
+//!
+//! On x64 there is only one calling convention, so we report the registers
+//! used to pass arguments and the count of arguments passed on the stack.
+use std::collections::{BTreeMap, VecDeque};
+
+use anyhow::Result;
+use log::debug;
+use smallvec::SmallVec;
+
+use crate::{
+    analysis::{call_graph::CallGraph, cfg, cfg::CFG, dis},
+    arch::Arch,
+    aspace::AddressSpace,
+    module::Module,
+    RVA, VA,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallingConvention {
+    /// x86: arguments on the stack, caller cleans up.
+    Cdecl,
+    /// x86: arguments on the stack, callee cleans up via `ret N`.
+    Stdcall,
+    /// x86: first two arguments in ecx and edx, remainder on the stack,
+    /// callee cleans up.
+    Fastcall,
+    /// x86: `this` pointer in ecx, remainder on the stack, callee cleans up.
+    Thiscall,
+    /// x64: the Microsoft x64 ABI, first four arguments in rcx/rdx/r8/r9 (or
+    /// xmm0-3), remainder on the stack, caller cleans up.
+    Win64,
+}
+
+impl std::fmt::Display for CallingConvention {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallingConvention::Cdecl => write!(f, "cdecl"),
+            CallingConvention::Stdcall => write!(f, "stdcall"),
+            CallingConvention::Fastcall => write!(f, "fastcall"),
+            CallingConvention::Thiscall => write!(f, "thiscall"),
+            CallingConvention::Win64 => write!(f, "win64"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Prototype {
+    pub calling_convention: CallingConvention,
+    /// registers used to pass arguments, in argument order.
+    /// on x64, a position may be filled by either a GPR or XMM register.
+    pub register_arguments: Vec<zydis::Register>,
+    /// number of arguments passed on the stack.
+    pub stack_arguments:    usize,
+    /// number of bytes popped from the stack by the function, via `ret N`.
+    pub callee_cleanup:     u64,
+}
+
+impl Prototype {
+    pub fn argument_count(&self) -> usize {
+        self.register_arguments.len() + self.stack_arguments
+    }
+}
+
+/// registers that may be used to pass arguments, in argument order.
+/// on x64, each position has both an integer and floating point register.
+fn get_argument_registers(arch: Arch) -> SmallVec<[(zydis::Register, zydis::Register); 4]> {
+    use zydis::Register::*;
+    match arch {
+        Arch::X32 => smallvec::smallvec![(ECX, NONE), (EDX, NONE)],
+        Arch::X64 => smallvec::smallvec![(RCX, XMM0), (RDX, XMM1), (R8, XMM2), (R9, XMM3)],
+    }
+}
+
+fn get_machine_mode(arch: Arch) -> zydis::MachineMode {
+    match arch {
+        Arch::X32 => zydis::MachineMode::LEGACY_32,
+        Arch::X64 => zydis::MachineMode::LONG_64,
+    }
+}
+
+/// the set of argument registers, as a bitmask indexed by `ArgumentRegisters`.
+type RegisterSet = u32;
+
+/// index the argument registers for `arch` so that they can be tracked in a
+/// `RegisterSet`. the integer registers are at index `i`, and the floating
+/// point registers at index `i + 8`.
+struct ArgumentRegisters {
+    mode:      zydis::MachineMode,
+    registers: Vec<zydis::Register>,
+}
+
+impl ArgumentRegisters {
+    fn new(arch: Arch) -> ArgumentRegisters {
+        let mode = get_machine_mode(arch);
+        let mut registers = vec![zydis::Register::NONE; 16];
+        for (i, (gpr, xmm)) in get_argument_registers(arch).into_iter().enumerate() {
+            registers[i] = gpr.get_largest_enclosing(mode);
+            if xmm != zydis::Register::NONE {
+                registers[i + 8] = xmm.get_largest_enclosing(mode);
+            }
+        }
+        ArgumentRegisters { mode, registers }
+    }
+
+    fn index(&self, reg: zydis::Register) -> Option<usize> {
+        if reg == zydis::Register::NONE {
+            return None;
+        }
+        let reg = reg.get_largest_enclosing(self.mode);
+        self.registers.iter().position(|&r| r == reg)
+    }
+
+    fn mask(&self, reg: zydis::Register) -> RegisterSet {
+        match self.index(reg) {
+            Some(i) => 1 << i,
+            None => 0,
+        }
+    }
+
+    /// the argument registers that are not preserved across a call.
+    /// on both x32 and x64 (Microsoft ABI) this is all of them.
+    fn volatile(&self) -> RegisterSet {
+        self.registers
+            .iter()
+            .enumerate()
+            .filter(|(_, &r)| r != zydis::Register::NONE)
+            .fold(0, |acc, (i, _)| acc | 1 << i)
+    }
+}
+
+fn is_zeroing_idiom(insn: &zydis::DecodedInstruction) -> bool {
+    // xor eax, eax
+    // sub eax, eax
+    matches!(insn.mnemonic, zydis::Mnemonic::XOR | zydis::Mnemonic::SUB)
+        && insn.operands[0].ty == zydis::OperandType::REGISTER
+        && insn.operands[1].ty == zydis::OperandType::REGISTER
+        && insn.operands[0].reg == insn.operands[1].reg
+}
+
+/// compute the argument registers read and written by the given instruction.
+/// returns: (read, written)
+fn get_register_accesses(regs: &ArgumentRegisters, insn: &zydis::DecodedInstruction) -> (RegisterSet, RegisterSet) {
+    if is_zeroing_idiom(insn) {
+        return (0, regs.mask(insn.operands[0].reg));
+    }
+
+    if insn.mnemonic == zydis::Mnemonic::CALL {
+        // the callee may clobber any volatile register.
+        // we don't consider the registers passed to the callee as reads,
+        // since they'd already be written by this function.
+        return (0, regs.volatile());
+    }
+
+    let mut read = 0;
+    let mut written = 0;
+    for op in insn.operands.iter().take(insn.operand_count as usize) {
+        match op.ty {
+            zydis::OperandType::REGISTER => {
+                if insn.mnemonic == zydis::Mnemonic::PUSH && op.visibility == zydis::OperandVisibility::EXPLICIT {
+                    // `push ecx` is used to save a register or allocate a stack slot,
+                    // not to consume an argument.
+                    continue;
+                }
+
+                if op
+                    .action
+                    .intersects(zydis::OperandAction::READ | zydis::OperandAction::CONDREAD)
+                {
+                    read |= regs.mask(op.reg);
+                }
+                if op.action.intersects(zydis::OperandAction::WRITE) {
+                    written |= regs.mask(op.reg);
+                }
+            }
+            zydis::OperandType::MEMORY => {
+                read |= regs.mask(op.mem.base);
+                read |= regs.mask(op.mem.index);
+            }
+            _ => continue,
+        }
+    }
+
+    (read, written)
+}
+
+/// the state of the stack, relative to the stack pointer at function entry.
+/// when unknown, such as after `and esp, 0xFFFFFFF0`, the value is None.
+#[derive(Debug, Clone, Copy, Default)]
+struct StackState {
+    /// offset of the stack pointer from the stack pointer at entry.
+    sp: Option<i64>,
+    /// offset of the frame pointer from the stack pointer at entry.
+    bp: Option<i64>,
+}
+
+fn get_stack_pointer(arch: Arch) -> zydis::Register {
+    match arch {
+        Arch::X32 => zydis::Register::ESP,
+        Arch::X64 => zydis::Register::RSP,
+    }
+}
+
+fn get_frame_pointer(arch: Arch) -> zydis::Register {
+    match arch {
+        Arch::X32 => zydis::Register::EBP,
+        Arch::X64 => zydis::Register::RBP,
+    }
+}
+
+fn get_signed_immediate(op: &zydis::DecodedOperand) -> i64 {
+    if op.imm.is_signed {
+        crate::util::u64_i64(op.imm.value)
+    } else {
+        op.imm.value as i64
+    }
+}
+
+/// update the stack state across the given instruction.
+/// `callee_cleanups` is used to account for callees that pop their own
+/// arguments.
+fn update_stack_state(
+    module: &Module,
+    va: VA,
+    insn: &zydis::DecodedInstruction,
+    state: &mut StackState,
+    callee_cleanups: &BTreeMap<VA, u64>,
+) {
+    let sp = get_stack_pointer(module.arch);
+    let bp = get_frame_pointer(module.arch);
+    let op0 = &insn.operands[0];
+    let op1 = &insn.operands[1];
+
+    let writes_sp = insn.operands.iter().take(insn.operand_count as usize).any(|op| {
+        op.visibility == zydis::OperandVisibility::EXPLICIT
+            && op.ty == zydis::OperandType::REGISTER
+            && op.reg == sp
+            && op.action.intersects(zydis::OperandAction::WRITE)
+    });
+
+    match insn.mnemonic {
+        zydis::Mnemonic::PUSH => {
+            state.sp = state.sp.map(|sp| sp - (insn.operand_width / 8) as i64);
+        }
+        zydis::Mnemonic::POP => {
+            if op0.ty == zydis::OperandType::REGISTER && op0.reg == bp {
+                state.bp = None;
+            }
+            state.sp = state.sp.map(|sp| sp + (insn.operand_width / 8) as i64);
+        }
+        zydis::Mnemonic::SUB | zydis::Mnemonic::ADD
+            if op0.ty == zydis::OperandType::REGISTER && op0.reg == sp && op1.ty == zydis::OperandType::IMMEDIATE =>
+        {
+            let imm = get_signed_immediate(op1);
+            state.sp = state.sp.map(|sp| {
+                if insn.mnemonic == zydis::Mnemonic::SUB {
+                    sp - imm
+                } else {
+                    sp + imm
+                }
+            });
+        }
+        zydis::Mnemonic::MOV
+            if op0.ty == zydis::OperandType::REGISTER
+                && op1.ty == zydis::OperandType::REGISTER
+                && op0.reg == bp
+                && op1.reg == sp =>
+        {
+            // mov ebp, esp
+            state.bp = state.sp;
+        }
+        zydis::Mnemonic::MOV
+            if op0.ty == zydis::OperandType::REGISTER
+                && op1.ty == zydis::OperandType::REGISTER
+                && op0.reg == sp
+                && op1.reg == bp =>
+        {
+            // mov esp, ebp
+            state.sp = state.bp;
+        }
+        zydis::Mnemonic::LEA if op0.ty == zydis::OperandType::REGISTER && (op0.reg == sp || op0.reg == bp) => {
+            // lea ebp, [esp+0x10]
+            // lea esp, [ebp-0xC]
+            let value = if op1.mem.index != zydis::Register::NONE {
+                None
+            } else if op1.mem.base == sp {
+                state.sp.map(|sp| sp + op1.mem.disp.displacement)
+            } else if op1.mem.base == bp {
+                state.bp.map(|bp| bp + op1.mem.disp.displacement)
+            } else {
+                None
+            };
+
+            if op0.reg == sp {
+                state.sp = value;
+            } else {
+                state.bp = value;
+            }
+        }
+        zydis::Mnemonic::LEAVE => {
+            // mov esp, ebp
+            // pop ebp
+            state.sp = state.bp.map(|bp| bp + (insn.stack_width / 8) as i64);
+            state.bp = None;
+        }
+        zydis::Mnemonic::CALL => {
+            if let Ok(flows) = cfg::get_call_insn_flow(module, va, insn) {
+                if let Some(cfg::Flow::Call(target)) = flows.first() {
+                    if let Some(&cleanup) = callee_cleanups.get(target) {
+                        state.sp = state.sp.map(|sp| sp + cleanup as i64);
+                    }
+                }
+            }
+        }
+        _ => {
+            if writes_sp {
+                // like: and esp, 0xFFFFFFF0
+                state.sp = None;
+            }
+            if op0.visibility == zydis::OperandVisibility::EXPLICIT
+                && op0.ty == zydis::OperandType::REGISTER
+                && op0.reg == bp
+                && op0.action.intersects(zydis::OperandAction::WRITE)
+            {
+                state.bp = None;
+            }
+        }
+    }
+}
+
+/// if the given operand references a stack slot above the return address,
+/// return the offset of the slot from the stack pointer at function entry.
+fn get_argument_slot_offset(arch: Arch, op: &zydis::DecodedOperand, state: &StackState) -> Option<i64> {
+    if op.ty != zydis::OperandType::MEMORY || op.mem.index != zydis::Register::NONE {
+        return None;
+    }
+
+    let offset = if op.mem.base == get_stack_pointer(arch) {
+        state.sp? + op.mem.disp.displacement
+    } else if op.mem.base == get_frame_pointer(arch) {
+        state.bp? + op.mem.disp.displacement
+    } else {
+        return None;
+    };
+
+    // the return address is at offset 0.
+    if offset >= arch.pointer_size() as i64 {
+        Some(offset)
+    } else {
+        None
+    }
+}
+
+fn read_basic_block_insns(
+    module: &Module,
+    decoder: &zydis::Decoder,
+    bb: &cfg::BasicBlock,
+) -> Result<Vec<(VA, zydis::DecodedInstruction)>> {
+    let buf = module.address_space.read_bytes(bb.address, bb.length as usize)?;
+
+    Ok(dis::linear_disassemble(decoder, &buf)
+        .filter_map(|(offset, insn)| match insn {
+            Ok(Some(insn)) => Some((bb.address + offset as RVA, insn)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// the number of bytes popped from the stack by the `ret` instructions found
+/// in the given CFG. zero when the function returns with a plain `ret`.
+pub fn get_callee_cleanup(module: &Module, cfg: &CFG) -> Result<u64> {
+    let decoder = dis::get_disassembler(module)?;
+    let mut cleanup = 0u64;
+
+    for bb in cfg.basic_blocks.values() {
+        // only the final instruction of a basic block may be a `ret`.
+        if !bb.successors.is_empty() {
+            continue;
+        }
+
+        for (_, insn) in read_basic_block_insns(module, &decoder, bb)?.iter() {
+            if insn.mnemonic == zydis::Mnemonic::RET && insn.operands[0].ty == zydis::OperandType::IMMEDIATE {
+                cleanup = cleanup.max(insn.operands[0].imm.value);
+            }
+        }
+    }
+
+    Ok(cleanup)
+}
+
+/// the number of bytes removed from the stack by callers of the given
+/// function, immediately following the `call` instruction, like:
+///
+/// ```text
+///     call  sub_401000
+///     add   esp, 8
+/// ```
+///
+/// returns None if there are no callers, or none of them clean up the stack.
+pub fn get_caller_cleanup(module: &Module, cg: &CallGraph, va: VA) -> Result<Option<u64>> {
+    let decoder = dis::get_disassembler(module)?;
+    let sp = get_stack_pointer(module.arch);
+    let psize = module.arch.pointer_size() as u64;
+    let mut cleanup: Option<u64> = None;
+
+    for &call in cg.calls_to.get(&va).map(|calls| calls.as_slice()).unwrap_or(&[]) {
+        let mut insn_buf = [0u8; 32];
+        if module.address_space.read_into(call, &mut insn_buf).is_err() {
+            continue;
+        }
+
+        let mut offset = match decoder.decode(&insn_buf) {
+            Ok(Some(insn)) => insn.length as usize,
+            _ => continue,
+        };
+
+        let mut call_cleanup = 0u64;
+        // at most two `pop` instructions, like: pop ecx; pop ecx
+        for _ in 0..2 {
+            let insn = match decoder.decode(&insn_buf[offset..]) {
+                Ok(Some(insn)) => insn,
+                _ => break,
+            };
+
+            let op0 = &insn.operands[0];
+            let op1 = &insn.operands[1];
+            if insn.mnemonic == zydis::Mnemonic::ADD
+                && op0.ty == zydis::OperandType::REGISTER
+                && op0.reg == sp
+                && op1.ty == zydis::OperandType::IMMEDIATE
+            {
+                call_cleanup += op1.imm.value;
+                break;
+            } else if matches!(module.arch, Arch::X32)
+                && insn.mnemonic == zydis::Mnemonic::POP
+                && op0.ty == zydis::OperandType::REGISTER
+                && op0.reg == zydis::Register::ECX
+            {
+                call_cleanup += psize;
+                offset += insn.length as usize;
+            } else {
+                break;
+            }
+        }
+
+        // compilers may merge the cleanup of consecutive calls into a single
+        // `add esp, N`, so the smallest cleanup is the best estimate.
+        if call_cleanup > 0 {
+            cleanup = Some(cleanup.map_or(call_cleanup, |cleanup| cleanup.min(call_cleanup)));
+        }
+    }
+
+    Ok(cleanup)
+}
+
+/// infer the prototype of the function at `va` with the given CFG.
+///
+/// `caller_cleanup` is the result of `get_caller_cleanup`, if known.
+/// `callee_cleanups` maps from function address to the result of
+/// `get_callee_cleanup`, and is used to track the stack pointer across calls.
+pub fn analyze_function(
+    module: &Module,
+    va: VA,
+    cfg: &CFG,
+    caller_cleanup: Option<u64>,
+    callee_cleanups: &BTreeMap<VA, u64>,
+) -> Result<Prototype> {
+    let decoder = dis::get_disassembler(module)?;
+    let regs = ArgumentRegisters::new(module.arch);
+    let psize = module.arch.pointer_size() as i64;
+
+    // first, find the registers read before they're written.
+    //
+    // this is a forward data flow analysis over the "must be written" registers:
+    // at a join, a register is written only if it was written along every path.
+    let mut insns: BTreeMap<VA, Vec<(VA, zydis::DecodedInstruction)>> = Default::default();
+    for (&bbva, bb) in cfg.basic_blocks.iter() {
+        insns.insert(bbva, read_basic_block_insns(module, &decoder, bb)?);
+    }
+
+    let all: RegisterSet = !0;
+    let mut written_out: BTreeMap<VA, RegisterSet> = cfg.basic_blocks.keys().map(|&bbva| (bbva, all)).collect();
+    let written_in = |bbva: VA, written_out: &BTreeMap<VA, RegisterSet>| -> RegisterSet {
+        if bbva == va {
+            return 0;
+        }
+        cfg.basic_blocks[&bbva]
+            .predecessors
+            .iter()
+            .filter_map(|pred| written_out.get(&pred.va()))
+            .fold(all, |acc, &w| acc & w)
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &bbva in cfg.basic_blocks.keys() {
+            let mut written = written_in(bbva, &written_out);
+            for (_, insn) in insns[&bbva].iter() {
+                written |= get_register_accesses(&regs, insn).1;
+            }
+
+            if written_out[&bbva] != written {
+                written_out.insert(bbva, written);
+                changed = true;
+            }
+        }
+    }
+
+    let mut read_before_write: RegisterSet = 0;
+    for &bbva in cfg.basic_blocks.keys() {
+        let mut written = written_in(bbva, &written_out);
+        for (_, insn) in insns[&bbva].iter() {
+            let (read, write) = get_register_accesses(&regs, insn);
+            read_before_write |= read & !written;
+            written |= write;
+        }
+    }
+
+    // second, track the stack pointer from the entry of the function,
+    // looking for references to stack slots above the return address.
+    let mut stack_states: BTreeMap<VA, StackState> = Default::default();
+    let mut queue: VecDeque<VA> = Default::default();
+    if cfg.basic_blocks.contains_key(&va) {
+        stack_states.insert(va, StackState { sp: Some(0), bp: None });
+        queue.push_back(va);
+    }
+
+    let mut max_slot_offset: Option<i64> = None;
+    while let Some(bbva) = queue.pop_front() {
+        let mut state = stack_states[&bbva];
+
+        for (insn_va, insn) in insns[&bbva].iter() {
+            for op in insn.operands.iter().take(insn.operand_count as usize) {
+                if op.visibility != zydis::OperandVisibility::EXPLICIT {
+                    continue;
+                }
+
+                // writes to argument slots on x64 are spills of register arguments,
+                // and writes on x32 don't tell us that an argument was passed.
+                if insn.mnemonic != zydis::Mnemonic::LEA
+                    && !op
+                        .action
+                        .intersects(zydis::OperandAction::READ | zydis::OperandAction::CONDREAD)
+                {
+                    continue;
+                }
+
+                if let Some(offset) = get_argument_slot_offset(module.arch, op, &state) {
+                    max_slot_offset = Some(max_slot_offset.unwrap_or(0).max(offset));
+                }
+            }
+
+            update_stack_state(module, *insn_va, insn, &mut state, callee_cleanups);
+        }
+
+        for succ in cfg.basic_blocks[&bbva].successors.iter() {
+            let succva = succ.va();
+            if cfg.basic_blocks.contains_key(&succva) && !stack_states.contains_key(&succva) {
+                stack_states.insert(succva, state);
+                queue.push_back(succva);
+            }
+        }
+    }
+
+    // slot zero is the first stack slot above the return address.
+    let slot_count = match max_slot_offset {
+        Some(offset) => ((offset - psize) / psize) as usize + 1,
+        None => 0,
+    };
+
+    let callee_cleanup = get_callee_cleanup(module, cfg)?;
+
+    let prototype = match module.arch {
+        Arch::X32 => {
+            let reads_ecx = read_before_write & regs.mask(zydis::Register::ECX) != 0;
+            let reads_edx = read_before_write & regs.mask(zydis::Register::EDX) != 0;
+
+            let (calling_convention, register_arguments) = if reads_edx {
+                (
+                    CallingConvention::Fastcall,
+                    vec![zydis::Register::ECX, zydis::Register::EDX],
+                )
+            } else if reads_ecx {
+                (CallingConvention::Thiscall, vec![zydis::Register::ECX])
+            } else if callee_cleanup > 0 {
+                (CallingConvention::Stdcall, vec![])
+            } else {
+                (CallingConvention::Cdecl, vec![])
+            };
+
+            // callers may merge the cleanup of many calls into a single `add esp, N`,
+            // so only rely on caller cleanup when the function doesn't touch its stack
+            // slots.
+            let stack_arguments = if slot_count > 0 {
+                slot_count.max((callee_cleanup / psize as u64) as usize)
+            } else {
+                ((callee_cleanup / psize as u64) as usize).max((caller_cleanup.unwrap_or(0) / psize as u64) as usize)
+            };
+
+            Prototype {
+                calling_convention,
+                register_arguments,
+                stack_arguments,
+                callee_cleanup,
+            }
+        }
+        Arch::X64 => {
+            // register arguments are positional, so the last used position
+            // determines the count of register arguments.
+            let positions = get_argument_registers(module.arch);
+            let mut register_arguments = vec![];
+            let last = positions
+                .iter()
+                .enumerate()
+                .rev()
+                .find(|(i, _)| read_before_write & (1 << i | 1 << (i + 8)) != 0)
+                .map(|(i, _)| i);
+
+            if let Some(last) = last {
+                for (i, &(gpr, xmm)) in positions.iter().enumerate().take(last + 1) {
+                    if read_before_write & (1 << (i + 8)) != 0 && read_before_write & (1 << i) == 0 {
+                        register_arguments.push(xmm);
+                    } else {
+                        register_arguments.push(gpr);
+                    }
+                }
+            }
+
+            // the first four slots are the home space for the register arguments.
+            let stack_arguments = slot_count.saturating_sub(positions.len());
+
+            Prototype {
+                calling_convention: CallingConvention::Win64,
+                register_arguments,
+                stack_arguments,
+                callee_cleanup,
+            }
+        }
+    };
+
+    debug!(
+        "calling convention: {:#x}: {} with {} arguments",
+        va,
+        prototype.calling_convention,
+        prototype.argument_count()
+    );
+
+    Ok(prototype)
+}
+
+/// infer the prototypes of all the functions with the given CFGs.
+pub fn find_prototypes(module: &Module, cfgs: &BTreeMap<VA, CFG>, cg: &CallGraph) -> Result<BTreeMap<VA, Prototype>> {
+    // first pass: find callee cleanup, so that we can track the stack across calls.
+    let mut callee_cleanups: BTreeMap<VA, u64> = Default::default();
+    for (&va, cfg) in cfgs.iter() {
+        callee_cleanups.insert(va, get_callee_cleanup(module, cfg)?);
+    }
+
+    let mut prototypes: BTreeMap<VA, Prototype> = Default::default();
+    for (&va, cfg) in cfgs.iter() {
+        let caller_cleanup = get_caller_cleanup(module, cg, va)?;
+        prototypes.insert(va, analyze_function(module, va, cfg, caller_cleanup, &callee_cleanups)?);
+    }
+
+    Ok(prototypes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::{
+            call_graph,
+            calling_convention::*,
+            cfg::{build_cfg, CFG},
+            pe,
+        },
+        rsrc::*,
+        test::*,
+        VA,
+    };
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    fn analyze(module: &crate::module::Module) -> Result<Prototype> {
+        let cfg = build_cfg(module, 0x0)?;
+        analyze_function(module, 0x0, &cfg, None, &Default::default())
+    }
+
+    #[test]
+    fn stdcall() -> Result<()> {
+        // 0:  55                      push   ebp
+        // 1:  8b ec                   mov    ebp,esp
+        // 3:  8b 45 08                mov    eax,DWORD PTR [ebp+0x8]
+        // 6:  03 45 0c                add    eax,DWORD PTR [ebp+0xc]
+        // 9:  5d                      pop    ebp
+        // a:  c2 08 00                ret    0x8
+        let module = load_shellcode32(b"\x55\x8B\xEC\x8B\x45\x08\x03\x45\x0C\x5D\xC2\x08\x00");
+        let p = analyze(&module)?;
+        assert_eq!(p.calling_convention, CallingConvention::Stdcall);
+        assert_eq!(p.argument_count(), 2);
+        assert_eq!(p.callee_cleanup, 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cdecl() -> Result<()> {
+        // 0:  8b 44 24 04             mov    eax,DWORD PTR [esp+0x4]
+        // 4:  03 44 24 08             add    eax,DWORD PTR [esp+0x8]
+        // 8:  03 44 24 0c             add    eax,DWORD PTR [esp+0xc]
+        // c:  c3                      ret
+        let module = load_shellcode32(b"\x8B\x44\x24\x04\x03\x44\x24\x08\x03\x44\x24\x0C\xC3");
+        let p = analyze(&module)?;
+        assert_eq!(p.calling_convention, CallingConvention::Cdecl);
+        assert_eq!(p.argument_count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn cdecl_pushes() -> Result<()> {
+        // the stack slot offsets shift as registers are pushed.
+        //
+        // 0:  56                      push   esi
+        // 1:  57                      push   edi
+        // 2:  8b 74 24 0c             mov    esi,DWORD PTR [esp+0xc]
+        // 6:  5f                      pop    edi
+        // 7:  5e                      pop    esi
+        // 8:  c3                      ret
+        let module = load_shellcode32(b"\x56\x57\x8B\x74\x24\x0C\x5F\x5E\xC3");
+        let p = analyze(&module)?;
+        assert_eq!(p.calling_convention, CallingConvention::Cdecl);
+        assert_eq!(p.argument_count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn thiscall() -> Result<()> {
+        // 0:  51                      push   ecx
+        // 1:  8b 41 04                mov    eax,DWORD PTR [ecx+0x4]
+        // 4:  59                      pop    ecx
+        // 5:  c2 04 00                ret    0x4
+        let module = load_shellcode32(b"\x51\x8B\x41\x04\x59\xC2\x04\x00");
+        let p = analyze(&module)?;
+        assert_eq!(p.calling_convention, CallingConvention::Thiscall);
+        assert_eq!(p.register_arguments, vec![zydis::Register::ECX]);
+        assert_eq!(p.argument_count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fastcall() -> Result<()> {
+        // 0:  8d 04 11                lea    eax,[ecx+edx*1]
+        // 3:  c3                      ret
+        let module = load_shellcode32(b"\x8D\x04\x11\xC3");
+        let p = analyze(&module)?;
+        assert_eq!(p.calling_convention, CallingConvention::Fastcall);
+        assert_eq!(p.argument_count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn clobbered_register() -> Result<()> {
+        // ecx is written on both paths before it is read.
+        //
+        // 0:  85 c0                   test   eax,eax
+        // 2:  74 04                   je     0x8
+        // 4:  33 c9                   xor    ecx,ecx
+        // 6:  eb 02                   jmp    0xa
+        // 8:  b1 01                   mov    cl,0x1
+        // a:  8b c1                   mov    eax,ecx
+        // c:  c3                      ret
+        let module = load_shellcode32(b"\x85\xC0\x74\x04\x33\xC9\xEB\x02\xB1\x01\x8B\xC1\xC3");
+        let p = analyze(&module)?;
+        assert_eq!(p.calling_convention, CallingConvention::Cdecl);
+        assert_eq!(p.argument_count(), 0);
+
+        // ecx is written on only one path.
+        //
+        // 0:  85 c0                   test   eax,eax
+        // 2:  74 02                   je     0x6
+        // 4:  33 c9                   xor    ecx,ecx
+        // 6:  8b c1                   mov    eax,ecx
+        // 8:  c3                      ret
+        let module = load_shellcode32(b"\x85\xC0\x74\x02\x33\xC9\x8B\xC1\xC3");
+        let p = analyze(&module)?;
+        assert_eq!(p.calling_convention, CallingConvention::Thiscall);
+
+        Ok(())
+    }
+
+    #[test]
+    fn win64() -> Result<()> {
+        // 0:  48 89 5c 24 08          mov    QWORD PTR [rsp+0x8],rbx
+        // 5:  48 8b c1                mov    rax,rcx
+        // 8:  49 03 c0                add    rax,r8
+        // b:  48 03 44 24 28          add    rax,QWORD PTR [rsp+0x28]
+        // 10: c3                      ret
+        let module = load_shellcode64(b"\x48\x89\x5C\x24\x08\x48\x8B\xC1\x49\x03\xC0\x48\x03\x44\x24\x28\xC3");
+        let p = analyze(&module)?;
+        assert_eq!(p.calling_convention, CallingConvention::Win64);
+        assert_eq!(
+            p.register_arguments,
+            vec![zydis::Register::RCX, zydis::Register::RDX, zydis::Register::R8]
+        );
+        assert_eq!(p.stack_arguments, 1);
+        assert_eq!(p.argument_count(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn win64_float() -> Result<()> {
+        // 0:  f2 0f 58 c1             addsd  xmm0,xmm1
+        // 4:  c3                      ret
+        let module = load_shellcode64(b"\xF2\x0F\x58\xC1\xC3");
+        let p = analyze(&module)?;
+        assert_eq!(p.register_arguments, vec![zydis::Register::XMM0, zydis::Register::XMM1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        // this is _report_gsfailure, which spills rcx to its home space:
+        //
+        //     mov [rsp+0x08], rcx
+        //     sub rsp, 0x88
+        let cfg = build_cfg(&pe.module, 0x1800202B0)?;
+        let p = analyze_function(&pe.module, 0x1800202B0, &cfg, None, &Default::default())?;
+        assert_eq!(p.calling_convention, CallingConvention::Win64);
+        assert_eq!(p.register_arguments, vec![zydis::Register::RCX]);
+        assert_eq!(p.stack_arguments, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+        for &function in pe::find_function_starts(&pe)?.iter() {
+            if let Ok(cfg) = build_cfg(&pe.module, function) {
+                cfgs.insert(function, cfg);
+            }
+        }
+        let cg = call_graph::build_call_graph(&pe.module, &cfgs)?;
+        let prototypes = find_prototypes(&pe.module, &cfgs, &cg)?;
+
+        // this function reads arguments from [ebp+0x8] through [ebp+0x18]
+        // and its callers clean up the stack via `add esp, 0x14`.
+        let p = &prototypes[&0x40D3FD];
+        assert_eq!(p.calling_convention, CallingConvention::Cdecl);
+        assert_eq!(p.argument_count(), 5);
+
+        // this function reads arguments at [ebp+0x8] and [ebp+0xC],
+        // though its sole caller merges the cleanup of many calls.
+        let p = &prototypes[&0x401391];
+        assert_eq!(p.calling_convention, CallingConvention::Cdecl);
+        assert_eq!(p.argument_count(), 2);
+
+        // this function ends with `ret 0x14`.
+        let p = &prototypes[&0x4011FB];
+        assert_eq!(p.calling_convention, CallingConvention::Stdcall);
+        assert_eq!(p.argument_count(), 5);
+
+        Ok(())
+    }
+}
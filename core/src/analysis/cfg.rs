@@ -2,17 +2,28 @@ use std::collections::{BTreeMap, VecDeque};
 
 use anyhow::Result;
 use log::debug;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use smallvec::{smallvec, SmallVec};
+use thiserror::Error;
 
 use crate::{
     analysis::dis,
     aspace::AddressSpace,
+    config::Config,
     module::{Module, Permissions},
     util, VA,
 };
 
+#[derive(Debug, Error)]
+pub enum CFGError {
+    #[error("too many instructions")]
+    TooManyInstructions,
+}
+
 /// The type and destination of a control flow.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Flow {
     // mov eax, eax
     // push ebp
@@ -68,7 +79,7 @@ impl Flow {
 /// most instructions have 1-2 flows, so attempt to store the inline.
 type Flows = SmallVec<[Flow; 2]>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BasicBlock {
     /// start VA of the basic block.
     pub address: VA,
@@ -83,6 +94,7 @@ pub struct BasicBlock {
     pub successors: Flows,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CFG {
     // we use a btree so that we can conveniently iterate in order.
     // alternative choice would be an FNV hash map,
@@ -575,7 +587,11 @@ fn empty<'a, T>(mut i: Box<dyn Iterator<Item = T> + 'a>) -> bool {
     i.next().is_none()
 }
 
-fn read_insn_descriptors(module: &Module, va: VA) -> Result<BTreeMap<VA, InstructionDescriptor>> {
+fn read_insn_descriptors(
+    module: &Module,
+    va: VA,
+    max_instructions: usize,
+) -> Result<BTreeMap<VA, InstructionDescriptor>> {
     let decoder = dis::get_disassembler(module)?;
     let mut insn_buf = [0u8; 16];
 
@@ -613,6 +629,10 @@ fn read_insn_descriptors(module: &Module, va: VA) -> Result<BTreeMap<VA, Instruc
                 };
 
                 insns.insert(va, desc);
+
+                if insns.len() > max_instructions {
+                    return Err(CFGError::TooManyInstructions.into());
+                }
             }
         }
     }
@@ -789,9 +809,13 @@ fn compute_basic_blocks(
 }
 
 pub fn build_cfg(module: &Module, va: VA) -> Result<CFG> {
+    build_cfg_with_config(module, va, &Default::default())
+}
+
+pub fn build_cfg_with_config(module: &Module, va: VA, config: &Config) -> Result<CFG> {
     debug!("cfg: {:#x}", va);
 
-    let insns = read_insn_descriptors(module, va)?;
+    let insns = read_insn_descriptors(module, va, config.cfg.max_instructions)?;
     debug!("cfg: {:#x}: {} instructions", va, insns.len());
 
     let successors = compute_successors(&insns);
@@ -803,9 +827,35 @@ pub fn build_cfg(module: &Module, va: VA) -> Result<CFG> {
     Ok(CFG { basic_blocks: bbs })
 }
 
+/// build the CFGs of the given functions, indexed by function start.
+/// functions whose CFG cannot be built are skipped.
+///
+/// with the `parallel` feature, the CFGs are built across a thread pool,
+/// though the results are the same.
+pub fn build_cfgs(module: &Module, functions: &[VA], config: &Config) -> BTreeMap<VA, CFG> {
+    let build = |&va: &VA| match build_cfg_with_config(module, va, config) {
+        Ok(cfg) => Some((va, cfg)),
+        Err(e) => {
+            debug!("cfg: {:#x}: failed to build CFG: {}", va, e);
+            None
+        }
+    };
+
+    #[cfg(feature = "parallel")]
+    let cfgs = functions.par_iter().filter_map(build).collect();
+    #[cfg(not(feature = "parallel"))]
+    let cfgs = functions.iter().filter_map(build).collect();
+
+    cfgs
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{analysis::cfg::build_cfg, rsrc::*};
+    use crate::{
+        analysis::cfg::{build_cfg, build_cfg_with_config, build_cfgs},
+        config::Config,
+        rsrc::*,
+    };
     use anyhow::Result;
 
     #[test]
@@ -820,6 +870,24 @@ mod tests {
         let cfg = build_cfg(&pe.module, 0x1800527B0)?;
         assert_eq!(cfg.basic_blocks.len(), 4);
 
+        let config = Config::from_toml("[cfg]\nmax_instructions = 8")?;
+        assert!(build_cfg_with_config(&pe.module, 0x1800527B0, &config).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cfgs() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let functions = crate::analysis::pe::find_function_starts(&pe)?;
+        let cfgs = build_cfgs(&pe.module, &functions, &Default::default());
+        assert_eq!(cfgs.len(), functions.len());
+        for (&va, cfg) in cfgs.iter() {
+            assert_eq!(cfg.basic_blocks.len(), build_cfg(&pe.module, va)?.basic_blocks.len());
+        }
+
         Ok(())
     }
 }
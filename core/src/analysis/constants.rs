@@ -0,0 +1,898 @@
+//! Track the values of registers and stack slots through a function, via
+//! forward constant propagation over the IR of its CFG.
+//!
+//! Values are either constants, like `mov eax, 0x401000`, addresses within
+//! the stack frame, like `lea ecx, [ebp-0x10]`, or pointers read from a fixed
+//! address, like `mov esi, [__imp_CreateFileA]`. At joins in the CFG, only
+//! the values that agree along all incoming paths are kept.
+//!
+//! This lets us answer questions like: what does `call eax` call?
+//! and, which string is passed to `printf`?
+//!
+//! Limitations:
+//!   - stores through pointers that aren't known to reference the stack frame
+//!     are assumed not to modify it.
+//!   - on x86, the stack pointer is lost after a call, unless we know how many
+//!     bytes the callee pops from the stack. the frame pointer remains valid.
+//!   - loops are not unrolled: a value modified in a loop is not known within
+//!     it.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    analysis::{
+        calling_convention::Prototype,
+        cfg,
+        cfg::{Flow, CFG},
+        dis,
+        ir::{self, eval, lift, mask, Expr, Flag, Size, Stmt, Var},
+    },
+    arch::Arch,
+    aspace::AddressSpace,
+    module::Module,
+    RVA, VA,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Value {
+    /// a constant, such as an immediate or the address of a global.
+    Const(u64),
+    /// an address in the stack frame: the value of the stack pointer at
+    /// function entry, plus this offset.
+    Stack(i64),
+    /// the pointer read from the given address, such as an import table entry.
+    Deref(VA),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Const(value) => write!(f, "{:#x}", value),
+            Value::Stack(offset) if *offset < 0 => write!(f, "sp-{:#x}", -offset),
+            Value::Stack(offset) => write!(f, "sp+{:#x}", offset),
+            Value::Deref(addr) => write!(f, "[{:#x}]", addr),
+        }
+    }
+}
+
+/// the known values of registers, flags, and stack slots at a program point.
+/// anything not present is unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    /// the pointer size, in bits.
+    width: Size,
+    vars:  HashMap<Var, Value>,
+    /// stack slots keyed by the offset from the stack pointer at function
+    /// entry, with the size of the value stored there, in bits.
+    stack: BTreeMap<i64, (Size, Value)>,
+}
+
+fn get_machine_mode(width: Size) -> zydis::MachineMode {
+    if width == 64 {
+        zydis::MachineMode::LONG_64
+    } else {
+        zydis::MachineMode::LEGACY_32
+    }
+}
+
+fn get_width(arch: Arch) -> Size {
+    match arch {
+        Arch::X32 => 32,
+        Arch::X64 => 64,
+    }
+}
+
+/// registers that the callee may overwrite.
+fn get_volatile_registers(width: Size) -> &'static [zydis::Register] {
+    use zydis::Register::*;
+    if width == 64 {
+        &[RAX, RCX, RDX, R8, R9, R10, R11]
+    } else {
+        &[EAX, ECX, EDX]
+    }
+}
+
+impl State {
+    /// the state at function entry: only the stack pointer is known.
+    fn entry(arch: Arch) -> State {
+        let mut state = State {
+            width: get_width(arch),
+            vars:  Default::default(),
+            stack: Default::default(),
+        };
+        state.vars.insert(state.sp(), Value::Stack(0));
+        // the ABI requires the direction flag is clear across calls.
+        state.vars.insert(Var::Flag(Flag::DF), Value::Const(0));
+        state
+    }
+
+    fn sp(&self) -> Var {
+        Var::Reg(if self.width == 64 {
+            zydis::Register::RSP
+        } else {
+            zydis::Register::ESP
+        })
+    }
+
+    /// the value of the given register, which may be a sub-register, like
+    /// `al`.
+    ///
+    /// only constants are tracked in sub-registers.
+    pub fn register(&self, reg: zydis::Register) -> Option<Value> {
+        let mode = get_machine_mode(self.width);
+        let full = reg.get_largest_enclosing(mode);
+        let value = *self.vars.get(&Var::Reg(full))?;
+        if reg == full {
+            return Some(value);
+        }
+
+        match value {
+            Value::Const(v) => {
+                let size = reg.get_width(mode) as Size;
+                let lsb = if matches!(
+                    reg,
+                    zydis::Register::AH | zydis::Register::BH | zydis::Register::CH | zydis::Register::DH
+                ) {
+                    8
+                } else {
+                    0
+                };
+                Some(Value::Const((v >> lsb) & mask(size)))
+            }
+            _ => None,
+        }
+    }
+
+    /// the value of the flag, as zero or one.
+    pub fn flag(&self, flag: Flag) -> Option<Value> {
+        self.vars.get(&Var::Flag(flag)).copied()
+    }
+
+    /// the value of `size` bits stored in the stack frame at the given offset
+    /// from the stack pointer at function entry.
+    ///
+    /// constants may be read from within larger slots,
+    /// such as the low byte of a stored dword.
+    pub fn stack_slot(&self, offset: i64, size: Size) -> Option<Value> {
+        let (&start, &(slot_size, value)) = self.stack.range(..=offset).next_back()?;
+        if start == offset && slot_size == size {
+            return Some(value);
+        }
+
+        let delta = (offset - start) as u64;
+        match value {
+            Value::Const(v) if delta * 8 + size as u64 <= slot_size as u64 => {
+                Some(Value::Const((v >> (delta * 8)) & mask(size)))
+            }
+            _ => None,
+        }
+    }
+
+    /// the values passed as arguments to a call, when this is the state
+    /// immediately before the call instruction.
+    ///
+    /// on x64, the first four arguments are in rcx/rdx/r8/r9 and the remainder
+    /// on the stack after the 0x20 bytes of home space;
+    /// on x86, all arguments are on the stack.
+    /// floating point arguments are not supported.
+    pub fn arguments(&self, count: usize) -> Vec<Option<Value>> {
+        let sp = match self.vars.get(&self.sp()) {
+            Some(Value::Stack(sp)) => Some(*sp),
+            _ => None,
+        };
+        let stack_argument =
+            |i: usize| sp.and_then(|sp| self.stack_slot(sp + (i as i64) * (self.width as i64 / 8), self.width));
+
+        (0..count)
+            .map(|i| {
+                if self.width == 64 {
+                    match i {
+                        0 => self.register(zydis::Register::RCX),
+                        1 => self.register(zydis::Register::RDX),
+                        2 => self.register(zydis::Register::R8),
+                        3 => self.register(zydis::Register::R9),
+                        // after the home space, starting at [rsp+0x20].
+                        _ => stack_argument(i),
+                    }
+                } else {
+                    stack_argument(i)
+                }
+            })
+            .collect()
+    }
+
+    /// keep only the values that are the same in both states.
+    /// returns true if this state changed.
+    fn meet(&mut self, other: &State) -> bool {
+        let (vars, stack) = (self.vars.len(), self.stack.len());
+        self.vars.retain(|var, value| other.vars.get(var) == Some(value));
+        self.stack.retain(|offset, slot| other.stack.get(offset) == Some(slot));
+        vars != self.vars.len() || stack != self.stack.len()
+    }
+
+    fn read_var(&self, temps: &HashMap<u16, Value>, var: Var) -> Option<Value> {
+        match var {
+            Var::Temp(id) => temps.get(&id).copied(),
+            _ => self.vars.get(&var).copied(),
+        }
+    }
+
+    fn write_var(&mut self, temps: &mut HashMap<u16, Value>, var: Var, value: Option<Value>) {
+        match (var, value) {
+            (Var::Temp(id), Some(value)) => {
+                temps.insert(id, value);
+            }
+            (Var::Temp(id), None) => {
+                temps.remove(&id);
+            }
+            (var, Some(value)) => {
+                self.vars.insert(var, value);
+            }
+            (var, None) => {
+                self.vars.remove(&var);
+            }
+        }
+    }
+
+    fn load(&self, addr: Value, size: Size) -> Option<Value> {
+        match addr {
+            Value::Stack(offset) => self.stack_slot(offset, size),
+            Value::Const(addr) if size == self.width => Some(Value::Deref(addr)),
+            _ => None,
+        }
+    }
+
+    /// forget the stack slots that overlap the given range.
+    fn clobber(&mut self, offset: i64, length: i64) {
+        let overlapping: Vec<i64> = self
+            .stack
+            .range(offset - 8..offset + length)
+            .filter(|(&start, &(size, _))| start + (size as i64 / 8) > offset)
+            .map(|(&start, _)| start)
+            .collect();
+        for start in overlapping {
+            self.stack.remove(&start);
+        }
+    }
+
+    fn store(&mut self, offset: i64, size: Size, value: Option<Value>) {
+        self.clobber(offset, (size as i64 + 7) / 8);
+        if let Some(value) = value {
+            self.stack.insert(offset, (size, value));
+        }
+    }
+
+    fn eval(&self, temps: &HashMap<u16, Value>, expr: &Expr) -> Option<Value> {
+        let constant = |expr: &Expr| match self.eval(temps, expr) {
+            Some(Value::Const(v)) => Some(v),
+            _ => None,
+        };
+
+        match expr {
+            Expr::Const { value, .. } => Some(Value::Const(*value)),
+            Expr::Var { var, size } => match self.read_var(temps, *var)? {
+                Value::Const(v) => Some(Value::Const(v & mask(*size))),
+                value if *size == self.width => Some(value),
+                _ => None,
+            },
+            Expr::Load { addr, size } => self.load(self.eval(temps, addr)?, *size),
+            Expr::Unary { op, arg } => Some(Value::Const(eval::unary(*op, constant(arg)?, arg.size()))),
+            // like `xor eax, eax`, which is zero regardless of the value of eax.
+            Expr::Binary { op, lhs, rhs } if matches!(op, ir::BinaryOp::Xor | ir::BinaryOp::Sub) && lhs == rhs => {
+                Some(Value::Const(0))
+            }
+            Expr::Binary { op, lhs, rhs } => {
+                let size = lhs.size();
+                match (op, self.eval(temps, lhs)?, self.eval(temps, rhs)?) {
+                    (_, Value::Const(a), Value::Const(b)) => eval::binary(*op, a, b, size).ok().map(Value::Const),
+                    (ir::BinaryOp::Add, Value::Stack(offset), Value::Const(c))
+                    | (ir::BinaryOp::Add, Value::Const(c), Value::Stack(offset)) => {
+                        Some(Value::Stack(offset.wrapping_add(eval::sign_extend(c, size))))
+                    }
+                    (ir::BinaryOp::Sub, Value::Stack(offset), Value::Const(c)) => {
+                        Some(Value::Stack(offset.wrapping_sub(eval::sign_extend(c, size))))
+                    }
+                    (ir::BinaryOp::Sub, Value::Stack(a), Value::Stack(b)) => {
+                        Some(Value::Const(a.wrapping_sub(b) as u64 & mask(size)))
+                    }
+                    (ir::BinaryOp::Eq, a, b) if a == b => Some(Value::Const(1)),
+                    _ => None,
+                }
+            }
+            Expr::ZeroExtend { arg, .. } => Some(Value::Const(constant(arg)?)),
+            Expr::SignExtend { arg, size } => Some(Value::Const(
+                eval::sign_extend(constant(arg)?, arg.size()) as u64 & mask(*size),
+            )),
+            Expr::Extract { arg, lsb, size } => Some(Value::Const((constant(arg)? >> lsb) & mask(*size))),
+            Expr::Insert { arg, lsb, value } => Some(Value::Const(eval::insert(
+                constant(arg)?,
+                arg.size(),
+                *lsb,
+                constant(value)?,
+                value.size(),
+            ))),
+            Expr::Ite { cond, then, els } => match constant(cond) {
+                Some(0) => self.eval(temps, els),
+                Some(_) => self.eval(temps, then),
+                None => {
+                    let then = self.eval(temps, then)?;
+                    if Some(then) == self.eval(temps, els) {
+                        Some(then)
+                    } else {
+                        None
+                    }
+                }
+            },
+        }
+    }
+
+    /// update the state with the effects of the instruction.
+    ///
+    /// `cleanup` is the number of bytes popped from the stack by the callee,
+    /// if the instruction is a call and this is known.
+    ///
+    /// returns the target of the call, if the instruction is a call.
+    fn transfer(&mut self, insn: &ir::Instruction, cleanup: Option<u64>) -> Option<Value> {
+        let mut temps: HashMap<u16, Value> = Default::default();
+        let mut call_target = None;
+
+        for stmt in insn.stmts.iter() {
+            match stmt {
+                Stmt::Assign { dst, value } => {
+                    let value = self.eval(&temps, value);
+                    self.write_var(&mut temps, *dst, value);
+                }
+                Stmt::Undefine { dst } => self.write_var(&mut temps, *dst, None),
+                Stmt::Store { addr, value } => {
+                    // stores to addresses outside the stack frame are not tracked.
+                    if let Some(Value::Stack(offset)) = self.eval(&temps, addr) {
+                        let v = self.eval(&temps, value);
+                        self.store(offset, value.size(), v);
+                    }
+                }
+                Stmt::Call { target } => {
+                    call_target = self.eval(&temps, target);
+                    self.call(cleanup);
+                }
+                Stmt::Jump { .. } | Stmt::Branch { .. } | Stmt::Return { .. } => {}
+            }
+        }
+
+        call_target
+    }
+
+    /// the effects of a callee that returns to the caller:
+    /// volatile registers and flags are clobbered,
+    /// as is the region of the stack used by the callee.
+    fn call(&mut self, cleanup: Option<u64>) {
+        for &reg in get_volatile_registers(self.width) {
+            self.vars.remove(&Var::Reg(reg));
+        }
+        self.vars.retain(|var, _| !matches!(var, Var::Flag(_)));
+        self.vars.insert(Var::Flag(Flag::DF), Value::Const(0));
+
+        let sp = self.sp();
+        match self.vars.get(&sp).copied() {
+            Some(Value::Stack(offset)) => {
+                // the return address has been pushed,
+                // so the callee's frame is below this.
+                let ret = offset + (self.width as i64 / 8);
+                let below: Vec<i64> = self.stack.range(..ret).map(|(&start, _)| start).collect();
+                for start in below {
+                    self.stack.remove(&start);
+                }
+                if self.width == 64 {
+                    // the callee may spill register arguments into the home space.
+                    self.clobber(ret, 0x20);
+                }
+
+                match cleanup {
+                    Some(cleanup) => {
+                        self.vars.insert(sp, Value::Stack(ret + cleanup as i64));
+                    }
+                    None => {
+                        self.vars.remove(&sp);
+                    }
+                }
+            }
+            _ => {
+                self.vars.remove(&sp);
+            }
+        }
+    }
+
+    /// conservatively apply the effects of an instruction that could not be
+    /// lifted: forget all the registers, flags, and stack slots that it
+    /// writes.
+    fn havoc(&mut self, insn: &zydis::DecodedInstruction) {
+        let mode = get_machine_mode(self.width);
+        self.vars.retain(|var, _| !matches!(var, Var::Flag(_)));
+
+        for op in insn.operands[..insn.operand_count as usize].iter() {
+            if !op
+                .action
+                .intersects(zydis::OperandAction::WRITE | zydis::OperandAction::CONDWRITE)
+            {
+                continue;
+            }
+
+            match op.ty {
+                zydis::OperandType::REGISTER
+                    if matches!(
+                        op.reg.get_class(),
+                        zydis::RegisterClass::GPR8
+                            | zydis::RegisterClass::GPR16
+                            | zydis::RegisterClass::GPR32
+                            | zydis::RegisterClass::GPR64
+                    ) =>
+                {
+                    self.vars.remove(&Var::Reg(op.reg.get_largest_enclosing(mode)));
+                }
+                zydis::OperandType::MEMORY => {
+                    let base = if op.mem.base == zydis::Register::NONE {
+                        None
+                    } else {
+                        self.register(op.mem.base)
+                    };
+
+                    if let Some(Value::Stack(offset)) = base {
+                        let offset = offset.wrapping_add(op.mem.disp.displacement);
+                        if op.mem.index != zydis::Register::NONE {
+                            // we don't know where in the frame the write goes.
+                            self.stack.clear();
+                        } else if insn.attributes.intersects(
+                            zydis::InstructionAttributes::HAS_REP
+                                | zydis::InstructionAttributes::HAS_REPE
+                                | zydis::InstructionAttributes::HAS_REPNE,
+                        ) {
+                            // string instructions write an unknown number of elements.
+                            let above: Vec<i64> = self.stack.range(offset - 8..).map(|(&start, _)| start).collect();
+                            for start in above {
+                                self.stack.remove(&start);
+                            }
+                        } else {
+                            self.clobber(offset, (op.size as i64 + 7) / 8);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallTarget {
+    /// the address of the called function.
+    Address(VA),
+    /// the address of a pointer to the called function, such as an import
+    /// table entry.
+    Pointer(VA),
+}
+
+pub struct Constants {
+    /// the state immediately before each instruction reachable from the
+    /// entry.
+    pub states:       BTreeMap<VA, State>,
+    /// the targets of call instructions, both direct and indirect,
+    /// when they are known.
+    pub call_targets: BTreeMap<VA, CallTarget>,
+}
+
+impl Constants {
+    pub fn state_at(&self, va: VA) -> Option<&State> {
+        self.states.get(&va)
+    }
+}
+
+/// the number of bytes popped from the stack by the function called by the
+/// instruction, if known.
+pub(crate) fn get_callee_cleanup(
+    module: &Module,
+    prototypes: &BTreeMap<VA, Prototype>,
+    va: VA,
+    insn: &zydis::DecodedInstruction,
+) -> Result<Option<u64>> {
+    if let Arch::X64 = module.arch {
+        // the caller always cleans up.
+        return Ok(Some(0));
+    }
+
+    for flow in cfg::get_call_insn_flow(module, va, insn)?.iter() {
+        if let Flow::Call(target) = flow {
+            if let Some(prototype) = prototypes.get(target) {
+                return Ok(Some(prototype.callee_cleanup));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// apply the instructions of the basic block to the state,
+/// invoking the callback with the state before each instruction,
+/// and the target of each call instruction.
+fn transfer_basic_block<F>(
+    module: &Module,
+    decoder: &zydis::Decoder,
+    prototypes: &BTreeMap<VA, Prototype>,
+    bb: &cfg::BasicBlock,
+    state: &mut State,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(VA, &State, Option<Value>),
+{
+    let buf = module.address_space.read_bytes(bb.address, bb.length as usize)?;
+    for (offset, insn) in dis::linear_disassemble(decoder, &buf) {
+        let insn = match insn {
+            Ok(Some(insn)) => insn,
+            _ => break,
+        };
+        let va = bb.address + offset as RVA;
+        let before = state.clone();
+
+        let mut call_target = None;
+        match lift::lift_insn(module.arch, va, &insn) {
+            Ok(lifted) => {
+                let cleanup = if insn.mnemonic == zydis::Mnemonic::CALL {
+                    get_callee_cleanup(module, prototypes, va, &insn)?
+                } else {
+                    None
+                };
+                call_target = state.transfer(&lifted, cleanup);
+            }
+            Err(_) => state.havoc(&insn),
+        }
+
+        f(va, &before, call_target);
+    }
+
+    Ok(())
+}
+
+/// propagate the values of registers and stack slots through the function.
+///
+/// `prototypes`, such as from `calling_convention::find_prototypes`, provides
+/// the stack cleanup of callees, so that the stack pointer can be tracked
+/// across calls on x86.
+///
+/// ```
+/// use lancelot::test::*;
+/// use lancelot::analysis::cfg::build_cfg;
+/// use lancelot::analysis::constants::{propagate_constants, Value};
+///
+/// // 0:  b8 00 10 40 00          mov    eax, 0x401000
+/// // 5:  ff d0                   call   eax
+/// // 7:  c3                      ret
+/// let module = load_shellcode32(b"\xB8\x00\x10\x40\x00\xFF\xD0\xC3");
+/// let cfg = build_cfg(&module, 0x0).unwrap();
+/// let constants = propagate_constants(&module, &cfg, 0x0, &Default::default()).unwrap();
+///
+/// let state = constants.state_at(0x5).unwrap();
+/// assert_eq!(state.register(zydis::Register::EAX), Some(Value::Const(0x401000)));
+/// assert_eq!(state.register(zydis::Register::ESP), Some(Value::Stack(0)));
+/// ```
+pub fn propagate_constants(
+    module: &Module,
+    cfg: &CFG,
+    entry: VA,
+    prototypes: &BTreeMap<VA, Prototype>,
+) -> Result<Constants> {
+    let decoder = dis::get_disassembler(module)?;
+
+    // the state at the start of each basic block.
+    // basic blocks not yet reached are not present,
+    // so they don't contribute to the meet.
+    let mut inputs: BTreeMap<VA, State> = Default::default();
+    if cfg.basic_blocks.contains_key(&entry) {
+        inputs.insert(entry, State::entry(module.arch));
+    }
+
+    let mut queue: VecDeque<VA> = Default::default();
+    queue.push_back(entry);
+
+    while let Some(bbva) = queue.pop_front() {
+        let bb = match cfg.basic_blocks.get(&bbva) {
+            Some(bb) => bb,
+            None => continue,
+        };
+        let mut state = inputs[&bbva].clone();
+        transfer_basic_block(module, &decoder, prototypes, bb, &mut state, |_, _, _| {})?;
+
+        for succ in bb.successors.iter() {
+            if let Flow::Call(_) = succ {
+                continue;
+            }
+            let succva = succ.va();
+            if !cfg.basic_blocks.contains_key(&succva) {
+                continue;
+            }
+
+            // values only ever leave a state, so this terminates.
+            let changed = match inputs.get_mut(&succva) {
+                Some(input) => input.meet(&state),
+                None => {
+                    inputs.insert(succva, state.clone());
+                    true
+                }
+            };
+            if changed && !queue.contains(&succva) {
+                queue.push_back(succva);
+            }
+        }
+    }
+
+    let mut constants = Constants {
+        states:       Default::default(),
+        call_targets: Default::default(),
+    };
+    for (bbva, input) in inputs.into_iter() {
+        let bb = &cfg.basic_blocks[&bbva];
+        let mut state = input;
+        transfer_basic_block(module, &decoder, prototypes, bb, &mut state, |va, before, target| {
+            constants.states.insert(va, before.clone());
+            match target {
+                Some(Value::Const(target)) => {
+                    constants.call_targets.insert(va, CallTarget::Address(target));
+                }
+                Some(Value::Deref(ptr)) => {
+                    constants.call_targets.insert(va, CallTarget::Pointer(ptr));
+                }
+                _ => {}
+            }
+        })?;
+    }
+
+    Ok(constants)
+}
+
+/// is the call target computed from registers, like `call eax` or `call
+/// [eax+0x10]`?
+fn is_indirect_call(insn: &zydis::DecodedInstruction) -> bool {
+    match cfg::get_first_operand(insn) {
+        Some(op) if op.ty == zydis::OperandType::REGISTER => true,
+        Some(op) if op.ty == zydis::OperandType::MEMORY => {
+            (op.mem.base != zydis::Register::NONE && op.mem.base != zydis::Register::RIP)
+                || op.mem.index != zydis::Register::NONE
+        }
+        _ => false,
+    }
+}
+
+/// find the targets of indirect calls, like `call eax`, within the given
+/// function, that can be resolved via constant propagation.
+///
+/// calls that can be resolved directly, like `call 0x401000` or `call
+/// [0x402000]`, are not included.
+pub fn find_indirect_call_targets(
+    module: &Module,
+    cfg: &CFG,
+    function: VA,
+    prototypes: &BTreeMap<VA, Prototype>,
+) -> Result<BTreeMap<VA, CallTarget>> {
+    let decoder = dis::get_disassembler(module)?;
+
+    let mut calls = vec![];
+    for bb in cfg.basic_blocks.values() {
+        let buf = module.address_space.read_bytes(bb.address, bb.length as usize)?;
+        for (offset, insn) in dis::linear_disassemble(&decoder, &buf) {
+            if let Ok(Some(insn)) = insn {
+                if insn.mnemonic == zydis::Mnemonic::CALL && is_indirect_call(&insn) {
+                    calls.push(bb.address + offset as RVA);
+                }
+            }
+        }
+    }
+
+    let mut targets: BTreeMap<VA, CallTarget> = Default::default();
+    if calls.is_empty() {
+        // don't bother propagating constants through the function.
+        return Ok(targets);
+    }
+
+    let constants = propagate_constants(module, cfg, function, prototypes)?;
+    for va in calls.into_iter() {
+        if let Some(&target) = constants.call_targets.get(&va) {
+            debug!("constants: indirect call at {:#x}: {:?}", va, target);
+            targets.insert(va, target);
+        }
+    }
+
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::{
+            call_graph, calling_convention,
+            cfg::{build_cfg, CFG},
+            constants::*,
+            pe,
+        },
+        rsrc::*,
+        test::*,
+        VA,
+    };
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn stack_frame() -> Result<()> {
+        // 0:  55                      push   ebp
+        // 1:  89 e5                   mov    ebp, esp
+        // 3:  83 ec 10                sub    esp, 0x10
+        // 6:  b8 00 10 40 00          mov    eax, 0x401000
+        // b:  89 45 fc                mov    DWORD PTR [ebp-0x4], eax
+        // e:  8d 55 f0                lea    edx, [ebp-0x10]
+        // 11: c7 02 44 43 42 41       mov    DWORD PTR [edx], 0x41424344
+        // 17: 8b 4d fc                mov    ecx, DWORD PTR [ebp-0x4]
+        // 1a: 6a 10                   push   0x10
+        // 1c: 68 00 20 40 00          push   0x402000
+        // 21: ff d1                   call   ecx
+        // 23: 8a 45 f0                mov    al, BYTE PTR [ebp-0x10]
+        // 26: ff 55 fc                call   DWORD PTR [ebp-0x4]
+        // 29: 89 ec                   mov    esp, ebp
+        // 2b: 5d                      pop    ebp
+        // 2c: c3                      ret
+        let module = load_shellcode32(b"\x55\x89\xE5\x83\xEC\x10\xB8\x00\x10\x40\x00\x89\x45\xFC\x8D\x55\xF0\xC7\x02\x44\x43\x42\x41\x8B\x4D\xFC\x6A\x10\x68\x00\x20\x40\x00\xFF\xD1\x8A\x45\xF0\xFF\x55\xFC\x89\xEC\x5D\xC3");
+        let cfg = build_cfg(&module, 0x0)?;
+        let constants = propagate_constants(&module, &cfg, 0x0, &Default::default())?;
+
+        let state = constants.state_at(0x21).unwrap();
+        assert_eq!(state.register(zydis::Register::EBP), Some(Value::Stack(-0x4)));
+        assert_eq!(state.register(zydis::Register::ESP), Some(Value::Stack(-0x1C)));
+        assert_eq!(state.register(zydis::Register::ECX), Some(Value::Const(0x401000)));
+        assert_eq!(state.register(zydis::Register::CH), Some(Value::Const(0x10)));
+        assert_eq!(state.register(zydis::Register::EDX), Some(Value::Stack(-0x14)));
+        assert_eq!(
+            state.arguments(2),
+            vec![Some(Value::Const(0x402000)), Some(Value::Const(0x10))]
+        );
+
+        // the callee's cleanup is unknown, so the stack pointer is lost,
+        // but the frame is still accessible via the frame pointer.
+        let state = constants.state_at(0x23).unwrap();
+        assert_eq!(state.register(zydis::Register::ESP), None);
+        assert_eq!(state.register(zydis::Register::ECX), None);
+        assert_eq!(state.stack_slot(-0x14, 8), Some(Value::Const(0x44)));
+        assert_eq!(state.stack_slot(-0x12, 16), Some(Value::Const(0x4142)));
+
+        let state = constants.state_at(0x2C).unwrap();
+        assert_eq!(state.register(zydis::Register::ESP), Some(Value::Stack(0x0)));
+
+        assert_eq!(constants.call_targets[&0x21], CallTarget::Address(0x401000));
+        assert_eq!(constants.call_targets[&0x26], CallTarget::Address(0x401000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn callee_cleanup() -> Result<()> {
+        // 0:  6a 01                   push   0x1
+        // 2:  e8 04 00 00 00          call   0xb
+        // 7:  6a 02                   push   0x2
+        // 9:  ff d0                   call   eax
+        // b:  c2 04 00                ret    0x4
+        let module = load_shellcode32(b"\x6A\x01\xE8\x04\x00\x00\x00\x6A\x02\xFF\xD0\xC2\x04\x00");
+        let cfg = build_cfg(&module, 0x0)?;
+
+        let mut prototypes: BTreeMap<VA, calling_convention::Prototype> = Default::default();
+        prototypes.insert(
+            0xB,
+            calling_convention::Prototype {
+                calling_convention: calling_convention::CallingConvention::Stdcall,
+                register_arguments: vec![],
+                stack_arguments:    1,
+                callee_cleanup:     4,
+            },
+        );
+        let constants = propagate_constants(&module, &cfg, 0x0, &prototypes)?;
+
+        let state = constants.state_at(0x9).unwrap();
+        assert_eq!(state.register(zydis::Register::ESP), Some(Value::Stack(-0x4)));
+        assert_eq!(state.arguments(1), vec![Some(Value::Const(0x2))]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn joins() -> Result<()> {
+        // 0:  31 c0                   xor    eax, eax
+        // 2:  85 c9                   test   ecx, ecx
+        // 4:  74 0d                   je     0x13
+        // 6:  ba 01 00 00 00          mov    edx, 0x1
+        // b:  41 b8 05 00 00 00       mov    r8d, 0x5
+        // 11: eb 0b                   jmp    0x1e
+        // 13: ba 02 00 00 00          mov    edx, 0x2
+        // 18: 41 b8 05 00 00 00       mov    r8d, 0x5
+        // 1e: 48 8d 0d 00 01 00 00    lea    rcx, [rip+0x100]        # 0x125
+        // 25: 48 8b 05 00 02 00 00    mov    rax, QWORD PTR [rip+0x200]        # 0x22c
+        // 2c: 48 83 ec 28             sub    rsp, 0x28
+        // 30: ff d0                   call   rax
+        // 32: 48 83 c4 28             add    rsp, 0x28
+        // 36: c3                      ret
+        let module = load_shellcode64(b"\x31\xC0\x85\xC9\x74\x0D\xBA\x01\x00\x00\x00\x41\xB8\x05\x00\x00\x00\xEB\x0B\xBA\x02\x00\x00\x00\x41\xB8\x05\x00\x00\x00\x48\x8D\x0D\x00\x01\x00\x00\x48\x8B\x05\x00\x02\x00\x00\x48\x83\xEC\x28\xFF\xD0\x48\x83\xC4\x28\xC3");
+        let cfg = build_cfg(&module, 0x0)?;
+        let constants = propagate_constants(&module, &cfg, 0x0, &Default::default())?;
+
+        let state = constants.state_at(0x1E).unwrap();
+        assert_eq!(state.register(zydis::Register::RAX), Some(Value::Const(0x0)));
+        assert_eq!(state.register(zydis::Register::RDX), None);
+        assert_eq!(state.register(zydis::Register::R8), Some(Value::Const(0x5)));
+
+        let state = constants.state_at(0x30).unwrap();
+        assert_eq!(
+            state.arguments(3),
+            vec![Some(Value::Const(0x125)), None, Some(Value::Const(0x5))]
+        );
+        assert_eq!(state.register(zydis::Register::RAX), Some(Value::Deref(0x22C)));
+
+        // on x64, the stack pointer survives calls.
+        let state = constants.state_at(0x36).unwrap();
+        assert_eq!(state.register(zydis::Register::RSP), Some(Value::Stack(0x0)));
+        assert_eq!(state.register(zydis::Register::RCX), None);
+
+        let targets = find_indirect_call_targets(&module, &cfg, 0x0, &Default::default())?;
+        assert_eq!(targets[&0x30], CallTarget::Pointer(0x22C));
+
+        Ok(())
+    }
+
+    #[test]
+    fn loops() -> Result<()> {
+        // 0:  31 c9                   xor    ecx, ecx
+        // 2:  ba 07 00 00 00          mov    edx, 0x7
+        // 7:  41                      inc    ecx
+        // 8:  83 f9 0a                cmp    ecx, 0xa
+        // b:  7c fa                   jl     0x7
+        // d:  c3                      ret
+        let module = load_shellcode32(b"\x31\xC9\xBA\x07\x00\x00\x00\x41\x83\xF9\x0A\x7C\xFA\xC3");
+        let cfg = build_cfg(&module, 0x0)?;
+        let constants = propagate_constants(&module, &cfg, 0x0, &Default::default())?;
+
+        let state = constants.state_at(0x7).unwrap();
+        assert_eq!(state.register(zydis::Register::ECX), None);
+        assert_eq!(state.register(zydis::Register::EDX), Some(Value::Const(0x7)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+        for &function in pe::find_function_starts(&pe)?.iter() {
+            if let Ok(cfg) = build_cfg(&pe.module, function) {
+                cfgs.insert(function, cfg);
+            }
+        }
+        let cg = call_graph::build_call_graph(&pe.module, &cfgs)?;
+        let prototypes = calling_convention::find_prototypes(&pe.module, &cfgs, &cg)?;
+        let mut targets: BTreeMap<VA, CallTarget> = Default::default();
+        for (&function, cfg) in cfgs.iter() {
+            targets.extend(find_indirect_call_targets(&pe.module, cfg, function, &prototypes)?);
+        }
+
+        // .text:004015CA push    [ebp+DeviceInfoSet]
+        // .text:004015CD mov     [ebp+RequiredSize], edi
+        // .text:004015D0 call    esi ; SetupDiGetDeviceInterfaceDetailW
+        let imports = pe::get_imports(&pe)?;
+        match targets[&0x4015D0] {
+            CallTarget::Pointer(ptr) => {
+                assert_eq!(
+                    imports[&ptr].to_string(),
+                    "SETUPAPI.dll!SetupDiGetDeviceInterfaceDetailW"
+                )
+            }
+            _ => panic!("expected pointer"),
+        }
+
+        Ok(())
+    }
+}
@@ -1,16 +1,29 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
 
 use anyhow::Result;
 use log::debug;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use thiserror::Error;
 
 use crate::{
     analysis::dis,
     aspace::AddressSpace,
+    config::Config,
     module::{Module, Permissions},
-    VA,
+    util, VA,
 };
 use lancelot_flirt::*;
 
+#[derive(Debug, Error)]
+pub enum FlirtError {
+    #[error("signature file must end with .pat or .sig: {0}")]
+    UnsupportedSignatureFile(String),
+}
+
 const EMPTY_CONTEXT: zydis::ffi::RegisterContext = zydis::ffi::RegisterContext { values: [0u64; 257] };
 
 /// make a best guess for the reference target, found at `ref_offset` from `va`.
@@ -46,7 +59,11 @@ fn guess_reference_target(
 ) -> Option<VA> {
     // scan from -1 to -4 bytes backwards from the reference
     for i in (1..=4u64).rev() {
-        let candidate_insn_va = va + ref_offset - i;
+        // such as a reference near the start of the address space.
+        let candidate_insn_va = match (va + ref_offset).checked_sub(i) {
+            Some(candidate_insn_va) => candidate_insn_va,
+            None => continue,
+        };
         let mut insn_buf = [0u8; 16];
 
         if module.address_space.read_into(candidate_insn_va, &mut insn_buf).is_ok() {
@@ -212,3 +229,477 @@ pub fn match_flirt(module: &Module, sigs: &FlirtSignatureSet, va: VA) -> Result<
     let mut cache = Default::default();
     match_flirt_inner(module, sigs, &decoder, va, &mut cache)
 }
+
+/// match the given flirt signatures at each of the given function starts.
+/// returns the signatures that match, indexed by function start.
+/// functions that fail to match are skipped.
+///
+/// with the `parallel` feature, the functions are matched across a thread
+/// pool, though the results are the same.
+pub fn match_flirt_functions(
+    module: &Module,
+    sigs: &FlirtSignatureSet,
+    functions: &[VA],
+) -> BTreeMap<VA, Vec<FlirtSignature>> {
+    let match_function = |&va: &VA| match match_flirt(module, sigs, va) {
+        Ok(matches) if !matches.is_empty() => Some((va, matches)),
+        Ok(_) => None,
+        Err(e) => {
+            debug!("flirt: {:#x}: failed to match: {}", va, e);
+            None
+        }
+    };
+
+    #[cfg(feature = "parallel")]
+    let matches = functions.par_iter().filter_map(match_function).collect();
+    #[cfg(not(feature = "parallel"))]
+    let matches = functions.iter().filter_map(match_function).collect();
+
+    matches
+}
+
+/// the results of matching FLIRT signatures across a module.
+#[derive(Default, Clone)]
+pub struct FlirtMatches {
+    /// the signatures that match each function, indexed by function start.
+    /// includes functions found only as the target of a reference from
+    /// another match.
+    pub matches: BTreeMap<VA, Vec<FlirtSignature>>,
+}
+
+impl FlirtMatches {
+    /// is the function at the given address recognized as library code?
+    pub fn is_library_function(&self, va: VA) -> bool {
+        self.matches.contains_key(&va)
+    }
+
+    /// the functions recognized as library code,
+    /// which downstream analyses may want to skip.
+    pub fn library_functions(&self) -> BTreeSet<VA> {
+        self.matches.keys().cloned().collect()
+    }
+
+    /// the public name of the function at the given address,
+    /// from the first signature that matches.
+    pub fn get_name(&self, va: VA) -> Option<&str> {
+        self.matches
+            .get(&va)
+            .and_then(|sigs| sigs.iter().filter_map(|sig| sig.get_name()).next())
+    }
+}
+
+/// a signature whose bytes match at a function,
+/// along with the targets of its references,
+/// which must have the given names for the signature to match.
+struct Candidate {
+    sig:        FlirtSignature,
+    references: Vec<(VA, String)>,
+}
+
+/// the public and local names that a signature applies to the function start.
+fn get_start_names(sig: &FlirtSignature) -> impl Iterator<Item = &str> {
+    sig.names.iter().filter_map(|name| match name {
+        Symbol::Public(Name { name, offset }) | Symbol::Local(Name { name, offset }) if *offset == 0 => {
+            Some(name.as_str())
+        }
+        _ => None,
+    })
+}
+
+/// find the signatures whose bytes match at the given address,
+/// and guess the targets of their references.
+/// signatures with references that can't be resolved are dropped.
+fn find_candidates(module: &Module, sigs: &FlirtSignatureSet, va: VA) -> Result<Vec<Candidate>> {
+    let sec = match module
+        .sections
+        .iter()
+        .find(|sec| sec.virtual_range.start <= va && va < sec.virtual_range.end)
+    {
+        Some(sec) => sec,
+        None => return Ok(vec![]),
+    };
+
+    let size = sec.virtual_range.end - va;
+    let buf = module.address_space.read_bytes(va, size as usize)?;
+    let decoder = dis::get_disassembler(module)?;
+
+    let mut candidates = vec![];
+    'sigs: for sig in sigs.r#match(&buf).into_iter() {
+        let mut references = vec![];
+
+        for name in sig.names.iter() {
+            if let Symbol::Reference(Name { offset, name }) = name {
+                if *offset < 0 {
+                    continue 'sigs;
+                }
+
+                // special case: name "." matches any data.
+                // see `match_flirt`.
+                let perms = if name == "." { Permissions::R } else { Permissions::X };
+
+                match guess_reference_target(module, &decoder, va, *offset as u64, perms) {
+                    Some(_) if name == "." => continue,
+                    Some(target) => references.push((target, name.clone())),
+                    None => continue 'sigs,
+                }
+            }
+        }
+
+        debug!("flirt: {:#x}: candidate: {:?}", va, sig);
+        candidates.push(Candidate {
+            sig: sig.clone(),
+            references,
+        });
+    }
+
+    Ok(candidates)
+}
+
+/// match the given flirt signatures at each of the given function starts,
+/// resolving the references of each signature using the names of the other
+/// matches.
+///
+/// since references depend on other matches, this iterates to a fixpoint:
+/// first, every signature whose bytes match is assumed to be correct,
+/// then signatures with a reference to an address without the wanted name
+/// are dropped, until nothing changes.
+/// this way, mutually recursive library functions can match one another.
+///
+/// references to addresses that aren't in the given list of functions are
+/// matched, too, so the results may include more functions.
+///
+/// with the `parallel` feature, the functions are matched across a thread
+/// pool, though the results are the same.
+pub fn match_flirt_module(module: &Module, sigs: &FlirtSignatureSet, functions: &[VA]) -> FlirtMatches {
+    let find_function_candidates = |&va: &VA| match find_candidates(module, sigs, va) {
+        Ok(candidates) if !candidates.is_empty() => Some((va, candidates)),
+        Ok(_) => None,
+        Err(e) => {
+            debug!("flirt: {:#x}: failed to match: {}", va, e);
+            None
+        }
+    };
+
+    #[cfg(feature = "parallel")]
+    let mut candidates: BTreeMap<VA, Vec<Candidate>> =
+        functions.par_iter().filter_map(find_function_candidates).collect();
+    #[cfg(not(feature = "parallel"))]
+    let mut candidates: BTreeMap<VA, Vec<Candidate>> = functions.iter().filter_map(find_function_candidates).collect();
+
+    // match the targets of references, too,
+    // since they may not have been discovered as functions.
+    let mut seen: BTreeSet<VA> = functions.iter().cloned().collect();
+    let mut queue: Vec<VA> = candidates
+        .values()
+        .flat_map(|candidates| candidates.iter())
+        .flat_map(|candidate| candidate.references.iter().map(|&(target, _)| target))
+        .collect();
+    while let Some(va) = queue.pop() {
+        if !seen.insert(va) {
+            continue;
+        }
+
+        if let Some((va, target_candidates)) = find_function_candidates(&va) {
+            debug!("flirt: {:#x}: found referenced function", va);
+            queue.extend(
+                target_candidates
+                    .iter()
+                    .flat_map(|candidate| candidate.references.iter().map(|&(target, _)| target)),
+            );
+            candidates.insert(va, target_candidates);
+        }
+    }
+
+    loop {
+        let names: BTreeMap<VA, BTreeSet<String>> = candidates
+            .iter()
+            .map(|(&va, candidates)| {
+                (
+                    va,
+                    candidates
+                        .iter()
+                        .flat_map(|candidate| get_start_names(&candidate.sig))
+                        .map(|name| name.to_string())
+                        .collect(),
+                )
+            })
+            .collect();
+
+        let mut changed = false;
+        for (&va, candidates) in candidates.iter_mut() {
+            let count = candidates.len();
+            candidates.retain(|candidate| {
+                candidate.references.iter().all(|(target, wanted_name)| {
+                    names
+                        .get(target)
+                        .map(|names| names.contains(wanted_name))
+                        .unwrap_or(false)
+                })
+            });
+
+            if candidates.len() != count {
+                debug!("flirt: {:#x}: dropped {} candidates", va, count - candidates.len());
+                changed = true;
+            }
+        }
+        candidates.retain(|_, candidates| !candidates.is_empty());
+
+        if !changed {
+            break;
+        }
+    }
+
+    let matches: BTreeMap<VA, Vec<FlirtSignature>> = candidates
+        .into_iter()
+        .map(|(va, candidates)| (va, candidates.into_iter().map(|candidate| candidate.sig).collect()))
+        .collect();
+    debug!("flirt: found {} library functions", matches.len());
+
+    FlirtMatches { matches }
+}
+
+/// signatures with fewer fixed bytes than this, such as for tiny functions
+/// like `xor eax, eax; ret`, match all over the place when scanning,
+/// so they're only matched at known function starts.
+const MIN_SCAN_SIGNATURE_SIZE: usize = 0x10;
+
+/// the number of bytes that the signature checks, including the CRC16 range.
+fn get_signature_fixed_size(sig: &FlirtSignature) -> usize {
+    sig.byte_sig
+        .0
+        .iter()
+        .filter(|element| matches!(element, SigElement::Byte(_)))
+        .count()
+        + sig.size_of_bytes_crc16 as usize
+}
+
+/// scan the executable sections of the module for the given flirt signatures,
+/// at every offset rather than just the known function starts,
+/// such as to find statically linked library functions missed by
+/// `find_functions`.
+/// returns the signatures whose bytes match, indexed by address.
+///
+/// signatures that are too short to be distinctive are ignored.
+/// references aren't checked here, since they depend on other matches;
+/// use `match_flirt_module` with the addresses to resolve them.
+pub fn scan_flirt_module(module: &Module, sigs: &FlirtSignatureSet) -> Result<BTreeMap<VA, Vec<FlirtSignature>>> {
+    let mut matches: BTreeMap<VA, Vec<FlirtSignature>> = Default::default();
+
+    for sec in module
+        .sections
+        .iter()
+        .filter(|sec| sec.permissions.intersects(Permissions::X))
+    {
+        let size = sec.virtual_range.end - sec.virtual_range.start;
+        let buf = module
+            .address_space
+            .read_bytes(sec.virtual_range.start, size as usize)?;
+
+        for (offset, sig) in sigs
+            .scan(&buf)
+            .into_iter()
+            .filter(|(_, sig)| get_signature_fixed_size(sig) >= MIN_SCAN_SIGNATURE_SIZE)
+        {
+            let va = sec.virtual_range.start + offset as VA;
+            debug!("flirt: {:#x}: scan found: {:?}", va, sig);
+            matches.entry(va).or_default().push(sig.clone());
+        }
+    }
+
+    debug!("flirt: scan found {} matches", matches.len());
+    Ok(matches)
+}
+
+/// match the FLIRT signatures listed by the configuration across the module,
+/// or nothing, if there are none.
+pub fn match_flirt_module_with_config(module: &Module, functions: &[VA], config: &Config) -> Result<FlirtMatches> {
+    if config.flirt.signatures.is_empty() {
+        return Ok(Default::default());
+    }
+
+    let sigs = load_flirt_signatures(&config.flirt.signatures)?;
+    Ok(match_flirt_module(module, &sigs, functions))
+}
+
+/// load the FLIRT signatures from the given .sig and .pat files,
+/// such as those listed by `config.flirt.signatures`.
+pub fn load_flirt_signatures<P: AsRef<Path>>(paths: &[P]) -> Result<FlirtSignatureSet> {
+    let mut sigs = vec![];
+
+    for path in paths.iter() {
+        let path = path.as_ref().to_string_lossy();
+        debug!("flirt: loading signatures: {}", path);
+
+        if path.ends_with(".pat") {
+            sigs.extend(pat::parse(&String::from_utf8(util::read_file(&path)?)?)?);
+        } else if path.ends_with(".sig") {
+            sigs.extend(sig::parse(&util::read_file(&path)?)?);
+        } else {
+            return Err(FlirtError::UnsupportedSignatureFile(path.to_string()).into());
+        }
+    }
+
+    Ok(FlirtSignatureSet::with_signatures(sigs))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analysis::flirt::*, test::*};
+    use anyhow::Result;
+
+    // 0x0:  E8 05 00 00 00  call 0xA
+    // 0x5:  C3              ret
+    // 0x6:  CC CC CC CC
+    // 0xA:  31 C0           xor eax, eax
+    // 0xC:  C3              ret
+    // 0xD:  CC ...
+    const CODE: &[u8] = b"\xE8\x05\x00\x00\x00\xC3\xCC\xCC\xCC\xCC\x31\xC0\xC3\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC\xCC";
+
+    fn parse_pat(pat: &str) -> FlirtSignatureSet {
+        FlirtSignatureSet::with_signatures(pat::parse(pat).unwrap())
+    }
+
+    #[test]
+    fn references() {
+        let module = load_shellcode32(CODE);
+        let sigs = parse_pat(
+            "\
+E8........C3.................................................... 00 0000 0006 :0000 foo ^0001 bar
+E8........C3.................................................... 00 0000 0006 :0000 foo2 ^0001 baz
+31C0C3.......................................................... 00 0000 0003 :0000 bar
+---
+",
+        );
+
+        // the callee isn't provided, but is found via the reference from foo.
+        let matches = match_flirt_module(&module, &sigs, &[0x0]);
+        assert_eq!(matches.get_name(0x0), Some("foo"));
+        assert_eq!(matches.matches[&0x0].len(), 1);
+        assert_eq!(matches.get_name(0xA), Some("bar"));
+        assert!(matches.is_library_function(0xA));
+        assert!(!matches.is_library_function(0x5));
+        assert_eq!(matches.library_functions().len(), 2);
+    }
+
+    #[test]
+    fn scan() -> Result<()> {
+        // 0x0:  55        push ebp
+        // 0x1:  8B EC     mov ebp, esp
+        // 0x3:  83 EC 10  sub esp, 0x10
+        // 0x6:  53        push ebx
+        // 0x7:  56        push esi
+        // 0x8:  57        push edi
+        // 0x9:  8B 7D 08  mov edi, [ebp+8]
+        // 0xC:  33 C0     xor eax, eax
+        // 0xE:  5F        pop edi
+        // 0xF:  5E        pop esi
+        // 0x10: 5B        pop ebx
+        // 0x11: 8B E5     mov esp, ebp
+        // 0x13: 5D        pop ebp
+        // 0x14: C3        ret
+        const FOO: &[u8] = b"\x55\x8B\xEC\x83\xEC\x10\x53\x56\x57\x8B\x7D\x08\x33\xC0\x5F\x5E\x5B\x8B\xE5\x5D\xC3";
+
+        let mut code = vec![0xCC; 0x80];
+        code[0x0..FOO.len()].copy_from_slice(FOO);
+        code[0x30..0x30 + FOO.len()].copy_from_slice(FOO);
+        code[0x60..0x63].copy_from_slice(b"\x31\xC0\xC3");
+        let module = load_shellcode32(&code);
+
+        let sigs = parse_pat(
+            "\
+558BEC83EC105356578B7D0833C05F5E5B8BE55DC3...................... 00 0000 0015 :0000 foo
+31C0C3.......................................................... 00 0000 0003 :0000 bar
+---
+",
+        );
+
+        // the copy of foo isn't a known function start, but is found by the scan.
+        // bar is too short to scan for.
+        let matches = scan_flirt_module(&module, &sigs)?;
+        assert_eq!(matches.keys().cloned().collect::<Vec<_>>(), vec![0x0, 0x30]);
+        assert_eq!(matches[&0x30][0].get_name(), Some("foo"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn k32_scan() -> Result<()> {
+        use crate::{
+            analysis::pe::{db::Analysis, find_function_candidates_with_config, sigmake, FunctionSource},
+            rsrc::*,
+        };
+
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        let analysis = Analysis::from_pe(&pe, &Default::default())?;
+        let sigs = sigmake::make_pe_signatures(&pe, &analysis)?;
+
+        let path = std::env::temp_dir().join(format!("lancelot-test-scan-{}.pat", std::process::id()));
+        std::fs::write(&path, pat::render(&sigs))?;
+
+        // find functions only by scanning for their own signatures.
+        let config = Config::from_toml(&format!(
+            r#"
+            [functions]
+            entrypoint = false
+            exports = false
+            safeseh = false
+            runtime_functions = false
+            exception_handlers = false
+            cfguard = false
+            call_targets = false
+            prologues = false
+            pointers = false
+            vtables = false
+            cxx_exceptions = false
+
+            [flirt]
+            signatures = ["{}"]
+            "#,
+            path.display()
+        ));
+        let candidates = config.and_then(|config| find_function_candidates_with_config(&pe, &config));
+        std::fs::remove_file(&path)?;
+        let candidates = candidates?;
+
+        assert!(candidates
+            .values()
+            .all(|sources| sources.iter().all(|&source| source == FunctionSource::Flirt)));
+        // scan hits are checked like matches at known function starts,
+        // so signatures with unresolved references, like to imports, are dropped.
+        // otherwise, the functions that match at their starts are all found,
+        // except those too short to scan for, and there are few false positives.
+        let starts: Vec<VA> = analysis.cfgs.keys().cloned().collect();
+        let expected = match_flirt_module(&pe.module, &FlirtSignatureSet::with_signatures(sigs), &starts);
+        let expected: Vec<VA> = expected
+            .matches
+            .iter()
+            .filter(|(_, sigs)| {
+                sigs.iter()
+                    .any(|sig| get_signature_fixed_size(sig) >= MIN_SCAN_SIGNATURE_SIZE)
+            })
+            .map(|(&va, _)| va)
+            .collect();
+        let found = expected.iter().filter(|va| candidates.contains_key(va)).count();
+        assert_eq!(found, expected.len());
+        assert!(candidates.len() - found < candidates.len() / 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unresolved_references() {
+        let module = load_shellcode32(CODE);
+        let sigs = parse_pat(
+            "\
+E8........C3.................................................... 00 0000 0006 :0000 foo ^0001 bar
+31C0C3.......................................................... 00 0000 0003 :0000 baz
+---
+",
+        );
+
+        let matches = match_flirt_module(&module, &sigs, &[0x0, 0xA]);
+        assert!(!matches.is_library_function(0x0));
+        assert_eq!(matches.get_name(0xA), Some("baz"));
+    }
+}
@@ -0,0 +1,759 @@
+//! Graph algorithms over the basic blocks of a `cfg::CFG`:
+//!
+//!   - dominator and post-dominator trees, via Cooper, Harvey, and Kennedy's "A
+//!     Simple, Fast Dominance Algorithm",
+//!   - dominance frontiers,
+//!   - back edges and natural loops, with nesting, and
+//!   - strongly connected components, via Tarjan's algorithm.
+//!
+//! Natural loops are only found in reducible regions of the CFG.
+//! Use the strongly connected components to find any cycle,
+//! such as to answer "does this function contain a loop?".
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::{analysis::cfg::CFG, VA};
+
+#[derive(Debug, Error)]
+pub enum GraphError {
+    #[error("entry {0:#x} is not a basic block in the CFG")]
+    EntryNotFound(VA),
+}
+
+/// the CFG with basic blocks identified by index,
+/// which is easier to work with than VAs.
+struct Graph {
+    nodes:        Vec<VA>,
+    index:        BTreeMap<VA, usize>,
+    successors:   Vec<Vec<usize>>,
+    predecessors: Vec<Vec<usize>>,
+}
+
+impl Graph {
+    fn from_cfg(cfg: &CFG) -> Graph {
+        let nodes: Vec<VA> = cfg.basic_blocks.keys().cloned().collect();
+        let index: BTreeMap<VA, usize> = nodes.iter().enumerate().map(|(i, &va)| (va, i)).collect();
+
+        let mut successors = vec![vec![]; nodes.len()];
+        let mut predecessors = vec![vec![]; nodes.len()];
+        for (i, bb) in cfg.basic_blocks.values().enumerate() {
+            for succ in bb.successors.iter() {
+                // flows to addresses that aren't basic blocks,
+                // such as when decoding failed, are ignored.
+                if let Some(&j) = index.get(&succ.va()) {
+                    if !successors[i].contains(&j) {
+                        successors[i].push(j);
+                        predecessors[j].push(i);
+                    }
+                }
+            }
+        }
+
+        Graph {
+            nodes,
+            index,
+            successors,
+            predecessors,
+        }
+    }
+
+    /// the same graph, with the edges reversed,
+    /// plus a virtual node (the last index) that flows to each of the given
+    /// nodes.
+    fn reverse_with_exit(&self, exits: &[usize]) -> Graph {
+        let mut successors = self.predecessors.clone();
+        let mut predecessors = self.successors.clone();
+
+        successors.push(exits.to_vec());
+        predecessors.push(vec![]);
+        for &exit in exits.iter() {
+            predecessors[exit].push(self.nodes.len());
+        }
+
+        Graph {
+            nodes: self.nodes.clone(),
+            index: self.index.clone(),
+            successors,
+            predecessors,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.successors.len()
+    }
+
+    /// the nodes reachable from the given root, in reverse postorder.
+    fn reverse_postorder(&self, root: usize) -> Vec<usize> {
+        let mut order = Vec::with_capacity(self.len());
+        let mut seen = vec![false; self.len()];
+
+        // stack of (node, index of next successor to visit).
+        let mut stack = vec![(root, 0)];
+        seen[root] = true;
+
+        while let Some((node, i)) = stack.pop() {
+            if let Some(&succ) = self.successors[node].get(i) {
+                stack.push((node, i + 1));
+                if !seen[succ] {
+                    seen[succ] = true;
+                    stack.push((succ, 0));
+                }
+            } else {
+                order.push(node);
+            }
+        }
+
+        order.reverse();
+        order
+    }
+
+    /// compute the immediate dominator of each node reachable from the root.
+    /// the root and unreachable nodes have no immediate dominator.
+    fn immediate_dominators(&self, root: usize) -> Vec<Option<usize>> {
+        let rpo = self.reverse_postorder(root);
+
+        // position of each node in the postorder, used to walk up the tree.
+        let mut postorder = vec![usize::MAX; self.len()];
+        for (i, &node) in rpo.iter().rev().enumerate() {
+            postorder[node] = i;
+        }
+
+        let mut idoms: Vec<Option<usize>> = vec![None; self.len()];
+        idoms[root] = Some(root);
+
+        let intersect = |idoms: &[Option<usize>], mut a: usize, mut b: usize| -> usize {
+            while a != b {
+                while postorder[a] < postorder[b] {
+                    a = idoms[a].expect("processed node must have idom");
+                }
+                while postorder[b] < postorder[a] {
+                    b = idoms[b].expect("processed node must have idom");
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &node in rpo.iter().skip(1) {
+                let mut new_idom: Option<usize> = None;
+                for &pred in self.predecessors[node].iter() {
+                    if idoms[pred].is_none() {
+                        // not yet processed, or unreachable.
+                        continue;
+                    }
+
+                    new_idom = match new_idom {
+                        None => Some(pred),
+                        Some(idom) => Some(intersect(&idoms, pred, idom)),
+                    };
+                }
+
+                if new_idom.is_some() && idoms[node] != new_idom {
+                    idoms[node] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        idoms[root] = None;
+        idoms
+    }
+
+    /// compute the dominance frontier of each node,
+    /// given the immediate dominators.
+    fn dominance_frontiers(&self, idoms: &[Option<usize>], root: usize) -> Vec<BTreeSet<usize>> {
+        let mut frontiers = vec![BTreeSet::new(); self.len()];
+
+        for node in 0..self.len() {
+            if node != root && idoms[node].is_none() {
+                // unreachable.
+                continue;
+            }
+
+            for &pred in self.predecessors[node].iter() {
+                if pred != root && idoms[pred].is_none() {
+                    continue;
+                }
+
+                // walk up from the predecessor to the node's immediate dominator.
+                // every node along the way dominates a predecessor,
+                // but doesn't strictly dominate this node.
+                let mut runner = Some(pred);
+                while let Some(r) = runner {
+                    if Some(r) == idoms[node] {
+                        break;
+                    }
+                    frontiers[r].insert(node);
+                    runner = idoms[r];
+                }
+            }
+        }
+
+        frontiers
+    }
+}
+
+/// a dominator (or post-dominator) tree over the basic blocks of a CFG.
+///
+/// only contains the basic blocks reachable from the entry
+/// (or, for post-dominators, that reach an exit).
+#[derive(Debug, Clone)]
+pub struct DominatorTree {
+    /// the roots of the tree:
+    /// the entry for dominators, and for post-dominators,
+    /// the blocks not post-dominated by any other block (such as the exits).
+    pub roots: Vec<VA>,
+    idoms:     BTreeMap<VA, Option<VA>>,
+    children:  BTreeMap<VA, Vec<VA>>,
+    frontiers: BTreeMap<VA, Vec<VA>>,
+}
+
+impl DominatorTree {
+    fn from_graph(
+        graph: &Graph,
+        roots: Vec<VA>,
+        idoms: &[Option<usize>],
+        frontiers: &[BTreeSet<usize>],
+        is_root: impl Fn(usize) -> bool,
+    ) -> DominatorTree {
+        let mut tree = DominatorTree {
+            roots,
+            idoms: Default::default(),
+            children: Default::default(),
+            frontiers: Default::default(),
+        };
+
+        // the virtual exit node of the reversed graph, if any, has no VA,
+        // so it is skipped, and its children become roots.
+        for (i, &va) in graph.nodes.iter().enumerate() {
+            if !is_root(i) && idoms[i].is_none() {
+                continue;
+            }
+
+            let idom = idoms[i].and_then(|idom| graph.nodes.get(idom)).cloned();
+            tree.idoms.insert(va, idom);
+            tree.children.entry(va).or_default();
+            if let Some(idom) = idom {
+                tree.children.entry(idom).or_default().push(va);
+            }
+
+            tree.frontiers.insert(
+                va,
+                frontiers[i]
+                    .iter()
+                    .filter_map(|&f| graph.nodes.get(f))
+                    .cloned()
+                    .collect(),
+            );
+        }
+
+        tree
+    }
+
+    /// is the given basic block in the tree?
+    pub fn contains(&self, va: VA) -> bool {
+        self.idoms.contains_key(&va)
+    }
+
+    /// the immediate dominator of the given basic block,
+    /// or `None` for roots and blocks not in the tree.
+    pub fn immediate_dominator(&self, va: VA) -> Option<VA> {
+        self.idoms.get(&va).cloned().flatten()
+    }
+
+    /// the basic blocks immediately dominated by the given basic block.
+    pub fn children(&self, va: VA) -> &[VA] {
+        self.children.get(&va).map(|c| c.as_slice()).unwrap_or(&[])
+    }
+
+    /// the dominance frontier of the given basic block:
+    /// the blocks where its dominance ends.
+    pub fn frontier(&self, va: VA) -> &[VA] {
+        self.frontiers.get(&va).map(|f| f.as_slice()).unwrap_or(&[])
+    }
+
+    /// the dominators of the given basic block,
+    /// from the block itself up to the root of the tree.
+    pub fn dominators(&self, va: VA) -> Vec<VA> {
+        let mut dominators = vec![];
+        if !self.contains(va) {
+            return dominators;
+        }
+
+        let mut current = Some(va);
+        while let Some(va) = current {
+            dominators.push(va);
+            current = self.immediate_dominator(va);
+        }
+        dominators
+    }
+
+    /// does basic block `a` dominate basic block `b`?
+    /// a basic block dominates itself.
+    pub fn dominates(&self, a: VA, b: VA) -> bool {
+        if !self.contains(a) || !self.contains(b) {
+            return false;
+        }
+
+        let mut current = Some(b);
+        while let Some(va) = current {
+            if va == a {
+                return true;
+            }
+            current = self.immediate_dominator(va);
+        }
+        false
+    }
+
+    /// does basic block `a` dominate basic block `b`, and `a != b`?
+    pub fn strictly_dominates(&self, a: VA, b: VA) -> bool {
+        a != b && self.dominates(a, b)
+    }
+}
+
+/// compute the dominator tree of the CFG, rooted at the given entry.
+///
+/// ```
+/// use lancelot::test::*;
+/// use lancelot::analysis::cfg::build_cfg;
+/// use lancelot::analysis::graph::compute_dominators;
+///
+/// // 0: 85 c9    test ecx, ecx
+/// // 2: 74 01    jz   0x5
+/// // 4: 40       inc  eax
+/// // 5: c3       ret
+/// let module = load_shellcode32(b"\x85\xC9\x74\x01\x40\xC3");
+/// let cfg = build_cfg(&module, 0x0).unwrap();
+/// let dom = compute_dominators(&cfg, 0x0).unwrap();
+///
+/// assert_eq!(dom.immediate_dominator(0x5), Some(0x0));
+/// assert!(dom.dominates(0x0, 0x4));
+/// assert!(!dom.dominates(0x4, 0x5));
+/// ```
+pub fn compute_dominators(cfg: &CFG, entry: VA) -> Result<DominatorTree> {
+    let graph = Graph::from_cfg(cfg);
+    let root = *graph.index.get(&entry).ok_or(GraphError::EntryNotFound(entry))?;
+
+    let idoms = graph.immediate_dominators(root);
+    let frontiers = graph.dominance_frontiers(&idoms, root);
+
+    Ok(DominatorTree::from_graph(
+        &graph,
+        vec![entry],
+        &idoms,
+        &frontiers,
+        |i| i == root,
+    ))
+}
+
+/// compute the post-dominator tree of the CFG.
+///
+/// the exits are the basic blocks reachable from the entry with no successors,
+/// such as those that end with a `ret`.
+/// basic blocks that never reach an exit, like infinite loops, are not in the
+/// tree.
+pub fn compute_post_dominators(cfg: &CFG, entry: VA) -> Result<DominatorTree> {
+    let graph = Graph::from_cfg(cfg);
+    let root = *graph.index.get(&entry).ok_or(GraphError::EntryNotFound(entry))?;
+
+    let exits: Vec<usize> = graph
+        .reverse_postorder(root)
+        .into_iter()
+        .filter(|&i| graph.successors[i].is_empty())
+        .collect();
+
+    // use a single virtual exit node that flows to each of the exits,
+    // so that there's a single root.
+    let reversed = graph.reverse_with_exit(&exits);
+    let exit = graph.len();
+    let idoms = reversed.immediate_dominators(exit);
+    let frontiers = reversed.dominance_frontiers(&idoms, exit);
+
+    // blocks immediately post-dominated by the virtual exit are the roots.
+    let is_root = |i: usize| idoms[i] == Some(exit);
+    let roots = (0..graph.len())
+        .filter(|&i| is_root(i))
+        .map(|i| graph.nodes[i])
+        .collect();
+    Ok(DominatorTree::from_graph(&graph, roots, &idoms, &frontiers, is_root))
+}
+
+#[derive(Debug, Clone)]
+pub struct Loop {
+    /// the basic block that dominates the loop, and the target of its back
+    /// edges.
+    pub header:   VA,
+    /// the sources of the back edges to the header.
+    pub latches:  Vec<VA>,
+    /// the basic blocks in the loop, including the header and those of any
+    /// nested loops.
+    pub blocks:   BTreeSet<VA>,
+    /// the header of the innermost enclosing loop.
+    pub parent:   Option<VA>,
+    /// the headers of the loops immediately nested in this one.
+    pub children: Vec<VA>,
+    /// the nesting depth, with outermost loops at depth 1.
+    pub depth:    u32,
+}
+
+/// find the back edges in the CFG: edges whose target dominates their source.
+/// returns pairs of (latch, header).
+pub fn find_back_edges(cfg: &CFG, dominators: &DominatorTree) -> Vec<(VA, VA)> {
+    let mut edges = vec![];
+
+    for bb in cfg.basic_blocks.values() {
+        for succ in bb.successors.iter() {
+            let header = succ.va();
+            if dominators.dominates(header, bb.address) && !edges.contains(&(bb.address, header)) {
+                edges.push((bb.address, header));
+            }
+        }
+    }
+
+    edges
+}
+
+/// find the natural loops in the CFG, keyed by loop header.
+/// back edges that share a header are merged into a single loop.
+///
+/// ```
+/// use lancelot::test::*;
+/// use lancelot::analysis::cfg::build_cfg;
+/// use lancelot::analysis::graph::{compute_dominators, find_loops};
+///
+/// // 0: 31 c0    xor  eax, eax
+/// // 2: 40       inc  eax
+/// // 3: 39 c8    cmp  eax, ecx
+/// // 5: 75 fb    jne  0x2
+/// // 7: c3       ret
+/// let module = load_shellcode32(b"\x31\xC0\x40\x39\xC8\x75\xFB\xC3");
+/// let cfg = build_cfg(&module, 0x0).unwrap();
+/// let dom = compute_dominators(&cfg, 0x0).unwrap();
+/// let loops = find_loops(&cfg, &dom);
+///
+/// assert_eq!(loops.len(), 1);
+/// assert_eq!(loops[&0x2].latches, vec![0x2]);
+/// ```
+pub fn find_loops(cfg: &CFG, dominators: &DominatorTree) -> BTreeMap<VA, Loop> {
+    let mut loops: BTreeMap<VA, Loop> = Default::default();
+
+    for (latch, header) in find_back_edges(cfg, dominators).into_iter() {
+        let l = loops.entry(header).or_insert_with(|| Loop {
+            header,
+            latches: vec![],
+            blocks: std::iter::once(header).collect(),
+            parent: None,
+            children: vec![],
+            depth: 0,
+        });
+        l.latches.push(latch);
+
+        // the body is the header plus every block that reaches the latch
+        // without passing through the header.
+        let mut queue = vec![latch];
+        while let Some(va) = queue.pop() {
+            if !l.blocks.insert(va) {
+                continue;
+            }
+
+            if let Some(bb) = cfg.basic_blocks.get(&va) {
+                queue.extend(
+                    bb.predecessors
+                        .iter()
+                        .map(|pred| pred.va())
+                        .filter(|&pred| dominators.contains(pred)),
+                );
+            }
+        }
+    }
+
+    // the parent of a loop is the smallest other loop that contains its header.
+    let headers: Vec<VA> = loops.keys().cloned().collect();
+    for &header in headers.iter() {
+        let parent = loops
+            .values()
+            .filter(|l| l.header != header && l.blocks.contains(&header))
+            .min_by_key(|l| l.blocks.len())
+            .map(|l| l.header);
+
+        loops.get_mut(&header).unwrap().parent = parent;
+        if let Some(parent) = parent {
+            loops.get_mut(&parent).unwrap().children.push(header);
+        }
+    }
+
+    for &header in headers.iter() {
+        let mut depth = 0;
+        let mut current = Some(header);
+        while let Some(va) = current {
+            depth += 1;
+            current = loops[&va].parent;
+        }
+        loops.get_mut(&header).unwrap().depth = depth;
+    }
+
+    loops
+}
+
+/// find the strongly connected components of the CFG, in reverse topological
+/// order: a component is emitted before any component that flows to it.
+///
+/// every basic block is in exactly one component.
+pub fn find_strongly_connected_components(cfg: &CFG) -> Vec<BTreeSet<VA>> {
+    let graph = Graph::from_cfg(cfg);
+    let n = graph.len();
+
+    let mut components = vec![];
+
+    let mut next_index = 0;
+    let mut indices: Vec<Option<usize>> = vec![None; n];
+    let mut lowlinks = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = vec![];
+
+    for start in 0..n {
+        if indices[start].is_some() {
+            continue;
+        }
+
+        // iterative Tarjan's, to avoid overflowing the call stack on large
+        // functions. each frame is (node, index of next successor to visit).
+        let mut frames = vec![(start, 0)];
+        indices[start] = Some(next_index);
+        lowlinks[start] = next_index;
+        next_index += 1;
+        stack.push(start);
+        on_stack[start] = true;
+
+        while let Some((node, i)) = frames.pop() {
+            if let Some(&succ) = graph.successors[node].get(i) {
+                frames.push((node, i + 1));
+
+                match indices[succ] {
+                    None => {
+                        indices[succ] = Some(next_index);
+                        lowlinks[succ] = next_index;
+                        next_index += 1;
+                        stack.push(succ);
+                        on_stack[succ] = true;
+                        frames.push((succ, 0));
+                    }
+                    Some(index) if on_stack[succ] => {
+                        lowlinks[node] = std::cmp::min(lowlinks[node], index);
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            // all successors visited.
+            if let Some(&(parent, _)) = frames.last() {
+                lowlinks[parent] = std::cmp::min(lowlinks[parent], lowlinks[node]);
+            }
+
+            if Some(lowlinks[node]) == indices[node] {
+                let mut component = BTreeSet::new();
+                loop {
+                    let member = stack.pop().expect("node must be on stack");
+                    on_stack[member] = false;
+                    component.insert(graph.nodes[member]);
+                    if member == node {
+                        break;
+                    }
+                }
+                components.push(component);
+            }
+        }
+    }
+
+    components
+}
+
+/// does the CFG contain a cycle, including irreducible loops and self loops?
+pub fn is_cyclic(cfg: &CFG) -> bool {
+    find_strongly_connected_components(cfg).iter().any(|component| {
+        component.len() > 1 || {
+            let va = *component.iter().next().unwrap();
+            cfg.basic_blocks[&va].successors.iter().any(|succ| succ.va() == va)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::{cfg::build_cfg, graph::*},
+        rsrc::*,
+        test::*,
+    };
+    use anyhow::Result;
+
+    // 0x0: 31 c0    xor  eax, eax     ; A
+    // 0x2: 85 c9    test ecx, ecx
+    // 0x4: 74 04    jz   0xa
+    // 0x6: 40       inc  eax          ; B
+    // 0x7: 49       dec  ecx
+    // 0x8: eb 01    jmp  0xb
+    // 0xa: 48       dec  eax          ; C
+    // 0xb: 40       inc  eax          ; D
+    // 0xc: 39 c8    cmp  eax, ecx
+    // 0xe: 75 fb    jne  0xb
+    // 0x10: c3      ret               ; E
+    const DIAMOND_LOOP: &[u8] = b"\x31\xC0\x85\xC9\x74\x04\x40\x49\xEB\x01\x48\x40\x39\xC8\x75\xFB\xC3";
+
+    #[test]
+    fn dominators() -> Result<()> {
+        let module = load_shellcode32(DIAMOND_LOOP);
+        let cfg = build_cfg(&module, 0x0)?;
+        let dom = compute_dominators(&cfg, 0x0)?;
+
+        assert_eq!(dom.roots, vec![0x0]);
+        assert_eq!(dom.immediate_dominator(0x0), None);
+        assert_eq!(dom.immediate_dominator(0x6), Some(0x0));
+        assert_eq!(dom.immediate_dominator(0xA), Some(0x0));
+        assert_eq!(dom.immediate_dominator(0xB), Some(0x0));
+        assert_eq!(dom.immediate_dominator(0x10), Some(0xB));
+        assert_eq!(dom.children(0x0), &[0x6, 0xA, 0xB]);
+        assert_eq!(dom.dominators(0x10), vec![0x10, 0xB, 0x0]);
+        assert!(dom.strictly_dominates(0xB, 0x10));
+        assert!(!dom.dominates(0x6, 0xB));
+
+        assert_eq!(dom.frontier(0x0), &[] as &[u64]);
+        assert_eq!(dom.frontier(0x6), &[0xB]);
+        assert_eq!(dom.frontier(0xA), &[0xB]);
+        assert_eq!(dom.frontier(0xB), &[0xB]);
+
+        assert!(compute_dominators(&cfg, 0x1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn post_dominators() -> Result<()> {
+        let module = load_shellcode32(DIAMOND_LOOP);
+        let cfg = build_cfg(&module, 0x0)?;
+        let pdom = compute_post_dominators(&cfg, 0x0)?;
+
+        assert_eq!(pdom.roots, vec![0x10]);
+        assert_eq!(pdom.immediate_dominator(0x0), Some(0xB));
+        assert_eq!(pdom.immediate_dominator(0x6), Some(0xB));
+        assert_eq!(pdom.immediate_dominator(0xA), Some(0xB));
+        assert_eq!(pdom.immediate_dominator(0xB), Some(0x10));
+        assert_eq!(pdom.immediate_dominator(0x10), None);
+
+        // control dependence: B and C depend on the branch in A.
+        assert_eq!(pdom.frontier(0x6), &[0x0]);
+        assert_eq!(pdom.frontier(0xA), &[0x0]);
+        assert_eq!(pdom.frontier(0xB), &[0xB]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn loops() -> Result<()> {
+        let module = load_shellcode32(DIAMOND_LOOP);
+        let cfg = build_cfg(&module, 0x0)?;
+        let dom = compute_dominators(&cfg, 0x0)?;
+
+        assert_eq!(find_back_edges(&cfg, &dom), vec![(0xB, 0xB)]);
+
+        let loops = find_loops(&cfg, &dom);
+        assert_eq!(loops.len(), 1);
+        let l = &loops[&0xB];
+        assert_eq!(l.latches, vec![0xB]);
+        assert_eq!(l.blocks.iter().cloned().collect::<Vec<_>>(), vec![0xB]);
+        assert_eq!(l.parent, None);
+        assert_eq!(l.depth, 1);
+
+        assert!(is_cyclic(&cfg));
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested_loops() -> Result<()> {
+        // 0x0: 31 c9        xor  ecx, ecx
+        // 0x2: 31 d2        xor  edx, edx     ; outer header
+        // 0x4: 42           inc  edx          ; inner header
+        // 0x5: 83 fa 0a     cmp  edx, 0xa
+        // 0x8: 75 fa        jne  0x4
+        // 0xa: 41           inc  ecx          ; outer latch
+        // 0xb: 83 f9 0a     cmp  ecx, 0xa
+        // 0xe: 75 f2        jne  0x2
+        // 0x10: c3          ret
+        let module = load_shellcode32(b"\x31\xC9\x31\xD2\x42\x83\xFA\x0A\x75\xFA\x41\x83\xF9\x0A\x75\xF2\xC3");
+        let cfg = build_cfg(&module, 0x0)?;
+        let dom = compute_dominators(&cfg, 0x0)?;
+        let loops = find_loops(&cfg, &dom);
+
+        assert_eq!(loops.len(), 2);
+
+        let outer = &loops[&0x2];
+        assert_eq!(outer.latches, vec![0xA]);
+        assert_eq!(outer.blocks.iter().cloned().collect::<Vec<_>>(), vec![0x2, 0x4, 0xA]);
+        assert_eq!(outer.parent, None);
+        assert_eq!(outer.children, vec![0x4]);
+        assert_eq!(outer.depth, 1);
+
+        let inner = &loops[&0x4];
+        assert_eq!(inner.latches, vec![0x4]);
+        assert_eq!(inner.blocks.iter().cloned().collect::<Vec<_>>(), vec![0x4]);
+        assert_eq!(inner.parent, Some(0x2));
+        assert_eq!(inner.depth, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn irreducible() -> Result<()> {
+        // two blocks that flow to each other,
+        // but neither dominates the other, so there's no natural loop.
+        //
+        // 0x0: 85 c9    test ecx, ecx
+        // 0x2: 74 03    jz   0x7
+        // 0x4: 40       inc  eax
+        // 0x5: eb 00    jmp  0x7
+        // 0x7: 48       dec  eax
+        // 0x8: 75 fa    jnz  0x4
+        // 0xa: c3       ret
+        let module = load_shellcode32(b"\x85\xC9\x74\x03\x40\xEB\x00\x48\x75\xFA\xC3");
+        let cfg = build_cfg(&module, 0x0)?;
+        let dom = compute_dominators(&cfg, 0x0)?;
+
+        assert!(find_loops(&cfg, &dom).is_empty());
+        assert!(is_cyclic(&cfg));
+
+        let sccs = find_strongly_connected_components(&cfg);
+        assert_eq!(sccs.len(), 3);
+        // reverse topological order: the exit first, the entry last.
+        assert_eq!(sccs[0].iter().cloned().collect::<Vec<_>>(), vec![0xA]);
+        assert_eq!(sccs[1].iter().cloned().collect::<Vec<_>>(), vec![0x4, 0x7]);
+        assert_eq!(sccs[2].iter().cloned().collect::<Vec<_>>(), vec![0x0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let cfg = build_cfg(&pe.module, 0x1800527B0)?;
+        let dom = compute_dominators(&cfg, 0x1800527B0)?;
+        let pdom = compute_post_dominators(&cfg, 0x1800527B0)?;
+
+        // the entry dominates everything, and nothing dominates the entry.
+        for &bb in cfg.basic_blocks.keys() {
+            assert!(dom.dominates(0x1800527B0, bb));
+            assert!(pdom.contains(bb));
+        }
+        assert!(!is_cyclic(&cfg));
+
+        Ok(())
+    }
+}
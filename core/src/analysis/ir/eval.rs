@@ -0,0 +1,434 @@
+//! Execute IR statements against a concrete machine state.
+//!
+//! The effects of an instruction are buffered until all of its statements
+//! have executed, so when an error occurs, such as a store to unmapped memory,
+//! the machine state is left unchanged and the caller may re-try.
+use std::collections::HashMap;
+
+use anyhow::Result;
+use thiserror::Error;
+
+use super::{mask, BinaryOp, Expr, Instruction, Size, Stmt, UnaryOp, Var};
+use crate::VA;
+
+#[derive(Debug, Error)]
+pub enum EvalError {
+    #[error("divide by zero")]
+    DivideByZero,
+    #[error("unsupported variable: {0:?}")]
+    UnsupportedVariable(Var),
+    #[error("unsupported memory access size: {0}")]
+    UnsupportedSize(Size),
+}
+
+/// the state that IR statements read and write.
+///
+/// temporaries are managed by the evaluator, so implementations only need to
+/// handle registers, segment bases, and flags.
+pub trait Machine {
+    /// read the full width register, segment base, or flag.
+    fn read_var(&self, var: Var) -> Result<u64>;
+    fn write_var(&mut self, var: Var, value: u64) -> Result<()>;
+    /// read `size` bits from the given address.
+    fn load(&self, addr: VA, size: Size) -> Result<u64>;
+    /// write the low `size` bits of the value to the given address.
+    fn store(&mut self, addr: VA, size: Size, value: u64) -> Result<()>;
+}
+
+pub(crate) fn sign_extend(value: u64, size: Size) -> i64 {
+    if size >= 64 {
+        value as i64
+    } else {
+        let shift = 64 - size as u32;
+        ((value << shift) as i64) >> shift
+    }
+}
+
+/// compute the unary operation over a value of the given size.
+pub(crate) fn unary(op: UnaryOp, value: u64, size: Size) -> u64 {
+    match op {
+        UnaryOp::Not => !value & mask(size),
+        UnaryOp::Neg => value.wrapping_neg() & mask(size),
+        UnaryOp::Parity => (value as u8).count_ones().is_multiple_of(2) as u64,
+    }
+}
+
+/// compute the binary operation over two values of the given size.
+///
+/// the result of a comparison is a single bit.
+pub(crate) fn binary(op: BinaryOp, a: u64, b: u64, size: Size) -> Result<u64> {
+    let m = mask(size);
+    Ok(match op {
+        BinaryOp::Add => a.wrapping_add(b) & m,
+        BinaryOp::Sub => a.wrapping_sub(b) & m,
+        BinaryOp::Mul => a.wrapping_mul(b) & m,
+        BinaryOp::UMulHi => (((a as u128) * (b as u128)) >> size) as u64 & m,
+        BinaryOp::SMulHi => {
+            let product = (sign_extend(a, size) as i128) * (sign_extend(b, size) as i128);
+            (product >> size) as u64 & m
+        }
+        BinaryOp::UDiv | BinaryOp::URem | BinaryOp::SDiv | BinaryOp::SRem if b == 0 => {
+            return Err(EvalError::DivideByZero.into())
+        }
+        BinaryOp::UDiv => a / b,
+        BinaryOp::URem => a % b,
+        BinaryOp::SDiv => sign_extend(a, size).wrapping_div(sign_extend(b, size)) as u64 & m,
+        BinaryOp::SRem => sign_extend(a, size).wrapping_rem(sign_extend(b, size)) as u64 & m,
+        BinaryOp::And => a & b,
+        BinaryOp::Or => a | b,
+        BinaryOp::Xor => a ^ b,
+        BinaryOp::Shl => {
+            if b >= size as u64 {
+                0
+            } else {
+                (a << b) & m
+            }
+        }
+        BinaryOp::Shr => {
+            if b >= size as u64 {
+                0
+            } else {
+                a >> b
+            }
+        }
+        BinaryOp::Sar => {
+            let shift = std::cmp::min(b, size as u64 - 1);
+            (sign_extend(a, size) >> shift) as u64 & m
+        }
+        BinaryOp::Rol | BinaryOp::Ror => {
+            let b = b % size as u64;
+            if b == 0 {
+                a
+            } else if op == BinaryOp::Rol {
+                ((a << b) | (a >> (size as u64 - b))) & m
+            } else {
+                ((a >> b) | (a << (size as u64 - b))) & m
+            }
+        }
+        BinaryOp::Eq => (a == b) as u64,
+        BinaryOp::Ne => (a != b) as u64,
+        BinaryOp::Ult => (a < b) as u64,
+        BinaryOp::Ule => (a <= b) as u64,
+        BinaryOp::Slt => (sign_extend(a, size) < sign_extend(b, size)) as u64,
+        BinaryOp::Sle => (sign_extend(a, size) <= sign_extend(b, size)) as u64,
+    })
+}
+
+/// replace the bits of `base` starting at `lsb` with the value.
+pub(crate) fn insert(base: u64, base_size: Size, lsb: u16, value: u64, value_size: Size) -> u64 {
+    let m = mask(value_size) << lsb;
+    ((base & !m) | (value << lsb)) & mask(base_size)
+}
+
+struct Evaluator<'a, M: Machine> {
+    machine: &'a M,
+    /// the variables written by prior statements of this instruction.
+    vars:    HashMap<Var, u64>,
+    stores:  Vec<(VA, Size, u64)>,
+}
+
+impl<'a, M: Machine> Evaluator<'a, M> {
+    fn read_var(&self, var: Var) -> Result<u64> {
+        match self.vars.get(&var) {
+            Some(&value) => Ok(value),
+            None => match var {
+                // temporaries are always assigned before use.
+                Var::Temp(_) => Ok(0),
+                _ => self.machine.read_var(var),
+            },
+        }
+    }
+
+    fn eval(&self, expr: &Expr) -> Result<u64> {
+        Ok(match expr {
+            Expr::Const { value, .. } => *value,
+            Expr::Var { var, size } => self.read_var(*var)? & mask(*size),
+            Expr::Load { addr, size } => {
+                let addr = self.eval(addr)?;
+                self.machine.load(addr, *size)? & mask(*size)
+            }
+            Expr::Unary { op, arg } => unary(*op, self.eval(arg)?, arg.size()),
+            Expr::Binary { op, lhs, rhs } => binary(*op, self.eval(lhs)?, self.eval(rhs)?, lhs.size())?,
+            Expr::ZeroExtend { arg, .. } => self.eval(arg)?,
+            Expr::SignExtend { arg, size } => sign_extend(self.eval(arg)?, arg.size()) as u64 & mask(*size),
+            Expr::Extract { arg, lsb, size } => (self.eval(arg)? >> lsb) & mask(*size),
+            Expr::Insert { arg, lsb, value } => {
+                insert(self.eval(arg)?, arg.size(), *lsb, self.eval(value)?, value.size())
+            }
+            Expr::Ite { cond, then, els } => {
+                if self.eval(cond)? != 0 {
+                    self.eval(then)?
+                } else {
+                    self.eval(els)?
+                }
+            }
+        })
+    }
+}
+
+/// execute the statements of the instruction against the machine.
+///
+/// returns the address of the next instruction to execute:
+/// either the fallthrough, or the target of a taken control transfer.
+///
+/// undefined variables are left unchanged.
+pub fn evaluate<M: Machine>(machine: &mut M, insn: &Instruction) -> Result<VA> {
+    let mut next = insn.address + insn.length;
+
+    let mut evaluator = Evaluator {
+        machine: &*machine,
+        vars:    Default::default(),
+        stores:  vec![],
+    };
+
+    for stmt in insn.stmts.iter() {
+        match stmt {
+            Stmt::Assign { dst, value } => {
+                let value = evaluator.eval(value)?;
+                evaluator.vars.insert(*dst, value);
+            }
+            Stmt::Undefine { .. } => {}
+            Stmt::Store { addr, value } => {
+                let size = value.size();
+                let addr = evaluator.eval(addr)?;
+                let value = evaluator.eval(value)?;
+                evaluator.stores.push((addr, size, value));
+            }
+            Stmt::Jump { target } | Stmt::Call { target } | Stmt::Return { target } => {
+                next = evaluator.eval(target)?;
+            }
+            Stmt::Branch { cond, target } => {
+                if evaluator.eval(cond)? != 0 {
+                    next = evaluator.eval(target)?;
+                }
+            }
+        }
+    }
+
+    let Evaluator { vars, stores, .. } = evaluator;
+
+    // lifted instructions have at most one store,
+    // so if it fails, then nothing has been changed.
+    for (addr, size, value) in stores.into_iter() {
+        machine.store(addr, size, value)?;
+    }
+
+    for (var, value) in vars.into_iter() {
+        if let Var::Temp(_) = var {
+            continue;
+        }
+        machine.write_var(var, value)?;
+    }
+
+    Ok(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        emu::{reg::STATUS_MASK, Emulator},
+        test::*,
+    };
+
+    fn step(emu: &mut Emulator) {
+        let insn = emu.fetch().unwrap();
+        emu.execute(&insn).unwrap();
+    }
+
+    #[test]
+    fn semantics64() {
+        // 0:  48 b8 ff ff ff ff ff ff ff 7f    movabs rax, 0x7fffffffffffffff
+        // a:  48 83 c0 01                      add    rax, 0x1
+        // e:  48 89 c3                         mov    rbx, rax
+        // 11: 48 83 eb 02                      sub    rbx, 0x2
+        // 15: 48 39 d8                         cmp    rax, rbx
+        // 18: 48 f7 d9                         neg    rcx
+        // 1b: 83 f1 55                         xor    ecx, 0x55
+        // 1e: 80 e1 f0                         and    cl, 0xf0
+        // 21: 80 cd 01                         or     ch, 0x1
+        // 24: 66 ff c2                         inc    dx
+        // 27: fe ca                            dec    dl
+        // 29: 48 85 d8                         test   rax, rbx
+        // 2c: 50                               push   rax
+        // 2d: 5a                               pop    rdx
+        // 2e: 48 8d 74 58 10                   lea    rsi, [rax+rbx*2+0x10]
+        // 33: 48 87 fe                         xchg   rsi, rdi
+        // 36: 89 44 24 f8                      mov    DWORD PTR [rsp-0x8], eax
+        // 3a: 44 8b 44 24 f8                   mov    r8d, DWORD PTR [rsp-0x8]
+        // 3f: 80 44 24 f8 80                   add    BYTE PTR [rsp-0x8], 0x80
+        // 44: 66 83 6c 24 fa 01                sub    WORD PTR [rsp-0x6], 0x1
+        let code = b"\x48\xB8\xFF\xFF\xFF\xFF\xFF\xFF\xFF\x7F\x48\x83\xC0\x01\x48\x89\xC3\x48\x83\xEB\x02\x48\x39\xD8\x48\xF7\xD9\x83\xF1\x55\x80\xE1\xF0\x80\xCD\x01\x66\xFF\xC2\xFE\xCA\x48\x85\xD8\x50\x5A\x48\x8D\x74\x58\x10\x48\x87\xFE\x89\x44\x24\xF8\x44\x8B\x44\x24\xF8\x80\x44\x24\xF8\x80\x66\x83\x6C\x24\xFA\x01";
+
+        let mut emu = emu_from_shellcode64(code);
+        for _ in 0..20 {
+            step(&mut emu);
+        }
+
+        let r = &emu.reg;
+        assert_eq!(r.rip, 0x4A);
+        assert_eq!(
+            [r.rax, r.rbx, r.rcx, r.rdx],
+            [
+                0x8000_0000_0000_0000,
+                0x7FFF_FFFF_FFFF_FFFE,
+                0x150,
+                0x8000_0000_0000_0000
+            ]
+        );
+        assert_eq!(
+            [r.rsp, r.rbp, r.rsi, r.rdi, r.r8],
+            [0x6000, 0x6000, 0x0, 0x8000_0000_0000_000C, 0x0]
+        );
+        assert_eq!(r.rflags & STATUS_MASK, 0x95);
+        assert_eq!(
+            &emu.mem.read_page(0x5000).unwrap()[0xFF8..],
+            b"\x80\x00\xFF\xFF\x00\x00\x00\x80"
+        );
+    }
+
+    #[test]
+    fn semantics32() {
+        // 0:  31 c0                   xor    eax, eax
+        // 2:  b9 05 00 00 00          mov    ecx, 0x5
+        // 7:  01 c8                   add    eax, ecx
+        // 9:  49                      dec    ecx
+        // a:  75 fb                   jne    0x7
+        // c:  e8 02 00 00 00          call   0x13
+        // 11: eb 0c                   jmp    0x1f
+        // 13: 55                      push   ebp
+        // 14: 89 e5                   mov    ebp, esp
+        // 16: c7 45 fc 41 00 00 00    mov    DWORD PTR [ebp-0x4], 0x41
+        // 1d: 5d                      pop    ebp
+        // 1e: c3                      ret
+        // 1f: 83 f8 0f                cmp    eax, 0xf
+        // 22: 73 01                   jae    0x25
+        // 24: 90                      nop
+        // 25: 90                      nop
+        let code = b"\x31\xC0\xB9\x05\x00\x00\x00\x01\xC8\x49\x75\xFB\xE8\x02\x00\x00\x00\xEB\x0C\x55\x89\xE5\xC7\x45\xFC\x41\x00\x00\x00\x5D\xC3\x83\xF8\x0F\x73\x01\x90\x90";
+
+        let mut emu = emu_from_shellcode32(code);
+        for _ in 0..27 {
+            step(&mut emu);
+        }
+
+        let r = &emu.reg;
+        assert_eq!(r.rip, 0x26);
+        assert_eq!([r.rax, r.rbx, r.rcx, r.rdx], [0xF, 0x0, 0x0, 0x0]);
+        assert_eq!([r.rsp, r.rbp], [0x6000, 0x6000]);
+        assert_eq!(r.rflags & STATUS_MASK, 0x44);
+        // the local, the saved ebp, and the return address.
+        assert_eq!(
+            &emu.mem.read_page(0x5000).unwrap()[0xFF4..],
+            b"\x41\x00\x00\x00\x00\x60\x00\x00\x11\x00\x00\x00"
+        );
+    }
+
+    #[test]
+    fn ir_only() {
+        // instructions beyond the basic integer arithmetic.
+        //
+        // 0:  b8 81 00 00 00          mov    eax, 0x81
+        // 5:  d0 e0                   shl    al, 1
+        // 7:  0f 92 c3                setb   bl
+        // a:  b9 03 00 00 00          mov    ecx, 0x3
+        // f:  d3 f8                   sar    eax, cl
+        // 11: 0f b6 d3                movzx  edx, bl
+        // 14: 48 0f be f0             movsx  rsi, al
+        // 18: 6b f6 fd                imul   esi, esi, 0xfffffffd
+        // 1b: 83 fe 0a                cmp    esi, 0xa
+        // 1e: 0f 4c fe                cmovl  edi, esi
+        // 21: 66 c1 c2 09             rol    dx, 0x9
+        // 25: 0f ba e2 09             bt     edx, 0x9
+        let mut emu = emu_from_shellcode64(b"\xB8\x81\x00\x00\x00\xD0\xE0\x0F\x92\xC3\xB9\x03\x00\x00\x00\xD3\xF8\x0F\xB6\xD3\x48\x0F\xBE\xF0\x6B\xF6\xFD\x83\xFE\x0A\x0F\x4C\xFE\x66\xC1\xC2\x09\x0F\xBA\xE2\x09");
+        emu.reg.rdi = 0xFFFF_FFFF_FFFF_FFFF;
+
+        step(&mut emu); // mov
+        step(&mut emu); // shl
+        assert_eq!(emu.reg.rax, 0x02);
+        assert!(emu.reg.cf());
+        assert!(emu.reg.of());
+
+        step(&mut emu); // setb
+        assert_eq!(emu.reg.rbx & 0xFF, 1);
+
+        step(&mut emu); // mov
+        step(&mut emu); // sar
+        assert_eq!(emu.reg.rax, 0x0);
+        assert!(!emu.reg.cf());
+        assert!(emu.reg.zf());
+
+        step(&mut emu); // movzx
+        assert_eq!(emu.reg.rdx, 1);
+
+        step(&mut emu); // movsx
+        assert_eq!(emu.reg.rsi, 0);
+
+        emu.reg.rsi = 0x10;
+        step(&mut emu); // imul
+        assert_eq!(emu.reg.rsi, (-0x30i32) as u32 as u64);
+        assert!(!emu.reg.cf());
+
+        step(&mut emu); // cmp
+        step(&mut emu); // cmovl
+        assert_eq!(emu.reg.rdi, (-0x30i32) as u32 as u64);
+
+        step(&mut emu); // rol
+        assert_eq!(emu.reg.rdx, 0x200);
+        assert!(!emu.reg.cf());
+
+        step(&mut emu); // bt
+        assert!(emu.reg.cf());
+        assert_eq!(emu.reg.rip, 0x29);
+    }
+
+    #[test]
+    fn multiply_divide() {
+        // 0:  b8 00 00 01 00          mov    eax, 0x10000
+        // 5:  b9 01 00 01 00          mov    ecx, 0x10001
+        // a:  f7 e1                   mul    ecx
+        // c:  bb 07 00 00 00          mov    ebx, 0x7
+        // 11: f7 f3                   div    ebx
+        // 13: be fd ff ff ff          mov    esi, 0xfffffffd
+        // 18: b8 05 00 00 00          mov    eax, 0x5
+        // 1d: f7 ee                   imul   esi
+        // 1f: 0f c8                   bswap  eax
+        // 21: 0f ba e8 04             bts    eax, 0x4
+        let mut emu = emu_from_shellcode64(b"\xB8\x00\x00\x01\x00\xB9\x01\x00\x01\x00\xF7\xE1\xBB\x07\x00\x00\x00\xF7\xF3\xBE\xFD\xFF\xFF\xFF\xB8\x05\x00\x00\x00\xF7\xEE\x0F\xC8\x0F\xBA\xE8\x04");
+
+        step(&mut emu); // mov
+        step(&mut emu); // mov
+        step(&mut emu); // mul
+        assert_eq!(emu.reg.rax, 0x10000);
+        assert_eq!(emu.reg.rdx, 0x1);
+        assert!(emu.reg.cf());
+        assert!(emu.reg.of());
+
+        step(&mut emu); // mov
+        step(&mut emu); // div
+        assert_eq!(emu.reg.rax, 0x1_0001_0000 / 7);
+        assert_eq!(emu.reg.rdx, 0x1_0001_0000 % 7);
+
+        step(&mut emu); // mov
+        step(&mut emu); // mov
+        step(&mut emu); // imul
+        assert_eq!(emu.reg.rax, (-15i32) as u32 as u64);
+        assert_eq!(emu.reg.rdx, 0xFFFF_FFFF);
+        assert!(!emu.reg.cf());
+
+        step(&mut emu); // bswap
+        assert_eq!(emu.reg.rax, 0xF1FF_FFFF);
+
+        step(&mut emu); // bts
+        assert!(emu.reg.cf());
+        assert_eq!(emu.reg.rax, 0xF1FF_FFFF);
+        assert_eq!(emu.reg.rip, 0x25);
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        // 0:  f7 f3                   div    ebx
+        let mut emu = emu_from_shellcode64(b"\xF7\xF3");
+        let insn = emu.fetch().unwrap();
+        assert!(emu.execute(&insn).is_err());
+    }
+}
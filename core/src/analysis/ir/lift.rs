@@ -0,0 +1,905 @@
+//! Lift x86/x64 instructions decoded by Zydis into IR statements.
+//!
+//! This covers the common integer instructions emitted by compilers:
+//! data movement, arithmetic and logic, shifts and rotates, comparisons,
+//! the stack, and control flow. Other instructions, such as string, division,
+//! and floating point instructions, result in an `UnsupportedInstruction`
+//! error, so that callers can fall back to a conservative approximation.
+use anyhow::Result;
+use thiserror::Error;
+use zydis::{DecodedInstruction, DecodedOperand, Mnemonic, OperandType, Register, RegisterClass};
+
+use super::{BinaryOp, Expr, Flag, Instruction, Size, Stmt, UnaryOp, Var};
+use crate::{arch::Arch, VA};
+
+#[derive(Debug, Error)]
+pub enum LiftError {
+    #[error("unsupported instruction: {0:?}")]
+    UnsupportedInstruction(Mnemonic),
+    #[error("unsupported operand")]
+    UnsupportedOperand,
+}
+
+struct Lifter<'a> {
+    mode:  zydis::MachineMode,
+    /// the size of a full width register and of the stack slots.
+    width: Size,
+    va:    VA,
+    insn:  &'a DecodedInstruction,
+    stmts: Vec<Stmt>,
+    temps: u16,
+}
+
+impl<'a> Lifter<'a> {
+    fn next_va(&self) -> VA {
+        self.va + self.insn.length as u64
+    }
+
+    fn sp(&self) -> Var {
+        Var::Reg(if self.width == 64 { Register::RSP } else { Register::ESP })
+    }
+
+    fn bp(&self) -> Var {
+        Var::Reg(if self.width == 64 { Register::RBP } else { Register::EBP })
+    }
+
+    fn assign(&mut self, dst: Var, value: Expr) {
+        self.stmts.push(Stmt::Assign { dst, value });
+    }
+
+    fn set_flag(&mut self, flag: Flag, value: Expr) {
+        self.assign(Var::Flag(flag), value);
+    }
+
+    fn undefine_flag(&mut self, flag: Flag) {
+        self.stmts.push(Stmt::Undefine { dst: Var::Flag(flag) });
+    }
+
+    /// assign the value to a new temporary, and return a reference to it.
+    /// use this when a value is used multiple times,
+    /// so that memory isn't read more than once.
+    fn temp(&mut self, value: Expr) -> Expr {
+        if let Expr::Const { .. } | Expr::Var { var: Var::Temp(_), .. } = value {
+            return value;
+        }
+
+        let size = value.size();
+        let var = Var::Temp(self.temps);
+        self.temps += 1;
+        self.assign(var, value);
+        Expr::var(var, size)
+    }
+
+    fn read_full_reg(&self, reg: Register) -> Expr {
+        Expr::var(Var::Reg(reg.get_largest_enclosing(self.mode)), self.width)
+    }
+
+    fn check_reg(&self, reg: Register) -> Result<()> {
+        match reg.get_class() {
+            RegisterClass::GPR8 | RegisterClass::GPR16 | RegisterClass::GPR32 | RegisterClass::GPR64 => Ok(()),
+            _ => Err(LiftError::UnsupportedOperand.into()),
+        }
+    }
+
+    fn is_high_byte(reg: Register) -> bool {
+        matches!(reg, Register::AH | Register::BH | Register::CH | Register::DH)
+    }
+
+    fn read_reg(&self, reg: Register) -> Result<Expr> {
+        self.check_reg(reg)?;
+
+        let size = reg.get_width(self.mode) as Size;
+        let full = self.read_full_reg(reg);
+        if Lifter::is_high_byte(reg) {
+            Ok(Expr::extract(full, 8, 8))
+        } else {
+            Ok(Expr::extract(full, 0, size))
+        }
+    }
+
+    fn write_reg(&mut self, reg: Register, value: Expr) -> Result<()> {
+        self.check_reg(reg)?;
+
+        let dst = reg.get_largest_enclosing(self.mode);
+        let size = reg.get_width(self.mode) as Size;
+        let full = self.read_full_reg(reg);
+
+        let value = if Lifter::is_high_byte(reg) {
+            Expr::insert(full, 8, value)
+        } else if size == 32 {
+            // on x64, writes to 32-bit registers clear the upper bits.
+            Expr::zero_extend(value, self.width)
+        } else {
+            Expr::insert(full, 0, value)
+        };
+
+        self.assign(Var::Reg(dst), value);
+        Ok(())
+    }
+
+    /// compute the address referenced by the memory operand.
+    fn address(&self, op: &DecodedOperand, with_segment: bool) -> Result<Expr> {
+        let size = self.insn.address_width as Size;
+        let disp = op.mem.disp.displacement as u64;
+
+        if matches!(op.mem.base, Register::RIP | Register::EIP) {
+            return Ok(Expr::constant(self.next_va().wrapping_add(disp), size));
+        }
+
+        let mut terms = vec![];
+
+        if with_segment && matches!(op.mem.segment, Register::FS | Register::GS) {
+            let base = Expr::var(Var::SegmentBase(op.mem.segment), self.width);
+            terms.push(if size < self.width {
+                Expr::extract(base, 0, size)
+            } else {
+                Expr::zero_extend(base, size)
+            });
+        }
+
+        if op.mem.base != Register::NONE {
+            terms.push(self.read_reg(op.mem.base)?);
+        }
+
+        if op.mem.index != Register::NONE {
+            let index = self.read_reg(op.mem.index)?;
+            terms.push(match op.mem.scale {
+                0 | 1 => index,
+                scale => Expr::binary(BinaryOp::Mul, index, Expr::constant(scale as u64, size)),
+            });
+        }
+
+        if op.mem.disp.has_displacement && disp != 0 {
+            terms.push(Expr::constant(disp, size));
+        }
+
+        let mut terms = terms.into_iter();
+        Ok(match terms.next() {
+            None => Expr::constant(0, size),
+            Some(first) => terms.fold(first, |acc, term| Expr::binary(BinaryOp::Add, acc, term)),
+        })
+    }
+
+    fn read_operand(&self, op: &DecodedOperand) -> Result<Expr> {
+        match op.ty {
+            OperandType::REGISTER => self.read_reg(op.reg),
+            OperandType::IMMEDIATE => {
+                if op.imm.is_relative {
+                    Ok(Expr::constant(self.next_va().wrapping_add(op.imm.value), self.width))
+                } else {
+                    Ok(Expr::constant(op.imm.value, op.size))
+                }
+            }
+            OperandType::MEMORY => Ok(Expr::load(self.address(op, true)?, op.size)),
+            _ => Err(LiftError::UnsupportedOperand.into()),
+        }
+    }
+
+    /// read the operand, with immediates extended to the given size.
+    /// zydis reports immediates already sign extended.
+    fn read_operand_sized(&self, op: &DecodedOperand, size: Size) -> Result<Expr> {
+        if op.ty == OperandType::IMMEDIATE && !op.imm.is_relative {
+            Ok(Expr::constant(op.imm.value, size))
+        } else {
+            self.read_operand(op)
+        }
+    }
+
+    fn write_operand(&mut self, op: &DecodedOperand, value: Expr) -> Result<()> {
+        match op.ty {
+            OperandType::REGISTER => self.write_reg(op.reg, value),
+            OperandType::MEMORY => {
+                let addr = self.address(op, true)?;
+                self.stmts.push(Stmt::Store { addr, value });
+                Ok(())
+            }
+            _ => Err(LiftError::UnsupportedOperand.into()),
+        }
+    }
+
+    fn push(&mut self, value: Expr) {
+        let size = value.size();
+        let sp = self.sp();
+        let new_sp = Expr::binary(
+            BinaryOp::Sub,
+            Expr::var(sp, self.width),
+            Expr::constant((size / 8) as u64, self.width),
+        );
+        self.assign(sp, new_sp);
+        self.stmts.push(Stmt::Store {
+            addr: Expr::var(sp, self.width),
+            value,
+        });
+    }
+
+    /// pop a value from the stack, and then adjust it by the additional bytes.
+    fn pop(&mut self, size: Size, extra: u64) -> Expr {
+        let sp = self.sp();
+        let value = self.temp(Expr::load(Expr::var(sp, self.width), size));
+        let new_sp = Expr::binary(
+            BinaryOp::Add,
+            Expr::var(sp, self.width),
+            Expr::constant((size / 8) as u64 + extra, self.width),
+        );
+        self.assign(sp, new_sp);
+        value
+    }
+
+    /// the accumulator and data registers used by multiplication and division
+    /// with the given operand size.
+    fn accumulator(&self, size: Size) -> (Register, Register) {
+        match size {
+            8 => (Register::AL, Register::DL),
+            16 => (Register::AX, Register::DX),
+            32 => (Register::EAX, Register::EDX),
+            _ => (Register::RAX, Register::RDX),
+        }
+    }
+
+    /// set ZF, SF, and PF from the result.
+    fn set_result_flags(&mut self, result: &Expr) {
+        let size = result.size();
+        self.set_flag(
+            Flag::ZF,
+            Expr::binary(BinaryOp::Eq, result.clone(), Expr::constant(0, size)),
+        );
+        self.set_flag(Flag::SF, Expr::msb(result.clone()));
+        self.set_flag(
+            Flag::PF,
+            Expr::unary(UnaryOp::Parity, Expr::extract(result.clone(), 0, 8)),
+        );
+    }
+
+    /// the auxiliary carry out of bit 3.
+    fn aux_carry(a: &Expr, b: &Expr, result: &Expr) -> Expr {
+        let x = Expr::binary(
+            BinaryOp::Xor,
+            Expr::binary(BinaryOp::Xor, a.clone(), b.clone()),
+            result.clone(),
+        );
+        Expr::extract(x, 4, 1)
+    }
+
+    fn add_overflow(a: &Expr, b: &Expr, result: &Expr) -> Expr {
+        Expr::msb(Expr::binary(
+            BinaryOp::And,
+            Expr::binary(BinaryOp::Xor, a.clone(), result.clone()),
+            Expr::binary(BinaryOp::Xor, b.clone(), result.clone()),
+        ))
+    }
+
+    fn sub_overflow(a: &Expr, b: &Expr, result: &Expr) -> Expr {
+        Expr::msb(Expr::binary(
+            BinaryOp::And,
+            Expr::binary(BinaryOp::Xor, a.clone(), b.clone()),
+            Expr::binary(BinaryOp::Xor, a.clone(), result.clone()),
+        ))
+    }
+
+    /// the condition tested by a conditional instruction,
+    /// given the condition code suffix of its mnemonic.
+    fn condition(&self, cc: &str) -> Result<Expr> {
+        use BinaryOp::*;
+        let not = |e: Expr| Expr::unary(UnaryOp::Not, e);
+        let cf = Expr::flag(Flag::CF);
+        let zf = Expr::flag(Flag::ZF);
+        let sf = Expr::flag(Flag::SF);
+        let of = Expr::flag(Flag::OF);
+        let pf = Expr::flag(Flag::PF);
+        let lt = Expr::binary(Ne, sf.clone(), of.clone());
+
+        Ok(match cc {
+            "O" => of,
+            "NO" => not(of),
+            "B" => cf,
+            "NB" => not(cf),
+            "Z" => zf,
+            "NZ" => not(zf),
+            "BE" => Expr::binary(Or, cf, zf),
+            "NBE" => not(Expr::binary(Or, cf, zf)),
+            "S" => sf,
+            "NS" => not(sf),
+            "P" => pf,
+            "NP" => not(pf),
+            "L" => lt,
+            "NL" => not(lt),
+            "LE" => Expr::binary(Or, zf, lt),
+            "NLE" => not(Expr::binary(Or, zf, lt)),
+            _ => return Err(LiftError::UnsupportedInstruction(self.insn.mnemonic).into()),
+        })
+    }
+
+    fn lift(&mut self) -> Result<()> {
+        use Mnemonic::*;
+
+        let insn = self.insn;
+        let ops: Vec<DecodedOperand> = insn.operands[..insn.operand_count as usize]
+            .iter()
+            // implicit operands, like the `cl` in `shl eax, cl`, are visible, but hidden ones are not.
+            .filter(|op| op.visibility != zydis::OperandVisibility::HIDDEN)
+            .cloned()
+            .collect();
+
+        let mnemonic = format!("{:?}", insn.mnemonic);
+
+        match insn.mnemonic {
+            NOP => {}
+
+            MOV => {
+                let value = self.read_operand_sized(&ops[1], ops[0].size)?;
+                self.write_operand(&ops[0], value)?;
+            }
+
+            MOVZX => {
+                let value = Expr::zero_extend(self.read_operand(&ops[1])?, ops[0].size);
+                self.write_operand(&ops[0], value)?;
+            }
+
+            MOVSX | MOVSXD => {
+                let value = Expr::sign_extend(self.read_operand(&ops[1])?, ops[0].size);
+                self.write_operand(&ops[0], value)?;
+            }
+
+            LEA => {
+                let addr = self.address(&ops[1], false)?;
+                let value = if addr.size() > ops[0].size {
+                    Expr::extract(addr, 0, ops[0].size)
+                } else {
+                    Expr::zero_extend(addr, ops[0].size)
+                };
+                self.write_operand(&ops[0], value)?;
+            }
+
+            XCHG => {
+                let a = self.temp(self.read_operand(&ops[0])?);
+                let b = self.temp(self.read_operand(&ops[1])?);
+                self.write_operand(&ops[0], b)?;
+                self.write_operand(&ops[1], a)?;
+            }
+
+            PUSH => {
+                let size = insn.operand_width as Size;
+                let value = self.temp(self.read_operand_sized(&ops[0], size)?);
+                self.push(value);
+            }
+
+            POP => {
+                let value = self.pop(insn.operand_width as Size, 0);
+                self.write_operand(&ops[0], value)?;
+            }
+
+            LEAVE => {
+                let bp = self.bp();
+                let sp = self.sp();
+                self.assign(sp, Expr::var(bp, self.width));
+                let value = self.pop(self.width, 0);
+                self.assign(bp, value);
+            }
+
+            ADD | ADC | SUB | SBB | CMP => {
+                let size = ops[0].size;
+                let a = self.temp(self.read_operand(&ops[0])?);
+                let b = self.temp(self.read_operand_sized(&ops[1], size)?);
+                let is_add = matches!(insn.mnemonic, ADD | ADC);
+                let with_carry = matches!(insn.mnemonic, ADC | SBB);
+                let op = if is_add { BinaryOp::Add } else { BinaryOp::Sub };
+
+                let mut result = Expr::binary(op, a.clone(), b.clone());
+                if with_carry {
+                    let carry = Expr::zero_extend(Expr::flag(Flag::CF), size);
+                    result = Expr::binary(op, result, carry);
+                }
+                let result = self.temp(result);
+
+                let carry = if is_add {
+                    Expr::binary(BinaryOp::Ult, result.clone(), a.clone())
+                } else {
+                    Expr::binary(BinaryOp::Ult, a.clone(), b.clone())
+                };
+                let carry = if with_carry {
+                    // with a carry in, the result may also wrap around to exactly the input.
+                    let wrapped = if is_add {
+                        Expr::binary(BinaryOp::Eq, result.clone(), a.clone())
+                    } else {
+                        Expr::binary(BinaryOp::Eq, a.clone(), b.clone())
+                    };
+                    Expr::binary(
+                        BinaryOp::Or,
+                        carry,
+                        Expr::binary(BinaryOp::And, Expr::flag(Flag::CF), wrapped),
+                    )
+                } else {
+                    carry
+                };
+
+                let overflow = if is_add {
+                    Lifter::add_overflow(&a, &b, &result)
+                } else {
+                    Lifter::sub_overflow(&a, &b, &result)
+                };
+                let aux = Lifter::aux_carry(&a, &b, &result);
+
+                // compute all the flags before assigning them,
+                // since ADC and SBB read the incoming carry.
+                let carry = self.temp(carry);
+                self.set_flag(Flag::OF, overflow);
+                self.set_flag(Flag::AF, aux);
+                self.set_result_flags(&result);
+                self.set_flag(Flag::CF, carry);
+
+                if insn.mnemonic != CMP {
+                    self.write_operand(&ops[0], result)?;
+                }
+            }
+
+            AND | OR | XOR | TEST => {
+                let size = ops[0].size;
+                let a = self.read_operand(&ops[0])?;
+                let b = self.read_operand_sized(&ops[1], size)?;
+                let op = match insn.mnemonic {
+                    OR => BinaryOp::Or,
+                    XOR => BinaryOp::Xor,
+                    _ => BinaryOp::And,
+                };
+                let result = self.temp(Expr::binary(op, a, b));
+
+                self.set_flag(Flag::CF, Expr::constant(0, 1));
+                self.set_flag(Flag::OF, Expr::constant(0, 1));
+                self.undefine_flag(Flag::AF);
+                self.set_result_flags(&result);
+
+                if insn.mnemonic != TEST {
+                    self.write_operand(&ops[0], result)?;
+                }
+            }
+
+            INC | DEC => {
+                let size = ops[0].size;
+                let a = self.temp(self.read_operand(&ops[0])?);
+                let b = Expr::constant(1, size);
+                let (op, overflow): (_, fn(&Expr, &Expr, &Expr) -> Expr) = if insn.mnemonic == INC {
+                    (BinaryOp::Add, Lifter::add_overflow)
+                } else {
+                    (BinaryOp::Sub, Lifter::sub_overflow)
+                };
+                let result = self.temp(Expr::binary(op, a.clone(), b.clone()));
+
+                // CF is not affected.
+                self.set_flag(Flag::OF, overflow(&a, &b, &result));
+                self.set_flag(Flag::AF, Lifter::aux_carry(&a, &b, &result));
+                self.set_result_flags(&result);
+                self.write_operand(&ops[0], result)?;
+            }
+
+            NEG => {
+                let size = ops[0].size;
+                let a = self.temp(self.read_operand(&ops[0])?);
+                let zero = Expr::constant(0, size);
+                let result = self.temp(Expr::unary(UnaryOp::Neg, a.clone()));
+
+                self.set_flag(Flag::CF, Expr::binary(BinaryOp::Ne, a.clone(), zero.clone()));
+                self.set_flag(Flag::OF, Lifter::sub_overflow(&zero, &a, &result));
+                self.set_flag(Flag::AF, Lifter::aux_carry(&zero, &a, &result));
+                self.set_result_flags(&result);
+                self.write_operand(&ops[0], result)?;
+            }
+
+            NOT => {
+                let value = Expr::unary(UnaryOp::Not, self.read_operand(&ops[0])?);
+                self.write_operand(&ops[0], value)?;
+            }
+
+            SHL | SHR | SAR | ROL | ROR => self.lift_shift(&ops)?,
+
+            IMUL if ops.len() >= 2 => {
+                let size = ops[0].size;
+                let (a, b) = if ops.len() == 3 {
+                    (self.read_operand(&ops[1])?, self.read_operand_sized(&ops[2], size)?)
+                } else {
+                    (self.read_operand(&ops[0])?, self.read_operand_sized(&ops[1], size)?)
+                };
+                let a = self.temp(a);
+                let b = self.temp(b);
+                let result = self.temp(Expr::binary(BinaryOp::Mul, a.clone(), b.clone()));
+
+                if size < 64 {
+                    // the product overflows if it doesn't fit in the destination.
+                    let full = Expr::binary(BinaryOp::Mul, Expr::sign_extend(a, 64), Expr::sign_extend(b, 64));
+                    let overflow = self.temp(Expr::binary(BinaryOp::Ne, Expr::sign_extend(result.clone(), 64), full));
+                    self.set_flag(Flag::CF, overflow.clone());
+                    self.set_flag(Flag::OF, overflow);
+                } else {
+                    self.undefine_flag(Flag::CF);
+                    self.undefine_flag(Flag::OF);
+                }
+                for &flag in [Flag::SF, Flag::ZF, Flag::AF, Flag::PF].iter() {
+                    self.undefine_flag(flag);
+                }
+
+                self.write_operand(&ops[0], result)?;
+            }
+
+            MUL | IMUL if ops.len() == 1 => {
+                let size = ops[0].size;
+                let (a, d) = self.accumulator(size);
+                let a_value = self.read_reg(a)?;
+                let b = self.temp(self.read_operand(&ops[0])?);
+                let a_value = self.temp(a_value);
+
+                let (hi_op, overflow) = if insn.mnemonic == MUL {
+                    (BinaryOp::UMulHi, None)
+                } else {
+                    (BinaryOp::SMulHi, Some(()))
+                };
+                let lo = self.temp(Expr::binary(BinaryOp::Mul, a_value.clone(), b.clone()));
+                let hi = self.temp(Expr::binary(hi_op, a_value, b));
+
+                // the flags are set when the high half is significant.
+                let significant = match overflow {
+                    None => Expr::binary(BinaryOp::Ne, hi.clone(), Expr::constant(0, size)),
+                    Some(()) => Expr::binary(
+                        BinaryOp::Ne,
+                        hi.clone(),
+                        Expr::binary(BinaryOp::Sar, lo.clone(), Expr::constant((size - 1) as u64, size)),
+                    ),
+                };
+                let significant = self.temp(significant);
+                self.set_flag(Flag::CF, significant.clone());
+                self.set_flag(Flag::OF, significant);
+                for &flag in [Flag::SF, Flag::ZF, Flag::AF, Flag::PF].iter() {
+                    self.undefine_flag(flag);
+                }
+
+                if size == 8 {
+                    // ax = ah:al
+                    self.write_reg(Register::AL, lo)?;
+                    self.write_reg(Register::AH, hi)?;
+                } else {
+                    self.write_reg(a, lo)?;
+                    self.write_reg(d, hi)?;
+                }
+            }
+
+            DIV | IDIV if ops[0].size <= 32 => {
+                // the dividend is twice the size of the divisor,
+                // so 64-bit division would require 128-bit values, which we don't support.
+                let size = ops[0].size;
+                let signed = insn.mnemonic == IDIV;
+                let extend = |e: Expr, size: Size| {
+                    if signed {
+                        Expr::sign_extend(e, size)
+                    } else {
+                        Expr::zero_extend(e, size)
+                    }
+                };
+
+                let (a, d) = self.accumulator(size);
+                let dividend = if size == 8 {
+                    self.read_reg(Register::AX)?
+                } else {
+                    // d:a
+                    Expr::binary(
+                        BinaryOp::Or,
+                        Expr::binary(
+                            BinaryOp::Shl,
+                            Expr::zero_extend(self.read_reg(d)?, size * 2),
+                            Expr::constant(size as u64, size * 2),
+                        ),
+                        Expr::zero_extend(self.read_reg(a)?, size * 2),
+                    )
+                };
+                let dividend = self.temp(dividend);
+                let divisor = self.temp(extend(self.read_operand(&ops[0])?, size * 2));
+
+                // quotients that overflow the destination are not detected.
+                let (div, rem) = if signed {
+                    (BinaryOp::SDiv, BinaryOp::SRem)
+                } else {
+                    (BinaryOp::UDiv, BinaryOp::URem)
+                };
+                let quotient = self.temp(Expr::extract(
+                    Expr::binary(div, dividend.clone(), divisor.clone()),
+                    0,
+                    size,
+                ));
+                let remainder = self.temp(Expr::extract(Expr::binary(rem, dividend, divisor), 0, size));
+
+                for &flag in [Flag::CF, Flag::OF, Flag::SF, Flag::ZF, Flag::AF, Flag::PF].iter() {
+                    self.undefine_flag(flag);
+                }
+
+                if size == 8 {
+                    self.write_reg(Register::AL, quotient)?;
+                    self.write_reg(Register::AH, remainder)?;
+                } else {
+                    self.write_reg(a, quotient)?;
+                    self.write_reg(d, remainder)?;
+                }
+            }
+
+            BSWAP => {
+                let size = ops[0].size;
+                let value = self.temp(self.read_operand(&ops[0])?);
+                let swapped = (0..size / 8)
+                    .map(|i| {
+                        let byte = Expr::zero_extend(Expr::extract(value.clone(), i * 8, 8), size);
+                        Expr::binary(BinaryOp::Shl, byte, Expr::constant((size - 8 - i * 8) as u64, size))
+                    })
+                    .fold(Expr::constant(0, size), |acc, byte| {
+                        Expr::binary(BinaryOp::Or, acc, byte)
+                    });
+                self.write_operand(&ops[0], swapped)?;
+            }
+
+            BT | BTS | BTR | BTC if ops[0].ty == OperandType::REGISTER || ops[1].ty == OperandType::IMMEDIATE => {
+                let size = ops[0].size;
+                let a = self.read_operand(&ops[0])?;
+                let b = self.read_operand_sized(&ops[1], size)?;
+                let a = self.temp(a);
+                let offset = self.temp(Expr::binary(BinaryOp::And, b, Expr::constant((size - 1) as u64, size)));
+                self.set_flag(
+                    Flag::CF,
+                    Expr::extract(Expr::binary(BinaryOp::Shr, a.clone(), offset.clone()), 0, 1),
+                );
+                for &flag in [Flag::OF, Flag::SF, Flag::AF, Flag::PF].iter() {
+                    self.undefine_flag(flag);
+                }
+
+                let bit = Expr::binary(BinaryOp::Shl, Expr::constant(1, size), offset);
+                let value = match insn.mnemonic {
+                    BTS => Some(Expr::binary(BinaryOp::Or, a, bit)),
+                    BTR => Some(Expr::binary(BinaryOp::And, a, Expr::unary(UnaryOp::Not, bit))),
+                    BTC => Some(Expr::binary(BinaryOp::Xor, a, bit)),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    self.write_operand(&ops[0], value)?;
+                }
+            }
+
+            CBW | CWDE | CDQE => {
+                let (src, dst) = match insn.mnemonic {
+                    CBW => (Register::AL, Register::AX),
+                    CWDE => (Register::AX, Register::EAX),
+                    _ => (Register::EAX, Register::RAX),
+                };
+                let value = Expr::sign_extend(self.read_reg(src)?, insn.operand_width as Size);
+                self.write_reg(dst, value)?;
+            }
+
+            CWD | CDQ | CQO => {
+                let (src, dst) = match insn.operand_width {
+                    16 => (Register::AX, Register::DX),
+                    32 => (Register::EAX, Register::EDX),
+                    _ => (Register::RAX, Register::RDX),
+                };
+                let size = insn.operand_width as Size;
+                let value = Expr::binary(
+                    BinaryOp::Sar,
+                    self.read_reg(src)?,
+                    Expr::constant((size - 1) as u64, size),
+                );
+                self.write_reg(dst, value)?;
+            }
+
+            CLC => self.set_flag(Flag::CF, Expr::constant(0, 1)),
+            STC => self.set_flag(Flag::CF, Expr::constant(1, 1)),
+            CMC => self.set_flag(Flag::CF, Expr::unary(UnaryOp::Not, Expr::flag(Flag::CF))),
+            CLD => self.set_flag(Flag::DF, Expr::constant(0, 1)),
+            STD => self.set_flag(Flag::DF, Expr::constant(1, 1)),
+
+            CALL => {
+                let target = self.temp(self.read_operand(&ops[0])?);
+                let ret = Expr::constant(self.next_va(), self.width);
+                self.push(ret);
+                self.stmts.push(Stmt::Call { target });
+            }
+
+            RET => {
+                let extra = ops.first().map(|op| op.imm.value).unwrap_or(0);
+                let target = self.pop(self.width, extra);
+                self.stmts.push(Stmt::Return { target });
+            }
+
+            JMP => {
+                let target = self.read_operand(&ops[0])?;
+                self.stmts.push(Stmt::Jump { target });
+            }
+
+            JCXZ | JECXZ | JRCXZ => {
+                let reg = match insn.mnemonic {
+                    JCXZ => Register::CX,
+                    JECXZ => Register::ECX,
+                    _ => Register::RCX,
+                };
+                let value = self.read_reg(reg)?;
+                let size = value.size();
+                let cond = Expr::binary(BinaryOp::Eq, value, Expr::constant(0, size));
+                let target = self.read_operand(&ops[0])?;
+                self.stmts.push(Stmt::Branch { cond, target });
+            }
+
+            LOOP => {
+                let reg = match insn.address_width {
+                    16 => Register::CX,
+                    32 => Register::ECX,
+                    _ => Register::RCX,
+                };
+                let value = self.read_reg(reg)?;
+                let size = value.size();
+                let count = self.temp(Expr::binary(BinaryOp::Sub, value, Expr::constant(1, size)));
+                self.write_reg(reg, count.clone())?;
+                let cond = Expr::binary(BinaryOp::Ne, count, Expr::constant(0, size));
+                let target = self.read_operand(&ops[0])?;
+                self.stmts.push(Stmt::Branch { cond, target });
+            }
+
+            _ if mnemonic.starts_with('J') => {
+                let cond = self.condition(&mnemonic[1..])?;
+                let target = self.read_operand(&ops[0])?;
+                self.stmts.push(Stmt::Branch { cond, target });
+            }
+
+            _ if mnemonic.starts_with("SET") => {
+                let cond = self.condition(&mnemonic[3..])?;
+                self.write_operand(&ops[0], Expr::zero_extend(cond, 8))?;
+            }
+
+            _ if mnemonic.starts_with("CMOV") => {
+                let cond = self.condition(&mnemonic[4..])?;
+                let value = Expr::ite(cond, self.read_operand(&ops[1])?, self.read_operand(&ops[0])?);
+                self.write_operand(&ops[0], value)?;
+            }
+
+            m => return Err(LiftError::UnsupportedInstruction(m).into()),
+        }
+
+        Ok(())
+    }
+
+    fn lift_shift(&mut self, ops: &[DecodedOperand]) -> Result<()> {
+        use Mnemonic::*;
+
+        let size = ops[0].size;
+        let a = self.temp(self.read_operand(&ops[0])?);
+
+        // the count is masked to 5 bits, or 6 bits for 64-bit operands.
+        let count_mask: u64 = if size == 64 { 0x3F } else { 0x1F };
+        let count = match ops.get(1) {
+            Some(op) => Expr::zero_extend(self.read_operand_sized(op, 8)?, size),
+            None => Expr::constant(1, size),
+        };
+        let count = match count {
+            Expr::Const { value, .. } => Expr::constant(value & count_mask, size),
+            count => self.temp(Expr::binary(BinaryOp::And, count, Expr::constant(count_mask, size))),
+        };
+
+        if let Expr::Const { value: 0, .. } = count {
+            // no effect: not even the flags are updated.
+            return Ok(());
+        }
+
+        let op = match self.insn.mnemonic {
+            SHL => BinaryOp::Shl,
+            SHR => BinaryOp::Shr,
+            SAR => BinaryOp::Sar,
+            ROL => BinaryOp::Rol,
+            _ => BinaryOp::Ror,
+        };
+        let result = self.temp(Expr::binary(op, a.clone(), count.clone()));
+        let one = Expr::constant(1, size);
+
+        // the carry is the last bit shifted out.
+        let carry = match op {
+            BinaryOp::Shl => Expr::extract(
+                Expr::binary(
+                    BinaryOp::Shr,
+                    a.clone(),
+                    Expr::binary(BinaryOp::Sub, Expr::constant(size as u64, size), count.clone()),
+                ),
+                0,
+                1,
+            ),
+            BinaryOp::Shr | BinaryOp::Sar => Expr::extract(
+                Expr::binary(op, a.clone(), Expr::binary(BinaryOp::Sub, count.clone(), one)),
+                0,
+                1,
+            ),
+            BinaryOp::Rol => Expr::extract(result.clone(), 0, 1),
+            _ => Expr::msb(result.clone()),
+        };
+        let carry = self.temp(carry);
+
+        // strictly, the overflow is only defined for 1-bit shifts.
+        let overflow = match op {
+            BinaryOp::Shl | BinaryOp::Rol => Expr::binary(BinaryOp::Xor, Expr::msb(result.clone()), carry.clone()),
+            BinaryOp::Shr => Expr::msb(a),
+            BinaryOp::Sar => Expr::constant(0, 1),
+            _ => Expr::binary(
+                BinaryOp::Xor,
+                Expr::msb(result.clone()),
+                Expr::extract(result.clone(), size - 2, 1),
+            ),
+        };
+
+        // when the count is only known at runtime, and it's zero, the flags are
+        // unchanged.
+        let is_zero = match count {
+            Expr::Const { .. } => None,
+            _ => Some(self.temp(Expr::binary(BinaryOp::Eq, count, Expr::constant(0, size)))),
+        };
+        let guard = |flag: Flag, value: Expr| match &is_zero {
+            Some(is_zero) => Expr::ite(is_zero.clone(), Expr::flag(flag), value),
+            None => value,
+        };
+
+        let overflow = guard(Flag::OF, overflow);
+        let carry = guard(Flag::CF, carry);
+        self.set_flag(Flag::OF, overflow);
+        self.set_flag(Flag::CF, carry);
+
+        // rotates don't affect the other flags.
+        if !matches!(op, BinaryOp::Rol | BinaryOp::Ror) {
+            let zf = guard(
+                Flag::ZF,
+                Expr::binary(BinaryOp::Eq, result.clone(), Expr::constant(0, size)),
+            );
+            let sf = guard(Flag::SF, Expr::msb(result.clone()));
+            let pf = guard(
+                Flag::PF,
+                Expr::unary(UnaryOp::Parity, Expr::extract(result.clone(), 0, 8)),
+            );
+            self.set_flag(Flag::ZF, zf);
+            self.set_flag(Flag::SF, sf);
+            self.set_flag(Flag::PF, pf);
+            self.undefine_flag(Flag::AF);
+        }
+
+        self.write_operand(&ops[0], result)
+    }
+}
+
+/// lift the given instruction at the given address into IR statements.
+///
+/// Errors:
+///   - LiftError::UnsupportedInstruction for instructions outside of the common
+///     integer instruction set.
+///   - LiftError::UnsupportedOperand for operands like segment or vector
+///     registers.
+///
+/// ```
+/// use lancelot::arch::Arch;
+/// use lancelot::test::*;
+/// use lancelot::analysis::ir::lift::lift_insn;
+///
+/// // 0: 50    push rax
+/// let insn = read_insn(&load_shellcode64(b"\x50"), 0x0);
+/// let insn = lift_insn(Arch::X64, 0x0, &insn).unwrap();
+///
+/// assert_eq!(insn.stmts.len(), 3);
+/// assert_eq!(insn.stmts[0].to_string(), "t0 = rax");
+/// assert_eq!(insn.stmts[1].to_string(), "rsp = (rsp - 0x8)");
+/// assert_eq!(insn.stmts[2].to_string(), "[rsp]:64 = t0");
+/// ```
+pub fn lift_insn(arch: Arch, va: VA, insn: &DecodedInstruction) -> Result<Instruction> {
+    let (mode, width) = match arch {
+        Arch::X32 => (zydis::MachineMode::LEGACY_32, 32),
+        Arch::X64 => (zydis::MachineMode::LONG_64, 64),
+    };
+
+    let mut lifter = Lifter {
+        mode,
+        width,
+        va,
+        insn,
+        stmts: vec![],
+        temps: 0,
+    };
+    lifter.lift()?;
+
+    Ok(Instruction {
+        address: va,
+        length:  insn.length as u64,
+        stmts:   lifter.stmts,
+    })
+}
@@ -0,0 +1,418 @@
+//! A small intermediate representation (IR) for x86/x64 instructions,
+//! with explicit side effects.
+//!
+//! Each instruction is lifted to a sequence of statements that assign
+//! registers, flags, and temporaries; store to memory; and transfer control.
+//! Statements compute values via side-effect free expression trees.
+//!
+//! Registers are always accessed at their full width (`rax` on x64, `eax` on
+//! x32), so that analyses don't have to reason about aliasing: a read of `al`
+//! is an extraction of the low byte of `rax`, and a write to `ax` inserts into
+//! `rax`. Flags are individual one-bit variables.
+//!
+//! use `lift::lift_insn` to translate a decoded instruction,
+//! and `eval::evaluate` to execute the statements against some `eval::Machine`,
+//! such as the emulator.
+use crate::VA;
+
+pub mod eval;
+pub mod lift;
+
+/// the size of a value, in bits: 1 (booleans), 8, 16, 32, or 64.
+pub type Size = u16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Flag {
+    CF,
+    PF,
+    AF,
+    ZF,
+    SF,
+    OF,
+    DF,
+}
+
+impl std::fmt::Display for Flag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Flag::CF => write!(f, "cf"),
+            Flag::PF => write!(f, "pf"),
+            Flag::AF => write!(f, "af"),
+            Flag::ZF => write!(f, "zf"),
+            Flag::SF => write!(f, "sf"),
+            Flag::OF => write!(f, "of"),
+            Flag::DF => write!(f, "df"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Var {
+    /// a full width general purpose register, like `rax` (x64) or `eax` (x32),
+    /// or the stack pointer.
+    Reg(zydis::Register),
+    /// the base address of the FS or GS segment.
+    SegmentBase(zydis::Register),
+    Flag(Flag),
+    /// a temporary value, local to the lifted instruction.
+    Temp(u16),
+}
+
+impl std::fmt::Display for Var {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Var::Reg(reg) => write!(f, "{}", reg.get_string().unwrap_or("?")),
+            Var::SegmentBase(reg) => write!(f, "{}.base", reg.get_string().unwrap_or("?")),
+            Var::Flag(flag) => write!(f, "{}", flag),
+            Var::Temp(id) => write!(f, "t{}", id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+    /// 1 if the low byte has an even number of set bits, otherwise 0.
+    Parity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    /// the low half of the product.
+    Mul,
+    /// the high half of the unsigned product.
+    UMulHi,
+    /// the high half of the signed product.
+    SMulHi,
+    UDiv,
+    URem,
+    SDiv,
+    SRem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Sar,
+    Rol,
+    Ror,
+    // the comparisons produce a one bit result.
+    Eq,
+    Ne,
+    Ult,
+    Ule,
+    Slt,
+    Sle,
+}
+
+impl BinaryOp {
+    fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Ult | BinaryOp::Ule | BinaryOp::Slt | BinaryOp::Sle
+        )
+    }
+}
+
+impl std::fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::UMulHi => "*hu",
+            BinaryOp::SMulHi => "*hs",
+            BinaryOp::UDiv => "/u",
+            BinaryOp::URem => "%u",
+            BinaryOp::SDiv => "/s",
+            BinaryOp::SRem => "%s",
+            BinaryOp::And => "&",
+            BinaryOp::Or => "|",
+            BinaryOp::Xor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::Sar => ">>s",
+            BinaryOp::Rol => "rol",
+            BinaryOp::Ror => "ror",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Ult => "<u",
+            BinaryOp::Ule => "<=u",
+            BinaryOp::Slt => "<s",
+            BinaryOp::Sle => "<=s",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// a side-effect free computation of a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Const {
+        value: u64,
+        size:  Size,
+    },
+    Var {
+        var:  Var,
+        size: Size,
+    },
+    Load {
+        addr: Box<Expr>,
+        size: Size,
+    },
+    Unary {
+        op:  UnaryOp,
+        arg: Box<Expr>,
+    },
+    /// both operands have the same size.
+    Binary {
+        op:  BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    ZeroExtend {
+        arg:  Box<Expr>,
+        size: Size,
+    },
+    SignExtend {
+        arg:  Box<Expr>,
+        size: Size,
+    },
+    /// the bits `[lsb, lsb+size)` of the argument.
+    /// with `lsb = 0`, this truncates the argument.
+    Extract {
+        arg:  Box<Expr>,
+        lsb:  u16,
+        size: Size,
+    },
+    /// the argument, with the bits starting at `lsb` replaced by the value.
+    Insert {
+        arg:   Box<Expr>,
+        lsb:   u16,
+        value: Box<Expr>,
+    },
+    /// if the one bit condition is set, then the first value, else the second.
+    Ite {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        els:  Box<Expr>,
+    },
+}
+
+impl Expr {
+    pub fn constant(value: u64, size: Size) -> Expr {
+        Expr::Const {
+            value: value & mask(size),
+            size,
+        }
+    }
+
+    pub fn var(var: Var, size: Size) -> Expr {
+        Expr::Var { var, size }
+    }
+
+    pub fn flag(flag: Flag) -> Expr {
+        Expr::Var {
+            var:  Var::Flag(flag),
+            size: 1,
+        }
+    }
+
+    pub fn load(addr: Expr, size: Size) -> Expr {
+        Expr::Load {
+            addr: Box::new(addr),
+            size,
+        }
+    }
+
+    pub fn unary(op: UnaryOp, arg: Expr) -> Expr {
+        Expr::Unary { op, arg: Box::new(arg) }
+    }
+
+    pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        }
+    }
+
+    pub fn zero_extend(arg: Expr, size: Size) -> Expr {
+        if arg.size() == size {
+            arg
+        } else {
+            Expr::ZeroExtend {
+                arg: Box::new(arg),
+                size,
+            }
+        }
+    }
+
+    pub fn sign_extend(arg: Expr, size: Size) -> Expr {
+        if arg.size() == size {
+            arg
+        } else {
+            Expr::SignExtend {
+                arg: Box::new(arg),
+                size,
+            }
+        }
+    }
+
+    pub fn extract(arg: Expr, lsb: u16, size: Size) -> Expr {
+        if lsb == 0 && arg.size() == size {
+            arg
+        } else {
+            Expr::Extract {
+                arg: Box::new(arg),
+                lsb,
+                size,
+            }
+        }
+    }
+
+    pub fn insert(arg: Expr, lsb: u16, value: Expr) -> Expr {
+        if lsb == 0 && arg.size() == value.size() {
+            value
+        } else {
+            Expr::Insert {
+                arg: Box::new(arg),
+                lsb,
+                value: Box::new(value),
+            }
+        }
+    }
+
+    pub fn ite(cond: Expr, then: Expr, els: Expr) -> Expr {
+        Expr::Ite {
+            cond: Box::new(cond),
+            then: Box::new(then),
+            els:  Box::new(els),
+        }
+    }
+
+    /// the most significant bit of the value.
+    pub fn msb(arg: Expr) -> Expr {
+        let size = arg.size();
+        Expr::extract(arg, size - 1, 1)
+    }
+
+    /// the size of the value computed by the expression, in bits.
+    pub fn size(&self) -> Size {
+        match self {
+            Expr::Const { size, .. } => *size,
+            Expr::Var { size, .. } => *size,
+            Expr::Load { size, .. } => *size,
+            Expr::Unary {
+                op: UnaryOp::Parity, ..
+            } => 1,
+            Expr::Unary { arg, .. } => arg.size(),
+            Expr::Binary { op, lhs, .. } => {
+                if op.is_comparison() {
+                    1
+                } else {
+                    lhs.size()
+                }
+            }
+            Expr::ZeroExtend { size, .. } => *size,
+            Expr::SignExtend { size, .. } => *size,
+            Expr::Extract { size, .. } => *size,
+            Expr::Insert { arg, .. } => arg.size(),
+            Expr::Ite { then, .. } => then.size(),
+        }
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Const { value, .. } => write!(f, "{:#x}", value),
+            Expr::Var { var, .. } => write!(f, "{}", var),
+            Expr::Load { addr, size } => write!(f, "[{}]:{}", addr, size),
+            Expr::Unary { op: UnaryOp::Not, arg } => write!(f, "~{}", arg),
+            Expr::Unary { op: UnaryOp::Neg, arg } => write!(f, "-{}", arg),
+            Expr::Unary {
+                op: UnaryOp::Parity,
+                arg,
+            } => write!(f, "parity({})", arg),
+            Expr::Binary { op, lhs, rhs } => write!(f, "({} {} {})", lhs, op, rhs),
+            Expr::ZeroExtend { arg, size } => write!(f, "zext{}({})", size, arg),
+            Expr::SignExtend { arg, size } => write!(f, "sext{}({})", size, arg),
+            Expr::Extract { arg, lsb, size } => write!(f, "{}[{}:{}]", arg, lsb, lsb + size),
+            Expr::Insert { arg, lsb, value } => write!(f, "insert({}, {}, {})", arg, lsb, value),
+            Expr::Ite { cond, then, els } => write!(f, "({} ? {} : {})", cond, then, els),
+        }
+    }
+}
+
+/// an effect of an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stmt {
+    Assign {
+        dst:   Var,
+        value: Expr,
+    },
+    /// the instruction leaves the variable in an undefined state,
+    /// such as the AF flag after an `and`.
+    Undefine {
+        dst: Var,
+    },
+    /// the size of the store is the size of the value.
+    Store {
+        addr:  Expr,
+        value: Expr,
+    },
+    Jump {
+        target: Expr,
+    },
+    /// if the condition is set, then jump to the target,
+    /// otherwise, fall through to the next instruction.
+    Branch {
+        cond:   Expr,
+        target: Expr,
+    },
+    /// a call to the target.
+    /// the return address has already been pushed by prior statements.
+    Call {
+        target: Expr,
+    },
+    /// a return to the target.
+    /// the return address has already been popped by prior statements.
+    Return {
+        target: Expr,
+    },
+}
+
+impl std::fmt::Display for Stmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stmt::Assign { dst, value } => write!(f, "{} = {}", dst, value),
+            Stmt::Undefine { dst } => write!(f, "{} = undefined", dst),
+            Stmt::Store { addr, value } => write!(f, "[{}]:{} = {}", addr, value.size(), value),
+            Stmt::Jump { target } => write!(f, "jump {}", target),
+            Stmt::Branch { cond, target } => write!(f, "if {} jump {}", cond, target),
+            Stmt::Call { target } => write!(f, "call {}", target),
+            Stmt::Return { target } => write!(f, "return {}", target),
+        }
+    }
+}
+
+/// the statements lifted from a single instruction.
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub address: VA,
+    pub length:  u64,
+    pub stmts:   Vec<Stmt>,
+}
+
+/// the mask of the low `size` bits.
+pub fn mask(size: Size) -> u64 {
+    if size >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << size) - 1
+    }
+}
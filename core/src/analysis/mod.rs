@@ -1,9 +1,23 @@
 #[cfg(feature = "disassembler")]
 pub mod call_graph;
 #[cfg(feature = "disassembler")]
+pub mod calling_convention;
+#[cfg(feature = "disassembler")]
 pub mod cfg;
 #[cfg(feature = "disassembler")]
+pub mod constants;
+#[cfg(feature = "disassembler")]
 pub mod dis;
 #[cfg(feature = "flirt")]
 pub mod flirt;
+#[cfg(feature = "disassembler")]
+pub mod graph;
+#[cfg(any(feature = "disassembler", feature = "emulator"))]
+pub mod ir;
 pub mod pe;
+#[cfg(feature = "disassembler")]
+pub mod stack_strings;
+#[cfg(feature = "disassembler")]
+pub mod strings;
+#[cfg(feature = "disassembler")]
+pub mod xrefs;
@@ -0,0 +1,641 @@
+//! Recover the arguments passed to calls of imported APIs, like
+//! `CreateFileA("C:\\foo.txt", 0x80000000, ...)`.
+//!
+//! We recognize calls to imports via the import table, thunks like `jmp
+//! [__imp_CreateFileA]`, and registers loaded from the import table, like
+//! `mov esi, [__imp_CreateFileA]; call esi`. The prototypes of well-known APIs
+//! come from `emu::plat::win::api::API`; calls to other APIs are not reported.
+//!
+//! For each argument, we walk backwards from the call to find the instruction
+//! that provides it: a `push` on x86, or a write to rcx/rdx/r8/r9 or the
+//! outgoing stack area on x64. Its value is then resolved via constant
+//! propagation, falling back to the operand itself, like `[ebp-0x10]`, when
+//! the value isn't known.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    analysis::{
+        calling_convention::Prototype,
+        cfg,
+        cfg::{Flow, CFG},
+        constants::{self, CallTarget, State, Value},
+        dis,
+        ir::mask,
+        pe::{find_thunks, get_imports, Import, ImportedSymbol},
+        strings::StringEncoding,
+    },
+    arch::Arch,
+    aspace::AddressSpace,
+    emu::plat::win::api::{FunctionDescriptor, API},
+    loader::pe::PE,
+    module::Module,
+    RVA, VA,
+};
+
+/// the maximum number of instructions to inspect, walking backwards from a
+/// call, while looking for its arguments.
+const MAX_BACKWARDS_INSTRUCTIONS: usize = 64;
+
+/// the maximum length of a string argument, in characters.
+const MAX_STRING_LENGTH: usize = 0x400;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgumentValue {
+    /// the instruction that provides the argument wasn't found.
+    Unknown,
+    /// the value of the argument is known.
+    Value(Value),
+    /// the argument is a pointer to a string in the module.
+    String(StringEncoding, String),
+    /// the value of the argument isn't known,
+    /// but it comes from this operand, like `eax` or `dword ptr [ebp-0x10]`.
+    Operand(String),
+}
+
+impl std::fmt::Display for ArgumentValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgumentValue::Unknown => write!(f, "?"),
+            ArgumentValue::Value(value) => write!(f, "{}", value),
+            ArgumentValue::String(StringEncoding::Ascii, s) => write!(f, "{:?}", s),
+            ArgumentValue::String(StringEncoding::Utf16le, s) => write!(f, "L{:?}", s),
+            ArgumentValue::Operand(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Argument {
+    /// the declared type, like `LPCSTR`.
+    pub ty:    String,
+    pub name:  String,
+    pub value: ArgumentValue,
+}
+
+#[derive(Clone)]
+pub struct ApiCall {
+    /// the address of the call instruction.
+    pub address:   VA,
+    /// the start of the function that contains the call.
+    pub function:  VA,
+    pub import:    Import,
+    pub arguments: Vec<Argument>,
+}
+
+impl std::fmt::Display for ApiCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.import.symbol {
+            ImportedSymbol::Name(name) => write!(f, "{}(", name)?,
+            ImportedSymbol::Ordinal(ord) => write!(f, "{}!#{}(", self.import.dll, ord)?,
+        }
+        for (i, argument) in self.arguments.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", argument.value)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// the key of the import in `API`, like `kernel32.dll!CreateFileA`.
+fn get_api_name(import: &Import) -> Option<String> {
+    match &import.symbol {
+        ImportedSymbol::Name(name) => Some(format!("{}!{}", import.dll.to_lowercase(), name)),
+        ImportedSymbol::Ordinal(_) => None,
+    }
+}
+
+fn get_string_encoding(ty: &str) -> Option<StringEncoding> {
+    match ty {
+        "LPCSTR" | "LPSTR" | "PCSTR" | "PSTR" => Some(StringEncoding::Ascii),
+        "LPCWSTR" | "LPWSTR" | "PCWSTR" | "PWSTR" => Some(StringEncoding::Utf16le),
+        _ => None,
+    }
+}
+
+fn is_string_char(c: u16) -> bool {
+    (0x20..0x7F).contains(&c) || c == 0x09 || c == 0x0A || c == 0x0D
+}
+
+/// read the NULL-terminated string at the given address, if it looks like
+/// text.
+fn read_string(module: &Module, va: VA, encoding: StringEncoding) -> Option<String> {
+    let section = module
+        .sections
+        .iter()
+        .find(|section| section.virtual_range.contains(&va))?;
+    let char_size = match encoding {
+        StringEncoding::Ascii => 1,
+        StringEncoding::Utf16le => 2,
+    };
+    let length = std::cmp::min((section.virtual_range.end - va) as usize, MAX_STRING_LENGTH * char_size);
+    let buf = module.address_space.read_bytes(va, length).ok()?;
+
+    let chars: Vec<u16> = match encoding {
+        StringEncoding::Ascii => buf.iter().map(|&b| b as u16).collect(),
+        StringEncoding::Utf16le => buf
+            .chunks_exact(2)
+            .map(|w| u16::from(w[1]) << 8 | u16::from(w[0]))
+            .collect(),
+    };
+
+    let end = chars.iter().position(|&c| c == 0)?;
+    if !chars[..end].iter().all(|&c| is_string_char(c)) {
+        return None;
+    }
+
+    String::from_utf16(&chars[..end]).ok()
+}
+
+fn format_operand(insn: &zydis::DecodedInstruction, index: usize, va: VA) -> Option<String> {
+    let formatter = zydis::Formatter::new(zydis::FormatterStyle::INTEL).ok()?;
+    let mut buffer = [0u8; 200];
+    let mut buffer = zydis::OutputBuffer::new(&mut buffer[..]);
+    formatter
+        .format_operand(insn, index as u8, &mut buffer, Some(va), None)
+        .ok()?;
+    Some(format!("{}", buffer))
+}
+
+/// the operand that provides an argument.
+struct Source {
+    va:    VA,
+    insn:  zydis::DecodedInstruction,
+    /// the index of the source operand, or None if the value can't be
+    /// described, such as when its computed by arithmetic.
+    index: Option<usize>,
+}
+
+/// the address computed by a memory operand, if known.
+fn get_operand_address(
+    state: Option<&State>,
+    va: VA,
+    insn: &zydis::DecodedInstruction,
+    op: &zydis::DecodedOperand,
+) -> Option<Value> {
+    let disp = op.mem.disp.displacement;
+    if op.mem.index != zydis::Register::NONE {
+        return None;
+    }
+
+    match op.mem.base {
+        zydis::Register::RIP => cfg::va_add_signed(va + insn.length as u64, disp).map(Value::Const),
+        zydis::Register::NONE => Some(Value::Const(disp as u64 & mask(insn.address_width as u16))),
+        base => match state?.register(base)? {
+            Value::Stack(offset) => Some(Value::Stack(offset.wrapping_add(disp))),
+            Value::Const(c) => Some(Value::Const(
+                c.wrapping_add(disp as u64) & mask(insn.address_width as u16),
+            )),
+            Value::Deref(_) => None,
+        },
+    }
+}
+
+/// resolve the value of the source operand, given the state before the
+/// instruction that provides it.
+fn evaluate_source(module: &Module, state: Option<&State>, source: &Source) -> ArgumentValue {
+    let index = match source.index {
+        Some(index) => index,
+        None => return ArgumentValue::Unknown,
+    };
+    let insn = &source.insn;
+    let op = &insn.operands[index];
+
+    let value = match op.ty {
+        zydis::OperandType::IMMEDIATE => Some(Value::Const(op.imm.value & mask(insn.operand_width as u16))),
+        zydis::OperandType::REGISTER => state.and_then(|state| state.register(op.reg)),
+        zydis::OperandType::MEMORY => {
+            let address = get_operand_address(state, source.va, insn, op);
+            if insn.mnemonic == zydis::Mnemonic::LEA {
+                address
+            } else {
+                match address {
+                    Some(Value::Stack(offset)) => state.and_then(|state| state.stack_slot(offset, op.size)),
+                    Some(Value::Const(addr)) if op.size as u32 == module.arch.pointer_size() as u32 * 8 => {
+                        Some(Value::Deref(addr))
+                    }
+                    _ => None,
+                }
+            }
+        }
+        _ => None,
+    };
+
+    match value {
+        Some(value) => ArgumentValue::Value(value),
+        None => match format_operand(insn, index, source.va) {
+            Some(op) => ArgumentValue::Operand(op),
+            None => ArgumentValue::Unknown,
+        },
+    }
+}
+
+/// where an argument is passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Location {
+    /// a full width register.
+    Register(zydis::Register),
+    /// the offset from the stack pointer at the call.
+    Stack(i64),
+}
+
+fn get_argument_locations(arch: Arch, count: usize) -> Vec<Location> {
+    let psize = arch.pointer_size() as i64;
+    (0..count)
+        .map(|i| match (arch, i) {
+            (Arch::X64, 0) => Location::Register(zydis::Register::RCX),
+            (Arch::X64, 1) => Location::Register(zydis::Register::RDX),
+            (Arch::X64, 2) => Location::Register(zydis::Register::R8),
+            (Arch::X64, 3) => Location::Register(zydis::Register::R9),
+            // on x64, the stack arguments follow the 0x20 bytes of home space.
+            _ => Location::Stack(i as i64 * psize),
+        })
+        .collect()
+}
+
+fn is_stack_pointer(reg: zydis::Register) -> bool {
+    reg == zydis::Register::ESP || reg == zydis::Register::RSP
+}
+
+/// the instructions that execute before the given call, in reverse order.
+/// follows unique predecessors across basic blocks.
+fn read_preceding_instructions(
+    module: &Module,
+    decoder: &zydis::Decoder,
+    cfg: &CFG,
+    bb: &cfg::BasicBlock,
+    va: VA,
+) -> Result<Vec<(VA, zydis::DecodedInstruction)>> {
+    let mut insns = vec![];
+    let mut bb = bb;
+    let mut end = va;
+    let mut seen: HashSet<VA> = Default::default();
+
+    loop {
+        seen.insert(bb.address);
+        let buf = module.address_space.read_bytes(bb.address, bb.length as usize)?;
+        let mut block: Vec<(VA, zydis::DecodedInstruction)> = dis::linear_disassemble(decoder, &buf)
+            .filter_map(|(offset, insn)| match insn {
+                Ok(Some(insn)) => Some((bb.address + offset as RVA, insn)),
+                _ => None,
+            })
+            .take_while(|(insnva, _)| *insnva < end)
+            .collect();
+        block.reverse();
+        insns.extend(block);
+
+        if insns.len() >= MAX_BACKWARDS_INSTRUCTIONS {
+            insns.truncate(MAX_BACKWARDS_INSTRUCTIONS);
+            break;
+        }
+
+        let predecessors: Vec<VA> = bb
+            .predecessors
+            .iter()
+            .filter(|flow| !matches!(flow, Flow::Call(_)))
+            .map(|flow| flow.va())
+            .collect();
+        match predecessors.as_slice() {
+            [pred] if !seen.contains(pred) => match cfg.basic_blocks.get(pred) {
+                Some(pred) => {
+                    bb = pred;
+                    end = pred.address + pred.length;
+                }
+                None => break,
+            },
+            _ => break,
+        }
+    }
+
+    Ok(insns)
+}
+
+/// walk backwards from the call to find the instructions that provide each
+/// argument.
+fn find_argument_sources(
+    module: &Module,
+    insns: &[(VA, zydis::DecodedInstruction)],
+    count: usize,
+) -> Vec<Option<Source>> {
+    let mode = match module.arch {
+        Arch::X32 => zydis::MachineMode::LEGACY_32,
+        Arch::X64 => zydis::MachineMode::LONG_64,
+    };
+    let mut sources: Vec<Option<Source>> = (0..count).map(|_| None).collect();
+    let mut needed: HashMap<Location, usize> = get_argument_locations(module.arch, count)
+        .into_iter()
+        .enumerate()
+        .map(|(i, location)| (location, i))
+        .collect();
+
+    // the stack pointer, after the current instruction,
+    // relative to the stack pointer at the call.
+    let mut depth: i64 = 0;
+
+    for (va, insn) in insns.iter() {
+        if needed.is_empty() {
+            break;
+        }
+
+        let mut found = |location: Location, index: Option<usize>| {
+            if let Some(i) = needed.remove(&location) {
+                sources[i] = Some(Source {
+                    va: *va,
+                    insn: insn.clone(),
+                    index,
+                });
+            }
+        };
+
+        let ops = &insn.operands[..insn.operand_count as usize];
+        match insn.mnemonic {
+            // the arguments of an earlier call, or clobbered by it.
+            zydis::Mnemonic::CALL => break,
+            zydis::Mnemonic::PUSH => {
+                found(Location::Stack(depth), Some(0));
+                depth += insn.operand_width as i64 / 8;
+                continue;
+            }
+            zydis::Mnemonic::POP => {
+                depth -= insn.operand_width as i64 / 8;
+            }
+            zydis::Mnemonic::SUB | zydis::Mnemonic::ADD
+                if ops[0].ty == zydis::OperandType::REGISTER
+                    && is_stack_pointer(ops[0].reg)
+                    && ops[1].ty == zydis::OperandType::IMMEDIATE =>
+            {
+                let delta = ops[1].imm.value as i64;
+                if insn.mnemonic == zydis::Mnemonic::SUB {
+                    depth += delta;
+                } else {
+                    depth -= delta;
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        for op in ops.iter() {
+            if !op
+                .action
+                .intersects(zydis::OperandAction::WRITE | zydis::OperandAction::CONDWRITE)
+            {
+                continue;
+            }
+
+            // moves are described by their source operand,
+            // except for partial writes like `mov cl, 0x1`, which are too complex.
+            let is_move = matches!(insn.mnemonic, zydis::Mnemonic::MOV | zydis::Mnemonic::LEA)
+                && op.visibility == zydis::OperandVisibility::EXPLICIT;
+
+            match op.ty {
+                // the stack pointer changes in a way we don't track,
+                // like `and esp, 0xFFFFFFF0`.
+                zydis::OperandType::REGISTER
+                    if is_stack_pointer(op.reg.get_largest_enclosing(mode))
+                        && insn.mnemonic != zydis::Mnemonic::POP =>
+                {
+                    return sources;
+                }
+                zydis::OperandType::REGISTER => {
+                    let reg = op.reg.get_largest_enclosing(mode);
+                    let index = if is_move && op.size >= 32 { Some(1) } else { None };
+                    found(Location::Register(reg), index);
+                }
+                zydis::OperandType::MEMORY
+                    if is_stack_pointer(op.mem.base) && op.mem.index == zydis::Register::NONE =>
+                {
+                    let index = if is_move && insn.mnemonic == zydis::Mnemonic::MOV {
+                        Some(1)
+                    } else {
+                        None
+                    };
+                    found(Location::Stack(depth + op.mem.disp.displacement), index);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    sources
+}
+
+/// the import called by the instruction, via the import table or a thunk.
+fn get_called_import(
+    pe: &PE,
+    imports: &BTreeMap<VA, Import>,
+    thunks: &mut HashMap<VA, Option<Import>>,
+    target: CallTarget,
+) -> Result<Option<Import>> {
+    match target {
+        CallTarget::Pointer(ptr) => Ok(imports.get(&ptr).cloned()),
+        CallTarget::Address(va) => {
+            if let Some(import) = thunks.get(&va) {
+                return Ok(import.clone());
+            }
+
+            let functions: HashSet<VA> = [va].iter().cloned().collect();
+            let import = find_thunks(pe, imports, &functions)?
+                .remove(&va)
+                .map(|thunk| thunk.import);
+            thunks.insert(va, import.clone());
+            Ok(import)
+        }
+    }
+}
+
+fn get_arguments(
+    module: &Module,
+    decoder: &zydis::Decoder,
+    cfg: &CFG,
+    constants: &constants::Constants,
+    bb: &cfg::BasicBlock,
+    va: VA,
+    desc: &FunctionDescriptor,
+) -> Result<Vec<Argument>> {
+    let count = desc.arguments.len();
+
+    // constant propagation knows the argument values, when it tracked the
+    // stack pointer to here; otherwise, find the instructions that provide
+    // them.
+    let known = constants
+        .state_at(va)
+        .map(|state| state.arguments(count))
+        .unwrap_or_else(|| vec![None; count]);
+    let insns = read_preceding_instructions(module, decoder, cfg, bb, va)?;
+    let sources = find_argument_sources(module, &insns, count);
+
+    Ok(desc
+        .arguments
+        .iter()
+        .zip(known.into_iter().zip(sources))
+        .map(|(arg, (known, source))| {
+            let value = match (known, source) {
+                (Some(value), _) => ArgumentValue::Value(value),
+                (None, Some(source)) => evaluate_source(module, constants.state_at(source.va), &source),
+                (None, None) => ArgumentValue::Unknown,
+            };
+
+            let value = match (&value, get_string_encoding(&arg.ty)) {
+                (ArgumentValue::Value(Value::Const(ptr)), Some(encoding)) => {
+                    match read_string(module, *ptr, encoding) {
+                        Some(s) => ArgumentValue::String(encoding, s),
+                        None => value,
+                    }
+                }
+                _ => value,
+            };
+
+            Argument {
+                ty: arg.ty.clone(),
+                name: arg.name.clone(),
+                value,
+            }
+        })
+        .collect())
+}
+
+/// find the calls to well-known APIs within the given functions,
+/// and recover the values of their arguments.
+///
+/// `prototypes`, such as from `calling_convention::find_prototypes`, helps
+/// track the stack pointer across calls to local functions on x86.
+pub fn find_api_calls(pe: &PE, cfgs: &BTreeMap<VA, CFG>, prototypes: &BTreeMap<VA, Prototype>) -> Result<Vec<ApiCall>> {
+    let module = &pe.module;
+    let decoder = dis::get_disassembler(module)?;
+    let imports = get_imports(pe)?;
+    let mut thunks: HashMap<VA, Option<Import>> = Default::default();
+    let mut calls = vec![];
+
+    for (&function, cfg) in cfgs.iter() {
+        let constants = constants::propagate_constants(module, cfg, function, prototypes)?;
+
+        for (&va, &target) in constants.call_targets.iter() {
+            let import = match get_called_import(pe, &imports, &mut thunks, target)? {
+                Some(import) => import,
+                None => continue,
+            };
+            let desc = match get_api_name(&import).and_then(|name| API.get(&name)) {
+                Some(desc) => desc,
+                None => continue,
+            };
+            let bb = match cfg.basic_blocks.range(..=va).next_back() {
+                Some((_, bb)) => bb,
+                None => continue,
+            };
+
+            let arguments = get_arguments(module, &decoder, cfg, &constants, bb, va, desc)?;
+            let call = ApiCall {
+                address: va,
+                function,
+                import,
+                arguments,
+            };
+            debug!("api calls: {:#x}: {}", va, call);
+            calls.push(call);
+        }
+    }
+
+    calls.sort_by_key(|call| call.address);
+    calls.dedup_by_key(|call| call.address);
+    Ok(calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::{
+            call_graph, calling_convention,
+            cfg::{build_cfg, CFG},
+            constants::Value,
+            pe,
+            pe::api_calls::*,
+            strings::StringEncoding,
+        },
+        rsrc::*,
+        VA,
+    };
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+        for &function in pe::find_function_starts(&pe)?.iter() {
+            if let Ok(cfg) = build_cfg(&pe.module, function) {
+                cfgs.insert(function, cfg);
+            }
+        }
+        let cg = call_graph::build_call_graph(&pe.module, &cfgs)?;
+        let prototypes = calling_convention::find_prototypes(&pe.module, &cfgs, &cg)?;
+
+        let cfgs: BTreeMap<VA, CFG> = cfgs
+            .into_iter()
+            .filter(|(va, _)| *va == 0x401553 || *va == 0x406859)
+            .collect();
+        let calls: BTreeMap<VA, ApiCall> = find_api_calls(&pe, &cfgs, &prototypes)?
+            .into_iter()
+            .map(|call| (call.address, call))
+            .collect();
+
+        // .text:00406860 push    esi
+        // .text:00406861 push    esi
+        // .text:00406862 push    3
+        // .text:00406864 push    esi
+        // .text:00406865 push    esi
+        // .text:00406866 push    0C0000000h
+        // .text:0040686B push    offset aMimidrv ; "\\\\.\\mimidrv"
+        // .text:00406870 call    ds:CreateFileW
+        let call = &calls[&0x406870];
+        assert_eq!(call.function, 0x406859);
+        assert_eq!(call.import.to_string(), "KERNEL32.dll!CreateFileW");
+        assert_eq!(call.arguments.len(), 7);
+        assert_eq!(call.arguments[0].name, "lpFileName");
+        assert_eq!(
+            call.arguments[0].value,
+            ArgumentValue::String(StringEncoding::Utf16le, String::from("\\\\.\\mimidrv"))
+        );
+        assert_eq!(call.arguments[1].value, ArgumentValue::Value(Value::Const(0xC0000000)));
+        assert_eq!(
+            call.to_string(),
+            r#"CreateFileW(L"\\\\.\\mimidrv", 0xc0000000, 0x0, 0x0, 0x3, 0x0, 0x0)"#
+        );
+
+        // .text:0040172F push    dword ptr [ebx]
+        // .text:00401731 push    offset StartAddress
+        // .text:00401736 push    esi
+        // .text:00401737 push    esi
+        // .text:00401738 call    ds:CreateThread
+        let call = &calls[&0x401738];
+        assert_eq!(call.arguments[2].value, ArgumentValue::Value(Value::Const(0x401A94)));
+        assert_eq!(call.arguments[3].value, ArgumentValue::Operand(String::from("[ebx]")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn descriptors() {
+        use crate::emu::plat::win::api::API;
+
+        let desc = &API["kernel32.dll!GetVersionExA"];
+        assert_eq!(desc.return_type, "BOOL");
+        assert_eq!(desc.arguments[0].ty, "LPOSVERSIONINFOA");
+        assert_eq!(API["kernel32.dll!GetVersionExW"].arguments[0].ty, "LPOSVERSIONINFOW");
+
+        let desc = &API["kernel32.dll!FindFirstFileW"];
+        assert_eq!(desc.arguments[0].ty, "LPCWSTR");
+        assert_eq!(desc.arguments[1].ty, "LPWIN32_FIND_DATAW");
+        assert_eq!(API["kernel32.dll!FindFirstFileA"].arguments[1].ty, "LPWIN32_FIND_DATAA");
+
+        // return types are expanded, too.
+        assert_eq!(API["kernel32.dll!lstrcpyA"].return_type, "LPSTR");
+        assert_eq!(API["kernel32.dll!lstrcpyW"].return_type, "LPWSTR");
+    }
+}
@@ -0,0 +1,1108 @@
+//! Parse the MSVC C++ exception handling metadata for catch handlers and
+//! unwind funclets.
+//!
+//! Functions that use C++ exceptions are described by a `FuncInfo` structure
+//! that's passed to `__CxxFrameHandler3`. It references:
+//!
+//!   - an unwind map, with the funclets that destroy the objects of each state,
+//!     and
+//!   - a try block map, with the catch handlers of each try block.
+//!
+//! Since these funclets are only referenced by the `FuncInfo`, they're often
+//! missed by other function discovery passes.
+//!
+//! On x86, the parent function registers a small stub named `__ehhandler$...`
+//! that loads the `FuncInfo` and jumps to the frame handler:
+//!
+//! ```text
+//!     push    ebp
+//!     mov     ebp, esp
+//!     push    0FFFFFFFFh
+//!     push    offset __ehhandler$?foo@@YAXXZ
+//!     ...
+//!
+//! __ehhandler$?foo@@YAXXZ:
+//!     ...
+//!     mov     eax, offset __ehfuncinfo$?foo@@YAXXZ
+//!     jmp     ___CxxFrameHandler3
+//! ```
+//!
+//! On x64, the `FuncInfo` is the language-specific handler data of the
+//! function's UNWIND_INFO. Recent compilers use `__CxxFrameHandler4`, whose
+//! `FuncInfo4` is compressed with variable length integers ("FH4").
+//!
+//! references:
+//!   - http://www.openrce.org/articles/full_view/21
+//!   - `ehdata4_export.h` from the MSVC runtime sources (FH4 encoding)
+
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Result;
+use byteorder::{ByteOrder, LittleEndian};
+use log::debug;
+use thiserror::Error;
+
+use crate::{
+    analysis::pe::{find_thunks, get_imports, runtime_functions, ImportedSymbol},
+    arch::Arch,
+    aspace::AddressSpace,
+    loader::pe::PE,
+    module::{Module, Permissions},
+    VA,
+};
+
+#[derive(Debug, Error)]
+pub enum CxxExceptionError {
+    #[error("invalid FuncInfo: {0:#x}")]
+    InvalidFuncInfo(VA),
+    #[error("invalid reference: {0:#x}")]
+    InvalidReference(VA),
+    #[error("invalid funclet: {0:#x}")]
+    InvalidFunclet(VA),
+}
+
+/// the range of `FuncInfo` magic numbers, one per version.
+const MAGIC_MIN: u32 = 0x1993_0520;
+const MAGIC_MAX: u32 = 0x1993_0522;
+
+/// the maximum number of states we expect to see in an unwind map.
+/// used to filter out garbage `FuncInfo` structures.
+const MAX_STATES: u32 = 0x10000;
+
+/// the maximum number of try blocks or catch handlers we expect to see.
+const MAX_TRY_BLOCKS: u32 = 0x1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FuncInfoFormat {
+    /// used by `__CxxFrameHandler3` and earlier.
+    V3,
+    /// the compressed format used by `__CxxFrameHandler4`.
+    V4,
+}
+
+#[derive(Debug, Clone)]
+pub struct UnwindMapEntry {
+    /// the state to transition to after the action runs.
+    /// not recorded by FH4, which links entries by offset instead.
+    pub to_state: Option<i32>,
+    /// the funclet that destroys the objects of this state, if any.
+    pub action:   Option<VA>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HandlerType {
+    pub adjectives:      u32,
+    /// the `TypeDescriptor` of the caught type, or None for `catch (...)`.
+    pub type_descriptor: Option<VA>,
+    /// the address of the catch funclet.
+    pub handler:         VA,
+}
+
+#[derive(Debug, Clone)]
+pub struct TryBlock {
+    pub try_low:    i32,
+    pub try_high:   i32,
+    pub catch_high: i32,
+    pub handlers:   Vec<HandlerType>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FuncInfo {
+    pub address:    VA,
+    pub format:     FuncInfoFormat,
+    /// the function whose exceptions are described, if known.
+    pub parent:     Option<VA>,
+    /// the x86 `__ehhandler$` stub that references this, if any.
+    pub stub:       Option<VA>,
+    pub unwind_map: Vec<UnwindMapEntry>,
+    pub try_blocks: Vec<TryBlock>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FuncletType {
+    /// a catch handler.
+    Catch,
+    /// an unwind action, which destroys local objects.
+    Unwind,
+    /// an x86 `__ehhandler$` stub.
+    Stub,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Funclet {
+    pub address: VA,
+    pub ty:      FuncletType,
+    /// the function that owns the funclet, if known.
+    pub parent:  Option<VA>,
+}
+
+impl FuncInfo {
+    pub fn funclets(&self) -> Vec<Funclet> {
+        let mut funclets: Vec<Funclet> = vec![];
+
+        if let Some(stub) = self.stub {
+            funclets.push(Funclet {
+                address: stub,
+                ty:      FuncletType::Stub,
+                parent:  self.parent,
+            });
+        }
+
+        funclets.extend(
+            self.unwind_map
+                .iter()
+                .filter_map(|entry| entry.action)
+                .map(|action| Funclet {
+                    address: action,
+                    ty:      FuncletType::Unwind,
+                    parent:  self.parent,
+                }),
+        );
+
+        funclets.extend(
+            self.try_blocks
+                .iter()
+                .flat_map(|try_block| try_block.handlers.iter())
+                .map(|handler| Funclet {
+                    address: handler.handler,
+                    ty:      FuncletType::Catch,
+                    parent:  self.parent,
+                }),
+        );
+
+        funclets.sort_unstable();
+        funclets.dedup();
+        funclets
+    }
+}
+
+/// reads references to other structures,
+/// which are VAs on x86, and RVAs on x64.
+fn read_reference(module: &Module, va: VA) -> Result<Option<VA>> {
+    let target = match module.arch {
+        Arch::X32 => module.address_space.read_u32(va)? as VA,
+        Arch::X64 => match module.address_space.read_u32(va)? {
+            0 => 0,
+            rva => module.address_space.base_address + rva as VA,
+        },
+    };
+
+    if target == 0 {
+        Ok(None)
+    } else if module.probe_va(target, Permissions::R) {
+        Ok(Some(target))
+    } else {
+        Err(CxxExceptionError::InvalidReference(va).into())
+    }
+}
+
+/// read a reference to a funclet, which must be executable.
+fn read_funclet_reference(module: &Module, va: VA) -> Result<Option<VA>> {
+    match read_reference(module, va)? {
+        Some(target) if !module.probe_va(target, Permissions::X) => {
+            Err(CxxExceptionError::InvalidFunclet(target).into())
+        }
+        target => Ok(target),
+    }
+}
+
+fn is_magic(module: &Module, va: VA) -> bool {
+    match module.address_space.read_u32(va) {
+        // the high bits are the BBT flags.
+        Ok(magic) => (MAGIC_MIN..=MAGIC_MAX).contains(&(magic & 0x1FFF_FFFF)),
+        Err(_) => false,
+    }
+}
+
+/// read the `FuncInfo` structure used by `__CxxFrameHandler3` and earlier.
+///
+/// ```text
+/// struct FuncInfo {
+///     u32  magicNumber;
+///     i32  maxState;
+///     ref  pUnwindMap;       // UnwindMapEntry[maxState]
+///     u32  nTryBlocks;
+///     ref  pTryBlockMap;     // TryBlockMapEntry[nTryBlocks]
+///     ...
+/// }
+/// ```
+///
+/// where references are VAs on x86 and RVAs on x64.
+pub fn read_func_info3(module: &Module, va: VA) -> Result<FuncInfo> {
+    if !is_magic(module, va) {
+        return Err(CxxExceptionError::InvalidFuncInfo(va).into());
+    }
+
+    let aspace = &module.address_space;
+    let max_state = aspace.read_u32(va + 0x4)?;
+    let try_block_count = aspace.read_u32(va + 0xC)?;
+    if max_state > MAX_STATES || try_block_count > MAX_TRY_BLOCKS {
+        return Err(CxxExceptionError::InvalidFuncInfo(va).into());
+    }
+
+    let mut unwind_map = vec![];
+    if let Some(entries) = read_reference(module, va + 0x8)? {
+        // struct UnwindMapEntry {
+        //     i32 toState;
+        //     ref action;
+        // }
+        for i in 0..max_state as VA {
+            let entry = entries + 8 * i;
+            unwind_map.push(UnwindMapEntry {
+                to_state: Some(aspace.read_u32(entry)? as i32),
+                action:   read_funclet_reference(module, entry + 4)?,
+            });
+        }
+    }
+
+    let mut try_blocks = vec![];
+    if let Some(entries) = read_reference(module, va + 0x10)? {
+        // struct TryBlockMapEntry {
+        //     i32 tryLow;
+        //     i32 tryHigh;
+        //     i32 catchHigh;
+        //     i32 nCatches;
+        //     ref pHandlerArray;
+        // }
+        for i in 0..try_block_count as VA {
+            let entry = entries + 0x14 * i;
+            let handler_count = aspace.read_u32(entry + 0xC)?;
+            if handler_count > MAX_TRY_BLOCKS {
+                return Err(CxxExceptionError::InvalidFuncInfo(entry).into());
+            }
+
+            // struct HandlerType {
+            //     u32 adjectives;
+            //     ref pType;
+            //     i32 dispCatchObj;
+            //     ref addressOfHandler;
+            //     u32 dispFrame;         // x64 only
+            // }
+            let handler_size = match module.arch {
+                Arch::X32 => 0x10,
+                Arch::X64 => 0x14,
+            };
+
+            let mut handlers = vec![];
+            if let Some(array) = read_reference(module, entry + 0x10)? {
+                for j in 0..handler_count as VA {
+                    let handler = array + handler_size * j;
+                    handlers.push(HandlerType {
+                        adjectives:      aspace.read_u32(handler)?,
+                        type_descriptor: read_reference(module, handler + 0x4)?,
+                        handler:         read_funclet_reference(module, handler + 0xC)?
+                            .ok_or(CxxExceptionError::InvalidFunclet(handler))?,
+                    });
+                }
+            }
+
+            try_blocks.push(TryBlock {
+                try_low: aspace.read_u32(entry)? as i32,
+                try_high: aspace.read_u32(entry + 0x4)? as i32,
+                catch_high: aspace.read_u32(entry + 0x8)? as i32,
+                handlers,
+            });
+        }
+    }
+
+    Ok(FuncInfo {
+        address: va,
+        format: FuncInfoFormat::V3,
+        parent: None,
+        stub: None,
+        unwind_map,
+        try_blocks,
+    })
+}
+
+/// reads the compressed `FuncInfo4` encoding used by `__CxxFrameHandler4`.
+struct Fh4Reader<'a> {
+    module: &'a Module,
+    va:     VA,
+}
+
+impl<'a> Fh4Reader<'a> {
+    fn read_u8(&mut self) -> Result<u8> {
+        let v = self.module.address_space.read_u8(self.va)?;
+        self.va += 1;
+        Ok(v)
+    }
+
+    /// read a variable length unsigned integer.
+    /// the number of trailing 1 bits in the first byte encodes the length.
+    fn read_unsigned(&mut self) -> Result<u32> {
+        let first = self.module.address_space.read_u8(self.va)?;
+        let length = std::cmp::min(first.trailing_ones(), 4) as usize + 1;
+        let buf = self.module.address_space.read_bytes(self.va, length)?;
+        self.va += length as VA;
+
+        Ok(match length {
+            5 => LittleEndian::read_u32(&buf[1..]),
+            _ => (LittleEndian::read_uint(&buf, length) >> length) as u32,
+        })
+    }
+
+    /// read a raw 32-bit integer, such as an RVA.
+    fn read_int(&mut self) -> Result<i32> {
+        let v = self.module.address_space.read_u32(self.va)?;
+        self.va += 4;
+        Ok(v as i32)
+    }
+
+    fn read_rva(&mut self) -> Result<Option<VA>> {
+        let rva = self.read_int()?;
+        let target = self.module.address_space.base_address + rva as u32 as VA;
+        if rva == 0 {
+            Ok(None)
+        } else if self.module.probe_va(target, Permissions::X) {
+            Ok(Some(target))
+        } else {
+            Err(CxxExceptionError::InvalidFunclet(target).into())
+        }
+    }
+
+    fn read_count(&mut self) -> Result<u32> {
+        let count = self.read_unsigned()?;
+        if count > MAX_STATES {
+            return Err(CxxExceptionError::InvalidFuncInfo(self.va).into());
+        }
+        Ok(count)
+    }
+}
+
+/// read the compressed `FuncInfo4` structure used by `__CxxFrameHandler4`.
+///
+/// FH4 is only used on x64.
+pub fn read_func_info4(module: &Module, va: VA) -> Result<FuncInfo> {
+    const IS_CATCH: u8 = 0x01;
+    const BBT: u8 = 0x04;
+    const UNWIND_MAP: u8 = 0x08;
+    const TRY_BLOCK_MAP: u8 = 0x10;
+
+    let base_address = module.address_space.base_address;
+    let rva = |rva: i32| base_address + rva as u32 as VA;
+
+    let mut r = Fh4Reader { module, va };
+    let header = r.read_u8()?;
+    if header & 0x80 != 0 {
+        return Err(CxxExceptionError::InvalidFuncInfo(va).into());
+    }
+    if header & BBT != 0 {
+        r.read_unsigned()?;
+    }
+    let unwind_map_address = if header & UNWIND_MAP != 0 {
+        Some(rva(r.read_int()?))
+    } else {
+        None
+    };
+    let try_block_map_address = if header & TRY_BLOCK_MAP != 0 {
+        Some(rva(r.read_int()?))
+    } else {
+        None
+    };
+    // dispIPtoStateMap, or the segment map when the function is separated.
+    r.read_int()?;
+    if header & IS_CATCH != 0 {
+        // dispFrame
+        r.read_unsigned()?;
+    }
+
+    let mut unwind_map = vec![];
+    if let Some(address) = unwind_map_address {
+        const DTOR_WITH_OBJ: u32 = 1;
+        const DTOR_WITH_PTR_TO_OBJ: u32 = 2;
+        const RVA: u32 = 3;
+
+        let mut r = Fh4Reader { module, va: address };
+        for _ in 0..r.read_count()? {
+            // the low two bits are the type, and the rest is the offset to the next entry.
+            let next_and_type = r.read_unsigned()?;
+            let action = match next_and_type & 0x3 {
+                DTOR_WITH_OBJ | DTOR_WITH_PTR_TO_OBJ => {
+                    let action = r.read_rva()?;
+                    // object
+                    r.read_unsigned()?;
+                    action
+                }
+                RVA => r.read_rva()?,
+                _ => None,
+            };
+
+            unwind_map.push(UnwindMapEntry { to_state: None, action });
+        }
+    }
+
+    let mut try_blocks = vec![];
+    if let Some(address) = try_block_map_address {
+        const ADJECTIVES: u8 = 0x01;
+        const DISP_TYPE: u8 = 0x02;
+        const DISP_CATCH_OBJ: u8 = 0x04;
+        const CONT_IS_RVA: u8 = 0x08;
+
+        let mut r = Fh4Reader { module, va: address };
+        for _ in 0..r.read_count()? {
+            let try_low = r.read_unsigned()? as i32;
+            let try_high = r.read_unsigned()? as i32;
+            let catch_high = r.read_unsigned()? as i32;
+            let handlers_address = rva(r.read_int()?);
+
+            let mut handlers = vec![];
+            let mut h = Fh4Reader {
+                module,
+                va: handlers_address,
+            };
+            for _ in 0..h.read_count()? {
+                let header = h.read_u8()?;
+                let adjectives = if header & ADJECTIVES != 0 {
+                    h.read_unsigned()?
+                } else {
+                    0
+                };
+                let type_descriptor = if header & DISP_TYPE != 0 {
+                    match h.read_int()? {
+                        0 => None,
+                        disp => Some(rva(disp)),
+                    }
+                } else {
+                    None
+                };
+                if header & DISP_CATCH_OBJ != 0 {
+                    h.read_unsigned()?;
+                }
+                let handler = h
+                    .read_rva()?
+                    .ok_or(CxxExceptionError::InvalidFunclet(handlers_address))?;
+                // the continuation addresses.
+                for _ in 0..(header >> 4) & 0x3 {
+                    if header & CONT_IS_RVA != 0 {
+                        h.read_int()?;
+                    } else {
+                        h.read_unsigned()?;
+                    }
+                }
+
+                handlers.push(HandlerType {
+                    adjectives,
+                    type_descriptor,
+                    handler,
+                });
+            }
+
+            try_blocks.push(TryBlock {
+                try_low,
+                try_high,
+                catch_high,
+                handlers,
+            });
+        }
+    }
+
+    Ok(FuncInfo {
+        address: va,
+        format: FuncInfoFormat::V4,
+        parent: None,
+        stub: None,
+        unwind_map,
+        try_blocks,
+    })
+}
+
+/// find the x86 `__ehhandler$` stubs, and the `FuncInfo`s they reference,
+/// by looking for parent functions that register them:
+///
+/// ```text
+///     push    0FFFFFFFFh
+///     push    offset __ehhandler$?foo@@YAXXZ
+/// ```
+pub fn find_ehhandler_stubs(module: &Module) -> Result<Vec<FuncInfo>> {
+    // push 0xFFFFFFFF; push imm32
+    const PUSH_PUSH: [u8; 3] = [0x6A, 0xFF, 0x68];
+    // push ebp; mov ebp, esp
+    const PROLOGUE: [u8; 3] = [0x55, 0x8B, 0xEC];
+    // the maximum distance from the start of the stub
+    // to the instruction that loads the FuncInfo.
+    const MAX_STUB_SIZE: usize = 0x40;
+
+    let mut func_infos: BTreeMap<VA, FuncInfo> = Default::default();
+    if !matches!(module.arch, Arch::X32) {
+        return Ok(vec![]);
+    }
+
+    for section in module.sections.iter() {
+        if !section.permissions.intersects(Permissions::X) {
+            continue;
+        }
+
+        let vstart: VA = section.virtual_range.start;
+        let vsize = (section.virtual_range.end - section.virtual_range.start) as usize;
+        let sec_buf = module.address_space.read_bytes(vstart, vsize)?;
+
+        for (i, window) in sec_buf.windows(7).enumerate() {
+            if window[..3] != PUSH_PUSH {
+                continue;
+            }
+
+            let stub = LittleEndian::read_u32(&window[3..]) as VA;
+            if !module.probe_va(stub, Permissions::X) {
+                continue;
+            }
+
+            // the stub ends with: mov eax, offset FuncInfo; jmp ___CxxFrameHandler3
+            let stub_buf = match module.address_space.read_bytes(stub, MAX_STUB_SIZE + 6) {
+                Ok(buf) => buf,
+                Err(_) => continue,
+            };
+            let func_info = stub_buf.windows(6).take(MAX_STUB_SIZE).find_map(|w| {
+                if w[0] == 0xB8 && (w[5] == 0xE9 || w[5] == 0xEB) {
+                    let va = LittleEndian::read_u32(&w[1..]) as VA;
+                    if is_magic(module, va) {
+                        return Some(va);
+                    }
+                }
+                None
+            });
+            let func_info = match func_info {
+                Some(va) => va,
+                None => continue,
+            };
+
+            let mut func_info = match read_func_info3(module, func_info) {
+                Ok(func_info) => func_info,
+                Err(e) => {
+                    debug!("eh: invalid FuncInfo at {:#x}: {}", func_info, e);
+                    continue;
+                }
+            };
+
+            let push = vstart + i as VA;
+            func_info.stub = Some(stub);
+            if i >= PROLOGUE.len() && sec_buf[i - PROLOGUE.len()..i] == PROLOGUE {
+                func_info.parent = Some(push - PROLOGUE.len() as VA);
+            }
+
+            debug!(
+                "eh: found __ehhandler stub {:#x} for FuncInfo {:#x}",
+                stub, func_info.address
+            );
+            func_infos.insert(func_info.address, func_info);
+        }
+    }
+
+    Ok(func_infos.into_values().collect())
+}
+
+/// find the `FuncInfo`s referenced by the x64 exception handlers.
+fn find_pe_x64_func_infos(pe: &PE) -> Result<Vec<FuncInfo>> {
+    let module = &pe.module;
+    let handlers = runtime_functions::find_pe_exception_handlers(pe)?;
+
+    // resolve the names of the exception handlers, which are usually thunks
+    // to imports from the C++ runtime.
+    let imports = get_imports(pe)?;
+    let handler_addresses: HashSet<VA> = handlers.iter().map(|h| h.handler).collect();
+    let names: BTreeMap<VA, String> = find_thunks(pe, &imports, &handler_addresses)?
+        .into_iter()
+        .filter_map(|(va, thunk)| match thunk.import.symbol {
+            ImportedSymbol::Name(name) => Some((va, name.to_string())),
+            ImportedSymbol::Ordinal(_) => None,
+        })
+        .collect();
+
+    // many RUNTIME_FUNCTIONs, such as funclets, may share a FuncInfo.
+    let mut references: BTreeMap<(VA, FuncInfoFormat), Vec<VA>> = Default::default();
+    for handler in handlers.iter() {
+        let format = match names.get(&handler.handler).map(|name| name.as_str()) {
+            Some("__CxxFrameHandler3") | Some("__GSHandlerCheck_EH") => FuncInfoFormat::V3,
+            Some("__CxxFrameHandler4") | Some("__GSHandlerCheck_EH4") => FuncInfoFormat::V4,
+            Some(_) => continue,
+            // statically linked handler: we can only recognize FH3 via its magic.
+            None => FuncInfoFormat::V3,
+        };
+
+        let func_info = match read_reference(module, handler.data) {
+            Ok(Some(va)) => va,
+            _ => continue,
+        };
+        references
+            .entry((func_info, format))
+            .or_default()
+            .push(handler.function);
+    }
+
+    let mut func_infos = vec![];
+    for ((va, format), functions) in references.into_iter() {
+        let func_info = match format {
+            FuncInfoFormat::V3 => read_func_info3(module, va),
+            FuncInfoFormat::V4 => read_func_info4(module, va),
+        };
+        let mut func_info = match func_info {
+            Ok(func_info) => func_info,
+            Err(e) => {
+                debug!("eh: invalid FuncInfo at {:#x}: {}", va, e);
+                continue;
+            }
+        };
+
+        // the parent is the function that references the FuncInfo, but isn't one of its
+        // funclets.
+        let funclets: HashSet<VA> = func_info.funclets().iter().map(|f| f.address).collect();
+        func_info.parent = functions.into_iter().filter(|f| !funclets.contains(f)).min();
+
+        debug!(
+            "eh: found FuncInfo {:#x} for {:#x?}",
+            func_info.address, func_info.parent
+        );
+        func_infos.push(func_info);
+    }
+
+    Ok(func_infos)
+}
+
+/// find the C++ exception handling metadata of the functions in the module.
+pub fn find_pe_func_infos(pe: &PE) -> Result<Vec<FuncInfo>> {
+    match pe.module.arch {
+        Arch::X32 => find_ehhandler_stubs(&pe.module),
+        Arch::X64 => find_pe_x64_func_infos(pe),
+    }
+}
+
+/// find the catch handlers, unwind funclets, and `__ehhandler$` stubs in the
+/// module.
+pub fn find_pe_funclets(pe: &PE) -> Result<Vec<Funclet>> {
+    let mut funclets: Vec<Funclet> = find_pe_func_infos(pe)?
+        .iter()
+        .flat_map(|func_info| func_info.funclets())
+        .collect();
+    funclets.sort_unstable();
+    funclets.dedup();
+    Ok(funclets)
+}
+
+pub fn find_pe_cxx_exception_functions(pe: &PE) -> Result<Vec<VA>> {
+    let functions: HashSet<VA> = find_pe_funclets(pe)?.iter().map(|f| f.address).collect();
+    debug!("eh: found {} funclets", functions.len());
+    Ok(functions.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analysis::pe::cxx_exceptions::*, rsrc::*, test::*};
+    use anyhow::Result;
+
+    #[test]
+    fn x32() -> Result<()> {
+        // 0x00: parent:
+        //   push ebp; mov ebp, esp; push 0xFFFFFFFF; push 0x20
+        //   ...
+        // 0x10: catch handler
+        // 0x14: unwind action
+        // 0x20: __ehhandler stub:
+        //   mov edx, [esp+8]
+        //   mov eax, 0x40
+        //   jmp 0x0 (___CxxFrameHandler3)
+        // 0x40: FuncInfo
+        // 0x60: UnwindMapEntry[2]
+        // 0x70: TryBlockMapEntry[1]
+        // 0x90: HandlerType[1]
+        let mut buf = vec![0xCCu8; 0x100];
+        buf[0x0..0xB].copy_from_slice(&[0x55, 0x8B, 0xEC, 0x6A, 0xFF, 0x68, 0x20, 0x00, 0x00, 0x00, 0xC3]);
+        buf[0x10] = 0xC3;
+        buf[0x14] = 0xC3;
+        buf[0x20..0x2E].copy_from_slice(&[
+            0x8B, 0x54, 0x24, 0x08, 0xB8, 0x40, 0x00, 0x00, 0x00, 0xE9, 0xD2, 0xFF, 0xFF, 0xFF,
+        ]);
+
+        let mut data = vec![0u8; 0x60];
+        let mut write = |offset: usize, v: u32| LittleEndian::write_u32(&mut data[offset..], v);
+        // FuncInfo
+        write(0x00, 0x1993_0522);
+        write(0x04, 2);
+        write(0x08, 0x60);
+        write(0x0C, 1);
+        write(0x10, 0x70);
+        // UnwindMapEntry
+        write(0x20, 0xFFFF_FFFF);
+        write(0x24, 0x14);
+        write(0x28, 0x0);
+        write(0x2C, 0x0);
+        // TryBlockMapEntry
+        write(0x30, 1);
+        write(0x34, 1);
+        write(0x38, 2);
+        write(0x3C, 1);
+        write(0x40, 0x90);
+        // HandlerType: catch (...)
+        write(0x50, 0x40);
+        write(0x54, 0x0);
+        write(0x58, 0x0);
+        write(0x5C, 0x10);
+        buf[0x40..0xA0].copy_from_slice(&data);
+
+        let module = load_shellcode32(&buf);
+        let func_infos = find_ehhandler_stubs(&module)?;
+        assert_eq!(func_infos.len(), 1);
+
+        let func_info = &func_infos[0];
+        assert_eq!(func_info.address, 0x40);
+        assert_eq!(func_info.format, FuncInfoFormat::V3);
+        assert_eq!(func_info.parent, Some(0x0));
+        assert_eq!(func_info.stub, Some(0x20));
+        assert_eq!(func_info.unwind_map.len(), 2);
+        assert_eq!(func_info.unwind_map[0].to_state, Some(-1));
+        assert_eq!(func_info.unwind_map[0].action, Some(0x14));
+        assert_eq!(func_info.unwind_map[1].action, None);
+        assert_eq!(func_info.try_blocks.len(), 1);
+        assert_eq!(func_info.try_blocks[0].handlers[0].type_descriptor, None);
+
+        assert_eq!(
+            func_info.funclets(),
+            vec![
+                Funclet {
+                    address: 0x10,
+                    ty:      FuncletType::Catch,
+                    parent:  Some(0x0),
+                },
+                Funclet {
+                    address: 0x14,
+                    ty:      FuncletType::Unwind,
+                    parent:  Some(0x0),
+                },
+                Funclet {
+                    address: 0x20,
+                    ty:      FuncletType::Stub,
+                    parent:  Some(0x0),
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fh4() -> Result<()> {
+        // 0x10: catch handler
+        // 0x14: unwind action
+        // 0x40: FuncInfo4
+        // 0x50: UnwindMap4
+        // 0x60: TryBlockMap4
+        // 0x70: HandlerMap4
+        let mut buf = vec![0xCCu8; 0x100];
+        buf[0x10] = 0xC3;
+        buf[0x14] = 0xC3;
+
+        // header: UnwindMap | TryBlockMap, dispUnwindMap, dispTryBlockMap,
+        // dispIPtoStateMap.
+        buf[0x40..0x4D].copy_from_slice(&[0x18, 0x50, 0, 0, 0, 0x60, 0, 0, 0, 0x00, 0x01, 0, 0]);
+        // two entries:
+        //   - RVA action 0x14, next offset 0
+        //   - no action, next offset 5 (encoded in 2 bytes)
+        buf[0x50..0x58].copy_from_slice(&[0x04, 0x06, 0x14, 0, 0, 0, 0x51, 0x00]);
+        // one try block: tryLow 1, tryHigh 1, catchHigh 2, dispHandlerArray 0x70.
+        buf[0x60..0x68].copy_from_slice(&[0x02, 0x02, 0x02, 0x04, 0x70, 0, 0, 0]);
+        // one handler: header DISP_TYPE, dispType 0, dispOfHandler 0x10, one
+        // continuation offset 0x20.
+        buf[0x70..0x7C].copy_from_slice(&[0x02, 0x12, 0, 0, 0, 0, 0x10, 0, 0, 0, 0x40, 0]);
+
+        let module = load_shellcode64(&buf);
+        let func_info = read_func_info4(&module, 0x40)?;
+        assert_eq!(func_info.format, FuncInfoFormat::V4);
+        assert_eq!(func_info.unwind_map.len(), 2);
+        assert_eq!(func_info.unwind_map[0].action, Some(0x14));
+        assert_eq!(func_info.unwind_map[1].action, None);
+        assert_eq!(func_info.try_blocks.len(), 1);
+        assert_eq!(func_info.try_blocks[0].try_low, 1);
+        assert_eq!(func_info.try_blocks[0].catch_high, 2);
+        assert_eq!(func_info.try_blocks[0].handlers.len(), 1);
+        assert_eq!(func_info.try_blocks[0].handlers[0].handler, 0x10);
+        assert_eq!(func_info.try_blocks[0].handlers[0].type_descriptor, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn compressed_integers() -> Result<()> {
+        #[rustfmt::skip]
+        let module = load_shellcode64(&[
+            // 1 byte: 0x7F
+            0xFE,
+            // 2 bytes: 0x80
+            0x01, 0x02,
+            // 3 bytes: 0x4000
+            0x03, 0x00, 0x02,
+            // 4 bytes: 0x200000
+            0x07, 0x00, 0x00, 0x02,
+            // 5 bytes: 0x12345678
+            0x0F, 0x78, 0x56, 0x34, 0x12,
+        ]);
+
+        let mut r = Fh4Reader {
+            module: &module,
+            va:     0x0,
+        };
+        assert_eq!(r.read_unsigned()?, 0x7F);
+        assert_eq!(r.read_unsigned()?, 0x80);
+        assert_eq!(r.read_unsigned()?, 0x4000);
+        assert_eq!(r.read_unsigned()?, 0x200000);
+        assert_eq!(r.read_unsigned()?, 0x12345678);
+        assert_eq!(r.va, 0xF);
+
+        Ok(())
+    }
+
+    /// build a minimal x64 PE with one function using `__CxxFrameHandler3`
+    /// and another using `__CxxFrameHandler4`, both imported from the runtime
+    /// via thunks.
+    ///
+    /// layout (RVAs, image base 0x140000000):
+    ///
+    /// ```text
+    /// .text:
+    ///   0x1000: FH3 parent function
+    ///   0x1020: FH3 catch funclet
+    ///   0x1030: FH3 unwind funclet
+    ///   0x1040: FH4 parent function
+    ///   0x1060: FH4 catch funclet
+    ///   0x1070: FH4 unwind funclet
+    ///   0x1100: thunk: jmp [__imp___CxxFrameHandler3]
+    ///   0x1108: thunk: jmp [__imp___CxxFrameHandler4]
+    /// .rdata:
+    ///   0x2000: IAT
+    ///   0x2020: import descriptors
+    ///   0x2060: import lookup table
+    ///   0x2090: DLL name and hint/name entries
+    ///   0x2100: UNWIND_INFO with FH3 handler, shared by the parent and its catch funclet
+    ///   0x2120: UNWIND_INFO with FH4 handler, shared by the parent and its catch funclet
+    ///   0x2140: UNWIND_INFO without handler, for the unwind funclets
+    ///   0x2200: FuncInfo, UnwindMapEntry[2], TryBlockMapEntry[1], HandlerType[1]
+    ///   0x2300: FuncInfo4, UnwindMap4, TryBlockMap4, HandlerMap4
+    /// .pdata:
+    ///   0x3000: RUNTIME_FUNCTION[6]
+    /// ```
+    fn build_pe64() -> Vec<u8> {
+        let mut buf = vec![0u8; 0x1000];
+        let w16 = |buf: &mut Vec<u8>, offset: usize, v: u16| LittleEndian::write_u16(&mut buf[offset..], v);
+        let w32 = |buf: &mut Vec<u8>, offset: usize, v: u32| LittleEndian::write_u32(&mut buf[offset..], v);
+        let w64 = |buf: &mut Vec<u8>, offset: usize, v: u64| LittleEndian::write_u64(&mut buf[offset..], v);
+
+        // (name, RVA, file offset, characteristics)
+        let sections: [(&[u8], u32, u32, u32); 3] = [
+            (b".text", 0x1000, 0x400, 0x6000_0020),
+            (b".rdata", 0x2000, 0x800, 0x4000_0040),
+            (b".pdata", 0x3000, 0xC00, 0x4000_0040),
+        ];
+        // translate an RVA into a file offset.
+        let off = |rva: u32| -> usize {
+            let &(_, va, raw, _) = sections.iter().rev().find(|(_, va, _, _)| *va <= rva).unwrap();
+            (rva - va + raw) as usize
+        };
+
+        // DOS header
+        buf[0x0..0x2].copy_from_slice(b"MZ");
+        w32(&mut buf, 0x3C, 0x40);
+        // PE header
+        buf[0x40..0x44].copy_from_slice(b"PE\x00\x00");
+        w16(&mut buf, 0x44, 0x8664);
+        w16(&mut buf, 0x46, sections.len() as u16);
+        w16(&mut buf, 0x54, 0xF0);
+        w16(&mut buf, 0x56, 0x22);
+        // optional header
+        w16(&mut buf, 0x58, 0x20B);
+        w32(&mut buf, 0x5C, 0x400);
+        w32(&mut buf, 0x60, 0x800);
+        w32(&mut buf, 0x68, 0x1000);
+        w32(&mut buf, 0x6C, 0x1000);
+        w64(&mut buf, 0x70, 0x1_4000_0000);
+        w32(&mut buf, 0x78, 0x1000);
+        w32(&mut buf, 0x7C, 0x200);
+        w16(&mut buf, 0x80, 6);
+        w16(&mut buf, 0x88, 6);
+        w32(&mut buf, 0x90, 0x4000);
+        w32(&mut buf, 0x94, 0x400);
+        w16(&mut buf, 0x9C, 3);
+        w64(&mut buf, 0xA0, 0x10_0000);
+        w64(&mut buf, 0xA8, 0x1000);
+        w64(&mut buf, 0xB0, 0x10_0000);
+        w64(&mut buf, 0xB8, 0x1000);
+        w32(&mut buf, 0xC4, 16);
+        // data directories: import, exception, IAT
+        w32(&mut buf, 0xC8 + 8, 0x2020);
+        w32(&mut buf, 0xC8 + 8 + 4, 0x28);
+        w32(&mut buf, 0xC8 + 3 * 8, 0x3000);
+        w32(&mut buf, 0xC8 + 3 * 8 + 4, 6 * 12);
+        w32(&mut buf, 0xC8 + 12 * 8, 0x2000);
+        w32(&mut buf, 0xC8 + 12 * 8 + 4, 0x18);
+        // section headers
+        for (i, &(name, rva, raw, characteristics)) in sections.iter().enumerate() {
+            let header = 0x148 + 0x28 * i;
+            buf[header..header + name.len()].copy_from_slice(name);
+            w32(&mut buf, header + 0x8, 0x400);
+            w32(&mut buf, header + 0xC, rva);
+            w32(&mut buf, header + 0x10, 0x400);
+            w32(&mut buf, header + 0x14, raw);
+            w32(&mut buf, header + 0x24, characteristics);
+        }
+
+        // .text
+        for &rva in [0x1000, 0x1040].iter() {
+            // sub rsp, 0x28; add rsp, 0x28; ret
+            buf[off(rva)..off(rva) + 9].copy_from_slice(&[0x48, 0x83, 0xEC, 0x28, 0x48, 0x83, 0xC4, 0x28, 0xC3]);
+        }
+        for &rva in [0x1020, 0x1030, 0x1060, 0x1070].iter() {
+            buf[off(rva)] = 0xC3;
+        }
+        // jmp [rip+0xEFA]
+        for &rva in [0x1100, 0x1108].iter() {
+            buf[off(rva)..off(rva) + 6].copy_from_slice(&[0xFF, 0x25, 0xFA, 0x0E, 0x00, 0x00]);
+        }
+
+        // imports
+        for &table in [0x2000, 0x2060].iter() {
+            w64(&mut buf, off(table), 0x20A8);
+            w64(&mut buf, off(table + 8), 0x20C0);
+        }
+        w32(&mut buf, off(0x2020), 0x2060);
+        w32(&mut buf, off(0x202C), 0x2090);
+        w32(&mut buf, off(0x2030), 0x2000);
+        buf[off(0x2090)..off(0x2090) + 16].copy_from_slice(b"VCRUNTIME140.dll");
+        buf[off(0x20AA)..off(0x20AA) + 18].copy_from_slice(b"__CxxFrameHandler3");
+        buf[off(0x20C2)..off(0x20C2) + 18].copy_from_slice(b"__CxxFrameHandler4");
+
+        // unwind info: version 1, UNW_FLAG_EHANDLER, prologue size 4, one code:
+        // UWOP_ALLOC_SMALL 0x28 at offset 4, then the handler and its data.
+        for &(rva, handler, func_info) in [(0x2100, 0x1100, 0x2200), (0x2120, 0x1108, 0x2300)].iter() {
+            buf[off(rva)..off(rva) + 6].copy_from_slice(&[0x09, 0x04, 0x01, 0x00, 0x04, 0x42]);
+            w32(&mut buf, off(rva + 8), handler);
+            w32(&mut buf, off(rva + 12), func_info);
+        }
+        buf[off(0x2140)] = 0x01;
+
+        // FuncInfo
+        w32(&mut buf, off(0x2200), 0x1993_0522);
+        w32(&mut buf, off(0x2204), 2);
+        w32(&mut buf, off(0x2208), 0x2240);
+        w32(&mut buf, off(0x220C), 1);
+        w32(&mut buf, off(0x2210), 0x2260);
+        // UnwindMapEntry
+        w32(&mut buf, off(0x2240), 0xFFFF_FFFF);
+        w32(&mut buf, off(0x2244), 0x1030);
+        // TryBlockMapEntry
+        w32(&mut buf, off(0x2260), 1);
+        w32(&mut buf, off(0x2264), 1);
+        w32(&mut buf, off(0x2268), 2);
+        w32(&mut buf, off(0x226C), 1);
+        w32(&mut buf, off(0x2270), 0x2280);
+        // HandlerType: catch (...)
+        w32(&mut buf, off(0x2280), 0x40);
+        w32(&mut buf, off(0x228C), 0x1020);
+        w32(&mut buf, off(0x2290), 0x38);
+
+        // FuncInfo4: UnwindMap | TryBlockMap, dispUnwindMap, dispTryBlockMap,
+        // dispIPtoStateMap.
+        buf[off(0x2300)] = 0x18;
+        w32(&mut buf, off(0x2301), 0x2340);
+        w32(&mut buf, off(0x2305), 0x2360);
+        // UnwindMap4: two entries: RVA action 0x1070, then no action.
+        buf[off(0x2340)..off(0x2340) + 7].copy_from_slice(&[0x04, 0x06, 0x70, 0x10, 0, 0, 0x00]);
+        // TryBlockMap4: tryLow 1, tryHigh 1, catchHigh 2, dispHandlerArray 0x2380.
+        buf[off(0x2360)..off(0x2360) + 8].copy_from_slice(&[0x02, 0x02, 0x02, 0x04, 0x80, 0x23, 0, 0]);
+        // HandlerMap4: header DISP_TYPE, dispType 0, dispOfHandler 0x1060.
+        buf[off(0x2380)..off(0x2380) + 10].copy_from_slice(&[0x02, 0x02, 0, 0, 0, 0, 0x60, 0x10, 0, 0]);
+
+        // RUNTIME_FUNCTIONs
+        for (i, &(start, end, unwind_info)) in [
+            (0x1000, 0x1009, 0x2100),
+            (0x1020, 0x1021, 0x2100),
+            (0x1030, 0x1031, 0x2140),
+            (0x1040, 0x1049, 0x2120),
+            (0x1060, 0x1061, 0x2120),
+            (0x1070, 0x1071, 0x2140),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let entry = off(0x3000) + 12 * i;
+            w32(&mut buf, entry, start);
+            w32(&mut buf, entry + 4, end);
+            w32(&mut buf, entry + 8, unwind_info);
+        }
+
+        buf
+    }
+
+    #[test]
+    fn x64() -> Result<()> {
+        let buf = build_pe64();
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        let base = 0x1_4000_0000;
+
+        let func_infos: BTreeMap<VA, FuncInfo> = find_pe_func_infos(&pe)?
+            .into_iter()
+            .map(|func_info| (func_info.address, func_info))
+            .collect();
+        assert_eq!(func_infos.len(), 2);
+
+        // __CxxFrameHandler3
+        let func_info = &func_infos[&(base + 0x2200)];
+        assert_eq!(func_info.format, FuncInfoFormat::V3);
+        // the catch funclet shares the unwind info, but isn't the parent.
+        assert_eq!(func_info.parent, Some(base + 0x1000));
+        assert_eq!(func_info.stub, None);
+        assert_eq!(func_info.unwind_map[0].action, Some(base + 0x1030));
+        assert_eq!(func_info.try_blocks[0].handlers[0].handler, base + 0x1020);
+        assert_eq!(func_info.try_blocks[0].handlers[0].adjectives, 0x40);
+
+        // __CxxFrameHandler4
+        let func_info = &func_infos[&(base + 0x2300)];
+        assert_eq!(func_info.format, FuncInfoFormat::V4);
+        assert_eq!(func_info.parent, Some(base + 0x1040));
+        assert_eq!(func_info.unwind_map[0].action, Some(base + 0x1070));
+        assert_eq!(func_info.try_blocks[0].handlers[0].handler, base + 0x1060);
+
+        assert_eq!(
+            find_pe_funclets(&pe)?,
+            vec![
+                Funclet {
+                    address: base + 0x1020,
+                    ty:      FuncletType::Catch,
+                    parent:  Some(base + 0x1000),
+                },
+                Funclet {
+                    address: base + 0x1030,
+                    ty:      FuncletType::Unwind,
+                    parent:  Some(base + 0x1000),
+                },
+                Funclet {
+                    address: base + 0x1060,
+                    ty:      FuncletType::Catch,
+                    parent:  Some(base + 0x1040),
+                },
+                Funclet {
+                    address: base + 0x1070,
+                    ty:      FuncletType::Unwind,
+                    parent:  Some(base + 0x1040),
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn k32() -> Result<()> {
+        // kernel32 is written in C, so there are no C++ exception handlers.
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(find_pe_func_infos(&pe)?.len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(find_pe_func_infos(&pe)?.len(), 0);
+
+        Ok(())
+    }
+}
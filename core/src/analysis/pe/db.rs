@@ -0,0 +1,351 @@
+//! Save the analysis of a PE to disk, and reload it without re-analysis.
+//!
+//! A database contains the sample itself, so that the `Module` can be
+//! reconstructed, along with the configuration used to analyze it,
+//! the functions, CFGs, call graph, xrefs, names, library functions,
+//! and any comments assigned by the user.
+//!
+//! The on-disk format is:
+//!
+//!   - the magic `LANCELOT`,
+//!   - the format version, as a little-endian u32, and
+//!   - the SHA-256 of the sample, the sample, the configuration, and the
+//!     analysis, encoded with bincode (using variable-length integers).
+//!
+//! The version must be bumped whenever the serialized structures change,
+//! and databases with a different version are rejected.
+//!
+//! `Database::from_cache` keeps one database per sample hash in a directory,
+//! so that the CLI and pylancelot can share the analysis of a sample.
+//! A database analyzed with another configuration is replaced, though the
+//! comments and names assigned by the user carry over.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use bincode::Options;
+use byteorder::{ByteOrder, LittleEndian};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+#[cfg(feature = "flirt")]
+use crate::analysis::flirt;
+use crate::{
+    analysis::{
+        call_graph::CallGraph,
+        cfg::CFG,
+        pe::{
+            names::{find_pe_names, NameSource, Names},
+            workspace, Function, Signatures,
+        },
+        xrefs,
+        xrefs::Xrefs,
+    },
+    config::Config,
+    loader::pe::PE,
+    util, VA,
+};
+
+pub const MAGIC: &[u8; 8] = b"LANCELOT";
+pub const VERSION: u32 = 4;
+
+#[derive(Debug, Error)]
+pub enum DatabaseError {
+    #[error("not a lancelot database")]
+    InvalidMagic,
+    #[error("unsupported database version: {0}")]
+    UnsupportedVersion(u32),
+    #[error("database is corrupt: sample hash mismatch")]
+    HashMismatch,
+}
+
+/// the results of analysis that are saved in the database.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Analysis {
+    /// the local functions, thunks, and imports, sorted.
+    pub functions:         Vec<Function>,
+    /// the CFG of each local function, indexed by function start.
+    pub cfgs:              BTreeMap<VA, CFG>,
+    pub call_graph:        CallGraph,
+    pub xrefs:             Xrefs,
+    /// names from the PE, its analysis, and the user.
+    pub names:             Names,
+    /// the functions recognized as library code by FLIRT signatures.
+    pub library_functions: BTreeSet<VA>,
+    /// comments assigned by the user, indexed by address.
+    pub comments:          BTreeMap<VA, String>,
+}
+
+impl Analysis {
+    /// analyze the given PE: discover its functions, along with their CFGs
+    /// and the call graph, and then collect the xrefs, names, and library
+    /// functions.
+    pub fn from_pe(pe: &PE, config: &Config) -> Result<Analysis> {
+        // load the signatures once, for both function discovery and naming.
+        let sigs = Signatures::from_config(config)?;
+
+        let ws = workspace::build_workspace_with_signatures(pe, config, &sigs)?;
+        let mut analysis = Analysis {
+            functions: ws.functions,
+            cfgs: ws.cfgs,
+            call_graph: ws.call_graph,
+            ..Default::default()
+        };
+
+        analysis.xrefs = xrefs::build_xrefs(&pe.module, &analysis.cfgs)?;
+        analysis.names = find_pe_names(pe, &analysis.functions)?;
+
+        #[cfg(feature = "flirt")]
+        {
+            if let Some(sigs) = &sigs.flirt {
+                let matches = flirt::match_flirt_module(&pe.module, sigs, &analysis.function_starts());
+                analysis.names.insert_flirt_matches(&matches);
+                analysis.library_functions = matches.library_functions();
+            }
+        }
+
+        Ok(analysis)
+    }
+
+    /// copy the comments and names assigned by the user from the given
+    /// analysis, such as one of the same sample with another configuration.
+    fn copy_annotations(&mut self, other: &Analysis) {
+        self.comments
+            .extend(other.comments.iter().map(|(&va, comment)| (va, comment.clone())));
+
+        for (va, _) in other.names.iter() {
+            for name in other.names.get_all(va).iter().filter(|n| n.source == NameSource::User) {
+                self.names.insert(va, &name.name, NameSource::User);
+            }
+        }
+    }
+
+    /// the addresses of the local functions, sorted.
+    pub fn function_starts(&self) -> Vec<VA> {
+        self.functions
+            .iter()
+            .filter_map(|f| match f {
+                Function::Local(f) => Some(f.address),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+pub struct Database {
+    /// the hex-encoded SHA-256 of the sample.
+    pub sha256:   String,
+    pub pe:       PE,
+    /// the configuration used to analyze the sample.
+    pub config:   Config,
+    pub analysis: Analysis,
+}
+
+/// compute the hex-encoded SHA-256 of the given buffer.
+pub fn sha256(buf: &[u8]) -> String {
+    Sha256::digest(buf).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn bincode_options() -> impl bincode::Options {
+    bincode::DefaultOptions::new()
+}
+
+impl Database {
+    /// analyze the given PE.
+    pub fn from_pe(pe: PE, config: &Config) -> Result<Database> {
+        let analysis = Analysis::from_pe(&pe, config)?;
+
+        Ok(Database {
+            sha256: sha256(&pe.buf),
+            pe,
+            config: config.clone(),
+            analysis,
+        })
+    }
+
+    /// load and analyze the PE in the given buffer.
+    pub fn from_bytes(buf: &[u8], config: &Config) -> Result<Database> {
+        Database::from_pe(PE::from_bytes(buf)?, config)
+    }
+
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut buf = MAGIC.to_vec();
+        let mut version = [0u8; 4];
+        LittleEndian::write_u32(&mut version, VERSION);
+        buf.extend_from_slice(&version);
+
+        bincode_options().serialize_into(&mut buf, &(&self.sha256, &self.pe.buf, &self.config, &self.analysis))?;
+
+        Ok(buf)
+    }
+
+    pub fn deserialize(buf: &[u8]) -> Result<Database> {
+        if buf.len() < MAGIC.len() + 4 || &buf[..MAGIC.len()] != MAGIC {
+            return Err(DatabaseError::InvalidMagic.into());
+        }
+
+        let version = LittleEndian::read_u32(&buf[MAGIC.len()..]);
+        if version != VERSION {
+            return Err(DatabaseError::UnsupportedVersion(version).into());
+        }
+
+        let (hash, sample, config, analysis): (String, Vec<u8>, Config, Analysis) =
+            bincode_options().deserialize(&buf[MAGIC.len() + 4..])?;
+        if hash != sha256(&sample) {
+            return Err(DatabaseError::HashMismatch.into());
+        }
+
+        Ok(Database {
+            sha256: hash,
+            pe: PE::from_bytes(&sample)?,
+            config,
+            analysis,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        debug!("db: saving: {}", path.as_ref().display());
+        std::fs::write(path, self.serialize()?)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Database> {
+        debug!("db: loading: {}", path.as_ref().display());
+        Database::deserialize(&util::read_file(&path.as_ref().to_string_lossy())?)
+    }
+
+    /// the path of the database for the sample with the given hash.
+    pub fn cache_path<P: AsRef<Path>>(dir: P, sha256: &str) -> PathBuf {
+        dir.as_ref().join(format!("{}.lancelot", sha256))
+    }
+
+    /// load the database for the sample in the given buffer from the cache
+    /// directory, or analyze the sample and save its database there.
+    ///
+    /// the cache is keyed by the sample hash, so when the cached database
+    /// was analyzed with another configuration, re-analyze the sample and
+    /// replace it, keeping the comments and names assigned by the user.
+    pub fn from_cache<P: AsRef<Path>>(dir: P, buf: &[u8], config: &Config) -> Result<Database> {
+        let path = Database::cache_path(&dir, &sha256(buf));
+
+        let mut previous = None;
+        if path.exists() {
+            match Database::load(&path) {
+                Ok(db) if db.config == *config => return Ok(db),
+                Ok(db) => {
+                    debug!("db: configuration changed, re-analyzing: {}", path.display());
+                    previous = Some(db.analysis);
+                }
+                // such as from an older version of lancelot, so replace it.
+                Err(e) => debug!("db: failed to load {}: {}", path.display(), e),
+            }
+        }
+
+        let mut db = Database::from_bytes(buf, config)?;
+        if let Some(previous) = previous {
+            db.analysis.copy_annotations(&previous);
+        }
+        std::fs::create_dir_all(&dir)?;
+        db.save(&path)?;
+
+        Ok(db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analysis::pe::db::*, rsrc::*};
+    use anyhow::Result;
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let mut db = Database::from_bytes(&buf, &Default::default())?;
+        db.analysis.names.insert(0x1800134D0, "foo", NameSource::User);
+        db.analysis.comments.insert(0x1800134D0, "bar".to_string());
+
+        let db2 = Database::deserialize(&db.serialize()?)?;
+        assert_eq!(db2.sha256, db.sha256);
+        assert_eq!(db2.pe.buf, buf);
+        assert_eq!(db2.pe.module.address_space.base_address, 0x180000000);
+        assert!(db2.analysis.functions == db.analysis.functions);
+        assert_eq!(db2.analysis.cfgs.len(), db.analysis.cfgs.len());
+        assert_eq!(
+            db2.analysis.call_graph.calls_to[&0x180001068],
+            db.analysis.call_graph.calls_to[&0x180001068]
+        );
+        assert_eq!(db2.analysis.xrefs.to, db.analysis.xrefs.to);
+        assert_eq!(db2.analysis.names.get(0x1800134D0).unwrap().name, "foo");
+        assert_eq!(db2.analysis.names.len(), db.analysis.names.len());
+        assert_eq!(db2.analysis.comments[&0x1800134D0], "bar");
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() -> Result<()> {
+        let buf = get_buf(Rsrc::TINY);
+        let db = Database::from_bytes(&buf, &Default::default())?;
+        let mut serialized = db.serialize()?;
+
+        assert!(Database::deserialize(b"MZ").is_err());
+
+        // unsupported version.
+        serialized[MAGIC.len()] = 0xFF;
+        assert!(Database::deserialize(&serialized).is_err());
+        serialized[MAGIC.len()] = VERSION as u8;
+        assert!(Database::deserialize(&serialized).is_ok());
+
+        // corrupt sample.
+        let offset = serialized.windows(buf.len()).position(|w| w == &buf[..]).unwrap();
+        serialized[offset + 0x100] ^= 0xFF;
+        assert!(Database::deserialize(&serialized).is_err());
+
+        // truncated.
+        assert!(Database::deserialize(&serialized[..serialized.len() - 1]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache() -> Result<()> {
+        let buf = get_buf(Rsrc::TINY);
+        let dir = std::env::temp_dir().join(format!("lancelot-test-cache-{}", std::process::id()));
+
+        let db = Database::from_cache(&dir, &buf, &Default::default())?;
+        let path = Database::cache_path(&dir, &db.sha256);
+        assert!(path.exists());
+
+        // the cached database is reused, including the user's annotations.
+        let mut db = db;
+        db.analysis.comments.insert(0x4000EC, "foo".to_string());
+        db.analysis.names.insert(0x4000EC, "bar", NameSource::User);
+        db.save(&path)?;
+        let db2 = Database::from_cache(&dir, &buf, &Default::default())?;
+        assert!(db2.analysis.functions == db.analysis.functions);
+        assert_eq!(db2.analysis.comments[&0x4000EC], "foo");
+
+        // but re-analyzed when the configuration changed,
+        // and the user's annotations survive.
+        let mut config: Config = Default::default();
+        config.functions.prologues = false;
+        let db3 = Database::from_cache(&dir, &buf, &config)?;
+        assert!(db3.config == config);
+        assert_eq!(db3.analysis.comments[&0x4000EC], "foo");
+        assert_eq!(db3.analysis.names.get(0x4000EC).unwrap().name, "bar");
+        assert_eq!(db3.analysis.names.get(0x4000EC).unwrap().source, NameSource::User);
+
+        let db4 = Database::load(&path)?;
+        assert!(db4.config == config);
+        assert_eq!(db4.analysis.comments[&0x4000EC], "foo");
+        assert_eq!(db4.analysis.names.get(0x4000EC).unwrap().name, "bar");
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
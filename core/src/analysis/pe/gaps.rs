@@ -0,0 +1,429 @@
+//! Find the regions of executable sections that belong to no function.
+//!
+//! After function discovery, some bytes in `.text` aren't claimed by any
+//! basic block. We classify each of these gaps as:
+//!
+//!   - alignment: runs of `int3` and `nop` between functions,
+//!   - jump tables: arrays of pointers (x86) or RVAs (x64) to code,
+//!   - code: instructions that decode cleanly up to a `ret` or `jmp`, or
+//!   - data: everything else, like string literals or lookup tables.
+//!
+//! The start of each code gap is proposed as a function start.
+//! Rely on the caller to validate that the function looks reasonable.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+};
+
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    analysis::{cfg, cfg::CFG, dis},
+    arch::Arch,
+    aspace::AddressSpace,
+    module::{Module, Permissions},
+    VA,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapType {
+    Alignment,
+    JumpTable,
+    Code,
+    Data,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gap {
+    pub range: Range<VA>,
+    pub ty:    GapType,
+}
+
+/// compute the ranges covered by the basic blocks of the given CFGs,
+/// indexed by start address.
+fn get_covered_ranges(cfgs: &BTreeMap<VA, CFG>) -> BTreeMap<VA, VA> {
+    // basic blocks may be shared among CFGs, so index them by start address,
+    // keeping the longest.
+    let mut covered: BTreeMap<VA, VA> = Default::default();
+    for cfg in cfgs.values() {
+        for bb in cfg.basic_blocks.values() {
+            let end = covered.entry(bb.address).or_insert(bb.address);
+            *end = std::cmp::max(*end, bb.address + bb.length);
+        }
+    }
+
+    covered
+}
+
+/// compute the ranges of the executable sections not covered by the given
+/// basic blocks.
+fn find_uncovered_ranges(module: &Module, cfgs: &BTreeMap<VA, CFG>) -> Vec<Range<VA>> {
+    let covered = get_covered_ranges(cfgs);
+
+    let mut ranges = vec![];
+    for section in module.sections.iter() {
+        if !section.permissions.intersects(Permissions::X) {
+            continue;
+        }
+
+        let mut offset = section.virtual_range.start;
+        for (&start, &end) in covered.range(section.virtual_range.clone()) {
+            if start > offset {
+                ranges.push(offset..start);
+            }
+            offset = std::cmp::max(offset, end);
+        }
+
+        if offset < section.virtual_range.end {
+            ranges.push(offset..section.virtual_range.end);
+        }
+    }
+
+    ranges
+}
+
+/// decode the instruction at the given address, which must fit before `end`.
+fn read_insn(module: &Module, decoder: &zydis::Decoder, va: VA, end: VA) -> Option<zydis::DecodedInstruction> {
+    // near the end of a gap, fewer than 16 bytes may be available.
+    let size = std::cmp::min(16, end.saturating_sub(va)) as usize;
+    let buf = module.address_space.read_bytes(va, size).ok()?;
+
+    match decoder.decode(&buf) {
+        Ok(Some(insn)) => Some(insn),
+        _ => None,
+    }
+}
+
+/// the size of the run of `int3` and `nop` instructions at the given address,
+/// including multi-byte nops like `66 0F 1F 44 00 00`.
+fn get_alignment_size(module: &Module, decoder: &zydis::Decoder, range: &Range<VA>) -> u64 {
+    let mut va = range.start;
+
+    while va < range.end {
+        match read_insn(module, decoder, va, range.end) {
+            Some(insn) if matches!(insn.mnemonic, zydis::Mnemonic::INT3 | zydis::Mnemonic::NOP) => {
+                va += insn.length as u64
+            }
+            _ => break,
+        }
+    }
+
+    va - range.start
+}
+
+/// the size of the jump table at the given address, if any.
+/// on x86, entries are pointers, and on x64, entries are 32-bit RVAs.
+fn get_jump_table_size(module: &Module, range: &Range<VA>) -> u64 {
+    // tables are usually aligned, but if not, require more evidence.
+    let min_entries: u64 = if range.start.is_multiple_of(4) { 2 } else { 3 };
+
+    let mut va = range.start;
+    while va + 4 <= range.end {
+        let target = match (module.arch, module.address_space.read_u32(va)) {
+            (Arch::X32, Ok(entry)) => entry as VA,
+            (Arch::X64, Ok(entry)) => module.address_space.base_address + entry as VA,
+            (_, Err(_)) => break,
+        };
+
+        if !module.probe_va(target, Permissions::X) {
+            break;
+        }
+
+        va += 4;
+    }
+
+    if va - range.start < min_entries * 4 {
+        0
+    } else {
+        va - range.start
+    }
+}
+
+/// the result of trying to decode code at the start of a gap.
+enum CodeProbe {
+    /// the size of code that decodes cleanly up to an instruction that
+    /// doesn't fall through.
+    Code(u64),
+    /// the size of the region that was decoded before the code was rejected,
+    /// including the offending bytes.
+    Data(u64),
+}
+
+/// try to decode code at the given address.
+fn probe_code(module: &Module, decoder: &zydis::Decoder, range: &Range<VA>) -> CodeProbe {
+    let mut va = range.start;
+
+    while va < range.end {
+        let insn = match read_insn(module, decoder, va, range.end) {
+            Some(insn) => insn,
+            None => return CodeProbe::Data(va + 1 - range.start),
+        };
+        let next = va + insn.length as u64;
+
+        if insn.attributes.contains(zydis::InstructionAttributes::IS_PRIVILEGED) {
+            return CodeProbe::Data(next - range.start);
+        }
+
+        // like `ret far`, which isn't found in user mode code.
+        if insn.meta.branch_type == zydis::BranchType::FAR {
+            return CodeProbe::Data(next - range.start);
+        }
+
+        match insn.mnemonic {
+            // 00 00: add [eax], al
+            zydis::Mnemonic::ADD if insn.length == 2 && matches!(module.address_space.read_u16(va), Ok(0x0)) => {
+                return CodeProbe::Data(next - range.start)
+            }
+            zydis::Mnemonic::INT3 if va == range.start => return CodeProbe::Data(1),
+            // the int3 may begin alignment padding, so don't consume it.
+            zydis::Mnemonic::INT3 => return CodeProbe::Code(va - range.start),
+            zydis::Mnemonic::RET | zydis::Mnemonic::JMP => return CodeProbe::Code(next - range.start),
+            _ => {}
+        }
+
+        va = next;
+    }
+
+    // ran off the end of the gap without finding the end of the code.
+    CodeProbe::Data(range.end - range.start)
+}
+
+fn push_gap(gaps: &mut Vec<Gap>, ty: GapType, start: VA, end: VA) {
+    // merge adjacent data gaps, which we step through byte by byte.
+    if let Some(prev) = gaps.last_mut() {
+        if prev.ty == GapType::Data && ty == GapType::Data && prev.range.end == start {
+            prev.range.end = end;
+            return;
+        }
+    }
+
+    gaps.push(Gap { range: start..end, ty });
+}
+
+/// split the given uncovered range into classified gaps.
+fn classify_range(module: &Module, decoder: &zydis::Decoder, range: &Range<VA>) -> Vec<Gap> {
+    let mut gaps: Vec<Gap> = vec![];
+
+    // code decoded from within a rejected region is very likely to be rejected
+    // at the same place, so don't decode it again. but keep looking for
+    // alignment and jump tables, which may overlap the bogus instructions.
+    let mut rejected_until = range.start;
+
+    let mut va = range.start;
+    while va < range.end {
+        let rest = va..range.end;
+
+        let size = get_alignment_size(module, decoder, &rest);
+        if size > 0 {
+            push_gap(&mut gaps, GapType::Alignment, va, va + size);
+            va += size;
+            continue;
+        }
+
+        let size = get_jump_table_size(module, &rest);
+        if size > 0 {
+            push_gap(&mut gaps, GapType::JumpTable, va, va + size);
+            va += size;
+            continue;
+        }
+
+        if va >= rejected_until {
+            match probe_code(module, decoder, &rest) {
+                CodeProbe::Code(size) => {
+                    push_gap(&mut gaps, GapType::Code, va, va + size);
+                    va += size;
+                    continue;
+                }
+                CodeProbe::Data(size) => rejected_until = va + size,
+            }
+        }
+
+        push_gap(&mut gaps, GapType::Data, va, va + 1);
+        va += 1;
+    }
+
+    gaps
+}
+
+/// find and classify the regions of executable sections that are not covered
+/// by the basic blocks of the given CFGs.
+pub fn find_gaps(module: &Module, cfgs: &BTreeMap<VA, CFG>) -> Result<Vec<Gap>> {
+    let decoder = dis::get_disassembler(module)?;
+
+    let mut gaps = vec![];
+    for range in find_uncovered_ranges(module, cfgs).iter() {
+        gaps.extend(classify_range(module, &decoder, range));
+    }
+
+    debug!(
+        "gaps: found {} gaps, {} code",
+        gaps.len(),
+        gaps.iter().filter(|gap| gap.ty == GapType::Code).count()
+    );
+
+    Ok(gaps)
+}
+
+/// the targets of the entries of the given jump table.
+fn read_jump_table_targets(module: &Module, gap: &Gap) -> Vec<VA> {
+    let mut targets = vec![];
+
+    let mut va = gap.range.start;
+    while va + 4 <= gap.range.end {
+        match (module.arch, module.address_space.read_u32(va)) {
+            (Arch::X32, Ok(entry)) => targets.push(entry as VA),
+            (Arch::X64, Ok(entry)) => targets.push(module.address_space.base_address + entry as VA),
+            (_, Err(_)) => break,
+        }
+        va += 4;
+    }
+
+    targets
+}
+
+/// propose function starts at the code found in the gaps between the
+/// functions with the given CFGs.
+///
+/// code that is the target of a jump table, or that flows into the middle
+/// of a known function, is probably a case of an unrecovered switch
+/// statement, so it's not proposed.
+pub fn find_gap_functions(module: &Module, cfgs: &BTreeMap<VA, CFG>) -> Result<Vec<VA>> {
+    let gaps = find_gaps(module, cfgs)?;
+
+    let jump_table_targets: BTreeSet<VA> = gaps
+        .iter()
+        .filter(|gap| gap.ty == GapType::JumpTable)
+        .flat_map(|gap| read_jump_table_targets(module, gap))
+        .collect();
+
+    let covered = get_covered_ranges(cfgs);
+    let is_covered = |va: VA| match covered.range(..=va).next_back() {
+        Some((_, &end)) => va < end,
+        None => false,
+    };
+
+    let mut ret = vec![];
+    for gap in gaps.iter().filter(|gap| gap.ty == GapType::Code) {
+        if jump_table_targets.contains(&gap.range.start) {
+            continue;
+        }
+
+        let cfg = match cfg::build_cfg(module, gap.range.start) {
+            Ok(cfg) => cfg,
+            Err(_) => continue,
+        };
+
+        // flowing to the start of a function is a tail call, which is ok.
+        if cfg
+            .basic_blocks
+            .keys()
+            .any(|&va| is_covered(va) && !cfgs.contains_key(&va))
+        {
+            continue;
+        }
+
+        ret.push(gap.range.start);
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::{cfg::build_cfg, pe::gaps::*},
+        rsrc::*,
+        test::*,
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn shellcode() -> Result<()> {
+        let module = load_shellcode32(
+            b"\
+            \x55\x8B\xEC\x5D\xC3\
+            \xCC\xCC\xCC\
+            \x33\xC0\xC3\
+            \x90\x66\x90\
+            \xFF\xFF\
+            \x00\x00\x00\x00\x03\x00\x00\x00\
+            \xFF\xFF\xFF\xFF\xFF\xFF",
+        );
+
+        let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+        cfgs.insert(0x0, build_cfg(&module, 0x0)?);
+
+        let gaps = find_gaps(&module, &cfgs)?;
+        assert_eq!(
+            gaps,
+            vec![
+                // 5:  cc cc cc                int3; int3; int3
+                Gap {
+                    range: 0x5..0x8,
+                    ty:    GapType::Alignment,
+                },
+                // 8:  33 c0                   xor    eax,eax
+                // a:  c3                      ret
+                Gap {
+                    range: 0x8..0xB,
+                    ty:    GapType::Code,
+                },
+                // b:  90                      nop
+                // c:  66 90                   xchg   ax,ax
+                Gap {
+                    range: 0xB..0xE,
+                    ty:    GapType::Alignment,
+                },
+                // e:  ff ff                   (bad)
+                Gap {
+                    range: 0xE..0x10,
+                    ty:    GapType::Data,
+                },
+                // 10: dd 0x0, 0x3
+                Gap {
+                    range: 0x10..0x18,
+                    ty:    GapType::JumpTable,
+                },
+                Gap {
+                    range: 0x18..0x1E,
+                    ty:    GapType::Data,
+                },
+            ]
+        );
+
+        assert_eq!(find_gap_functions(&module, &cfgs)?, vec![0x8]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+        for &function in crate::analysis::pe::find_function_starts(&pe)?.iter() {
+            if let Ok(cfg) = build_cfg(&pe.module, function) {
+                cfgs.insert(function, cfg);
+            }
+        }
+
+        let gaps = find_gaps(&pe.module, &cfgs)?;
+        assert!(gaps.iter().any(|gap| gap.ty == GapType::Alignment));
+        assert!(gaps.iter().any(|gap| gap.ty == GapType::JumpTable));
+        assert!(gaps.iter().any(|gap| gap.ty == GapType::Code));
+
+        // gaps don't overlap any basic block.
+        for cfg in cfgs.values() {
+            for bb in cfg.basic_blocks.values() {
+                let bb = bb.address..bb.address + bb.length;
+                assert!(!gaps
+                    .iter()
+                    .any(|gap| gap.range.start < bb.end && bb.start < gap.range.end));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use anyhow::Result;
 use log::debug;
+use serde::{Deserialize, Serialize};
 
 use crate::{
     aspace::AddressSpace,
@@ -15,25 +16,43 @@ use crate::{
 #[cfg(feature = "disassembler")]
 use crate::analysis::{cfg, dis};
 #[cfg(feature = "disassembler")]
+use crate::config::Config;
+#[cfg(feature = "disassembler")]
 use std::collections::HashSet;
 
+#[cfg(all(feature = "disassembler", feature = "emulator"))]
+pub mod api_calls;
 #[cfg(feature = "disassembler")]
 pub mod call_targets;
 pub mod control_flow_guard;
+#[cfg(feature = "disassembler")]
+pub mod cxx_exceptions;
+#[cfg(feature = "disassembler")]
+pub mod db;
 pub mod entrypoints;
 pub mod exports;
+#[cfg(feature = "disassembler")]
+pub mod gaps;
+pub mod names;
 pub mod patterns;
 pub mod pointers;
+pub mod rtti;
 pub mod runtime_functions;
 pub mod safeseh;
+#[cfg(feature = "flirt")]
+pub mod sigmake;
+#[cfg(feature = "disassembler")]
+pub mod validation;
+#[cfg(feature = "disassembler")]
+pub mod workspace;
 
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum ImportedSymbol {
     Ordinal(u32),
     Name(smol_str::SmolStr),
 }
 
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Import {
     /// the address of the First Thunk.
     /// that is, the thing that will be referenced by code.
@@ -51,16 +70,132 @@ impl std::fmt::Display for Import {
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub struct Thunk {
     /// the address of the function thunk
     pub address: VA,
     pub import:  Import,
 }
 
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
+/// the analysis pass that found a function start.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum FunctionSource {
+    Entrypoint,
+    Export,
+    SafeSEH,
+    RuntimeFunction,
+    ExceptionHandler,
+    CFGuard,
+    CallTarget,
+    Prologue,
+    Pointer,
+    Vtable,
+    CxxException,
+    /// an immediate or `lea` operand of an instruction in a discovered
+    /// function.
+    CodeReference,
+    /// the target of a jump from a discovered function.
+    TailCall,
+    /// code found between the discovered functions.
+    Gap,
+    /// a match of a FLIRT signature, found by scanning the executable
+    /// sections.
+    Flirt,
+}
+
+impl FunctionSource {
+    /// how likely, as a percentage, is a function start from this source to be
+    /// correct?
+    pub fn confidence(&self) -> u8 {
+        match self {
+            // these come from metadata that the loader or OS relies upon.
+            FunctionSource::Entrypoint => 100,
+            FunctionSource::SafeSEH => 100,
+            FunctionSource::RuntimeFunction => 100,
+            FunctionSource::ExceptionHandler => 100,
+            FunctionSource::CFGuard => 100,
+            // exports may also be data.
+            FunctionSource::Export => 90,
+            FunctionSource::CxxException => 90,
+            FunctionSource::Vtable => 80,
+            FunctionSource::CallTarget => 70,
+            FunctionSource::Prologue => 50,
+            FunctionSource::CodeReference => 50,
+            FunctionSource::TailCall => 50,
+            FunctionSource::Pointer => 30,
+            FunctionSource::Gap => 30,
+            // library code, though short signatures may match by chance.
+            FunctionSource::Flirt => 70,
+        }
+    }
+
+    /// does this source come from metadata, rather than a heuristic?
+    /// candidates from only heuristics are validated before being accepted.
+    pub fn is_trusted(&self) -> bool {
+        matches!(
+            self,
+            FunctionSource::Entrypoint
+                | FunctionSource::Export
+                | FunctionSource::SafeSEH
+                | FunctionSource::RuntimeFunction
+                | FunctionSource::ExceptionHandler
+                | FunctionSource::CFGuard
+        )
+    }
+}
+
+impl std::fmt::Display for FunctionSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FunctionSource::Entrypoint => write!(f, "entrypoint"),
+            FunctionSource::Export => write!(f, "export"),
+            FunctionSource::SafeSEH => write!(f, "safeseh"),
+            FunctionSource::RuntimeFunction => write!(f, "runtime function"),
+            FunctionSource::ExceptionHandler => write!(f, "exception handler"),
+            FunctionSource::CFGuard => write!(f, "cfguard"),
+            FunctionSource::CallTarget => write!(f, "call target"),
+            FunctionSource::Prologue => write!(f, "prologue"),
+            FunctionSource::Pointer => write!(f, "pointer"),
+            FunctionSource::Vtable => write!(f, "vtable"),
+            FunctionSource::CxxException => write!(f, "c++ exception"),
+            FunctionSource::CodeReference => write!(f, "code reference"),
+            FunctionSource::TailCall => write!(f, "tail call"),
+            FunctionSource::Gap => write!(f, "gap"),
+            FunctionSource::Flirt => write!(f, "flirt"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
+pub struct LocalFunction {
+    pub address:    VA,
+    /// the analysis passes that found this function.
+    pub sources:    BTreeSet<FunctionSource>,
+    /// how likely, as a percentage, is this function start to be correct?
+    /// combines the confidence of each of the sources.
+    pub confidence: u8,
+}
+
+impl LocalFunction {
+    pub fn new(address: VA, sources: BTreeSet<FunctionSource>) -> LocalFunction {
+        // the probability that at least one source is correct,
+        // assuming they're independent.
+        let incorrect = sources
+            .iter()
+            .fold(1.0f64, |acc, source| acc * (1.0 - (source.confidence() as f64 / 100.0)));
+        let confidence = ((1.0 - incorrect) * 100.0).round() as u8;
+
+        LocalFunction {
+            address,
+            sources,
+            confidence,
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
 pub enum Function {
-    Local(VA),
+    Local(LocalFunction),
     Thunk(Thunk),
     Import(Import),
 }
@@ -184,37 +319,210 @@ pub fn find_thunks(pe: &PE, imports: &BTreeMap<VA, Import>, functions: &HashSet<
     Ok(thunks)
 }
 
+/// the FLIRT signatures listed by the configuration, if any.
+/// load them once and pass them to each analysis pass that uses them,
+/// like function discovery and naming.
+#[derive(Default)]
+pub struct Signatures {
+    #[cfg(feature = "flirt")]
+    pub flirt: Option<lancelot_flirt::FlirtSignatureSet>,
+}
+
+impl Signatures {
+    pub fn from_config(config: &crate::config::Config) -> Result<Signatures> {
+        #[cfg(feature = "flirt")]
+        {
+            if config.flirt.signatures.is_empty() {
+                return Ok(Default::default());
+            }
+
+            Ok(Signatures {
+                flirt: Some(crate::analysis::flirt::load_flirt_signatures(&config.flirt.signatures)?),
+            })
+        }
+        #[cfg(not(feature = "flirt"))]
+        {
+            let _ = config;
+            Ok(Default::default())
+        }
+    }
+}
+
+/// collect the function starts found by each analysis pass,
+/// before any validation.
+#[cfg(feature = "disassembler")]
+pub fn find_function_candidates(pe: &PE) -> Result<BTreeMap<VA, BTreeSet<FunctionSource>>> {
+    find_function_candidates_with_config(pe, &Default::default())
+}
+
+/// collect the function starts found by each analysis pass
+/// enabled by the configuration, before any validation.
+#[cfg(feature = "disassembler")]
+pub fn find_function_candidates_with_config(
+    pe: &PE,
+    config: &Config,
+) -> Result<BTreeMap<VA, BTreeSet<FunctionSource>>> {
+    find_function_candidates_with_signatures(pe, config, &Signatures::from_config(config)?)
+}
+
+/// like `find_function_candidates_with_config`,
+/// but with the signatures already loaded.
+#[cfg(feature = "disassembler")]
+pub fn find_function_candidates_with_signatures(
+    pe: &PE,
+    config: &Config,
+    sigs: &Signatures,
+) -> Result<BTreeMap<VA, BTreeSet<FunctionSource>>> {
+    let mut candidates: BTreeMap<VA, BTreeSet<FunctionSource>> = Default::default();
+    let mut add = |source: FunctionSource, vas: Vec<VA>| {
+        for va in vas.into_iter() {
+            candidates.entry(va).or_default().insert(source);
+        }
+    };
+
+    if config.functions.entrypoint {
+        add(
+            FunctionSource::Entrypoint,
+            crate::analysis::pe::entrypoints::find_pe_entrypoint(pe)?,
+        );
+    }
+    if config.functions.exports {
+        add(
+            FunctionSource::Export,
+            crate::analysis::pe::exports::find_pe_exports(pe)?,
+        );
+    }
+    if config.functions.safeseh {
+        add(
+            FunctionSource::SafeSEH,
+            crate::analysis::pe::safeseh::find_pe_safeseh_handlers(pe)?,
+        );
+    }
+    if config.functions.runtime_functions {
+        add(
+            FunctionSource::RuntimeFunction,
+            crate::analysis::pe::runtime_functions::find_pe_runtime_functions(pe)?,
+        );
+    }
+    if config.functions.exception_handlers {
+        add(
+            FunctionSource::ExceptionHandler,
+            crate::analysis::pe::runtime_functions::find_pe_exception_handler_functions(pe)?,
+        );
+    }
+    if config.functions.cfguard {
+        add(
+            FunctionSource::CFGuard,
+            crate::analysis::pe::control_flow_guard::find_pe_cfguard_functions(pe)?,
+        );
+    }
+    if config.functions.call_targets {
+        add(
+            FunctionSource::CallTarget,
+            crate::analysis::pe::call_targets::find_pe_call_targets(pe)?,
+        );
+    }
+    if config.functions.prologues {
+        add(
+            FunctionSource::Prologue,
+            crate::analysis::pe::patterns::find_function_prologues(pe)?,
+        );
+    }
+    if config.functions.pointers {
+        add(
+            FunctionSource::Pointer,
+            crate::analysis::pe::pointers::find_pe_nonrelocated_executable_pointers(pe)?,
+        );
+    }
+    if config.functions.vtables {
+        add(
+            FunctionSource::Vtable,
+            crate::analysis::pe::rtti::find_pe_vtable_functions(pe)?,
+        );
+    }
+    if config.functions.cxx_exceptions {
+        add(
+            FunctionSource::CxxException,
+            crate::analysis::pe::cxx_exceptions::find_pe_cxx_exception_functions(pe)?,
+        );
+    }
+    #[cfg(feature = "flirt")]
+    {
+        if let (true, Some(sigs)) = (config.functions.flirt, &sigs.flirt) {
+            use crate::analysis::flirt;
+
+            // the scan doesn't check the references of the signatures,
+            // so resolve them like for the matches at known function starts,
+            // dropping the hits that fail.
+            let hits: Vec<VA> = flirt::scan_flirt_module(&pe.module, sigs)?.into_keys().collect();
+            add(
+                FunctionSource::Flirt,
+                flirt::match_flirt_module(&pe.module, sigs, &hits)
+                    .library_functions()
+                    .into_iter()
+                    .collect(),
+            );
+        }
+    }
+    #[cfg(not(feature = "flirt"))]
+    let _ = sigs;
+    debug!("functions: found {} function candidates", candidates.len());
+
+    Ok(candidates)
+}
+
 #[cfg(feature = "disassembler")]
 pub fn find_functions(pe: &PE) -> Result<Vec<Function>> {
+    find_functions_with_config(pe, &Default::default())
+}
+
+#[cfg(feature = "disassembler")]
+pub fn find_functions_with_config(pe: &PE, config: &Config) -> Result<Vec<Function>> {
+    find_functions_with_signatures(pe, config, &Signatures::from_config(config)?)
+}
+
+/// like `find_functions_with_config`, but with the signatures already loaded.
+#[cfg(feature = "disassembler")]
+pub fn find_functions_with_signatures(pe: &PE, config: &Config, sigs: &Signatures) -> Result<Vec<Function>> {
     let imports = get_imports(pe)?;
     debug!("imports: found {} imports", imports.len());
 
-    let mut function_starts: HashSet<VA> = Default::default();
-    function_starts.extend(crate::analysis::pe::entrypoints::find_pe_entrypoint(pe)?);
-    function_starts.extend(crate::analysis::pe::exports::find_pe_exports(pe)?);
-    function_starts.extend(crate::analysis::pe::safeseh::find_pe_safeseh_handlers(pe)?);
-    function_starts.extend(crate::analysis::pe::runtime_functions::find_pe_runtime_functions(pe)?);
-    function_starts.extend(crate::analysis::pe::control_flow_guard::find_pe_cfguard_functions(pe)?);
-    function_starts.extend(crate::analysis::pe::call_targets::find_pe_call_targets(pe)?);
-    function_starts.extend(crate::analysis::pe::patterns::find_function_prologues(pe)?);
-    function_starts.extend(crate::analysis::pe::pointers::find_pe_nonrelocated_executable_pointers(
-        pe,
-    )?);
-
-    // TODO: validate that the code looks ok
+    let mut candidates = find_function_candidates_with_signatures(pe, config, sigs)?;
+
+    // candidates found only by heuristics may be data,
+    // so check that their code looks ok.
+    if config.functions.validate {
+        let trusted = validation::find_pe_trusted_functions(pe, &candidates)?;
+        let heuristic: Vec<VA> = candidates
+            .iter()
+            .filter(|(_, sources)| !sources.iter().any(|source| source.is_trusted()))
+            .map(|(&va, _)| va)
+            .collect();
+        let valid: HashSet<VA> = validation::validate_functions(&pe.module, &trusted, &heuristic)?
+            .into_iter()
+            .collect();
+        debug!(
+            "functions: rejected {} function candidates",
+            heuristic.len() - valid.len()
+        );
+        candidates.retain(|va, sources| valid.contains(va) || sources.iter().any(|source| source.is_trusted()));
+    }
 
+    let function_starts: HashSet<VA> = candidates.keys().cloned().collect();
     let thunks = find_thunks(pe, &imports, &function_starts)?;
-    debug!("functions: found {} function candidates", function_starts.len());
     debug!("functions: found {} thunks", thunks.len());
 
-    let function_starts: Vec<_> = function_starts
-        .difference(&thunks.keys().cloned().collect())
-        .cloned()
-        .collect();
-    debug!("functions: found {} functions", function_starts.len());
+    candidates.retain(|va, _| !thunks.contains_key(va));
 
     let mut functions: Vec<Function> = Default::default();
-    functions.extend(function_starts.iter().map(|&f| Function::Local(f)));
+    functions.extend(
+        candidates
+            .into_iter()
+            .map(|(va, sources)| LocalFunction::new(va, sources))
+            .filter(|f| f.confidence >= config.functions.min_confidence)
+            .map(Function::Local),
+    );
+    debug!("functions: found {} functions", functions.len());
     functions.extend(thunks.values().cloned().map(Function::Thunk));
     functions.extend(imports.values().cloned().map(Function::Import));
     functions.sort_unstable();
@@ -224,11 +532,16 @@ pub fn find_functions(pe: &PE) -> Result<Vec<Function>> {
 
 #[cfg(feature = "disassembler")]
 pub fn find_function_starts(pe: &PE) -> Result<Vec<VA>> {
-    Ok(find_functions(pe)?
+    find_function_starts_with_config(pe, &Default::default())
+}
+
+#[cfg(feature = "disassembler")]
+pub fn find_function_starts_with_config(pe: &PE, config: &Config) -> Result<Vec<VA>> {
+    Ok(find_functions_with_config(pe, config)?
         .into_iter()
         .filter(|f| matches!(f, Function::Local(_)))
         .map(|f| match f {
-            Function::Local(va) => va,
+            Function::Local(f) => f.address,
             _ => unreachable!(),
         })
         .collect())
@@ -0,0 +1,373 @@
+//! Collect the names of addresses from the metadata of a PE and its analysis.
+//!
+//! An address may be named by more than one source, such as an export and a
+//! debug symbol, and the sources may disagree.
+//! So, we record all the names of an address, and pick the one from the most
+//! trusted source when rendering it. Addresses with disagreeing names are
+//! reported by `Names::conflicts`.
+//!
+//! The sources, from most to least trusted, are:
+//!
+//!   - names assigned by the user,
+//!   - debug symbols, from the COFF symbol table (emitted by MinGW, for
+//!     example),
+//!   - exports,
+//!   - imports and the thunks that jump to them,
+//!   - FLIRT signature matches, and
+//!   - RTTI vtables.
+use std::{collections::BTreeMap, ops::Range};
+
+use anyhow::Result;
+use goblin::pe::symbol::{IMAGE_SYM_CLASS_EXTERNAL, IMAGE_SYM_CLASS_STATIC, IMAGE_SYM_DTYPE_FUNCTION};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analysis::pe::{rtti, Function, Signatures},
+    config::Config,
+    loader::pe::PE,
+    RVA, VA,
+};
+
+/// the source of a name, ordered from the least to the most trusted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum NameSource {
+    Rtti,
+    Flirt,
+    Import,
+    Export,
+    Debug,
+    User,
+}
+
+impl std::fmt::Display for NameSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NameSource::Rtti => write!(f, "rtti"),
+            NameSource::Flirt => write!(f, "flirt"),
+            NameSource::Import => write!(f, "import"),
+            NameSource::Export => write!(f, "export"),
+            NameSource::Debug => write!(f, "debug"),
+            NameSource::User => write!(f, "user"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Name {
+    pub name:   String,
+    pub source: NameSource,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Names {
+    /// map from address to its names, from the most to the least trusted
+    /// source. names from the same source are in the order they were added.
+    names: BTreeMap<VA, Vec<Name>>,
+}
+
+impl Names {
+    /// record a name for the given address.
+    /// the address keeps any names it already has.
+    pub fn insert(&mut self, va: VA, name: &str, source: NameSource) {
+        let names = self.names.entry(va).or_default();
+        if names.iter().any(|n| n.source == source && n.name == name) {
+            return;
+        }
+
+        // after any names from the same source, so that the first one wins.
+        let index = names.iter().position(|n| n.source < source).unwrap_or(names.len());
+        names.insert(
+            index,
+            Name {
+                name: name.to_string(),
+                source,
+            },
+        );
+    }
+
+    /// forget the names from the given source for the given address,
+    /// such as before assigning a new name from the user.
+    pub fn remove(&mut self, va: VA, source: NameSource) {
+        if let Some(names) = self.names.get_mut(&va) {
+            names.retain(|n| n.source != source);
+            if names.is_empty() {
+                self.names.remove(&va);
+            }
+        }
+    }
+
+    /// fetch the name of the given address from the most trusted source.
+    pub fn get(&self, va: VA) -> Option<&Name> {
+        self.names.get(&va).and_then(|names| names.first())
+    }
+
+    /// fetch all the names of the given address, or an empty list.
+    pub fn get_all(&self, va: VA) -> &[Name] {
+        self.names.get(&va).map(|names| &names[..]).unwrap_or(&[])
+    }
+
+    /// iterate over the named addresses and their most trusted name, sorted
+    /// by address.
+    pub fn iter(&self) -> impl Iterator<Item = (VA, &Name)> {
+        self.names.iter().map(|(&va, names)| (va, &names[0]))
+    }
+
+    /// iterate over the named addresses in the given range and their most
+    /// trusted name, sorted by address.
+    pub fn range(&self, range: Range<VA>) -> impl Iterator<Item = (VA, &Name)> {
+        // unlike `BTreeMap::range`, don't panic when the range is reversed.
+        let end = std::cmp::max(range.start, range.end);
+        self.names.range(range.start..end).map(|(&va, names)| (va, &names[0]))
+    }
+
+    /// iterate over the addresses that have more than one distinct name,
+    /// and all their names.
+    pub fn conflicts(&self) -> impl Iterator<Item = (VA, &[Name])> {
+        self.names
+            .iter()
+            .filter(|(_, names)| names.iter().any(|n| n.name != names[0].name))
+            .map(|(&va, names)| (va, &names[..]))
+    }
+
+    /// the number of named addresses.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// record the names of the functions and locals matched by FLIRT
+    /// signatures, such as from `flirt::match_flirt_module`.
+    #[cfg(feature = "flirt")]
+    pub fn insert_flirt_matches(&mut self, matches: &crate::analysis::flirt::FlirtMatches) {
+        use lancelot_flirt::Symbol;
+
+        for (&va, sigs) in matches.matches.iter() {
+            for sig in sigs.iter() {
+                for symbol in sig.names.iter() {
+                    match symbol {
+                        Symbol::Public(name) | Symbol::Local(name) => {
+                            self.insert((va as i64 + name.offset) as VA, &name.name, NameSource::Flirt)
+                        }
+                        // references name other addresses, which are resolved elsewhere.
+                        Symbol::Reference(_) => continue,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// find the names of the exports, including data.
+/// re-exports are skipped, since they're names of other modules.
+pub fn find_pe_export_names(pe: &PE) -> Result<Vec<(VA, String)>> {
+    let base_address = pe.module.address_space.base_address;
+
+    Ok(pe
+        .pe()?
+        .exports
+        .iter()
+        .filter(|exp| exp.reexport.is_none())
+        .filter_map(|exp| exp.name.map(|name| (base_address + exp.rva as RVA, name.to_string())))
+        .collect())
+}
+
+/// find the names of functions in the COFF symbol table, if present.
+/// images are usually stripped of this, though MinGW leaves it in place.
+pub fn find_pe_debug_names(pe: &PE) -> Result<Vec<(VA, String)>> {
+    let coff_header = &pe.header.coff_header;
+    if coff_header.pointer_to_symbol_table == 0 || coff_header.number_of_symbol_table == 0 {
+        return Ok(vec![]);
+    }
+
+    let symbols = coff_header.symbols(&pe.buf)?;
+    let strings = coff_header.strings(&pe.buf)?;
+    let sections = pe.pe()?.sections;
+    let base_address = pe.module.address_space.base_address;
+
+    let mut names = vec![];
+    for (_, _, symbol) in symbols.iter() {
+        if symbol.storage_class != IMAGE_SYM_CLASS_EXTERNAL && symbol.storage_class != IMAGE_SYM_CLASS_STATIC {
+            continue;
+        }
+
+        if symbol.derived_type() != IMAGE_SYM_DTYPE_FUNCTION {
+            continue;
+        }
+
+        // section numbers are one-based,
+        // and zero and negative numbers are for absolute and external symbols.
+        let section = match sections.get((symbol.section_number as usize).wrapping_sub(1)) {
+            Some(section) if symbol.section_number > 0 => section,
+            _ => continue,
+        };
+
+        let name = match symbol.name(&strings) {
+            Ok(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+
+        let va = base_address + section.virtual_address as RVA + symbol.value as RVA;
+        debug!("names: debug symbol: {:#x}: {}", va, name);
+        names.push((va, name.to_string()));
+    }
+
+    Ok(names)
+}
+
+/// find the names of the vtables described by RTTI, like `Foo::vftable`.
+/// when a class has more than one vtable, the others are suffixed with the
+/// offset of their subobject, like `Foo::vftable_8`.
+pub fn find_pe_rtti_names(pe: &PE) -> Result<Vec<(VA, String)>> {
+    let mut names = vec![];
+
+    for class in rtti::find_pe_classes(pe)?.values() {
+        for vtable in class.vtables.iter() {
+            let name = if vtable.locator.offset == 0 {
+                format!("{}::vftable", class.name())
+            } else {
+                format!("{}::vftable_{:x}", class.name(), vtable.locator.offset)
+            };
+            names.push((vtable.address, name));
+        }
+    }
+
+    Ok(names)
+}
+
+/// collect the names of addresses in the PE from its metadata,
+/// including the given imports and thunks, such as from `find_functions`.
+pub fn find_pe_names(pe: &PE, functions: &[Function]) -> Result<Names> {
+    let mut names: Names = Default::default();
+
+    for (va, name) in find_pe_debug_names(pe)?.into_iter() {
+        names.insert(va, &name, NameSource::Debug);
+    }
+
+    for (va, name) in find_pe_export_names(pe)?.into_iter() {
+        names.insert(va, &name, NameSource::Export);
+    }
+
+    for function in functions.iter() {
+        match function {
+            Function::Import(import) => names.insert(import.address, &import.to_string(), NameSource::Import),
+            Function::Thunk(thunk) => names.insert(thunk.address, &thunk.import.to_string(), NameSource::Import),
+            Function::Local(_) => continue,
+        }
+    }
+
+    for (va, name) in find_pe_rtti_names(pe)?.into_iter() {
+        names.insert(va, &name, NameSource::Rtti);
+    }
+
+    debug!("names: found {} named addresses", names.len());
+    Ok(names)
+}
+
+/// collect the names of addresses in the PE from its metadata,
+/// including the given imports and thunks, such as from `find_functions`,
+/// and the matches of the FLIRT signatures in the configuration.
+pub fn find_pe_names_with_config(pe: &PE, functions: &[Function], config: &Config) -> Result<Names> {
+    find_pe_names_with_signatures(pe, functions, &Signatures::from_config(config)?)
+}
+
+/// like `find_pe_names_with_config`, but with the signatures already loaded.
+pub fn find_pe_names_with_signatures(pe: &PE, functions: &[Function], sigs: &Signatures) -> Result<Names> {
+    #[allow(unused_mut)]
+    let mut names = find_pe_names(pe, functions)?;
+
+    #[cfg(feature = "flirt")]
+    {
+        if let Some(sigs) = &sigs.flirt {
+            let starts: Vec<VA> = functions
+                .iter()
+                .filter_map(|f| match f {
+                    Function::Local(f) => Some(f.address),
+                    _ => None,
+                })
+                .collect();
+            names.insert_flirt_matches(&crate::analysis::flirt::match_flirt_module(&pe.module, sigs, &starts));
+        }
+    }
+    #[cfg(not(feature = "flirt"))]
+    let _ = sigs;
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analysis::pe::names::*, rsrc::*};
+    use anyhow::Result;
+
+    #[test]
+    fn priority() {
+        let mut names: Names = Default::default();
+        names.insert(0x1000, "sub_1000", NameSource::Flirt);
+        names.insert(0x1000, "foo", NameSource::Export);
+        names.insert(0x1000, "bar", NameSource::Export);
+        names.insert(0x1000, "foo", NameSource::Export);
+        names.insert(0x2000, "baz", NameSource::Rtti);
+
+        assert_eq!(names.len(), 2);
+        assert_eq!(names.get(0x1000).unwrap().name, "foo");
+        assert_eq!(names.get(0x1000).unwrap().source, NameSource::Export);
+        assert_eq!(names.get_all(0x1000).len(), 3);
+        assert!(names.get(0x3000).is_none());
+        assert!(names.get_all(0x3000).is_empty());
+
+        // the user always wins.
+        names.insert(0x1000, "user", NameSource::User);
+        assert_eq!(names.get(0x1000).unwrap().name, "user");
+        names.remove(0x1000, NameSource::User);
+        assert_eq!(names.get(0x1000).unwrap().name, "foo");
+
+        let conflicts: Vec<VA> = names.conflicts().map(|(va, _)| va).collect();
+        assert_eq!(conflicts, vec![0x1000]);
+
+        let range: Vec<VA> = names.range(0x1001..0x3000).map(|(va, _)| va).collect();
+        assert_eq!(range, vec![0x2000]);
+        // a reversed range is empty, rather than a panic.
+        let va: VA = 0x2000;
+        assert_eq!(names.range(va + 1..va).count(), 0);
+
+        names.remove(0x2000, NameSource::Rtti);
+        assert_eq!(names.len(), 1);
+    }
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        let functions = crate::analysis::pe::find_functions(&pe)?;
+        let names = find_pe_names(&pe, &functions)?;
+
+        // import
+        assert_eq!(
+            names.get(0x1800773F0).unwrap().name,
+            "KERNELBASE.dll!KernelBaseGetGlobalData"
+        );
+        assert_eq!(names.get(0x1800773F0).unwrap().source, NameSource::Import);
+
+        // thunk
+        assert_eq!(
+            names.get(0x180020576).unwrap().name,
+            "api-ms-win-core-rtlsupport-l1-1-0.dll!RtlLookupFunctionEntry"
+        );
+
+        // this thunk is also exported, and the export wins.
+        assert_eq!(names.get(0x180020570).unwrap().name, "RtlCaptureContext");
+        assert_eq!(names.get(0x180020570).unwrap().source, NameSource::Export);
+        assert_eq!(names.get_all(0x180020570)[1].source, NameSource::Import);
+        assert!(names.conflicts().any(|(va, _)| va == 0x180020570));
+
+        // kernel32 has no debug symbols or RTTI.
+        assert!(find_pe_debug_names(&pe)?.is_empty());
+        assert!(find_pe_rtti_names(&pe)?.is_empty());
+
+        Ok(())
+    }
+}
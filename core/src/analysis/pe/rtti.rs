@@ -0,0 +1,796 @@
+//! Recover C++ classes and their vtables from MSVC run-time type information
+//! (RTTI).
+//!
+//! MSVC emits, for each polymorphic class, a vtable that is preceded by a
+//! pointer to a `RTTICompleteObjectLocator`. The locator references:
+//!
+//!   - a `TypeDescriptor` that contains the mangled class name, like
+//!     `.?AVFoo@@`, and
+//!   - a `RTTIClassHierarchyDescriptor` that lists all the base classes (via
+//!     `RTTIBaseClassDescriptor`s), starting with the class itself.
+//!
+//! On x86, these structures reference each other via VAs. On x64, they use
+//! RVAs, and the locator contains its own RVA, which makes it easy to find.
+//!
+//! We scan the module for complete object locators, validate the structures
+//! they reference, and then scan for the vtables that point to the locators.
+//! Since virtual methods are often referenced only by vtables, the vtable
+//! entries are good function candidates.
+//!
+//! references:
+//!   - http://www.openrce.org/articles/full_view/23
+
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Result;
+use byteorder::{ByteOrder, LittleEndian};
+use log::debug;
+
+use crate::{
+    arch::Arch,
+    aspace::AddressSpace,
+    loader::pe::PE,
+    module::{Module, Permissions},
+    VA,
+};
+
+/// the maximum number of base classes we expect to see in a hierarchy.
+/// used to filter out garbage class hierarchy descriptors.
+const MAX_BASE_CLASSES: u32 = 0x400;
+
+/// the maximum number of entries we expect to see in a vtable.
+const MAX_VTABLE_ENTRIES: usize = 0x1000;
+
+#[derive(Debug, Clone)]
+pub struct TypeDescriptor {
+    pub address:      VA,
+    /// like `.?AVFoo@ns@@`.
+    pub mangled_name: String,
+    /// like `ns::Foo`. if the name can't be demangled, then the mangled name.
+    pub name:         String,
+}
+
+#[derive(Debug, Clone)]
+pub struct BaseClass {
+    pub type_descriptor: TypeDescriptor,
+    /// the number of base classes of this base class,
+    /// which follow it in the base class array.
+    pub contained_bases: u32,
+    /// the offset of the base class within the derived class,
+    /// when it isn't a virtual base.
+    pub mdisp:           i32,
+    /// the offset of the vbtable within the class, or -1 if the base isn't
+    /// virtual.
+    pub pdisp:           i32,
+    /// the offset of the base within the vbtable.
+    pub vdisp:           i32,
+    pub attributes:      u32,
+}
+
+impl BaseClass {
+    pub fn is_virtual(&self) -> bool {
+        self.pdisp != -1
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompleteObjectLocator {
+    pub address:         VA,
+    /// the offset of the vtable's subobject within the complete class.
+    pub offset:          u32,
+    /// the offset of the constructor displacement, used with virtual bases.
+    pub cd_offset:       u32,
+    pub type_descriptor: VA,
+    pub class_hierarchy: VA,
+}
+
+#[derive(Debug, Clone)]
+pub struct Vtable {
+    /// the address of the first entry.
+    /// the complete object locator is referenced by the pointer just before
+    /// this.
+    pub address: VA,
+    pub locator: CompleteObjectLocator,
+    pub entries: Vec<VA>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Class {
+    pub type_descriptor: TypeDescriptor,
+    /// the address of the class hierarchy descriptor.
+    pub class_hierarchy: VA,
+    /// the attributes of the class hierarchy,
+    /// like `CHD_MULTINH` (0x1) and `CHD_VIRTINH` (0x2).
+    pub attributes:      u32,
+    /// all the base classes, flattened in pre-order, excluding this class.
+    pub bases:           Vec<BaseClass>,
+    /// the vtables of the class, one per subobject with virtual methods.
+    pub vtables:         Vec<Vtable>,
+}
+
+impl Class {
+    pub fn name(&self) -> &str {
+        &self.type_descriptor.name
+    }
+
+    /// the classes from which this class directly inherits.
+    pub fn direct_bases(&self) -> Vec<&BaseClass> {
+        let mut direct = vec![];
+        let mut i = 0;
+        while i < self.bases.len() {
+            direct.push(&self.bases[i]);
+            i += self.bases[i].contained_bases as usize + 1;
+        }
+        direct
+    }
+}
+
+/// reads the RTTI structures, which reference each other via VAs on x86,
+/// and RVAs on x64.
+struct Reader<'a> {
+    module: &'a Module,
+}
+
+impl<'a> Reader<'a> {
+    fn read_i32(&self, va: VA) -> Result<i32> {
+        Ok(self.module.address_space.read_u32(va)? as i32)
+    }
+
+    fn read_u32(&self, va: VA) -> Result<u32> {
+        self.module.address_space.read_u32(va)
+    }
+
+    /// read a reference to another RTTI structure,
+    /// returning None if it doesn't point within the module.
+    fn read_reference(&self, va: VA) -> Result<Option<VA>> {
+        let target = match self.module.arch {
+            Arch::X32 => self.read_u32(va)? as VA,
+            Arch::X64 => self.module.address_space.base_address + self.read_u32(va)? as VA,
+        };
+
+        if self.module.probe_va(target, Permissions::R) {
+            Ok(Some(target))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn read_type_descriptor(&self, va: VA) -> Result<Option<TypeDescriptor>> {
+        // pVFTable, spare, then the name
+        let name_offset = 2 * self.module.arch.pointer_size() as VA;
+        let mangled_name = match self.module.address_space.read_ascii(va + name_offset, 4) {
+            Ok(name) => name,
+            Err(_) => return Ok(None),
+        };
+
+        if !mangled_name.starts_with(".?A") {
+            return Ok(None);
+        }
+
+        let name = demangle_type_name(&mangled_name).unwrap_or_else(|| mangled_name.clone());
+        Ok(Some(TypeDescriptor {
+            address: va,
+            mangled_name,
+            name,
+        }))
+    }
+
+    fn read_complete_object_locator(&self, va: VA) -> Result<Option<CompleteObjectLocator>> {
+        let signature = self.read_u32(va)?;
+        match (self.module.arch, signature) {
+            (Arch::X32, 0) => {}
+            (Arch::X64, 1) => {
+                // pSelf
+                if self.read_reference(va + 0x14)? != Some(va) {
+                    return Ok(None);
+                }
+            }
+            _ => return Ok(None),
+        }
+
+        let type_descriptor = match self.read_reference(va + 0xC)? {
+            Some(td) => td,
+            None => return Ok(None),
+        };
+        let class_hierarchy = match self.read_reference(va + 0x10)? {
+            Some(chd) => chd,
+            None => return Ok(None),
+        };
+
+        Ok(Some(CompleteObjectLocator {
+            address: va,
+            offset: self.read_u32(va + 0x4)?,
+            cd_offset: self.read_u32(va + 0x8)?,
+            type_descriptor,
+            class_hierarchy,
+        }))
+    }
+
+    fn read_base_class(&self, va: VA) -> Result<Option<BaseClass>> {
+        let type_descriptor = match self.read_reference(va)? {
+            Some(td) => match self.read_type_descriptor(td)? {
+                Some(td) => td,
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+
+        Ok(Some(BaseClass {
+            type_descriptor,
+            contained_bases: self.read_u32(va + 0x4)?,
+            mdisp: self.read_i32(va + 0x8)?,
+            pdisp: self.read_i32(va + 0xC)?,
+            vdisp: self.read_i32(va + 0x10)?,
+            attributes: self.read_u32(va + 0x14)?,
+        }))
+    }
+
+    /// read the class hierarchy descriptor at the given address,
+    /// returning its attributes and base classes, including the class itself.
+    fn read_class_hierarchy(&self, va: VA) -> Result<Option<(u32, Vec<BaseClass>)>> {
+        let signature = self.read_u32(va)?;
+        let attributes = self.read_u32(va + 0x4)?;
+        let count = self.read_u32(va + 0x8)?;
+        if signature != 0 || count == 0 || count > MAX_BASE_CLASSES {
+            return Ok(None);
+        }
+
+        let array = match self.read_reference(va + 0xC)? {
+            Some(array) => array,
+            None => return Ok(None),
+        };
+
+        let mut bases = vec![];
+        for i in 0..count as VA {
+            let bcd = match self.read_reference(array + 4 * i) {
+                Ok(Some(bcd)) => bcd,
+                _ => return Ok(None),
+            };
+            match self.read_base_class(bcd) {
+                Ok(Some(base)) => bases.push(base),
+                _ => return Ok(None),
+            }
+        }
+
+        // the total number of bases contained by the class
+        // must account for the rest of the array.
+        if bases[0].contained_bases + 1 != count {
+            return Ok(None);
+        }
+
+        Ok(Some((attributes, bases)))
+    }
+}
+
+/// find the C++ classes described by RTTI in the given module,
+/// indexed by the address of their type descriptor.
+pub fn find_classes(module: &Module) -> Result<BTreeMap<VA, Class>> {
+    let reader = Reader { module };
+    let psize = module.arch.pointer_size();
+
+    // first pass: find complete object locators.
+    let mut locators: BTreeMap<VA, CompleteObjectLocator> = Default::default();
+    for section in module.sections.iter() {
+        let vstart: VA = section.virtual_range.start;
+        let vsize = (section.virtual_range.end - section.virtual_range.start) as usize;
+        let sec_buf = module.address_space.read_bytes(vstart, vsize)?;
+
+        debug!(
+            "rtti: scanning section {:#x}-{:#x}",
+            section.virtual_range.start, section.virtual_range.end
+        );
+
+        let signature = match module.arch {
+            Arch::X32 => 0,
+            Arch::X64 => 1,
+        };
+
+        for (i, chunk) in sec_buf.chunks_exact(4).enumerate() {
+            // naive signature filter that is very fast
+            if LittleEndian::read_u32(chunk) != signature {
+                continue;
+            }
+
+            let va = vstart + 4 * i as VA;
+            if let Ok(Some(locator)) = reader.read_complete_object_locator(va) {
+                if let Ok(Some(_)) = reader.read_type_descriptor(locator.type_descriptor) {
+                    locators.insert(va, locator);
+                }
+            }
+        }
+    }
+    debug!("rtti: found {} complete object locator candidates", locators.len());
+
+    // second pass: find the vtables that reference the locators.
+    // the slots that point to locators also bound the preceding vtables.
+    let mut meta_slots: BTreeMap<VA, VA> = Default::default();
+    for section in module.sections.iter() {
+        let vstart: VA = section.virtual_range.start;
+        let vsize = (section.virtual_range.end - section.virtual_range.start) as usize;
+        let sec_buf = module.address_space.read_bytes(vstart, vsize)?;
+
+        for (i, chunk) in sec_buf.chunks_exact(psize).enumerate() {
+            let ptr = match module.arch {
+                Arch::X32 => LittleEndian::read_u32(chunk) as VA,
+                Arch::X64 => LittleEndian::read_u64(chunk) as VA,
+            };
+
+            if locators.contains_key(&ptr) {
+                meta_slots.insert(vstart + (psize * i) as VA, ptr);
+            }
+        }
+    }
+
+    let mut vtables: Vec<Vtable> = vec![];
+    for (&slot, locator) in meta_slots.iter() {
+        let address = slot + psize as VA;
+        let mut entries = vec![];
+        let mut va = address;
+        while entries.len() < MAX_VTABLE_ENTRIES && !meta_slots.contains_key(&va) {
+            match module.read_va_at_va(va) {
+                Ok(ptr) if ptr != 0 && module.probe_va(ptr, Permissions::X) => entries.push(ptr),
+                _ => break,
+            }
+            va += psize as VA;
+        }
+
+        if entries.is_empty() {
+            continue;
+        }
+
+        debug!("rtti: found vtable at {:#x} with {} entries", address, entries.len());
+        vtables.push(Vtable {
+            address,
+            locator: locators[locator].clone(),
+            entries,
+        });
+    }
+
+    // third pass: group the locators and vtables by class.
+    let mut classes: BTreeMap<VA, Class> = Default::default();
+    for locator in locators.values() {
+        if classes.contains_key(&locator.type_descriptor) {
+            continue;
+        }
+
+        // like the locators, these may be truncated by the end of the section,
+        // which shouldn't prevent the other classes from being found.
+        let type_descriptor = match reader.read_type_descriptor(locator.type_descriptor) {
+            Ok(Some(td)) => td,
+            _ => continue,
+        };
+        let (attributes, mut bases) = match reader.read_class_hierarchy(locator.class_hierarchy) {
+            Ok(Some(chd)) => chd,
+            _ => continue,
+        };
+        // the first base is the class itself.
+        bases.remove(0);
+
+        debug!(
+            "rtti: found class {} at {:#x}",
+            type_descriptor.name, type_descriptor.address
+        );
+        classes.insert(
+            locator.type_descriptor,
+            Class {
+                type_descriptor,
+                class_hierarchy: locator.class_hierarchy,
+                attributes,
+                bases,
+                vtables: vec![],
+            },
+        );
+    }
+
+    for vtable in vtables.into_iter() {
+        if let Some(class) = classes.get_mut(&vtable.locator.type_descriptor) {
+            class.vtables.push(vtable);
+        }
+    }
+
+    Ok(classes)
+}
+
+pub fn find_pe_classes(pe: &PE) -> Result<BTreeMap<VA, Class>> {
+    find_classes(&pe.module)
+}
+
+/// find the virtual methods referenced by vtables described by RTTI.
+pub fn find_pe_vtable_functions(pe: &PE) -> Result<Vec<VA>> {
+    let functions: HashSet<VA> = find_pe_classes(pe)?
+        .values()
+        .flat_map(|class| class.vtables.iter())
+        .flat_map(|vtable| vtable.entries.iter().cloned())
+        .collect();
+
+    debug!("rtti: found {} virtual methods", functions.len());
+    Ok(functions.into_iter().collect())
+}
+
+/// demangles the MSVC type names found in `TypeDescriptor`s,
+/// like `.?AV?$vector@HV?$allocator@H@std@@@std@@`.
+///
+/// this supports only the subset of the mangling scheme that's commonly used
+/// by type names, and not function signatures.
+struct Demangler<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Demangler<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.buf.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn consume(&mut self, prefix: &str) -> bool {
+        if self.buf[self.pos..].starts_with(prefix.as_bytes()) {
+            self.pos += prefix.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// read the identifier terminated by `@`.
+    fn identifier(&mut self) -> Option<String> {
+        let end = self.buf[self.pos..].iter().position(|&c| c == b'@')?;
+        let name = std::str::from_utf8(&self.buf[self.pos..self.pos + end]).ok()?;
+        self.pos += end + 1;
+        Some(name.to_string())
+    }
+
+    /// read a number, like `0` (1), `A@` (0), or `?BA@` (-16).
+    fn number(&mut self) -> Option<i64> {
+        let negative = self.consume("?");
+        let value = match self.next()? {
+            c @ b'0'..=b'9' => (c - b'0') as i64 + 1,
+            c @ b'A'..=b'P' => {
+                let mut value = (c - b'A') as i64;
+                loop {
+                    match self.next()? {
+                        b'@' => break,
+                        c @ b'A'..=b'P' => value = value * 16 + (c - b'A') as i64,
+                        _ => return None,
+                    }
+                }
+                value
+            }
+            _ => return None,
+        };
+
+        Some(if negative { -value } else { value })
+    }
+
+    /// read a name fragment, which may be a back reference or template
+    /// instance, recording new names in the back reference table.
+    fn fragment(&mut self, names: &mut Vec<String>) -> Option<String> {
+        if let Some(c @ b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+            return names.get((c - b'0') as usize).cloned();
+        }
+
+        let name = if self.consume("?$") {
+            // template arguments have their own back reference table.
+            let mut template_names = vec![];
+            let name = self.identifier()?;
+            template_names.push(name.clone());
+
+            let mut args = vec![];
+            while !self.consume("@") {
+                args.push(self.ty(&mut template_names)?);
+            }
+
+            let args = args.join(",");
+            if args.ends_with('>') {
+                format!("{}<{} >", name, args)
+            } else {
+                format!("{}<{}>", name, args)
+            }
+        } else if self.consume("?A") {
+            self.identifier()?;
+            String::from("`anonymous namespace'")
+        } else {
+            self.identifier()?
+        };
+
+        if names.len() < 10 {
+            names.push(name.clone());
+        }
+        Some(name)
+    }
+
+    /// read a qualified name, like `Foo@ns@@` (`ns::Foo`).
+    fn qualified_name(&mut self, names: &mut Vec<String>) -> Option<String> {
+        let mut fragments = vec![];
+        while !self.consume("@") {
+            fragments.push(self.fragment(names)?);
+        }
+        fragments.reverse();
+        Some(fragments.join("::"))
+    }
+
+    fn ty(&mut self, names: &mut Vec<String>) -> Option<String> {
+        let ty = match self.next()? {
+            b'C' => "signed char",
+            b'D' => "char",
+            b'E' => "unsigned char",
+            b'F' => "short",
+            b'G' => "unsigned short",
+            b'H' => "int",
+            b'I' => "unsigned int",
+            b'J' => "long",
+            b'K' => "unsigned long",
+            b'M' => "float",
+            b'N' => "double",
+            b'O' => "long double",
+            b'X' => "void",
+            b'_' => match self.next()? {
+                b'N' => "bool",
+                b'J' => "__int64",
+                b'K' => "unsigned __int64",
+                b'S' => "char16_t",
+                b'U' => "char32_t",
+                b'W' => "wchar_t",
+                _ => return None,
+            },
+            b'V' => return Some(format!("class {}", self.qualified_name(names)?)),
+            b'U' => return Some(format!("struct {}", self.qualified_name(names)?)),
+            b'T' => return Some(format!("union {}", self.qualified_name(names)?)),
+            b'W' => {
+                // the underlying type of the enum.
+                self.next()?;
+                return Some(format!("enum {}", self.qualified_name(names)?));
+            }
+            c @ b'P' | c @ b'Q' | c @ b'A' => {
+                // __ptr64
+                self.consume("E");
+                let cv = match self.next()? {
+                    b'A' => "",
+                    b'B' => " const",
+                    b'C' => " volatile",
+                    b'D' => " const volatile",
+                    _ => return None,
+                };
+                let pointee = self.ty(names)?;
+                let sigil = if c == b'A' { "&" } else { "*" };
+                return Some(format!("{}{} {}", pointee, cv, sigil));
+            }
+            b'$' => match self.next()? {
+                b'0' => return Some(format!("{}", self.number()?)),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        Some(ty.to_string())
+    }
+}
+
+/// demangle the name found in a `TypeDescriptor`.
+///
+/// ```
+/// use lancelot::analysis::pe::rtti::demangle_type_name;
+/// assert_eq!(demangle_type_name(".?AVFoo@ns@@").unwrap(), "ns::Foo");
+/// assert_eq!(
+///     demangle_type_name(".?AV?$vector@HV?$allocator@H@std@@@std@@").unwrap(),
+///     "std::vector<int,class std::allocator<int> >"
+/// );
+/// ```
+pub fn demangle_type_name(name: &str) -> Option<String> {
+    let mut demangler = Demangler {
+        buf: name.as_bytes(),
+        pos: 0,
+    };
+
+    if !demangler.consume(".?A") {
+        return None;
+    }
+
+    match demangler.next()? {
+        b'V' | b'U' | b'T' => {}
+        b'W' => {
+            demangler.next()?;
+        }
+        _ => return None,
+    }
+
+    let name = demangler.qualified_name(&mut vec![])?;
+    if demangler.pos != demangler.buf.len() {
+        return None;
+    }
+
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analysis::pe::rtti::*, test::*};
+    use anyhow::Result;
+    use byteorder::{ByteOrder, LittleEndian};
+
+    #[test]
+    fn demangle() {
+        assert_eq!(demangle_type_name(".?AVFoo@@").unwrap(), "Foo");
+        assert_eq!(demangle_type_name(".?AUBar@ns@@").unwrap(), "ns::Bar");
+        assert_eq!(demangle_type_name(".?AW4Color@@").unwrap(), "Color");
+        assert_eq!(
+            demangle_type_name(".?AVFoo@?A0x12345678@@").unwrap(),
+            "`anonymous namespace'::Foo"
+        );
+        assert_eq!(
+            demangle_type_name(".?AV?$basic_ostream@DU?$char_traits@D@std@@@std@@").unwrap(),
+            "std::basic_ostream<char,struct std::char_traits<char> >"
+        );
+        assert_eq!(
+            demangle_type_name(".?AV?$_Ref_count_obj@PEAVFoo@@@std@@").unwrap(),
+            "std::_Ref_count_obj<class Foo *>"
+        );
+        assert_eq!(demangle_type_name(".?AV?$Buffer@$0BA@@@").unwrap(), "Buffer<16>");
+        // back reference to `Foo`.
+        assert_eq!(
+            demangle_type_name(".?AV?$Pair@VFoo@@V1@@@").unwrap(),
+            "Pair<class Foo,class Foo>"
+        );
+
+        assert!(demangle_type_name("Foo").is_none());
+        assert!(demangle_type_name(".?AVFoo@").is_none());
+    }
+
+    /// code:
+    ///   0x10: Base::f
+    ///   0x14: Base::g
+    ///   0x18: Child::g
+    ///
+    /// data:
+    ///   0x100: TypeDescriptor Base
+    ///   0x120: TypeDescriptor ns::Child
+    ///   0x140: BaseClassDescriptor Child
+    ///   0x160: BaseClassDescriptor Base
+    ///   0x180: ClassHierarchyDescriptor Child, base class array at 0x190
+    ///   0x1A0: ClassHierarchyDescriptor Base, base class array at 0x1B0
+    ///   0x1C0: CompleteObjectLocator Child
+    ///   0x1E0: CompleteObjectLocator Base
+    ///   0x200: vtable Base, two entries
+    ///   0x200 + 3 * psize: vtable Child, two entries
+    ///
+    /// the structures reference each other via VAs on x86 and RVAs on x64,
+    /// which are the same, since the image base is 0x0.
+    fn build(arch: Arch) -> Vec<u8> {
+        let mut buf = vec![0xCCu8; 0x100];
+        buf[0x10] = 0xC3;
+        buf[0x14] = 0xC3;
+        buf[0x18] = 0xC3;
+        buf.resize(0x240, 0x0);
+
+        let psize = arch.pointer_size();
+        let write_u32 = |buf: &mut Vec<u8>, offset: usize, v: u32| LittleEndian::write_u32(&mut buf[offset..], v);
+        let write_ptr = |buf: &mut Vec<u8>, offset: usize, v: u64| match arch {
+            Arch::X32 => LittleEndian::write_u32(&mut buf[offset..], v as u32),
+            Arch::X64 => LittleEndian::write_u64(&mut buf[offset..], v),
+        };
+
+        // type descriptors
+        buf[0x100 + 2 * psize..][..10].copy_from_slice(b".?AVBase@@");
+        buf[0x120 + 2 * psize..][..14].copy_from_slice(b".?AVChild@ns@@");
+
+        // base class descriptors
+        for &(offset, td, contained, chd) in [(0x140, 0x120, 1, 0x180), (0x160, 0x100, 0, 0x1A0)].iter() {
+            write_u32(&mut buf, offset, td);
+            write_u32(&mut buf, offset + 0x4, contained);
+            write_u32(&mut buf, offset + 0x8, 0);
+            write_u32(&mut buf, offset + 0xC, 0xFFFF_FFFF);
+            write_u32(&mut buf, offset + 0x10, 0);
+            // BCD_HASPCHD
+            write_u32(&mut buf, offset + 0x14, 0x40);
+            write_u32(&mut buf, offset + 0x18, chd);
+        }
+
+        // class hierarchy descriptors and base class arrays
+        write_u32(&mut buf, 0x188, 2);
+        write_u32(&mut buf, 0x18C, 0x190);
+        write_u32(&mut buf, 0x190, 0x140);
+        write_u32(&mut buf, 0x194, 0x160);
+        write_u32(&mut buf, 0x1A8, 1);
+        write_u32(&mut buf, 0x1AC, 0x1B0);
+        write_u32(&mut buf, 0x1B0, 0x160);
+
+        // complete object locators
+        for &(offset, td, chd) in [(0x1C0, 0x120, 0x180), (0x1E0, 0x100, 0x1A0)].iter() {
+            if let Arch::X64 = arch {
+                write_u32(&mut buf, offset, 1);
+                write_u32(&mut buf, offset + 0x14, offset as u32);
+            }
+            write_u32(&mut buf, offset + 0xC, td);
+            write_u32(&mut buf, offset + 0x10, chd);
+        }
+
+        // vtables
+        let base = 0x200;
+        write_ptr(&mut buf, base, 0x1E0);
+        write_ptr(&mut buf, base + psize, 0x10);
+        write_ptr(&mut buf, base + 2 * psize, 0x14);
+        let child = base + 3 * psize;
+        write_ptr(&mut buf, child, 0x1C0);
+        write_ptr(&mut buf, child + psize, 0x10);
+        write_ptr(&mut buf, child + 2 * psize, 0x18);
+
+        buf
+    }
+
+    fn check(arch: Arch) -> Result<()> {
+        let module = load_shellcode(arch, &build(arch));
+        let classes = find_classes(&module)?;
+        let psize = arch.pointer_size() as VA;
+
+        assert_eq!(classes.len(), 2);
+
+        let base = &classes[&0x100];
+        assert_eq!(base.name(), "Base");
+        assert_eq!(base.type_descriptor.mangled_name, ".?AVBase@@");
+        assert_eq!(base.bases.len(), 0);
+        assert_eq!(base.vtables.len(), 1);
+        assert_eq!(base.vtables[0].address, 0x200 + psize);
+        assert_eq!(base.vtables[0].locator.address, 0x1E0);
+        // the entries stop at the start of the next vtable.
+        assert_eq!(base.vtables[0].entries, vec![0x10, 0x14]);
+
+        let child = &classes[&0x120];
+        assert_eq!(child.name(), "ns::Child");
+        assert_eq!(child.bases.len(), 1);
+        assert!(!child.bases[0].is_virtual());
+        assert_eq!(child.direct_bases().len(), 1);
+        assert_eq!(child.direct_bases()[0].type_descriptor.name, "Base");
+        assert_eq!(child.vtables.len(), 1);
+        assert_eq!(child.vtables[0].address, 0x200 + 4 * psize);
+        assert_eq!(child.vtables[0].entries, vec![0x10, 0x18]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn x32() -> Result<()> {
+        check(Arch::X32)
+    }
+
+    #[test]
+    fn x64() -> Result<()> {
+        check(Arch::X64)
+    }
+
+    #[test]
+    fn truncated() -> Result<()> {
+        for &arch in [Arch::X32, Arch::X64].iter() {
+            let mut buf = build(arch);
+            buf.resize(0x2000, 0x0);
+            // point the class hierarchy of the Child locator at the end of the section,
+            // which extends beyond the mapped data, so that the hierarchy can't be read.
+            LittleEndian::write_u32(&mut buf[0x1C0 + 0x10..], 0x3000 - 4);
+
+            let mut module = load_shellcode(arch, &buf);
+            module.sections[0].virtual_range.end = 0x3000;
+            assert!(module.address_space.read_u32(0x3000 - 4 + 0x8).is_err());
+
+            let classes = find_classes(&module)?;
+            assert_eq!(classes.len(), 1);
+            assert_eq!(classes[&0x100].name(), "Base");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn k32() -> Result<()> {
+        // kernel32 is written in C, so there's no RTTI.
+        let buf = crate::rsrc::get_buf(crate::rsrc::Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(find_pe_classes(&pe)?.len(), 0);
+
+        Ok(())
+    }
+}
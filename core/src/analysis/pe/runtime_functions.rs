@@ -1,5 +1,5 @@
 /// > Table-based exception handling requires a table entry for all functions
-/// >  that allocate stack space or call another function (for example, nonleaf
+/// > that allocate stack space or call another function (for example, nonleaf
 /// functions). > The RUNTIME_FUNCTION structure must be DWORD aligned in
 /// memory. > All addresses are image relative, that is, they're 32-bit offsets
 /// from > the starting address of the image that contains the function table
@@ -19,7 +19,6 @@ use crate::{
     module::Permissions,
     RVA, VA,
 };
-use byteorder::ByteOrder;
 
 #[derive(Debug, Error)]
 pub enum RuntimeFunctionError {
@@ -31,29 +30,185 @@ pub enum RuntimeFunctionError {
     InvalidUnwindInfo,
 }
 
-#[allow(dead_code)]
-enum UnwindInfoData {
-    ExceptionHandler { rva: RVA },
+// https://docs.microsoft.com/en-us/windows/win32/api/winnt/nf-winnt-rtlvirtualunwind
+pub const UNW_FLAG_EHANDLER: u8 = 0x1;
+pub const UNW_FLAG_UHANDLER: u8 = 0x2;
+pub const UNW_FLAG_CHAININFO: u8 = 0x4;
+
+/// the name of the register with the given number, as used by unwind codes.
+pub fn get_register_name(reg: u8) -> &'static str {
+    match reg {
+        0 => "rax",
+        1 => "rcx",
+        2 => "rdx",
+        3 => "rbx",
+        4 => "rsp",
+        5 => "rbp",
+        6 => "rsi",
+        7 => "rdi",
+        8 => "r8",
+        9 => "r9",
+        10 => "r10",
+        11 => "r11",
+        12 => "r12",
+        13 => "r13",
+        14 => "r14",
+        15 => "r15",
+        _ => "?",
+    }
+}
+
+/// an operation performed by the prologue.
+///
+/// `offset` is the offset of the end of the instruction from the start of the
+/// prologue, and `reg` is the number of a register, see `get_register_name`.
+///
+/// ref: https://docs.microsoft.com/en-us/cpp/build/exception-handling-x64#unwind-operation-code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnwindCode {
+    /// UWOP_PUSH_NONVOL: push a nonvolatile register.
+    PushNonvol {
+        offset: u8,
+        reg:    u8,
+    },
+    /// UWOP_ALLOC_LARGE: allocate a large area on the stack.
+    AllocLarge {
+        offset: u8,
+        size:   u32,
+    },
+    /// UWOP_ALLOC_SMALL: allocate 8 to 128 bytes on the stack.
+    AllocSmall {
+        offset: u8,
+        size:   u32,
+    },
+    /// UWOP_SET_FPREG: establish the frame pointer register.
+    SetFpreg {
+        offset: u8,
+    },
+    /// UWOP_SAVE_NONVOL, UWOP_SAVE_NONVOL_FAR: save a nonvolatile register
+    /// with a MOV, at the given offset from the stack pointer.
+    SaveNonvol {
+        offset:       u8,
+        reg:          u8,
+        stack_offset: u32,
+    },
+    /// UWOP_SAVE_XMM128, UWOP_SAVE_XMM128_FAR: save all 128 bits of a
+    /// nonvolatile XMM register, at the given offset from the stack pointer.
+    SaveXmm128 {
+        offset:       u8,
+        reg:          u8,
+        stack_offset: u32,
+    },
+    /// UWOP_PUSH_MACHFRAME: push a machine frame, as done by hardware
+    /// interrupts and exceptions.
+    PushMachframe {
+        offset:     u8,
+        error_code: bool,
+    },
+    /// UWOP_EPILOG (version 2): describes the epilogs of the function.
+    /// the first code gives the size of the epilogs, and whether there's one
+    /// at the end of the function. the rest give the offset of each epilog
+    /// from the end of the function; an offset of zero is padding.
+    Epilog {
+        size:   u8,
+        at_end: bool,
+    },
+    EpilogOffset {
+        offset: u16,
+    },
+    /// UWOP_SAVE_XMM, UWOP_SAVE_XMM_FAR, and UWOP_SPARE_CODE: obsolete codes
+    /// that we skip over.
+    Unknown {
+        offset: u8,
+        op:     u8,
+        info:   u8,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum UnwindInfoData {
+    None,
+    /// the address of the exception handler, and the language-specific
+    /// handler data that follows it.
+    ExceptionHandler {
+        handler: VA,
+        data:    VA,
+    },
+    /// the primary RUNTIME_FUNCTION that this unwind info extends.
     ChainedUnwindInfo(RuntimeFunction),
 }
 
-#[allow(dead_code)]
-struct UnwindInfo {
-    version:               u8,
-    flags:                 u8,
-    prologue_size:         u8,
-    code_count:            u8,
-    frame_register:        u8,
-    frame_register_offset: u8,
-    unwind_codes:          Vec<u16>,
-    data:                  UnwindInfoData,
+#[derive(Debug, Clone)]
+pub struct UnwindInfo {
+    pub address:               VA,
+    pub version:               u8,
+    pub flags:                 u8,
+    pub prologue_size:         u8,
+    /// the number of the frame register, or None if there's no frame pointer.
+    pub frame_register:        Option<u8>,
+    /// the offset from RSP at which the frame register is established, in
+    /// bytes.
+    pub frame_register_offset: u32,
+    pub codes:                 Vec<UnwindCode>,
+    pub data:                  UnwindInfoData,
 }
 
-#[allow(dead_code)]
-struct RuntimeFunction {
-    function_start:      VA,
-    function_end:        VA,
-    unwind_info_address: VA,
+impl UnwindInfo {
+    /// the number of bytes allocated by the prologue, excluding pushes.
+    pub fn stack_allocation(&self) -> u32 {
+        self.codes
+            .iter()
+            .map(|code| match code {
+                UnwindCode::AllocLarge { size, .. } => *size,
+                UnwindCode::AllocSmall { size, .. } => *size,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// the number of bytes between the return address and the stack pointer
+    /// after the prologue, including pushes and allocations.
+    pub fn frame_size(&self) -> u32 {
+        self.stack_allocation()
+            + self
+                .codes
+                .iter()
+                .map(|code| match code {
+                    UnwindCode::PushNonvol { .. } => 8,
+                    // SS, RSP, EFLAGS, CS, RIP, and maybe an error code,
+                    // less the return address that's otherwise implied.
+                    UnwindCode::PushMachframe { error_code: false, .. } => 0x28 - 8,
+                    UnwindCode::PushMachframe { error_code: true, .. } => 0x30 - 8,
+                    _ => 0,
+                })
+                .sum::<u32>()
+    }
+
+    /// the nonvolatile registers pushed or saved by the prologue.
+    pub fn saved_registers(&self) -> Vec<u8> {
+        self.codes
+            .iter()
+            .filter_map(|code| match code {
+                UnwindCode::PushNonvol { reg, .. } => Some(*reg),
+                UnwindCode::SaveNonvol { reg, .. } => Some(*reg),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn exception_handler(&self) -> Option<VA> {
+        match self.data {
+            UnwindInfoData::ExceptionHandler { handler, .. } => Some(handler),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuntimeFunction {
+    pub function_start:      VA,
+    pub function_end:        VA,
+    pub unwind_info_address: VA,
 }
 
 /// Read the RUNTIME_FUNCTION structure at the given address,
@@ -84,12 +239,143 @@ fn read_runtime_function(pe: &PE, offset: VA) -> Result<Option<RuntimeFunction>>
     }))
 }
 
-fn read_unwind_info(pe: &PE, offset: VA) -> Result<UnwindInfo> {
+/// decode the array of unwind codes, each of which takes one or more slots.
+fn read_unwind_codes(version: u8, slots: &[u16]) -> Result<Vec<UnwindCode>> {
+    const UWOP_PUSH_NONVOL: u8 = 0;
+    const UWOP_ALLOC_LARGE: u8 = 1;
+    const UWOP_ALLOC_SMALL: u8 = 2;
+    const UWOP_SET_FPREG: u8 = 3;
+    const UWOP_SAVE_NONVOL: u8 = 4;
+    const UWOP_SAVE_NONVOL_FAR: u8 = 5;
+    // version 1: UWOP_SAVE_XMM
+    const UWOP_EPILOG: u8 = 6;
+    // version 1: UWOP_SAVE_XMM_FAR
+    const UWOP_SPARE_CODE: u8 = 7;
+    const UWOP_SAVE_XMM128: u8 = 8;
+    const UWOP_SAVE_XMM128_FAR: u8 = 9;
+    const UWOP_PUSH_MACHFRAME: u8 = 10;
+
+    let slot = |i: usize| -> Result<u32> {
+        slots
+            .get(i)
+            .map(|&v| v as u32)
+            .ok_or_else(|| RuntimeFunctionError::InvalidUnwindInfo.into())
+    };
+
+    let mut codes = vec![];
+    let mut i = 0;
+    // epilog codes follow the first UWOP_EPILOG, until another operation.
+    let mut in_epilog = false;
+    while i < slots.len() {
+        let offset = (slots[i] & 0xFF) as u8;
+        let op = ((slots[i] >> 8) & 0xF) as u8;
+        let info = (slots[i] >> 12) as u8;
+
+        let (code, count) = match op {
+            UWOP_PUSH_NONVOL => (UnwindCode::PushNonvol { offset, reg: info }, 1),
+            UWOP_ALLOC_LARGE if info == 0 => (
+                UnwindCode::AllocLarge {
+                    offset,
+                    size: slot(i + 1)? * 8,
+                },
+                2,
+            ),
+            UWOP_ALLOC_LARGE if info == 1 => (
+                UnwindCode::AllocLarge {
+                    offset,
+                    size: slot(i + 1)? | (slot(i + 2)? << 16),
+                },
+                3,
+            ),
+            UWOP_ALLOC_LARGE => return Err(RuntimeFunctionError::InvalidUnwindInfo.into()),
+            UWOP_ALLOC_SMALL => (
+                UnwindCode::AllocSmall {
+                    offset,
+                    size: info as u32 * 8 + 8,
+                },
+                1,
+            ),
+            UWOP_SET_FPREG => (UnwindCode::SetFpreg { offset }, 1),
+            UWOP_SAVE_NONVOL => (
+                UnwindCode::SaveNonvol {
+                    offset,
+                    reg: info,
+                    stack_offset: slot(i + 1)? * 8,
+                },
+                2,
+            ),
+            UWOP_SAVE_NONVOL_FAR => (
+                UnwindCode::SaveNonvol {
+                    offset,
+                    reg: info,
+                    stack_offset: slot(i + 1)? | (slot(i + 2)? << 16),
+                },
+                3,
+            ),
+            UWOP_EPILOG if version >= 2 && !in_epilog => {
+                in_epilog = true;
+                (
+                    UnwindCode::Epilog {
+                        size:   offset,
+                        at_end: info & 0x1 == 0x1,
+                    },
+                    1,
+                )
+            }
+            UWOP_EPILOG if version >= 2 => (
+                UnwindCode::EpilogOffset {
+                    offset: offset as u16 | ((info as u16) << 8),
+                },
+                1,
+            ),
+            UWOP_EPILOG => (UnwindCode::Unknown { offset, op, info }, 2),
+            UWOP_SPARE_CODE => (UnwindCode::Unknown { offset, op, info }, 3),
+            UWOP_SAVE_XMM128 => (
+                UnwindCode::SaveXmm128 {
+                    offset,
+                    reg: info,
+                    stack_offset: slot(i + 1)? * 16,
+                },
+                2,
+            ),
+            UWOP_SAVE_XMM128_FAR => (
+                UnwindCode::SaveXmm128 {
+                    offset,
+                    reg: info,
+                    stack_offset: slot(i + 1)? | (slot(i + 2)? << 16),
+                },
+                3,
+            ),
+            UWOP_PUSH_MACHFRAME => (
+                UnwindCode::PushMachframe {
+                    offset,
+                    error_code: info == 1,
+                },
+                1,
+            ),
+            _ => return Err(RuntimeFunctionError::InvalidUnwindInfo.into()),
+        };
+
+        if op != UWOP_EPILOG {
+            in_epilog = false;
+        }
+
+        codes.push(code);
+        i += count;
+    }
+
+    Ok(codes)
+}
+
+/// Read the UNWIND_INFO structure at the given address.
+///
+/// ref: https://docs.microsoft.com/en-us/cpp/build/exception-handling-x64#struct-unwind_info
+pub fn read_unwind_info(pe: &PE, offset: VA) -> Result<UnwindInfo> {
     let hdr = pe.module.address_space.read_bytes(offset, 4)?;
     let version = hdr[0] & 0b0000_0111;
     let flags = (hdr[0] & 0b1111_1000) >> 3;
 
-    if version != 0x1 {
+    if version != 0x1 && version != 0x2 {
         return Err(RuntimeFunctionError::UnsupportedUnwindInfoVersion.into());
     }
 
@@ -98,19 +384,19 @@ fn read_unwind_info(pe: &PE, offset: VA) -> Result<UnwindInfo> {
     let frame_register = hdr[3] & 0b0000_1111;
     let frame_register_offset = (hdr[3] & 0b1111_0000) >> 4;
 
-    let unwind_codes = pe
+    let slots: Vec<u16> = pe
         .module
         .address_space
         .read_bytes(offset + 4, 2 * code_count as usize)?
-        .windows(2)
-        .map(|b| byteorder::LittleEndian::read_u16(b))
+        .chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
         .collect();
+    let codes = read_unwind_codes(version, &slots)?;
 
-    // https://docs.microsoft.com/en-us/windows/win32/api/winnt/nf-winnt-rtlvirtualunwind
-    const UNW_FLAG_CHAININFO: u8 = 0x4;
-
-    let data_address = offset + 4 + 2 * code_count as RVA;
-    let data = if flags == UNW_FLAG_CHAININFO {
+    // > For alignment purposes, this array always has an even number of entries,
+    // > and the final entry is potentially unused.
+    let data_address = offset + 4 + 2 * ((code_count as RVA + 1) & !1);
+    let data = if flags & UNW_FLAG_CHAININFO != 0 {
         // > If the UNW_FLAG_CHAININFO flag is set,
         // > then an unwind info structure is a secondary one,
         // > and the shared exception-handler/chained-info
@@ -130,25 +416,33 @@ fn read_unwind_info(pe: &PE, offset: VA) -> Result<UnwindInfo> {
             Some(runtime_function) => UnwindInfoData::ChainedUnwindInfo(runtime_function),
             None => return Err(RuntimeFunctionError::InvalidUnwindInfo.into()),
         }
-    } else {
+    } else if flags & (UNW_FLAG_EHANDLER | UNW_FLAG_UHANDLER) != 0 {
         UnwindInfoData::ExceptionHandler {
-            rva: pe.module.address_space.read_u32(data_address)? as RVA,
+            handler: pe.module.address_space.base_address + pe.module.address_space.read_u32(data_address)? as RVA,
+            data:    data_address + 4,
         }
+    } else {
+        UnwindInfoData::None
     };
 
     Ok(UnwindInfo {
+        address: offset,
         version,
         flags,
         prologue_size,
-        code_count,
-        frame_register,
-        frame_register_offset,
-        unwind_codes,
+        frame_register: if frame_register == 0 {
+            None
+        } else {
+            Some(frame_register)
+        },
+        frame_register_offset: frame_register_offset as u32 * 16,
+        codes,
         data,
     })
 }
 
-pub fn find_pe_runtime_functions(pe: &PE) -> Result<Vec<VA>> {
+/// Read the entries of the exception directory (.pdata).
+pub fn read_pe_runtime_functions(pe: &PE) -> Result<Vec<RuntimeFunction>> {
     let mut ret = vec![];
 
     if !matches!(pe.module.arch, Arch::X64) {
@@ -163,23 +457,7 @@ pub fn find_pe_runtime_functions(pe: &PE) -> Result<Vec<VA>> {
             .step_by(sizeof_RUNTIME_FUNCTION)
         {
             if let Some(runtime_function) = read_runtime_function(pe, va)? {
-                let mut unwind_info = read_unwind_info(pe, runtime_function.unwind_info_address)?;
-
-                // if the UNWIND_INFO is chained,
-                // keep following it until it reaches the "primary entry".
-                while let UnwindInfoData::ChainedUnwindInfo(runtime_function) = unwind_info.data {
-                    debug!("pdata: found chained UNWIND_INFO");
-                    unwind_info = read_unwind_info(pe, runtime_function.unwind_info_address)?;
-                }
-
-                if !pe.module.probe_va(runtime_function.function_start, Permissions::X) {
-                    return Err(RuntimeFunctionError::InvalidRuntimeFunction.into());
-                }
-
-                let function = runtime_function.function_start;
-
-                debug!("pdata: found RUNTIME_FUNCTION: {:#x}", function);
-                ret.push(function);
+                ret.push(runtime_function);
             } else {
                 // just read an entry filled with zeros.
                 // assume this means we reached the end of the table.
@@ -191,6 +469,115 @@ pub fn find_pe_runtime_functions(pe: &PE) -> Result<Vec<VA>> {
     Ok(ret)
 }
 
+/// Read the unwind info of the given RUNTIME_FUNCTION, and if its chained,
+/// keep following it until it reaches the "primary entry".
+///
+/// Returns the primary RUNTIME_FUNCTION and its unwind info.
+pub fn read_primary_unwind_info(pe: &PE, runtime_function: &RuntimeFunction) -> Result<(RuntimeFunction, UnwindInfo)> {
+    let mut runtime_function = *runtime_function;
+    let mut unwind_info = read_unwind_info(pe, runtime_function.unwind_info_address)?;
+
+    while let UnwindInfoData::ChainedUnwindInfo(primary) = unwind_info.data {
+        debug!("pdata: found chained UNWIND_INFO");
+        unwind_info = read_unwind_info(pe, primary.unwind_info_address)?;
+        runtime_function = primary;
+    }
+
+    Ok((runtime_function, unwind_info))
+}
+
+/// Read the unwind info of each function described by the exception directory,
+/// indexed by function start.
+///
+/// This describes the exact stack frame of each function.
+/// Chained entries, which describe the parts of a function, are merged into
+/// the primary entry.
+pub fn find_pe_unwind_infos(pe: &PE) -> Result<std::collections::BTreeMap<VA, UnwindInfo>> {
+    let mut ret: std::collections::BTreeMap<VA, UnwindInfo> = Default::default();
+
+    for runtime_function in read_pe_runtime_functions(pe)?.iter() {
+        let (primary, unwind_info) = match read_primary_unwind_info(pe, runtime_function) {
+            Ok(v) => v,
+            Err(e) => {
+                debug!(
+                    "pdata: invalid UNWIND_INFO for {:#x}: {}",
+                    runtime_function.function_start, e
+                );
+                continue;
+            }
+        };
+        ret.entry(primary.function_start).or_insert(unwind_info);
+    }
+
+    Ok(ret)
+}
+
+/// an exception handler registered by an UNWIND_INFO structure.
+#[cfg(feature = "disassembler")]
+pub(crate) struct ExceptionHandler {
+    /// the start of the function described by the primary RUNTIME_FUNCTION.
+    pub function: VA,
+    /// the address of the exception handler, like `__CxxFrameHandler3`.
+    pub handler:  VA,
+    /// the address of the language-specific handler data,
+    /// such as the RVA of a `FuncInfo` structure.
+    pub data:     VA,
+}
+
+/// find the exception and termination handlers registered by the
+/// RUNTIME_FUNCTIONs, along with the functions they protect.
+#[cfg(feature = "disassembler")]
+pub(crate) fn find_pe_exception_handlers(pe: &PE) -> Result<Vec<ExceptionHandler>> {
+    Ok(find_pe_unwind_infos(pe)?
+        .into_iter()
+        .filter_map(|(function, unwind_info)| match unwind_info.data {
+            UnwindInfoData::ExceptionHandler { handler, data } => Some(ExceptionHandler {
+                function,
+                handler,
+                data,
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+/// find the exception handlers, like `__C_specific_handler`,
+/// which are also function starts.
+pub fn find_pe_exception_handler_functions(pe: &PE) -> Result<Vec<VA>> {
+    let mut handlers: Vec<VA> = find_pe_unwind_infos(pe)?
+        .values()
+        .filter_map(|unwind_info| unwind_info.exception_handler())
+        .filter(|&handler| pe.module.probe_va(handler, Permissions::X))
+        .collect();
+    handlers.sort_unstable();
+    handlers.dedup();
+
+    debug!("pdata: found {} exception handlers", handlers.len());
+    Ok(handlers)
+}
+
+pub fn find_pe_runtime_functions(pe: &PE) -> Result<Vec<VA>> {
+    let mut ret = vec![];
+
+    for runtime_function in read_pe_runtime_functions(pe)?.iter() {
+        // validate the unwind info, including any chained entries.
+        if let Err(e) = read_primary_unwind_info(pe, runtime_function) {
+            debug!(
+                "pdata: invalid UNWIND_INFO for {:#x}: {}",
+                runtime_function.function_start, e
+            );
+            continue;
+        }
+
+        let function = runtime_function.function_start;
+
+        debug!("pdata: found RUNTIME_FUNCTION: {:#x}", function);
+        ret.push(function);
+    }
+
+    Ok(ret)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::rsrc::*;
@@ -241,4 +628,122 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn k32_unwind_info() -> Result<()> {
+        use crate::analysis::pe::runtime_functions::*;
+
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let unwind_infos = find_pe_unwind_infos(&pe)?;
+        assert_eq!(unwind_infos.len(), 1461);
+
+        // .text:0000000180001068  48 89 5c 24 08   mov [rsp+0x08], rbx
+        // .text:000000018000106d  48 89 54 24 10   mov [rsp+0x10], rdx
+        // .text:0000000180001072  55               push rbp
+        // .text:0000000180001073  56               push rsi
+        // .text:0000000180001074  57               push rdi
+        // .text:0000000180001075  41 54            push r12
+        // .text:0000000180001077  41 55            push r13
+        // .text:0000000180001079  41 56            push r14
+        // .text:000000018000107b  41 57            push r15
+        // .text:000000018000107d  48 8b ec         mov rbp, rsp
+        // .text:0000000180001080  48 83 ec 50      sub rsp, 0x50
+        let unwind_info = &unwind_infos[&0x180001068];
+        assert_eq!(unwind_info.version, 1);
+        assert_eq!(unwind_info.prologue_size, 0x1C);
+        assert_eq!(unwind_info.frame_register, None);
+        assert_eq!(unwind_info.stack_allocation(), 0x50);
+        assert_eq!(unwind_info.frame_size(), 0x50 + 7 * 8);
+        assert_eq!(
+            unwind_info.codes[0],
+            UnwindCode::SaveNonvol {
+                offset:       0x1C,
+                reg:          3,
+                stack_offset: 0x90,
+            }
+        );
+        assert_eq!(
+            unwind_info
+                .saved_registers()
+                .into_iter()
+                .map(get_register_name)
+                .collect::<Vec<_>>(),
+            vec!["rbx", "r15", "r14", "r13", "r12", "rdi", "rsi", "rbp"]
+        );
+
+        // jmp [__imp___C_specific_handler]
+        let handlers = find_pe_exception_handler_functions(&pe)?;
+        assert_eq!(handlers, vec![0x180020596, 0x180021970, 0x1800219EC]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unwind_codes() -> Result<()> {
+        use crate::analysis::pe::runtime_functions::*;
+
+        // version 2, with codes listed in reverse order of the prologue.
+        #[rustfmt::skip]
+        let slots: Vec<u16> = vec![
+            // epilog: size 0x5, at the end of the function.
+            0x1605,
+            // another epilog, 0x123 bytes from the end of the function.
+            0x1623,
+            // 0x20: movaps [rsp+0x20], xmm6
+            0x6820, 0x0002,
+            // 0x18: lea rbp, [rsp+0x20]
+            0x0318,
+            // 0x10: sub rsp, 0x12345678
+            0x1110, 0x5678, 0x1234,
+            // 0x8: sub rsp, 0x1000
+            0x0108, 0x0200,
+            // 0x4: sub rsp, 0x28
+            0x4204,
+            // 0x2: push rbp
+            0x5002,
+            // 0x1: machine frame, with error code
+            0x1A01,
+        ];
+
+        assert_eq!(
+            read_unwind_codes(2, &slots)?,
+            vec![
+                UnwindCode::Epilog {
+                    size:   5,
+                    at_end: true,
+                },
+                UnwindCode::EpilogOffset { offset: 0x123 },
+                UnwindCode::SaveXmm128 {
+                    offset:       0x20,
+                    reg:          6,
+                    stack_offset: 0x20,
+                },
+                UnwindCode::SetFpreg { offset: 0x18 },
+                UnwindCode::AllocLarge {
+                    offset: 0x10,
+                    size:   0x12345678,
+                },
+                UnwindCode::AllocLarge {
+                    offset: 0x8,
+                    size:   0x1000,
+                },
+                UnwindCode::AllocSmall {
+                    offset: 0x4,
+                    size:   0x28,
+                },
+                UnwindCode::PushNonvol { offset: 0x2, reg: 5 },
+                UnwindCode::PushMachframe {
+                    offset:     0x1,
+                    error_code: true,
+                },
+            ]
+        );
+
+        // truncated UWOP_ALLOC_LARGE
+        assert!(read_unwind_codes(1, &[0x0108]).is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,314 @@
+//! Generate FLIRT signatures for the functions of a PE, like sigmake.
+//!
+//! For each local function, we take the bytes of the contiguous basic blocks
+//! at its start, and wildcard the bytes that would change if the function was
+//! linked into another program:
+//!
+//!   - base relocations, and
+//!   - operands that refer to addresses outside the function, such as `call
+//!     sub_401000`, `mov eax, [0x404000]`, or `lea rax, [rip+0x10]`.
+//!
+//! Relative jumps within the function don't change, so they're left as-is.
+//!
+//! The public name of a signature is the name of the function, or `sub_*`,
+//! and when a wildcarded operand refers to a named address,
+//! such as an import, the name is recorded as a reference.
+//! Signatures that share the same leading bytes and CRC16 are distinguished
+//! by tail bytes.
+//!
+//! Render the signatures into a .pat file via `lancelot_flirt::pat::render`.
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use byteorder::{ByteOrder, LittleEndian};
+use log::debug;
+
+use crate::{
+    analysis::{
+        cfg,
+        cfg::CFG,
+        dis,
+        pe::{db::Analysis, names::Names},
+    },
+    arch::Arch,
+    aspace::AddressSpace,
+    loader::pe::{
+        relocs::{read_base_relocations, Relocation},
+        PE,
+    },
+    module::{Module, Permissions},
+    RVA, VA,
+};
+use lancelot_flirt::{add_tail_bytes, FlirtSignature, Name, Symbol};
+
+/// the contiguous range of basic blocks at the start of the function.
+/// blocks elsewhere, such as cold code moved by the compiler, are not
+/// included.
+fn get_function_extent(cfg: &CFG, va: VA) -> std::ops::Range<VA> {
+    let mut end = va;
+    for bb in cfg.basic_blocks.range(va..).map(|(_, bb)| bb) {
+        if bb.address > end {
+            break;
+        }
+        end = std::cmp::max(end, bb.address + bb.length);
+    }
+
+    va..end
+}
+
+/// the fields of the given instruction that refer to addresses outside the
+/// given function, as (offset into the instruction, size, target).
+fn get_insn_variable_fields(
+    module: &Module,
+    extent: &std::ops::Range<VA>,
+    va: VA,
+    insn: &zydis::DecodedInstruction,
+) -> Vec<(usize, usize, VA)> {
+    let mut fields = vec![];
+    let next = va + insn.length as u64;
+
+    for imm in insn.raw.imm.iter().filter(|imm| imm.size > 0) {
+        let target = if imm.is_relative {
+            match cfg::va_add_signed(next, imm.value as i64) {
+                Some(target) if !extent.contains(&target) => target,
+                _ => continue,
+            }
+        } else if imm.size >= 32 {
+            // like `push offset aFoo`. small constants never fall within the module.
+            match module.arch {
+                Arch::X32 => imm.value as u32 as VA,
+                Arch::X64 => imm.value,
+            }
+        } else {
+            continue;
+        };
+
+        fields.push((imm.offset as usize, (imm.size / 8) as usize, target));
+    }
+
+    if insn.raw.disp_size >= 32 {
+        let is_rip_relative = insn.operands[..insn.operand_count as usize]
+            .iter()
+            .any(|op| op.ty == zydis::OperandType::MEMORY && op.mem.base == zydis::Register::RIP);
+
+        let target = if is_rip_relative {
+            cfg::va_add_signed(next, insn.raw.disp_value)
+        } else {
+            // like `mov eax, [0x404000]`, or a table like `[0x404000+eax*4]`.
+            match module.arch {
+                Arch::X32 => Some(insn.raw.disp_value as u32 as VA),
+                Arch::X64 if insn.raw.disp_value >= 0 => Some(insn.raw.disp_value as VA),
+                Arch::X64 => None,
+            }
+        };
+
+        if let Some(target) = target {
+            fields.push((insn.raw.disp_offset as usize, (insn.raw.disp_size / 8) as usize, target));
+        }
+    }
+
+    fields
+        .into_iter()
+        .filter(|&(_, _, target)| module.probe_va(target, Permissions::RWX))
+        .collect()
+}
+
+/// create a FLIRT signature for the local function at the given address,
+/// wildcarding the given relocations and the operands that refer outside the
+/// function.
+pub fn make_function_signature(
+    module: &Module,
+    relocs: &[Relocation],
+    names: &Names,
+    cfg: &CFG,
+    va: VA,
+) -> Result<FlirtSignature> {
+    let extent = get_function_extent(cfg, va);
+    let size = (extent.end - extent.start) as usize;
+    let buf = module.address_space.read_bytes(va, size)?;
+    let mut variable = vec![false; size];
+
+    // map from offset into the function to the name of the referenced address.
+    let mut references: BTreeMap<usize, String> = Default::default();
+
+    let start = relocs.partition_point(|reloc| reloc.address < va);
+    for reloc in relocs[start..].iter().take_while(|reloc| reloc.address < extent.end) {
+        let offset = (reloc.address - va) as usize;
+        for v in variable.iter_mut().skip(offset).take(reloc.size()) {
+            *v = true;
+        }
+
+        let target = match module.arch {
+            Arch::X32 if offset + 4 <= size => LittleEndian::read_u32(&buf[offset..]) as VA,
+            Arch::X64 if offset + 8 <= size => LittleEndian::read_u64(&buf[offset..]),
+            _ => continue,
+        };
+        if let Some(name) = names.get(target) {
+            references.insert(offset, name.name.clone());
+        }
+    }
+
+    let decoder = dis::get_disassembler(module)?;
+    for bb in cfg.basic_blocks.range(extent.clone()).map(|(_, bb)| bb) {
+        let bb_offset = (bb.address - va) as usize;
+        let bb_buf = &buf[bb_offset..bb_offset + bb.length as usize];
+
+        for (insn_offset, insn) in dis::linear_disassemble(&decoder, bb_buf) {
+            if let Ok(Some(insn)) = insn {
+                let insn_va = bb.address + insn_offset as RVA;
+
+                for (field_offset, field_size, target) in get_insn_variable_fields(module, &extent, insn_va, &insn) {
+                    let offset = bb_offset + insn_offset + field_offset;
+                    for v in variable.iter_mut().skip(offset).take(field_size) {
+                        *v = true;
+                    }
+
+                    if let Some(name) = names.get(target) {
+                        references.insert(offset, name.name.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    let mut symbols = vec![Symbol::Public(Name {
+        offset: 0,
+        name:   names
+            .get(va)
+            .map(|name| name.name.clone())
+            .unwrap_or_else(|| format!("sub_{:x}", va)),
+    })];
+
+    for (local, name) in names.range(va + 1..extent.end) {
+        symbols.push(Symbol::Local(Name {
+            offset: (local - va) as i64,
+            name:   name.name.clone(),
+        }));
+    }
+
+    for (offset, name) in references.into_iter() {
+        symbols.push(Symbol::Reference(Name {
+            offset: offset as i64,
+            name,
+        }));
+    }
+
+    Ok(FlirtSignature::from_function(&buf, &variable, symbols))
+}
+
+/// create FLIRT signatures for the local functions of the given PE,
+/// such as to render into a .pat file.
+pub fn make_pe_signatures(pe: &PE, analysis: &Analysis) -> Result<Vec<FlirtSignature>> {
+    let relocs = read_base_relocations(pe)?;
+
+    let mut sigs = vec![];
+    for (&va, cfg) in analysis.cfgs.iter() {
+        match make_function_signature(&pe.module, &relocs, &analysis.names, cfg, va) {
+            Ok(sig) => sigs.push(sig),
+            Err(e) => debug!("sigmake: {:#x}: failed to create signature: {}", va, e),
+        }
+    }
+
+    add_tail_bytes(&mut sigs);
+
+    debug!("sigmake: created {} signatures", sigs.len());
+    Ok(sigs)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::{
+            cfg::build_cfg,
+            pe::{db::Analysis, names::NameSource, sigmake::*},
+        },
+        rsrc::*,
+        test::*,
+    };
+    use anyhow::Result;
+    use lancelot_flirt::{pat, sig, FlirtSignatureSet};
+
+    #[test]
+    fn shellcode() -> Result<()> {
+        // 0x0:  55                 push ebp
+        // 0x1:  8B EC              mov ebp, esp
+        // 0x3:  E8 0A 00 00 00     call 0x12
+        // 0x8:  8B 05 20 00 00 00  mov eax, [0x20]
+        // 0xE:  5D                 pop ebp
+        // 0xF:  C3                 ret
+        // 0x10: CC CC              int3
+        // 0x12: C3                 ret
+        let mut code = b"\x55\x8B\xEC\xE8\x0A\x00\x00\x00\x8B\x05\x20\x00\x00\x00\x5D\xC3\xCC\xCC\xC3".to_vec();
+        code.resize(0x24, 0x00);
+        let module = load_shellcode32(&code);
+        let cfg = build_cfg(&module, 0x0)?;
+
+        let mut names: Names = Default::default();
+        names.insert(0x0, "foo", NameSource::User);
+        names.insert(0x12, "bar", NameSource::User);
+        names.insert(0x20, "baz", NameSource::User);
+
+        let sig = make_function_signature(&module, &[], &names, &cfg, 0x0)?;
+        assert_eq!(
+            sig.render_pat(),
+            "558bece8........8b05........5dc3................................ 00 0000 0010 :0000 foo ^0004 bar ^000a baz"
+        );
+
+        // the relocated bytes are wildcarded, too.
+        let relocs = vec![Relocation {
+            address: 0x1,
+            kind:    crate::loader::pe::relocs::IMAGE_REL_BASED_HIGHLOW,
+        }];
+        let sig = make_function_signature(&module, &relocs, &Default::default(), &cfg, 0x0)?;
+        assert!(sig.render_pat().starts_with("55..............8b05"));
+        assert_eq!(sig.get_name(), Some("sub_0"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        let analysis = Analysis::from_pe(&pe, &Default::default())?;
+
+        let sigs = make_pe_signatures(&pe, &analysis)?;
+        assert_eq!(sigs.len(), analysis.cfgs.len());
+
+        // the .pat file can be parsed back.
+        let pat = pat::parse(&pat::render(&sigs))?;
+        assert_eq!(pat.len(), sigs.len());
+
+        // and compiled into a .sig file, which keeps the tail bytes
+        // as-is rather than adding them again.
+        let compiled = sig::parse(&sig::write(&pat, "k32")?)?;
+        assert_eq!(compiled.len(), pat.len());
+        // tail bytes render like `(0005: C3)`.
+        let tail_bytes =
+            |sigs: &[FlirtSignature]| -> usize { sigs.iter().map(|sig| sig.render_pat().matches(": ").count()).sum() };
+        assert!(tail_bytes(&sigs) > 0);
+        assert_eq!(tail_bytes(&compiled), tail_bytes(&sigs));
+
+        // each exported function is recognized by its own signature.
+        let sigs = FlirtSignatureSet::with_signatures(sigs);
+        let mut count = 0;
+        for (va, name) in analysis.names.iter() {
+            if name.source != NameSource::Export || !analysis.cfgs.contains_key(&va) {
+                continue;
+            }
+
+            let cfg = &analysis.cfgs[&va];
+            let extent = get_function_extent(cfg, va);
+            // the byte signature of a short function is padded with wildcards,
+            // which must be backed by data during matching.
+            let size = std::cmp::max((extent.end - extent.start) as usize, 0x20);
+            let buf = pe.module.address_space.read_bytes(va, size)?;
+
+            assert!(sigs.r#match(&buf).iter().any(|sig| sig.get_name() == Some(&name.name)));
+            count += 1;
+        }
+        assert!(count > 100);
+
+        Ok(())
+    }
+}
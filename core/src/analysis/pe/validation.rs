@@ -0,0 +1,350 @@
+//! Validate function candidates found by heuristics, like prologue patterns
+//! and pointers into executable sections, which sometimes point into data.
+//!
+//! We build the CFG of each candidate and reject it when:
+//!
+//!   - it flows into an invalid instruction, or off the end of a section,
+//!   - it starts with, or runs into, padding, like `int3` or `add [eax], al`,
+//!   - it runs into the middle of a function that we trust, such as one
+//!     described by a RUNTIME_FUNCTION,
+//!   - it uses privileged instructions, like `hlt` or `in`, or
+//!   - its stack pointer is unbalanced at a `ret`.
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use log::debug;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::{
+    analysis::{cfg, cfg::Flow, constants, dis, pe::FunctionSource},
+    aspace::AddressSpace,
+    loader::pe::PE,
+    module::Module,
+    RVA, VA,
+};
+
+/// the reason a function candidate was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// flows to an address that doesn't contain a valid instruction.
+    InvalidInstruction(VA),
+    /// starts with, or runs into, padding bytes.
+    Padding(VA),
+    /// runs into the middle of a trusted function.
+    OverlapsFunction(VA),
+    /// the instruction is privileged or does port I/O.
+    PrivilegedInstruction(VA),
+    /// the stack pointer at the `ret` doesn't match the entry.
+    StackImbalance(VA),
+}
+
+/// the functions that we trust, against which candidates are checked.
+#[derive(Default)]
+pub struct TrustedFunctions {
+    /// the start addresses of the trusted functions.
+    pub starts: BTreeSet<VA>,
+    /// the ranges of the trusted functions, when known, indexed by start
+    /// address, such as from RUNTIME_FUNCTIONs.
+    pub ranges: BTreeMap<VA, VA>,
+}
+
+impl TrustedFunctions {
+    /// the trusted function whose body (but not start) contains the address.
+    fn containing(&self, va: VA) -> Option<VA> {
+        match self.ranges.range(..va).next_back() {
+            Some((&start, &end)) if va < end && !self.starts.contains(&va) => Some(start),
+            _ => None,
+        }
+    }
+
+    /// the trusted function start strictly within the given range.
+    fn straddled(&self, start: VA, end: VA) -> Option<VA> {
+        self.starts.range(start + 1..end).next().cloned()
+    }
+}
+
+fn is_padding(buf: &[u8], insn: &zydis::DecodedInstruction) -> bool {
+    match insn.mnemonic {
+        zydis::Mnemonic::INT3 => true,
+        // 00 00: add [eax], al
+        zydis::Mnemonic::ADD => insn.length == 2 && buf[0] == 0x0 && buf[1] == 0x0,
+        _ => false,
+    }
+}
+
+fn is_privileged(insn: &zydis::DecodedInstruction) -> bool {
+    if insn.attributes.contains(zydis::InstructionAttributes::IS_PRIVILEGED) {
+        return true;
+    }
+
+    matches!(
+        insn.mnemonic,
+        zydis::Mnemonic::IN
+            | zydis::Mnemonic::INSB
+            | zydis::Mnemonic::INSW
+            | zydis::Mnemonic::INSD
+            | zydis::Mnemonic::OUT
+            | zydis::Mnemonic::OUTSB
+            | zydis::Mnemonic::OUTSW
+            | zydis::Mnemonic::OUTSD
+    )
+}
+
+/// the stack pointer at each `ret` should be where it was at entry.
+/// we only check the returns whose stack pointer is known.
+fn check_stack(module: &Module, cfg: &cfg::CFG, va: VA, rets: &[VA]) -> Result<Option<Rejection>> {
+    if rets.is_empty() {
+        return Ok(None);
+    }
+
+    let sp = match module.arch {
+        crate::arch::Arch::X32 => zydis::Register::ESP,
+        crate::arch::Arch::X64 => zydis::Register::RSP,
+    };
+
+    let constants = constants::propagate_constants(module, cfg, va, &Default::default())?;
+    for &ret in rets.iter() {
+        if let Some(constants::Value::Stack(offset)) = constants.state_at(ret).and_then(|state| state.register(sp)) {
+            if offset != 0 {
+                return Ok(Some(Rejection::StackImbalance(ret)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// collect the function candidates found by trusted sources, such as exports
+/// and RUNTIME_FUNCTIONs, along with the function ranges from the exception
+/// directory.
+pub fn find_pe_trusted_functions(
+    pe: &PE,
+    candidates: &BTreeMap<VA, BTreeSet<FunctionSource>>,
+) -> Result<TrustedFunctions> {
+    let mut trusted: TrustedFunctions = Default::default();
+
+    trusted.starts.extend(
+        candidates
+            .iter()
+            .filter(|(_, sources)| sources.iter().any(|source| source.is_trusted()))
+            .map(|(&va, _)| va),
+    );
+    trusted.ranges.extend(
+        crate::analysis::pe::runtime_functions::read_pe_runtime_functions(pe)?
+            .iter()
+            .map(|rf| (rf.function_start, rf.function_end)),
+    );
+
+    Ok(trusted)
+}
+
+/// validate the function candidate at the given address,
+/// returning the reason to reject it, if any.
+pub fn validate_function(module: &Module, trusted: &TrustedFunctions, va: VA) -> Result<Option<Rejection>> {
+    if let Some(function) = trusted.containing(va) {
+        return Ok(Some(Rejection::OverlapsFunction(function)));
+    }
+
+    let cfg = cfg::build_cfg(module, va)?;
+    if !cfg.basic_blocks.contains_key(&va) {
+        return Ok(Some(Rejection::InvalidInstruction(va)));
+    }
+
+    let decoder = dis::get_disassembler(module)?;
+    let mut rets = vec![];
+    for bb in cfg.basic_blocks.values() {
+        // the CFG silently drops flows to invalid instructions.
+        for flow in bb.successors.iter() {
+            if !matches!(flow, Flow::Call(_)) && !cfg.basic_blocks.contains_key(&flow.va()) {
+                return Ok(Some(Rejection::InvalidInstruction(flow.va())));
+            }
+        }
+
+        let buf = module.address_space.read_bytes(bb.address, bb.length as usize)?;
+        for (offset, insn) in dis::linear_disassemble(&decoder, &buf) {
+            let insnva = bb.address + offset as RVA;
+            let insn = match insn {
+                Ok(Some(insn)) => insn,
+                _ => return Ok(Some(Rejection::InvalidInstruction(insnva))),
+            };
+
+            // int3 is a common filler after calls to functions that don't return,
+            // so we only reject it at the start.
+            if is_padding(&buf[offset..], &insn) && (insnva == va || insn.mnemonic != zydis::Mnemonic::INT3) {
+                return Ok(Some(Rejection::Padding(insnva)));
+            }
+
+            if is_privileged(&insn) {
+                return Ok(Some(Rejection::PrivilegedInstruction(insnva)));
+            }
+
+            if let Some(function) = trusted.straddled(insnva, insnva + insn.length as u64) {
+                return Ok(Some(Rejection::OverlapsFunction(function)));
+            }
+            if let Some(function) = trusted.containing(insnva) {
+                // flowing to the start of another function is a tail call, which is ok,
+                // but not into its middle.
+                if function != va {
+                    return Ok(Some(Rejection::OverlapsFunction(function)));
+                }
+            }
+
+            if let zydis::Mnemonic::RET = insn.mnemonic {
+                rets.push(insnva);
+            }
+        }
+    }
+
+    check_stack(module, &cfg, va, &rets)
+}
+
+/// validate the given function candidates, returning the ones that look ok.
+pub fn validate_functions(module: &Module, trusted: &TrustedFunctions, candidates: &[VA]) -> Result<Vec<VA>> {
+    let validate = |&va: &VA| match validate_function(module, trusted, va) {
+        Ok(None) => Some(va),
+        Ok(Some(rejection)) => {
+            debug!("validation: rejected {:#x}: {:x?}", va, rejection);
+            None
+        }
+        Err(e) => {
+            debug!("validation: rejected {:#x}: {}", va, e);
+            None
+        }
+    };
+
+    #[cfg(feature = "parallel")]
+    let ret = candidates.par_iter().filter_map(validate).collect();
+    #[cfg(not(feature = "parallel"))]
+    let ret = candidates.iter().filter_map(validate).collect();
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{analysis::pe::validation::*, test::*};
+    use anyhow::Result;
+
+    fn validate(buf: &[u8]) -> Result<Option<Rejection>> {
+        let module = load_shellcode32(buf);
+        validate_function(&module, &Default::default(), 0x0)
+    }
+
+    #[test]
+    fn ok() -> Result<()> {
+        // 0:  55                      push   ebp
+        // 1:  8b ec                   mov    ebp,esp
+        // 3:  33 c0                   xor    eax,eax
+        // 5:  5d                      pop    ebp
+        // 6:  c3                      ret
+        assert_eq!(validate(b"\x55\x8B\xEC\x33\xC0\x5D\xC3")?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_instruction() -> Result<()> {
+        // 0:  85 c0                   test   eax,eax
+        // 2:  74 01                   je     0x5
+        // 4:  c3                      ret
+        // 5:  90                      nop
+        // 6:  ff ff                   (bad)
+        assert_eq!(
+            validate(b"\x85\xC0\x74\x01\xC3\x90\xFF\xFF")?,
+            Some(Rejection::InvalidInstruction(0x6))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn padding() -> Result<()> {
+        // 0:  cc                      int3
+        assert_eq!(validate(b"\xCC\xC3")?, Some(Rejection::Padding(0x0)));
+
+        // 0:  90                      nop
+        // 1:  00 00                   add    BYTE PTR [eax],al
+        assert_eq!(validate(b"\x90\x00\x00\xC3")?, Some(Rejection::Padding(0x1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn privileged() -> Result<()> {
+        // 0:  90                      nop
+        // 1:  f4                      hlt
+        assert_eq!(validate(b"\x90\xF4\xC3")?, Some(Rejection::PrivilegedInstruction(0x1)));
+
+        // 0:  e4 60                   in     al,0x60
+        assert_eq!(validate(b"\xE4\x60\xC3")?, Some(Rejection::PrivilegedInstruction(0x0)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stack_imbalance() -> Result<()> {
+        // 0:  55                      push   ebp
+        // 1:  8b ec                   mov    ebp,esp
+        // 3:  c3                      ret
+        assert_eq!(validate(b"\x55\x8B\xEC\xC3")?, Some(Rejection::StackImbalance(0x3)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlaps() -> Result<()> {
+        // 0:  b8 55 8b ec c3          mov    eax,0xc3ec8b55
+        // 5:  c3                      ret
+        let module = load_shellcode32(b"\xB8\x55\x8B\xEC\xC3\xC3");
+
+        let mut trusted: TrustedFunctions = Default::default();
+        trusted.starts.insert(0x1);
+        assert_eq!(
+            validate_function(&module, &trusted, 0x0)?,
+            Some(Rejection::OverlapsFunction(0x1))
+        );
+
+        let mut trusted: TrustedFunctions = Default::default();
+        trusted.starts.insert(0x0);
+        trusted.ranges.insert(0x0, 0x6);
+        assert_eq!(
+            validate_function(&module, &trusted, 0x5)?,
+            Some(Rejection::OverlapsFunction(0x0))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        use crate::{
+            analysis::pe::{Function, FunctionSource},
+            rsrc::*,
+        };
+
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        let functions = crate::analysis::pe::find_functions(&pe)?;
+        let local = |va| {
+            functions.iter().find_map(|f| match f {
+                Function::Local(f) if f.address == va => Some(f.clone()),
+                _ => None,
+            })
+        };
+
+        let entrypoint = crate::analysis::pe::entrypoints::find_pe_entrypoint(&pe)?[0];
+        let f = local(entrypoint).unwrap();
+        assert!(f.sources.contains(&FunctionSource::Entrypoint));
+        assert_eq!(f.confidence, 100);
+
+        // a jump table case that flows into the tail of another function.
+        assert!(local(0x443B8D).is_none());
+        assert_eq!(
+            validate_function(&pe.module, &Default::default(), 0x443B8D)?,
+            Some(Rejection::StackImbalance(0x443B8C))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,380 @@
+//! Discover functions recursively, until there are no more to find.
+//!
+//! `find_functions` collects candidates from metadata and linear scans once.
+//! Here we build the CFG of each function and harvest new candidates from its
+//! instructions:
+//!
+//!   - the targets of `call` instructions,
+//!   - immediate and `lea` operands that refer to code, like `push offset
+//!     sub_401000`, and
+//!   - the targets of tail calls, which are jumps to a function start, or to an
+//!     address that follows `int3` padding.
+//!
+//! New candidates are validated, and then their CFGs are built in the next
+//! round, until a round finds nothing new. Then, we look for code in the gaps
+//! between the functions, which may be unreachable from the known functions,
+//! and repeat.
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    analysis::{
+        call_graph,
+        call_graph::CallGraph,
+        cfg,
+        cfg::{Flow, CFG},
+        dis,
+        pe::{
+            find_functions_with_signatures, find_thunks, gaps, get_imports, validation, Function, FunctionSource,
+            LocalFunction, Signatures, Thunk,
+        },
+        xrefs,
+    },
+    aspace::AddressSpace,
+    config::Config,
+    loader::pe::PE,
+    module::{Module, Permissions},
+    RVA, VA,
+};
+
+pub struct Workspace {
+    /// the local functions, thunks, and imports, sorted.
+    pub functions:  Vec<Function>,
+    /// the CFG of each local function, indexed by function start.
+    pub cfgs:       BTreeMap<VA, CFG>,
+    pub call_graph: CallGraph,
+}
+
+impl Workspace {
+    pub fn local_functions(&self) -> impl Iterator<Item = &LocalFunction> {
+        self.functions.iter().filter_map(|f| match f {
+            Function::Local(f) => Some(f),
+            _ => None,
+        })
+    }
+
+    pub fn thunks(&self) -> impl Iterator<Item = &Thunk> {
+        self.functions.iter().filter_map(|f| match f {
+            Function::Thunk(thunk) => Some(thunk),
+            _ => None,
+        })
+    }
+}
+
+fn is_executable(module: &Module, va: VA) -> bool {
+    module.probe_va(va, Permissions::X)
+}
+
+/// is the given address preceded by `int3` padding, like the start of a
+/// function emitted by MSVC?
+///
+/// a single 0xCC byte may also be the last byte of an instruction,
+/// like `mov eax, [ebp-0x34]`, so require either a run of padding or
+/// an aligned address.
+fn follows_padding(module: &Module, va: VA) -> bool {
+    let is_int3 = |va: VA| matches!(module.address_space.read_u8(va), Ok(0xCC));
+
+    va >= 2 && is_int3(va - 1) && (va.is_multiple_of(0x10) || is_int3(va - 2))
+}
+
+/// collect the function candidates referenced by the instructions in the CFG.
+fn harvest_candidates(
+    module: &Module,
+    decoder: &zydis::Decoder,
+    function: VA,
+    cfg: &CFG,
+    functions: &BTreeMap<VA, BTreeSet<FunctionSource>>,
+    seen: &mut BTreeSet<VA>,
+    candidates: &mut BTreeMap<VA, BTreeSet<FunctionSource>>,
+) -> Result<()> {
+    for bb in cfg.basic_blocks.values() {
+        // basic blocks may be shared among CFGs, such as via tail calls,
+        // so only process each once.
+        if !seen.insert(bb.address) {
+            continue;
+        }
+
+        let buf = module.address_space.read_bytes(bb.address, bb.length as usize)?;
+        for (offset, insn) in dis::linear_disassemble(decoder, &buf) {
+            let insn = match insn {
+                Ok(Some(insn)) => insn,
+                _ => continue,
+            };
+            let va = bb.address + offset as RVA;
+
+            let flows = match insn.mnemonic {
+                zydis::Mnemonic::CALL => cfg::get_call_insn_flow(module, va, &insn)?,
+                zydis::Mnemonic::JMP => cfg::get_jmp_insn_flow(module, va, &insn)?,
+                _ => Default::default(),
+            };
+
+            for flow in flows.iter() {
+                match *flow {
+                    Flow::Call(target) if is_executable(module, target) => {
+                        candidates.entry(target).or_default().insert(FunctionSource::CallTarget);
+                    }
+                    Flow::UnconditionalJump(target)
+                        if target != function
+                            && (functions.contains_key(&target) || follows_padding(module, target)) =>
+                    {
+                        candidates.entry(target).or_default().insert(FunctionSource::TailCall);
+                    }
+                    _ => {}
+                }
+            }
+
+            for xref in xrefs::get_operand_xrefs(module, va, &insn).into_iter() {
+                if let xrefs::XrefType::Offset = xref.ty {
+                    if is_executable(module, xref.dst) {
+                        candidates
+                            .entry(xref.dst)
+                            .or_default()
+                            .insert(FunctionSource::CodeReference);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// find the functions in the PE, along with their CFGs and the call graph,
+/// by iterating function discovery until no new functions are found.
+pub fn build_workspace(pe: &PE) -> Result<Workspace> {
+    build_workspace_with_config(pe, &Default::default())
+}
+
+pub fn build_workspace_with_config(pe: &PE, config: &Config) -> Result<Workspace> {
+    build_workspace_with_signatures(pe, config, &Signatures::from_config(config)?)
+}
+
+/// like `build_workspace_with_config`, but with the signatures already loaded.
+pub fn build_workspace_with_signatures(pe: &PE, config: &Config, sigs: &Signatures) -> Result<Workspace> {
+    let imports = get_imports(pe)?;
+    let decoder = dis::get_disassembler(&pe.module)?;
+
+    let mut functions: BTreeMap<VA, BTreeSet<FunctionSource>> = Default::default();
+    let mut thunks: BTreeMap<VA, Thunk> = Default::default();
+    for f in find_functions_with_signatures(pe, config, sigs)?.into_iter() {
+        match f {
+            Function::Local(f) => {
+                functions.insert(f.address, f.sources);
+            }
+            Function::Thunk(thunk) => {
+                thunks.insert(thunk.address, thunk);
+            }
+            Function::Import(_) => {}
+        }
+    }
+
+    let trusted = if config.functions.validate {
+        validation::find_pe_trusted_functions(pe, &functions)?
+    } else {
+        Default::default()
+    };
+
+    let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+    let mut seen: BTreeSet<VA> = Default::default();
+    let mut rejected: BTreeSet<VA> = Default::default();
+    let mut queue: Vec<VA> = functions.keys().cloned().collect();
+    let mut round = 0usize;
+    loop {
+        round += 1;
+
+        let mut candidates: BTreeMap<VA, BTreeSet<FunctionSource>> = Default::default();
+        let from_gaps = queue.is_empty();
+        if from_gaps {
+            // we've explored everything reachable from the known functions,
+            // so look for unreachable code in the gaps between them.
+            debug!("workspace: round {}: gaps", round);
+            for va in gaps::find_gap_functions(&pe.module, &cfgs)?.into_iter() {
+                candidates.entry(va).or_default().insert(FunctionSource::Gap);
+            }
+        } else {
+            debug!("workspace: round {}: {} functions", round, queue.len());
+            for (va, cfg) in cfg::build_cfgs(&pe.module, &queue, config).into_iter() {
+                harvest_candidates(&pe.module, &decoder, va, &cfg, &functions, &mut seen, &mut candidates)?;
+                cfgs.insert(va, cfg);
+            }
+            queue.clear();
+        }
+
+        if !config.functions.call_targets {
+            for sources in candidates.values_mut() {
+                sources.remove(&FunctionSource::CallTarget);
+            }
+            candidates.retain(|_, sources| !sources.is_empty());
+        }
+
+        let mut new: BTreeMap<VA, BTreeSet<FunctionSource>> = Default::default();
+        for (va, sources) in candidates.into_iter() {
+            if let Some(existing) = functions.get_mut(&va) {
+                existing.extend(sources);
+            } else if !thunks.contains_key(&va) && !imports.contains_key(&va) && !rejected.contains(&va) {
+                new.insert(va, sources);
+            }
+        }
+
+        // like `find_functions`, skip the candidates we aren't confident in.
+        // these aren't rejected, since a later round may find more evidence.
+        new.retain(|&va, sources| {
+            LocalFunction::new(va, sources.clone()).confidence >= config.functions.min_confidence
+        });
+
+        let candidates: Vec<VA> = new.keys().cloned().collect();
+        let valid: HashSet<VA> = if config.functions.validate {
+            validation::validate_functions(&pe.module, &trusted, &candidates)?
+                .into_iter()
+                .collect()
+        } else {
+            candidates.into_iter().collect()
+        };
+
+        let new_thunks = find_thunks(pe, &imports, &valid)?;
+        debug!(
+            "workspace: round {}: found {} new functions and {} new thunks",
+            round,
+            valid.len() - new_thunks.len(),
+            new_thunks.len()
+        );
+
+        for (va, sources) in new.into_iter() {
+            if !valid.contains(&va) {
+                rejected.insert(va);
+                continue;
+            }
+
+            if new_thunks.contains_key(&va) {
+                continue;
+            }
+
+            functions.insert(va, sources);
+            queue.push(va);
+        }
+        thunks.extend(new_thunks);
+
+        if from_gaps && queue.is_empty() {
+            break;
+        }
+    }
+
+    let call_graph = call_graph::build_call_graph(&pe.module, &cfgs)?;
+
+    let mut ret: Vec<Function> = Default::default();
+    ret.extend(
+        functions
+            .into_iter()
+            .map(|(va, sources)| Function::Local(LocalFunction::new(va, sources))),
+    );
+    ret.extend(thunks.into_values().map(Function::Thunk));
+    ret.extend(imports.into_values().map(Function::Import));
+    ret.sort_unstable();
+
+    Ok(Workspace {
+        functions: ret,
+        cfgs,
+        call_graph,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::pe::{self, workspace::*},
+        rsrc::*,
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let ws = build_workspace(&pe)?;
+        let starts = pe::find_function_starts(&pe)?;
+
+        // discovery only adds functions.
+        assert!(ws.local_functions().count() >= starts.len());
+        for va in starts.iter() {
+            assert!(ws.local_functions().any(|f| f.address == *va));
+        }
+
+        // every local function has a CFG and call graph entry.
+        for f in ws.local_functions() {
+            assert!(ws.cfgs.contains_key(&f.address));
+            assert!(ws.call_graph.calls_to.contains_key(&f.address));
+        }
+
+        assert!(ws.call_graph.calls_to[&0x180001068].contains(&0x18000F775));
+
+        Ok(())
+    }
+
+    #[test]
+    fn config() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let config = Config::from_toml("[functions]\ncall_targets = false\nprologues = false")?;
+        assert!(pe::find_function_starts_with_config(&pe, &config)?.len() < pe::find_function_starts(&pe)?.len());
+
+        let ws = build_workspace_with_config(&pe, &config)?;
+        for f in ws.local_functions() {
+            assert!(!f.sources.contains(&FunctionSource::CallTarget));
+            assert!(!f.sources.contains(&FunctionSource::Prologue));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn confidence_and_validation() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        // candidates found only in gaps or via tail calls aren't confident enough.
+        let config = Config::from_toml("[functions]\nmin_confidence = 60")?;
+        let ws = build_workspace_with_config(&pe, &config)?;
+        assert!(ws.local_functions().count() > 0);
+        for f in ws.local_functions() {
+            assert!(f.confidence >= 60);
+        }
+
+        // without validation, more candidates are accepted.
+        let config = Config::from_toml("[functions]\nvalidate = false")?;
+        let unvalidated = build_workspace_with_config(&pe, &config)?;
+        let validated = build_workspace(&pe)?;
+        assert!(unvalidated.local_functions().count() > validated.local_functions().count());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let ws = build_workspace(&pe)?;
+        let starts = pe::find_function_starts(&pe)?;
+
+        assert!(ws.local_functions().count() >= starts.len());
+        for f in ws.local_functions() {
+            assert!(ws.cfgs.contains_key(&f.address));
+        }
+        assert!(ws.thunks().count() > 0);
+
+        // only referenced by `push offset sub_4166B3` at 0x416A08.
+        assert!(!starts.contains(&0x4166B3));
+        let f = ws.local_functions().find(|f| f.address == 0x4166B3).unwrap();
+        assert!(f.sources.contains(&FunctionSource::CodeReference));
+
+        // not referenced by any discovered code, but found in a gap.
+        let f = ws.local_functions().find(|f| f.address == 0x45D468).unwrap();
+        assert!(f.sources.contains(&FunctionSource::Gap));
+
+        Ok(())
+    }
+}
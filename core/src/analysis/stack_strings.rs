@@ -0,0 +1,688 @@
+//! Recover strings that are constructed at runtime, and so are not found by
+//! scanning the data sections for strings.
+//!
+//! There are two techniques:
+//!
+//!   1. stack strings: sequences of immediate stores to stack slots, like `mov
+//!      byte [ebp-0x10], 0x41`. We reconstruct the stack frame within each
+//!      basic block, and then search it for strings.
+//!   2. decoded strings: small, self-contained loops that decode a buffer, like
+//!      `xor byte [ebp+ecx-0x10], 0x55`. With the `emulator` feature, we
+//!      emulate the function up through the loop, and then search the stack and
+//!      writable sections for new strings.
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use log::debug;
+
+#[cfg(feature = "emulator")]
+use crate::{analysis::calling_convention::Prototype, config::Config};
+use crate::{
+    analysis::{cfg::CFG, dis, strings::StringEncoding},
+    aspace::AddressSpace,
+    module::Module,
+    util, RVA, VA,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackStringType {
+    /// constructed from immediate stores to the stack.
+    Stack,
+    /// written by a decoding loop, as observed via emulation.
+    Decoded,
+}
+
+#[derive(Debug, Clone)]
+pub struct StackString {
+    /// the start of the function that constructs the string.
+    pub function: VA,
+    /// the address of the instruction that constructs the string:
+    /// for stack strings, the store of the first character;
+    /// for decoded strings, the start of the decoding loop.
+    pub address:  VA,
+    pub ty:       StackStringType,
+    pub encoding: StringEncoding,
+    pub string:   String,
+}
+
+fn is_frame_register(reg: zydis::Register) -> bool {
+    matches!(
+        reg,
+        zydis::Register::ESP | zydis::Register::EBP | zydis::Register::RSP | zydis::Register::RBP
+    )
+}
+
+fn get_machine_mode(module: &Module) -> zydis::MachineMode {
+    match module.arch {
+        crate::arch::Arch::X32 => zydis::MachineMode::LEGACY_32,
+        crate::arch::Arch::X64 => zydis::MachineMode::LONG_64,
+    }
+}
+
+/// mask the value to the given size, in bits.
+fn truncate(value: u64, size: u16) -> u64 {
+    if size >= 64 {
+        value
+    } else {
+        value & ((1u64 << size) - 1)
+    }
+}
+
+/// the bytes stored into a stack frame by a basic block.
+/// keyed by the frame register, and then by the offset from it.
+/// each byte is tagged with the address of the instruction that stored it.
+type StackFrame = HashMap<zydis::Register, BTreeMap<i64, (u8, VA)>>;
+
+/// emulate the immediate stores to the stack in a single basic block.
+/// registers assigned constants, like `mov eax, 0x41`, are tracked,
+/// so that stores like `mov [ebp-0x10], eax` can be resolved, too.
+fn read_stack_frame(module: &Module, decoder: &zydis::Decoder, bbva: VA, length: u64) -> Result<StackFrame> {
+    let mode = get_machine_mode(module);
+    let mut frame: StackFrame = Default::default();
+    let mut constants: HashMap<zydis::Register, u64> = Default::default();
+
+    let buf = module.address_space.read_bytes(bbva, length as usize)?;
+    for (offset, insn) in dis::linear_disassemble(decoder, &buf) {
+        let insn = match insn {
+            Ok(Some(insn)) => insn,
+            _ => break,
+        };
+        let va = bbva + offset as RVA;
+
+        let dst = &insn.operands[0];
+        let src = &insn.operands[1];
+
+        if insn.mnemonic == zydis::Mnemonic::MOV && dst.ty == zydis::OperandType::MEMORY {
+            if is_frame_register(dst.mem.base) && dst.mem.index == zydis::Register::NONE {
+                let value = match src.ty {
+                    zydis::OperandType::IMMEDIATE => Some(src.imm.value),
+                    zydis::OperandType::REGISTER => constants.get(&src.reg.get_largest_enclosing(mode)).copied(),
+                    _ => None,
+                };
+
+                let slots = frame.entry(dst.mem.base).or_default();
+                let disp = dst.mem.disp.displacement;
+                let size = dst.size / 8;
+                for i in 0..size as i64 {
+                    match value {
+                        Some(value) => {
+                            let b = (truncate(value, dst.size) >> (i * 8)) as u8;
+                            slots.insert(disp + i, (b, va));
+                        }
+                        // unknown value, so it can't be part of a string.
+                        None => {
+                            slots.remove(&(disp + i));
+                        }
+                    }
+                }
+            }
+            continue;
+        }
+
+        // track the constants assigned to registers,
+        // and forget any register that is otherwise written.
+        if insn.mnemonic == zydis::Mnemonic::MOV
+            && dst.ty == zydis::OperandType::REGISTER
+            && src.ty == zydis::OperandType::IMMEDIATE
+        {
+            let reg = dst.reg.get_largest_enclosing(mode);
+            if dst.reg == reg || dst.size == 32 {
+                constants.insert(reg, truncate(src.imm.value, dst.size));
+            } else {
+                constants.remove(&reg);
+            }
+        } else if insn.mnemonic == zydis::Mnemonic::XOR
+            && dst.ty == zydis::OperandType::REGISTER
+            && src.ty == zydis::OperandType::REGISTER
+            && dst.reg == src.reg
+            && dst.size >= 32
+        {
+            constants.insert(dst.reg.get_largest_enclosing(mode), 0);
+        } else if insn.mnemonic == zydis::Mnemonic::CALL {
+            // conservatively assume the callee clobbers everything.
+            constants.clear();
+        } else {
+            for op in insn.operands[..insn.operand_count as usize].iter() {
+                if op.ty == zydis::OperandType::REGISTER && op.action.intersects(zydis::OperandAction::MASK_WRITE) {
+                    constants.remove(&op.reg.get_largest_enclosing(mode));
+                }
+            }
+        }
+    }
+
+    Ok(frame)
+}
+
+/// split the stack frame into runs of contiguous bytes,
+/// and search each run for strings.
+fn extract_frame_strings(function: VA, frame: &StackFrame) -> Vec<StackString> {
+    let mut strings = vec![];
+
+    for slots in frame.values() {
+        let mut runs: Vec<Vec<(u8, VA)>> = vec![];
+        let mut prev: Option<i64> = None;
+        for (&offset, &slot) in slots.iter() {
+            match (prev, runs.last_mut()) {
+                (Some(prev), Some(run)) if prev + 1 == offset => run.push(slot),
+                _ => runs.push(vec![slot]),
+            }
+            prev = Some(offset);
+        }
+
+        for run in runs.iter() {
+            let buf: Vec<u8> = run.iter().map(|&(b, _)| b).collect();
+
+            let found = util::find_ascii_strings(&buf)
+                .map(|(range, s)| (range, s, StringEncoding::Ascii))
+                .chain(util::find_unicode_strings(&buf).map(|(range, s)| (range, s, StringEncoding::Utf16le)));
+
+            for (range, s, encoding) in found {
+                strings.push(StackString {
+                    function,
+                    address: run[range.start].1,
+                    ty: StackStringType::Stack,
+                    encoding,
+                    string: s,
+                });
+            }
+        }
+    }
+
+    strings
+}
+
+/// recover the strings constructed via immediate stores to the stack
+/// within the basic blocks of the given function.
+///
+/// ```
+/// use lancelot::test::*;
+/// use lancelot::analysis::cfg::build_cfg;
+/// use lancelot::analysis::stack_strings::find_stack_strings;
+///
+/// // 0:  c6 45 f0 41    mov    BYTE PTR [ebp-0x10], 0x41
+/// // 4:  c6 45 f1 42    mov    BYTE PTR [ebp-0xf], 0x42
+/// // 8:  c6 45 f2 43    mov    BYTE PTR [ebp-0xe], 0x43
+/// // c:  c6 45 f3 44    mov    BYTE PTR [ebp-0xd], 0x44
+/// // 10: c6 45 f4 00    mov    BYTE PTR [ebp-0xc], 0x0
+/// // 14: c3             ret
+/// let module = load_shellcode32(
+///     b"\xC6\x45\xF0\x41\xC6\x45\xF1\x42\xC6\x45\xF2\x43\xC6\x45\xF3\x44\xC6\x45\xF4\x00\xC3",
+/// );
+/// let cfg = build_cfg(&module, 0x0).unwrap();
+/// let strings = find_stack_strings(&module, 0x0, &cfg).unwrap();
+///
+/// assert_eq!(strings.len(), 1);
+/// assert_eq!(strings[0].string, "ABCD");
+/// assert_eq!(strings[0].address, 0x0);
+/// ```
+pub fn find_stack_strings(module: &Module, function: VA, cfg: &CFG) -> Result<Vec<StackString>> {
+    let decoder = dis::get_disassembler(module)?;
+    let mut strings = vec![];
+
+    for bb in cfg.basic_blocks.values() {
+        let frame = read_stack_frame(module, &decoder, bb.address, bb.length)?;
+        strings.extend(extract_frame_strings(function, &frame));
+    }
+    strings.sort_by_key(|s| s.address);
+
+    if !strings.is_empty() {
+        debug!("stack strings: {:#x}: found {} strings", function, strings.len());
+    }
+
+    Ok(strings)
+}
+
+#[cfg(feature = "emulator")]
+mod decoding {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use anyhow::Result;
+    use log::debug;
+
+    use super::{StackString, StackStringType};
+    use crate::{
+        analysis::{
+            calling_convention::Prototype, cfg::CFG, constants::get_callee_cleanup, dis, ir::lift::lift_insn,
+            strings::StringEncoding,
+        },
+        aspace::AddressSpace,
+        config::EmulatorConfig,
+        emu::{mmu::PAGE_SIZE, Emulator},
+        module::{Module, Permissions},
+        util, VA,
+    };
+
+    /// loops with more instructions than this are probably not simple decoders.
+    const MAX_LOOP_INSTRUCTIONS: usize = 64;
+
+    /// a loop that may decode a buffer.
+    struct Loop {
+        header: VA,
+        /// the address ranges of the basic blocks in the loop.
+        blocks: Vec<(VA, VA)>,
+    }
+
+    impl Loop {
+        fn contains(&self, va: VA) -> bool {
+            self.blocks.iter().any(|&(start, end)| start <= va && va < end)
+        }
+    }
+
+    /// is this an instruction that modifies memory in place, like `xor [eax],
+    /// cl`?
+    fn is_decoding_insn(insn: &zydis::DecodedInstruction) -> bool {
+        use zydis::Mnemonic::*;
+        matches!(insn.mnemonic, XOR | ADD | SUB | INC | DEC | NEG | AND | OR)
+            && insn.operands[0].ty == zydis::OperandType::MEMORY
+            && insn.operands[0].action.intersects(zydis::OperandAction::MASK_WRITE)
+    }
+
+    /// find the loops in the CFG that:
+    ///   - are formed by a backwards branch to a prior block,
+    ///   - contain few instructions,
+    ///   - contain no calls,
+    ///   - contain only instructions that can be lifted to IR, and
+    ///   - modify memory in place.
+    fn find_decoding_loops(module: &Module, cfg: &CFG) -> Result<Vec<Loop>> {
+        let decoder = dis::get_disassembler(module)?;
+        let mut loops = vec![];
+
+        for bb in cfg.basic_blocks.values() {
+            for succ in bb.successors.iter() {
+                let header = succ.va();
+                if header > bb.address || !cfg.basic_blocks.contains_key(&header) {
+                    continue;
+                }
+
+                // approximate the loop body as the blocks from the header through the
+                // latch. this works well for the simple loops we're interested in.
+                let blocks: Vec<(VA, VA)> = cfg
+                    .basic_blocks
+                    .range(header..=bb.address)
+                    .map(|(_, b)| (b.address, b.address + b.length))
+                    .collect();
+
+                let mut count = 0;
+                let mut is_supported = true;
+                let mut is_decoding = false;
+                'blocks: for &(start, end) in blocks.iter() {
+                    let buf = module.address_space.read_bytes(start, (end - start) as usize)?;
+                    for (offset, insn) in dis::linear_disassemble(&decoder, &buf) {
+                        let insn = match insn {
+                            Ok(Some(insn)) => insn,
+                            _ => {
+                                is_supported = false;
+                                break 'blocks;
+                            }
+                        };
+
+                        count += 1;
+                        if count > MAX_LOOP_INSTRUCTIONS
+                            || insn.mnemonic == zydis::Mnemonic::CALL
+                            || lift_insn(module.arch, start + offset as u64, &insn).is_err()
+                        {
+                            is_supported = false;
+                            break 'blocks;
+                        }
+
+                        is_decoding |= is_decoding_insn(&insn);
+                    }
+                }
+
+                if is_supported && is_decoding {
+                    loops.push(Loop { header, blocks });
+                }
+            }
+        }
+
+        Ok(loops)
+    }
+
+    /// emulate from the start of the function until the given loop exits.
+    /// calls and instructions that fail to execute outside of the loop are
+    /// skipped.
+    ///
+    /// when a skipped call's callee pops its arguments, like stdcall functions,
+    /// the stack pointer is adjusted by the cleanup from its prototype,
+    /// so that stack references after the call remain in sync.
+    fn emulate_through_loop(
+        module: &Module,
+        config: &EmulatorConfig,
+        prototypes: &BTreeMap<VA, Prototype>,
+        function: VA,
+        l: &Loop,
+    ) -> Result<Option<Emulator>> {
+        let mut emu = Emulator::from_module(module);
+
+        let stack_end = config.stack_address + config.stack_size;
+        if module
+            .sections
+            .iter()
+            .any(|sec| sec.virtual_range.start < stack_end && config.stack_address < sec.virtual_range.end)
+        {
+            // the module overlaps our stack. rare, so don't bother relocating it.
+            return Ok(None);
+        }
+        emu.mem.mmap(config.stack_address, config.stack_size, Permissions::RW)?;
+
+        // leave room above the stack pointer for arguments.
+        let sp = config.stack_address + config.stack_size / 2;
+        emu.reg.rsp = sp;
+        emu.reg.rbp = sp;
+        emu.reg.rip = function;
+
+        let mut has_entered = false;
+        for _ in 0..config.max_steps {
+            let pc = emu.reg.rip;
+            let is_in_loop = l.contains(pc);
+
+            if is_in_loop {
+                has_entered = true;
+            } else if has_entered {
+                // the loop has exited, so the buffer should be decoded.
+                return Ok(Some(emu));
+            }
+
+            let insn = match emu.fetch() {
+                Ok(insn) => insn,
+                Err(_) => break,
+            };
+
+            if matches!(insn.mnemonic, zydis::Mnemonic::RET) {
+                break;
+            }
+
+            if !is_in_loop && insn.mnemonic == zydis::Mnemonic::CALL {
+                // skip, assuming a zero result.
+                // when the cleanup isn't known, assume the caller cleans up.
+                let cleanup = get_callee_cleanup(module, prototypes, pc, &insn)
+                    .unwrap_or(None)
+                    .unwrap_or(0);
+                emu.reg.rax = 0;
+                emu.reg.rsp = emu.reg.rsp.wrapping_add(cleanup);
+                emu.reg.rip += insn.length as u64;
+                continue;
+            }
+
+            // the emulator leaves the state unchanged when an instruction fails.
+            if emu.execute(&insn).is_err() {
+                if is_in_loop {
+                    break;
+                }
+                emu.reg.rip = pc + insn.length as u64;
+            }
+        }
+
+        if has_entered {
+            Ok(Some(emu))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// read the given region of emulator memory, treating unmapped pages as
+    /// empty.
+    fn read_region(emu: &Emulator, start: VA, end: VA) -> Vec<u8> {
+        let mut buf = Vec::with_capacity((end - start) as usize);
+        let mut addr = start;
+        while addr < end {
+            let page_start = addr & !(PAGE_SIZE as u64 - 1);
+            let page = emu.mem.read_page(page_start).unwrap_or([0u8; PAGE_SIZE]);
+            let offset = (addr - page_start) as usize;
+            let count = std::cmp::min(PAGE_SIZE - offset, (end - addr) as usize);
+            buf.extend_from_slice(&page[offset..offset + count]);
+            addr += count as u64;
+        }
+        buf
+    }
+
+    fn find_buf_strings(buf: &[u8]) -> Vec<(std::ops::Range<usize>, String, StringEncoding)> {
+        util::find_ascii_strings(buf)
+            .map(|(range, s)| (range, s, StringEncoding::Ascii))
+            .chain(util::find_unicode_strings(buf).map(|(range, s)| (range, s, StringEncoding::Utf16le)))
+            .collect()
+    }
+
+    pub fn find_decoded_strings(
+        module: &Module,
+        function: VA,
+        cfg: &CFG,
+        prototypes: &BTreeMap<VA, Prototype>,
+        config: &EmulatorConfig,
+    ) -> Result<Vec<StackString>> {
+        let mut strings = vec![];
+        let mut seen: BTreeSet<String> = Default::default();
+
+        // strings that are already present statically aren't interesting.
+        for s in super::find_stack_strings(module, function, cfg)?.into_iter() {
+            seen.insert(s.string);
+        }
+
+        let stack_end = config.stack_address + config.stack_size;
+        for l in find_decoding_loops(module, cfg)?.iter() {
+            debug!("decoded strings: {:#x}: emulating loop {:#x}", function, l.header);
+
+            let emu = match emulate_through_loop(module, config, prototypes, function, l)? {
+                Some(emu) => emu,
+                None => continue,
+            };
+
+            let mut found = vec![];
+
+            // everything on the stack is new.
+            let stack = read_region(&emu, config.stack_address, stack_end);
+            found.extend(
+                find_buf_strings(&stack)
+                    .into_iter()
+                    .map(|(_, s, encoding)| (s, encoding)),
+            );
+
+            // but within the module, only consider strings that changed.
+            for section in module.sections.iter() {
+                if !section.permissions.intersects(Permissions::W) || section.permissions.intersects(Permissions::X) {
+                    continue;
+                }
+
+                let start = section.virtual_range.start;
+                let end = section.virtual_range.end;
+                let before = module.address_space.read_bytes(start, (end - start) as usize)?;
+                let after = read_region(&emu, start, end);
+
+                found.extend(
+                    find_buf_strings(&after)
+                        .into_iter()
+                        .filter(|(range, _, _)| before[range.clone()] != after[range.clone()])
+                        .map(|(_, s, encoding)| (s, encoding)),
+                );
+            }
+
+            for (s, encoding) in found.into_iter() {
+                if seen.insert(s.clone()) {
+                    strings.push(StackString {
+                        function,
+                        address: l.header,
+                        ty: StackStringType::Decoded,
+                        encoding,
+                        string: s,
+                    });
+                }
+            }
+        }
+
+        Ok(strings)
+    }
+}
+
+/// recover the strings decoded by small, self-contained loops within the given
+/// function, via emulation. strings also recovered by `find_stack_strings` are
+/// not reported.
+///
+/// `prototypes`, such as from `calling_convention::find_prototypes`, provides
+/// the stack cleanup of callees, so that the stack pointer can be tracked
+/// across calls on x86.
+///
+/// ```
+/// use lancelot::test::*;
+/// use lancelot::analysis::cfg::build_cfg;
+/// use lancelot::analysis::stack_strings::{find_decoded_strings, StackStringType};
+///
+/// // 0:  c7 45 f0 14 17 16 11    mov    DWORD PTR [ebp-0x10], 0x11161714
+/// // 7:  c6 45 f4 55             mov    BYTE PTR [ebp-0xc], 0x55
+/// // b:  31 c9                   xor    ecx, ecx
+/// // d:  80 74 0d f0 55          xor    BYTE PTR [ebp+ecx*1-0x10], 0x55
+/// // 12: 41                      inc    ecx
+/// // 13: 83 f9 05                cmp    ecx, 0x5
+/// // 16: 75 f5                   jne    0xd
+/// // 18: c3                      ret
+/// let module = load_shellcode32(
+///     b"\xC7\x45\xF0\x14\x17\x16\x11\xC6\x45\xF4\x55\x31\xC9\x80\x74\x0D\xF0\x55\x41\x83\xF9\x05\x75\xF5\xC3",
+/// );
+/// let cfg = build_cfg(&module, 0x0).unwrap();
+/// let strings = find_decoded_strings(&module, 0x0, &cfg, &Default::default()).unwrap();
+///
+/// assert_eq!(strings.len(), 1);
+/// assert_eq!(strings[0].string, "ABCD");
+/// assert_eq!(strings[0].address, 0xD);
+/// assert_eq!(strings[0].ty, StackStringType::Decoded);
+/// ```
+#[cfg(feature = "emulator")]
+pub fn find_decoded_strings(
+    module: &Module,
+    function: VA,
+    cfg: &CFG,
+    prototypes: &BTreeMap<VA, Prototype>,
+) -> Result<Vec<StackString>> {
+    find_decoded_strings_with_config(module, function, cfg, prototypes, &Default::default())
+}
+
+/// like `find_decoded_strings`, using the emulator limits from the
+/// configuration.
+#[cfg(feature = "emulator")]
+pub fn find_decoded_strings_with_config(
+    module: &Module,
+    function: VA,
+    cfg: &CFG,
+    prototypes: &BTreeMap<VA, Prototype>,
+    config: &Config,
+) -> Result<Vec<StackString>> {
+    decoding::find_decoded_strings(module, function, cfg, prototypes, &config.emulator)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::{
+            cfg::build_cfg,
+            stack_strings::{self, StackStringType},
+            strings::StringEncoding,
+        },
+        rsrc::*,
+        test::*,
+    };
+    use anyhow::Result;
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let cfg = build_cfg(&pe.module, 0x457BC3)?;
+        let strings = stack_strings::find_stack_strings(&pe.module, 0x457BC3, &cfg)?;
+        let s = strings.iter().find(|s| s.string == "Kerberos-Newer-Keys").unwrap();
+        assert_eq!(s.function, 0x457BC3);
+        assert_eq!(s.address, 0x457C7A);
+        assert_eq!(s.encoding, StringEncoding::Utf16le);
+        assert_eq!(s.ty, StackStringType::Stack);
+
+        let cfg = build_cfg(&pe.module, 0x458770)?;
+        let strings = stack_strings::find_stack_strings(&pe.module, 0x458770, &cfg)?;
+        let s = strings.iter().find(|s| s.string == "mimilsa.log").unwrap();
+        assert_eq!(s.address, 0x458776);
+        assert_eq!(s.encoding, StringEncoding::Ascii);
+
+        Ok(())
+    }
+
+    #[test]
+    fn register_constants() -> Result<()> {
+        // 0:  48 b8 41 42 43 44 45 46 47 00    movabs rax, 0x47464544434241
+        // a:  48 89 44 24 10                   mov    QWORD PTR [rsp+0x10], rax
+        // f:  31 c0                            xor    eax, eax
+        // 11: 48 89 44 24 18                   mov    QWORD PTR [rsp+0x18], rax
+        // 16: c3                               ret
+        let module = load_shellcode64(
+            b"\x48\xB8\x41\x42\x43\x44\x45\x46\x47\x00\x48\x89\x44\x24\x10\x31\xC0\x48\x89\x44\x24\x18\xC3",
+        );
+        let cfg = build_cfg(&module, 0x0)?;
+        let strings = stack_strings::find_stack_strings(&module, 0x0, &cfg)?;
+
+        assert_eq!(strings.len(), 1);
+        assert_eq!(strings[0].string, "ABCDEFG");
+        assert_eq!(strings[0].address, 0xA);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "emulator")]
+    #[test]
+    fn decoded_after_stdcall() -> Result<()> {
+        use crate::{analysis::calling_convention, VA};
+        use std::collections::BTreeMap;
+
+        // the buffer is encoded before the call, and decoded after it,
+        // both relative to esp, so the call's stack cleanup must be applied.
+        //
+        // 0:  c7 44 24 f0 14 17 16 11    mov    DWORD PTR [esp-0x10], 0x11161714
+        // 8:  c6 44 24 f4 55             mov    BYTE PTR [esp-0xc], 0x55
+        // d:  6a 01                      push   0x1
+        // f:  e8 0e 00 00 00             call   0x22
+        // 14: 31 c9                      xor    ecx, ecx
+        // 16: 80 74 0c f0 55             xor    BYTE PTR [esp+ecx*1-0x10], 0x55
+        // 1b: 41                         inc    ecx
+        // 1c: 83 f9 05                   cmp    ecx, 0x5
+        // 1f: 75 f5                      jne    0x16
+        // 21: c3                         ret
+        // 22: c2 04 00                   ret    0x4
+        let module = load_shellcode32(b"\xC7\x44\x24\xF0\x14\x17\x16\x11\xC6\x44\x24\xF4\x55\x6A\x01\xE8\x0E\x00\x00\x00\x31\xC9\x80\x74\x0C\xF0\x55\x41\x83\xF9\x05\x75\xF5\xC3\xC2\x04\x00");
+        let cfg = build_cfg(&module, 0x0)?;
+
+        let mut prototypes: BTreeMap<VA, calling_convention::Prototype> = Default::default();
+        prototypes.insert(
+            0x22,
+            calling_convention::Prototype {
+                calling_convention: calling_convention::CallingConvention::Stdcall,
+                register_arguments: vec![],
+                stack_arguments:    1,
+                callee_cleanup:     4,
+            },
+        );
+
+        let strings = stack_strings::find_decoded_strings(&module, 0x0, &cfg, &prototypes)?;
+        assert_eq!(strings.len(), 1);
+        assert_eq!(strings[0].string, "ABCD");
+        assert_eq!(strings[0].address, 0x16);
+
+        // without the cleanup, the loop decodes the wrong bytes.
+        let strings = stack_strings::find_decoded_strings(&module, 0x0, &cfg, &Default::default())?;
+        assert!(strings.iter().all(|s| s.string != "ABCD"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_store() -> Result<()> {
+        // a store of an unknown value splits the string.
+        //
+        // 0:  c7 45 f0 41 42 43 44    mov    DWORD PTR [ebp-0x10], 0x44434241
+        // 7:  c7 45 f4 45 46 47 48    mov    DWORD PTR [ebp-0xc], 0x48474645
+        // e:  89 4d f2                mov    DWORD PTR [ebp-0xe], ecx
+        // 11: c3                      ret
+        let module = load_shellcode32(b"\xC7\x45\xF0\x41\x42\x43\x44\xC7\x45\xF4\x45\x46\x47\x48\x89\x4D\xF2\xC3");
+        let cfg = build_cfg(&module, 0x0)?;
+        let strings = stack_strings::find_stack_strings(&module, 0x0, &cfg)?;
+
+        assert!(strings.is_empty());
+
+        Ok(())
+    }
+}
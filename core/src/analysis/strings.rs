@@ -0,0 +1,281 @@
+//! Recover the strings found in the data sections of a module,
+//! and link each to the instructions and functions that reference it.
+//!
+//! A string may be referenced:
+//!
+//!   1. directly by an instruction, like `push offset aFoo` or `lea rcx, aFoo`,
+//!   2. indirectly via a pointer in a data section, like a table of strings
+//!      that is indexed by an instruction.
+//!
+//! Strings in executable sections are not recovered, since code often
+//! looks like short ASCII strings.
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    analysis::{
+        cfg,
+        xrefs::{XrefType, Xrefs},
+    },
+    aspace::AddressSpace,
+    module::{Module, Permissions},
+    util, VA,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringEncoding {
+    Ascii,
+    Utf16le,
+}
+
+impl std::fmt::Display for StringEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StringEncoding::Ascii => write!(f, "ascii"),
+            StringEncoding::Utf16le => write!(f, "utf-16le"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ModuleString {
+    pub address:    VA,
+    pub encoding:   StringEncoding,
+    /// name of the section that contains the string.
+    pub section:    String,
+    pub string:     String,
+    /// addresses of the instructions that reference the string.
+    pub references: Vec<VA>,
+    /// starts of the functions whose CFGs contain a referencing instruction.
+    pub functions:  Vec<VA>,
+}
+
+/// the basic blocks of the given CFGs, indexed by their start address,
+/// so that we can find the functions that contain an instruction.
+///
+/// basic blocks may be shared among functions,
+/// so each entry is a list of (basic block length, function start).
+/// blocks from different CFGs may also overlap,
+/// such as when a function jumps into the middle of another's block,
+/// so we track the longest block to bound the search for containing blocks.
+#[derive(Default)]
+struct BasicBlockIndex {
+    blocks:     BTreeMap<VA, Vec<(u64, VA)>>,
+    max_length: u64,
+}
+
+fn index_basic_blocks(cfgs: &BTreeMap<VA, cfg::CFG>) -> BasicBlockIndex {
+    let mut index: BasicBlockIndex = Default::default();
+
+    for (&function, cfg) in cfgs.iter() {
+        for bb in cfg.basic_blocks.values() {
+            index.blocks.entry(bb.address).or_default().push((bb.length, function));
+            index.max_length = std::cmp::max(index.max_length, bb.length);
+        }
+    }
+
+    index
+}
+
+/// find the starts of the functions whose CFGs contain the given instruction.
+fn find_containing_functions(index: &BasicBlockIndex, va: VA) -> Vec<VA> {
+    // any block that starts within the longest block length prior may contain
+    // the instruction, not just the closest one.
+    let start = va.saturating_sub(index.max_length);
+    let mut functions: Vec<VA> = index
+        .blocks
+        .range(start..=va)
+        .flat_map(|(&bbva, entries)| {
+            entries
+                .iter()
+                .filter(move |(length, _)| va < bbva + length)
+                .map(|&(_, function)| function)
+        })
+        .collect();
+
+    functions.sort_unstable();
+    functions.dedup();
+    functions
+}
+
+/// find the instructions that reference the given address,
+/// either directly or via a pointer in a data section.
+fn find_referencing_instructions(module: &Module, xrefs: &Xrefs, va: VA) -> Vec<VA> {
+    let mut references = vec![];
+
+    for xref in xrefs.xrefs_to(va).iter() {
+        match xref.ty {
+            XrefType::Read | XrefType::Write | XrefType::Offset => references.push(xref.src),
+            XrefType::Pointer => {
+                // only follow a single level of indirection, from code.
+                references.extend(
+                    xrefs
+                        .xrefs_to(xref.src)
+                        .iter()
+                        .filter(|x| x.ty != XrefType::Pointer)
+                        .filter(|x| module.probe_va(x.src, Permissions::X))
+                        .map(|x| x.src),
+                );
+            }
+            _ => continue,
+        }
+    }
+
+    references.sort_unstable();
+    references.dedup();
+    references
+}
+
+/// recover the ASCII and UTF-16LE strings from the non-executable sections of
+/// the module, and link them to their referencing instructions and functions.
+///
+/// the code references are derived from the given CFGs and cross-reference
+/// index, so they're only as good as function discovery.
+pub fn find_strings(
+    module: &Module,
+    cfgs: &BTreeMap<VA, cfg::CFG>,
+    xrefs: &Xrefs,
+) -> Result<BTreeMap<VA, ModuleString>> {
+    let bbs = index_basic_blocks(cfgs);
+    let mut strings: BTreeMap<VA, ModuleString> = Default::default();
+
+    for section in module.sections.iter() {
+        if section.permissions.intersects(Permissions::X) {
+            continue;
+        }
+
+        debug!(
+            "strings: scanning section {} {:#x}-{:#x}",
+            section.name, section.virtual_range.start, section.virtual_range.end
+        );
+
+        let vstart: VA = section.virtual_range.start;
+        let vsize = (section.virtual_range.end - section.virtual_range.start) as usize;
+        let sec_buf = module.address_space.read_bytes(vstart, vsize)?;
+
+        let found = util::find_ascii_strings(&sec_buf)
+            .map(|(range, s)| (range, s, StringEncoding::Ascii))
+            .chain(util::find_unicode_strings(&sec_buf).map(|(range, s)| (range, s, StringEncoding::Utf16le)));
+
+        for (range, s, encoding) in found {
+            let va = vstart + range.start as u64;
+
+            let references = find_referencing_instructions(module, xrefs, va);
+            let mut functions: Vec<VA> = references
+                .iter()
+                .flat_map(|&insn| find_containing_functions(&bbs, insn))
+                .collect();
+            functions.sort_unstable();
+            functions.dedup();
+
+            strings.insert(
+                va,
+                ModuleString {
+                    address: va,
+                    encoding,
+                    section: section.name.clone(),
+                    string: s,
+                    references,
+                    functions,
+                },
+            );
+        }
+    }
+
+    Ok(strings)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::{
+            cfg::{BasicBlock, CFG},
+            pe,
+            strings::{self, StringEncoding},
+            xrefs,
+        },
+        rsrc::*,
+        VA,
+    };
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    fn find_strings(rsrc: Rsrc) -> Result<BTreeMap<VA, strings::ModuleString>> {
+        let buf = get_buf(rsrc);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+        for &function in pe::find_function_starts(&pe)?.iter() {
+            if let Ok(cfg) = crate::analysis::cfg::build_cfg(&pe.module, function) {
+                cfgs.insert(function, cfg);
+            }
+        }
+
+        let xrefs = xrefs::build_xrefs(&pe.module, &cfgs)?;
+        strings::find_strings(&pe.module, &cfgs, &xrefs)
+    }
+
+    fn make_cfg(blocks: &[(VA, u64)]) -> CFG {
+        CFG {
+            basic_blocks: blocks
+                .iter()
+                .map(|&(address, length)| {
+                    (
+                        address,
+                        BasicBlock {
+                            address,
+                            length,
+                            predecessors: Default::default(),
+                            successors: Default::default(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn overlapping_blocks() {
+        // function B enters in the middle of function A's block 0x100-0x120.
+        let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+        cfgs.insert(0x100, make_cfg(&[(0x100, 0x20)]));
+        cfgs.insert(0x200, make_cfg(&[(0x200, 0x8), (0x110, 0x10)]));
+        let index = strings::index_basic_blocks(&cfgs);
+
+        assert_eq!(strings::find_containing_functions(&index, 0x105), vec![0x100]);
+        assert_eq!(strings::find_containing_functions(&index, 0x115), vec![0x100, 0x200]);
+        assert_eq!(strings::find_containing_functions(&index, 0x204), vec![0x200]);
+        assert!(strings::find_containing_functions(&index, 0x120).is_empty());
+        assert!(strings::find_containing_functions(&index, 0x0).is_empty());
+    }
+
+    #[test]
+    fn k32() -> Result<()> {
+        let strings = find_strings(Rsrc::K32)?;
+
+        let s = &strings[&0x180083CD0];
+        assert_eq!(s.string, "FindFirstFile failed for %ws [GLE: %x]");
+        assert_eq!(s.encoding, StringEncoding::Ascii);
+        assert_eq!(s.section, ".rdata");
+        assert_eq!(s.references, vec![0x18006863E]);
+        assert_eq!(s.functions, vec![0x180068554]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let strings = find_strings(Rsrc::MIMI)?;
+
+        let s = &strings[&0x47F4D8];
+        assert_eq!(s.string, "CERT_SYSTEM_STORE_CURRENT_USER");
+        assert_eq!(s.encoding, StringEncoding::Utf16le);
+        assert_eq!(s.section, ".rdata");
+        assert_eq!(s.references, vec![0x40438D]);
+        assert_eq!(s.functions, vec![0x404379]);
+
+        Ok(())
+    }
+}
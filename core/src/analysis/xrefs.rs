@@ -0,0 +1,420 @@
+//! Index the cross-references among code and data in a module.
+//!
+//! We collect references from:
+//!
+//!   1. code to code, via call and jump instructions,
+//!   2. code to data, via memory operands (read/write) and immediate or `lea`
+//!      operands (offset),
+//!   3. data to code and data to data, via pointer-sized values found in
+//!      non-executable sections.
+//!
+//! Code references are only collected from instructions found in the given
+//! CFGs, so the index is only as good as function discovery.
+//!
+//! Assumes:
+//!   - pointers are 32-bits on x32 and 64-bits on x64 (*not* 32-bits on x64)
+//!   - pointers in data sections are aligned to the pointer size
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use byteorder::ByteOrder;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    analysis::{cfg, dis},
+    arch::Arch,
+    aspace::AddressSpace,
+    module::{Module, Permissions},
+    RVA, VA,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum XrefType {
+    /// code -> code, via a call instruction.
+    Call,
+    /// code -> code, via an unconditional jump.
+    UnconditionalJump,
+    /// code -> code, via a conditional jump.
+    ConditionalJump,
+    /// code -> data, the instruction reads from the address.
+    Read,
+    /// code -> data, the instruction writes to the address.
+    Write,
+    /// code -> *, the instruction uses the address as a value,
+    /// such as `push offset aFoo` or `lea eax, [aFoo]`.
+    Offset,
+    /// data -> *, the address is stored in a data section.
+    Pointer,
+}
+
+impl std::fmt::Display for XrefType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            XrefType::Call => write!(f, "call"),
+            XrefType::UnconditionalJump => write!(f, "jump"),
+            XrefType::ConditionalJump => write!(f, "conditional jump"),
+            XrefType::Read => write!(f, "read"),
+            XrefType::Write => write!(f, "write"),
+            XrefType::Offset => write!(f, "offset"),
+            XrefType::Pointer => write!(f, "pointer"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Xref {
+    /// the address of the instruction or pointer that makes the reference.
+    pub src: VA,
+    /// the referenced address.
+    pub dst: VA,
+    pub ty:  XrefType,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct Xrefs {
+    /// map from address to the references to it.
+    pub to:   BTreeMap<VA, Vec<Xref>>,
+    /// map from address to the references made by it.
+    pub from: BTreeMap<VA, Vec<Xref>>,
+}
+
+impl Xrefs {
+    /// fetch the references to the given address, or an empty list.
+    pub fn xrefs_to(&self, va: VA) -> &[Xref] {
+        self.to.get(&va).map(|xrefs| &xrefs[..]).unwrap_or(&[])
+    }
+
+    /// fetch the references made by the instruction or pointer at the given
+    /// address, or an empty list.
+    pub fn xrefs_from(&self, va: VA) -> &[Xref] {
+        self.from.get(&va).map(|xrefs| &xrefs[..]).unwrap_or(&[])
+    }
+
+    fn insert(&mut self, xref: Xref) {
+        self.to.entry(xref.dst).or_default().push(xref);
+        self.from.entry(xref.src).or_default().push(xref);
+    }
+}
+
+/// is the given address within a section of the module?
+fn is_mapped(module: &Module, va: VA) -> bool {
+    module.probe_va(va, Permissions::RWX)
+}
+
+/// compute the address referenced by a memory operand, when it can be known
+/// statically, such as `[0x401000]`, `[rip+0x10]`, or `[0x401000+eax*4]`.
+fn get_memory_operand_address(va: VA, insn: &zydis::DecodedInstruction, op: &zydis::DecodedOperand) -> Option<VA> {
+    if !op.mem.disp.has_displacement {
+        return None;
+    }
+
+    if op.mem.base == zydis::Register::RIP {
+        // only valid on x64
+        cfg::va_add_signed(va + insn.length as u64, op.mem.disp.displacement)
+    } else if op.mem.base == zydis::Register::NONE {
+        // either an absolute address, like `[0x401000]`,
+        // or the start of a table, like `[0x401000+eax*4]`.
+        if op.mem.disp.displacement < 0 {
+            None
+        } else {
+            Some(op.mem.disp.displacement as u64)
+        }
+    } else {
+        // something like `[ebp-0x10]` or `[eax+0x401000]`,
+        // which can't be resolved without dataflow analysis.
+        None
+    }
+}
+
+/// compute the data references made by the explicit operands of an
+/// instruction.
+pub(crate) fn get_operand_xrefs(module: &Module, va: VA, insn: &zydis::DecodedInstruction) -> Vec<Xref> {
+    let mut xrefs = vec![];
+
+    for op in insn.operands[..insn.operand_count as usize]
+        .iter()
+        .filter(|op| op.visibility == zydis::OperandVisibility::EXPLICIT)
+    {
+        match op.ty {
+            zydis::OperandType::MEMORY => {
+                let dst = match get_memory_operand_address(va, insn, op) {
+                    Some(dst) if is_mapped(module, dst) => dst,
+                    _ => continue,
+                };
+
+                if insn.mnemonic == zydis::Mnemonic::LEA {
+                    // the memory operand of `lea` is not dereferenced.
+                    xrefs.push(Xref {
+                        src: va,
+                        dst,
+                        ty: XrefType::Offset,
+                    });
+                    continue;
+                }
+
+                if op.action.intersects(zydis::OperandAction::MASK_WRITE) {
+                    xrefs.push(Xref {
+                        src: va,
+                        dst,
+                        ty: XrefType::Write,
+                    });
+                }
+
+                if op.action.intersects(zydis::OperandAction::MASK_READ)
+                    || !op.action.intersects(zydis::OperandAction::MASK_WRITE)
+                {
+                    xrefs.push(Xref {
+                        src: va,
+                        dst,
+                        ty: XrefType::Read,
+                    });
+                }
+            }
+            zydis::OperandType::IMMEDIATE if !op.imm.is_relative => {
+                // like `push offset aFoo` or `mov eax, offset sub_401000`.
+                // small constants never fall within the module,
+                // so this is pretty reliable, particularly on x64.
+                let dst = match module.arch {
+                    Arch::X32 => op.imm.value as u32 as VA,
+                    Arch::X64 => op.imm.value,
+                };
+
+                if is_mapped(module, dst) {
+                    xrefs.push(Xref {
+                        src: va,
+                        dst,
+                        ty: XrefType::Offset,
+                    });
+                }
+            }
+            _ => continue,
+        }
+    }
+
+    xrefs
+}
+
+/// compute the code references made by a call or jump instruction.
+fn get_flow_xrefs(module: &Module, va: VA, insn: &zydis::DecodedInstruction) -> Result<Vec<Xref>> {
+    Ok(cfg::get_insn_flow(module, va, insn)?
+        .iter()
+        .filter_map(|flow| {
+            let ty = match flow {
+                cfg::Flow::Call(_) => XrefType::Call,
+                cfg::Flow::UnconditionalJump(_) => XrefType::UnconditionalJump,
+                cfg::Flow::ConditionalJump(_) => XrefType::ConditionalJump,
+                // fallthrough and cmov are not references.
+                cfg::Flow::Fallthrough(_) | cfg::Flow::ConditionalMove(_) => return None,
+            };
+
+            Some(Xref {
+                src: va,
+                dst: flow.va(),
+                ty,
+            })
+        })
+        .collect())
+}
+
+/// compute the references made by the given instruction:
+/// code references via calls and jumps,
+/// and data references via its operands.
+pub fn get_insn_xrefs(module: &Module, va: VA, insn: &zydis::DecodedInstruction) -> Result<Vec<Xref>> {
+    let mut xrefs = get_flow_xrefs(module, va, insn)?;
+    xrefs.extend(get_operand_xrefs(module, va, insn));
+    Ok(xrefs)
+}
+
+/// find pointer-sized values in non-executable sections that refer to
+/// addresses within the module.
+fn find_data_xrefs(module: &Module) -> Result<Vec<Xref>> {
+    let mut xrefs = vec![];
+
+    let psize = module.arch.pointer_size();
+
+    for section in module.sections.iter() {
+        if section.permissions.intersects(Permissions::X) {
+            continue;
+        }
+
+        debug!(
+            "xrefs: scanning section {:#x}-{:#x}",
+            section.virtual_range.start, section.virtual_range.end
+        );
+
+        let vstart: VA = section.virtual_range.start;
+        let vsize = (section.virtual_range.end - section.virtual_range.start) as usize;
+        let sec_buf = module.address_space.read_bytes(vstart, vsize)?;
+
+        for (i, b) in sec_buf.chunks_exact(psize).enumerate() {
+            let dst = match module.arch {
+                Arch::X32 => byteorder::LittleEndian::read_u32(b) as VA,
+                Arch::X64 => byteorder::LittleEndian::read_u64(b) as VA,
+            };
+
+            if is_mapped(module, dst) {
+                xrefs.push(Xref {
+                    src: vstart + (i * psize) as RVA,
+                    dst,
+                    ty: XrefType::Pointer,
+                });
+            }
+        }
+    }
+
+    Ok(xrefs)
+}
+
+/// construct an index of the references among code and data in the module.
+/// code references are collected from the instructions in the given CFGs.
+///
+/// ```
+/// use lancelot::test::*;
+/// use lancelot::analysis::cfg::build_cfg;
+/// use lancelot::analysis::xrefs::{build_xrefs, XrefType};
+///
+/// // 0:  48 8D 05 01 00 00 00  lea rax, [rip+0x1]
+/// // 7:  C3                    ret
+/// // 8:  00                    db 0x0
+/// let module = load_shellcode64(b"\x48\x8D\x05\x01\x00\x00\x00\xC3\x00");
+/// let mut cfgs = std::collections::BTreeMap::new();
+/// cfgs.insert(0x0, build_cfg(&module, 0x0).unwrap());
+///
+/// let xrefs = build_xrefs(&module, &cfgs).unwrap();
+/// assert_eq!(xrefs.xrefs_to(0x8)[0].src, 0x0);
+/// assert_eq!(xrefs.xrefs_to(0x8)[0].ty, XrefType::Offset);
+/// assert_eq!(xrefs.xrefs_from(0x7).len(), 0);
+/// ```
+pub fn build_xrefs(module: &Module, cfgs: &BTreeMap<VA, cfg::CFG>) -> Result<Xrefs> {
+    debug!("xrefs");
+
+    let decoder = dis::get_disassembler(module)?;
+    let mut xrefs: BTreeSet<Xref> = Default::default();
+    // basic blocks may be shared among CFGs, so only process each once.
+    let mut seen: BTreeSet<VA> = Default::default();
+
+    for (&function, cfg) in cfgs.iter() {
+        debug!("xrefs: {:#x}", function);
+
+        for basic_block in cfg.basic_blocks.values() {
+            if !seen.insert(basic_block.address) {
+                continue;
+            }
+
+            let buf = module
+                .address_space
+                .read_bytes(basic_block.address, basic_block.length as usize)?;
+
+            for (offset, insn) in dis::linear_disassemble(&decoder, &buf) {
+                if let Ok(Some(insn)) = insn {
+                    let va = basic_block.address + offset as RVA;
+
+                    xrefs.extend(get_insn_xrefs(module, va, &insn)?);
+                }
+            }
+        }
+    }
+
+    xrefs.extend(find_data_xrefs(module)?);
+
+    let mut index: Xrefs = Default::default();
+    for xref in xrefs.into_iter() {
+        index.insert(xref);
+    }
+
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::{
+            cfg::CFG,
+            pe,
+            xrefs::{self, XrefType},
+        },
+        rsrc::*,
+        VA,
+    };
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    fn build_xrefs(rsrc: Rsrc) -> Result<xrefs::Xrefs> {
+        let buf = get_buf(rsrc);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+        for &function in pe::find_function_starts(&pe)?.iter() {
+            if let Ok(cfg) = crate::analysis::cfg::build_cfg(&pe.module, function) {
+                cfgs.insert(function, cfg);
+            }
+        }
+
+        xrefs::build_xrefs(&pe.module, &cfgs)
+    }
+
+    #[test]
+    fn k32() -> Result<()> {
+        let xrefs = build_xrefs(Rsrc::K32)?;
+
+        // .text:000000018005E9B2  call [0x0000000180079800]
+        assert!(xrefs
+            .xrefs_from(0x18005E9B2)
+            .iter()
+            .any(|x| x.dst == 0x180079800 && x.ty == XrefType::Read));
+        assert!(xrefs
+            .xrefs_to(0x180079800)
+            .iter()
+            .any(|x| x.src == 0x18005E9B2 && x.ty == XrefType::Read));
+
+        // .text:0000000180051F9C  lea r8, [0x00000001800803C0]
+        assert_eq!(xrefs.xrefs_to(0x1800803C0).len(), 1);
+        assert_eq!(xrefs.xrefs_to(0x1800803C0)[0].src, 0x180051F9C);
+        assert_eq!(xrefs.xrefs_to(0x1800803C0)[0].ty, XrefType::Offset);
+
+        // call graph: 0x180060504 calls 0x180001068
+        assert!(xrefs
+            .xrefs_to(0x180001068)
+            .iter()
+            .any(|x| x.src == 0x180060504 && x.ty == XrefType::Call));
+
+        // not a referenced address
+        assert!(xrefs.xrefs_to(0x0).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let xrefs = build_xrefs(Rsrc::MIMI)?;
+
+        // .text:0040E7AB  cmp esi, [0x004B9E44]
+        // .text:0040E7B3  mov [0x004B9E44], esi
+        assert!(xrefs
+            .xrefs_to(0x4B9E44)
+            .iter()
+            .any(|x| x.src == 0x40E7AB && x.ty == XrefType::Read));
+        assert!(xrefs
+            .xrefs_to(0x4B9E44)
+            .iter()
+            .any(|x| x.src == 0x40E7B3 && x.ty == XrefType::Write));
+
+        // .text:00413564  call [0x004B858C]
+        // and 0x4B858C contains the pointer 0x46B578
+        assert!(xrefs
+            .xrefs_from(0x413564)
+            .iter()
+            .any(|x| x.dst == 0x4B858C && x.ty == XrefType::Read));
+        assert!(xrefs
+            .xrefs_from(0x413564)
+            .iter()
+            .any(|x| x.dst == 0x46B578 && x.ty == XrefType::Call));
+        assert!(xrefs
+            .xrefs_from(0x4B858C)
+            .iter()
+            .any(|x| x.dst == 0x46B578 && x.ty == XrefType::Pointer));
+
+        Ok(())
+    }
+}
@@ -1,2 +1,234 @@
-#[derive(Default, Debug)]
-pub struct Config {}
+//! Configuration of the analysis passes.
+//!
+//! The defaults enable everything, and may be overridden by a TOML file like:
+//!
+//! ```toml
+//! [functions]
+//! prologues = false
+//! min_confidence = 50
+//!
+//! [cfg]
+//! max_instructions = 0x4000
+//!
+//! [flirt]
+//! signatures = ["sigs/vc32_14.sig", "sigs/vc32_14.pat"]
+//!
+//! [emulator]
+//! max_steps = 0x1000
+//! ```
+//!
+//! Sections and fields that are not present take their default values.
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{util, VA};
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+}
+
+/// the sources of function starts used by `find_functions`,
+/// and how the candidates are filtered.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FunctionsConfig {
+    pub entrypoint:         bool,
+    pub exports:            bool,
+    pub safeseh:            bool,
+    pub runtime_functions:  bool,
+    pub exception_handlers: bool,
+    pub cfguard:            bool,
+    pub call_targets:       bool,
+    /// linear scan for common function prologues.
+    pub prologues:          bool,
+    /// pointers into executable sections that are not relocated.
+    pub pointers:           bool,
+    pub vtables:            bool,
+    pub cxx_exceptions:     bool,
+    /// scan the executable sections for the FLIRT signatures listed by
+    /// `[flirt]`.
+    pub flirt:              bool,
+    /// check the code of candidates found only by heuristics,
+    /// such as prologues and pointers.
+    pub validate:           bool,
+    /// drop local functions with a lower confidence, from 0 to 100.
+    pub min_confidence:     u8,
+}
+
+impl Default for FunctionsConfig {
+    fn default() -> Self {
+        FunctionsConfig {
+            entrypoint:         true,
+            exports:            true,
+            safeseh:            true,
+            runtime_functions:  true,
+            exception_handlers: true,
+            cfguard:            true,
+            call_targets:       true,
+            prologues:          true,
+            pointers:           true,
+            vtables:            true,
+            cxx_exceptions:     true,
+            flirt:              true,
+            validate:           true,
+            min_confidence:     0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CfgConfig {
+    /// give up reconstructing a CFG with more instructions than this.
+    pub max_instructions: usize,
+}
+
+impl Default for CfgConfig {
+    fn default() -> Self {
+        CfgConfig {
+            max_instructions: 0x10_0000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlirtConfig {
+    /// paths to .sig and .pat files.
+    /// relative paths are resolved against the directory of the config file.
+    pub signatures: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmulatorConfig {
+    /// give up emulating after this many instructions.
+    pub max_steps:     usize,
+    pub stack_address: VA,
+    pub stack_size:    u64,
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        EmulatorConfig {
+            max_steps:     0x10000,
+            stack_address: 0x0010_0000,
+            stack_size:    0x4_0000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub functions: FunctionsConfig,
+    pub cfg:       CfgConfig,
+    pub flirt:     FlirtConfig,
+    pub emulator:  EmulatorConfig,
+}
+
+impl Config {
+    /// parse the configuration from the given TOML document.
+    ///
+    /// ```
+    /// use lancelot::config::Config;
+    ///
+    /// let config = Config::from_toml("[functions]\nprologues = false").unwrap();
+    /// assert!(!config.functions.prologues);
+    /// assert!(config.functions.pointers);
+    /// ```
+    pub fn from_toml(s: &str) -> Result<Config> {
+        let config: Config = toml::from_str(s).map_err(|e| ConfigError::InvalidConfig(e.to_string()))?;
+
+        if config.functions.min_confidence > 100 {
+            return Err(ConfigError::InvalidConfig("functions.min_confidence must be at most 100".to_string()).into());
+        }
+
+        if config.emulator.stack_size == 0 || !config.emulator.stack_size.is_multiple_of(0x1000) {
+            return Err(
+                ConfigError::InvalidConfig("emulator.stack_size must be a multiple of 0x1000".to_string()).into(),
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// load the configuration from the TOML file at the given path.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let path = path.as_ref();
+        let buf = util::read_file(&path.to_string_lossy())?;
+        let mut config = Config::from_toml(&String::from_utf8(buf)?)?;
+
+        if let Some(dir) = path.parent() {
+            for sig in config.flirt.signatures.iter_mut() {
+                if sig.is_relative() {
+                    *sig = dir.join(&sig);
+                }
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::*;
+    use anyhow::Result;
+
+    #[test]
+    fn defaults() -> Result<()> {
+        let config = Config::from_toml("")?;
+        assert!(config.functions.prologues);
+        assert!(config.functions.validate);
+        assert_eq!(config.functions.min_confidence, 0);
+        assert_eq!(config.cfg.max_instructions, 0x10_0000);
+        assert!(config.flirt.signatures.is_empty());
+        assert_eq!(config.emulator.max_steps, 0x10000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn sections() -> Result<()> {
+        let config = Config::from_toml(
+            r#"
+            [functions]
+            pointers = false
+            min_confidence = 50
+
+            [cfg]
+            max_instructions = 0x100
+
+            [flirt]
+            signatures = ["a.sig", "b.pat"]
+
+            [emulator]
+            max_steps = 10
+            "#,
+        )?;
+
+        assert!(!config.functions.pointers);
+        assert!(config.functions.prologues);
+        assert_eq!(config.functions.min_confidence, 50);
+        assert_eq!(config.cfg.max_instructions, 0x100);
+        assert_eq!(config.flirt.signatures.len(), 2);
+        assert_eq!(config.emulator.max_steps, 10);
+        assert_eq!(config.emulator.stack_size, 0x4_0000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        // unknown fields are probably typos.
+        assert!(Config::from_toml("[functions]\nprolog = false").is_err());
+        assert!(Config::from_toml("[functions]\nmin_confidence = 101").is_err());
+        assert!(Config::from_toml("[emulator]\nstack_size = 0x10").is_err());
+    }
+}
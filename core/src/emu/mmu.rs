@@ -281,7 +281,7 @@ impl MMU {
     pub fn read(&self, addr: VA, buf: &mut [u8], perms: Permissions) -> Result<(), MMUError> {
         assert!(buf.len() <= PAGE_SIZE);
 
-        let end_addr = addr + buf.len() as u64;
+        let end_addr = addr.wrapping_add(buf.len() as u64);
         if page_number(addr) != page_number(end_addr) && !is_page_aligned(end_addr) {
             // split read
             let read_size: usize = buf.len();
@@ -474,7 +474,7 @@ impl MMU {
     fn write_inner(&mut self, addr: VA, buf: &[u8], sudo: bool) -> Result<(), MMUError> {
         assert!(buf.len() <= PAGE_SIZE);
 
-        let end_addr = addr + buf.len() as u64;
+        let end_addr = addr.wrapping_add(buf.len() as u64);
         if page_number(addr) != page_number(end_addr) && !is_page_aligned(end_addr) {
             // split write
             let write_size: usize = buf.len();
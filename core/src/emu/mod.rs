@@ -3,9 +3,13 @@ use std::unimplemented;
 use anyhow::Result;
 use log::debug;
 use thiserror::Error;
-use zydis::{enums::Register, DecodedInstruction, DecodedOperand};
+use zydis::{enums::Register, DecodedInstruction};
 
 use crate::{
+    analysis::ir::{
+        eval::{self, EvalError, Machine},
+        lift,
+    },
     arch::Arch,
     aspace::AddressSpace,
     module::{Module, Permissions},
@@ -394,135 +398,6 @@ impl Emulator {
         }
     }
 
-    fn get_operand_address(&self, op: &DecodedOperand) -> VA {
-        use zydis::Register::*;
-        assert!(op.ty == zydis::OperandType::MEMORY);
-
-        // http://www.c-jump.com/CIS77/ASM/Addressing/lecture.html
-
-        let mut addr = 0;
-
-        addr += self.get_segment_address(op.mem.segment);
-
-        if op.mem.base != NONE {
-            if op.mem.base == RIP {
-                // TODO: if RIP-relative, add insn length.
-                unimplemented!("rip-relative addressing");
-            } else {
-                addr += self.read_register(op.mem.base);
-            }
-        }
-
-        if op.mem.index != NONE {
-            addr += self.read_register(op.mem.index) * (op.mem.scale as u64);
-        }
-
-        if op.mem.disp.has_displacement {
-            if op.mem.disp.displacement < 0 {
-                addr -= (-op.mem.disp.displacement) as u64;
-            } else {
-                addr += op.mem.disp.displacement as u64;
-            }
-        }
-
-        addr
-    }
-
-    /// Errors:
-    ///   - ReadError::AddressNotMapped when a memory address is not mapped.
-    ///   - ReadError::AccessViolation when a memory address is not readable.
-    fn read_memory(&self, src: &DecodedOperand) -> Result<u64, ReadError> {
-        let addr = self.get_operand_address(src);
-
-        let ret = match src.size {
-            64 => self.mem.read_u64(addr),
-            32 => self.mem.read_u32(addr).map(|v| v as u64),
-            16 => self.mem.read_u16(addr).map(|v| v as u64),
-            8 => self.mem.read_u8(addr).map(|v| v as u64),
-            s => unimplemented!("memory read size: {:?}", s),
-        };
-
-        match ret {
-            Ok(v) => Ok(v),
-            Err(e @ mmu::MMUError::AddressNotMapped(..)) => Err(ReadError::AddressNotMapped {
-                va:     addr,
-                size:   src.size / 8,
-                source: e,
-            }),
-            Err(e @ mmu::MMUError::AccessViolation(..)) => Err(ReadError::AddressNotMapped {
-                va:     addr,
-                size:   src.size / 8,
-                source: e,
-            }),
-            _ => panic!("unexpected error"),
-        }
-    }
-
-    /// Errors:
-    ///   - WriteError::AddressNotMapped when a memory address is not mapped.
-    ///   - WriteError::AccessViolation when a memory address is not writable.
-    fn write_memory(&mut self, dst: &DecodedOperand, value: u64) -> Result<(), WriteError> {
-        let addr = self.get_operand_address(dst);
-
-        let ret = match dst.size {
-            64 => self.mem.write_u64(addr, value),
-            32 => self.mem.write_u32(addr, value as u32),
-            16 => self.mem.write_u16(addr, value as u16),
-            8 => self.mem.write_u8(addr, value as u8),
-            s => unimplemented!("memory write size: {:?}", s),
-        };
-
-        match ret {
-            Ok(()) => Ok(()),
-            Err(e @ mmu::MMUError::AddressNotMapped(..)) => Err(WriteError::AddressNotMapped {
-                va:     addr,
-                size:   dst.size / 8,
-                source: e,
-            }),
-            Err(e @ mmu::MMUError::AccessViolation(..)) => Err(WriteError::AccessViolation {
-                va:     addr,
-                size:   dst.size / 8,
-                source: e,
-            }),
-            _ => panic!("unexpected error"),
-        }
-    }
-
-    /// Errors:
-    ///   - ReadError::AddressNotMapped when a memory address is not mapped.
-    ///   - ReadError::AccessViolation when a memory address is not readable.
-    fn read_operand(&mut self, insn: &DecodedInstruction, src: &DecodedOperand) -> Result<u64, ReadError> {
-        use zydis::enums::OperandType::*;
-        Ok(match src.ty {
-            IMMEDIATE => {
-                if src.imm.is_relative {
-                    self.reg.rip + insn.length as u64 + src.imm.value
-                } else {
-                    src.imm.value
-                }
-            }
-            REGISTER => self.read_register(src.reg),
-            // handle unmapped read
-            MEMORY => self.read_memory(src)?,
-            t => unimplemented!("read operand type: {:?}", t),
-        })
-    }
-
-    /// Errors:
-    ///   - WriteError::AddressNotMapped when a memory address is not mapped.
-    ///   - WriteError::AccessViolation when a memory address is not executable.
-    fn write_operand(&mut self, dst: &DecodedOperand, value: u64) -> Result<(), WriteError> {
-        use zydis::enums::OperandType::*;
-
-        match dst.ty {
-            REGISTER => self.write_register(dst.reg, value),
-            MEMORY => self.write_memory(dst, value)?,
-            t => unimplemented!("write operand type: {:?}", t),
-        }
-
-        Ok(())
-    }
-
     /// Errors:
     ///   - FetchError::InvalidInstruction for instructions that cannot be
     ///     decoded.
@@ -552,7 +427,13 @@ impl Emulator {
         }
     }
 
+    /// execute the given instruction via its IR semantics,
+    /// which are shared with the data flow analyses.
+    ///
     /// Errors:
+    ///   - LiftError::UnsupportedInstruction for instructions without IR
+    ///     semantics.
+    ///   - EvalError::DivideByZero when dividing by zero.
     ///   - WriteError::AddressNotMapped when a memory address is not mapped.
     ///   - WriteError::AccessViolation when a memory address is not executable.
     ///   - ReadError::AddressNotMapped when a memory address is not mapped.
@@ -563,511 +444,15 @@ impl Emulator {
     /// that is, the caller may page in some additional memory, for example,
     /// and then invoke this routine again.
     pub fn execute(&mut self, insn: &DecodedInstruction) -> Result<()> {
-        use zydis::enums::{Mnemonic::*, Register::*};
-
         debug!("emu: insn: {:#x}: {:#?}", self.reg.rip, insn.mnemonic);
-        match insn.mnemonic {
-            NOP => {
-                //println!("{:#?}", insn);
-                self.reg.rip += insn.length as u64;
-            }
 
-            MOV => {
-                let dst = &insn.operands[0];
-                let src = &insn.operands[1];
-
-                let value = self.read_operand(insn, src)?;
-                self.write_operand(dst, value)?;
-
-                self.reg.rip += insn.length as u64;
-            }
-
-            XCHG => {
-                let m = &insn.operands[0];
-                let n = &insn.operands[1];
-
-                let mm = self.read_operand(insn, m)?;
-                let nn = self.read_operand(insn, n)?;
-                self.write_operand(m, nn)?;
-                self.write_operand(n, mm)?;
-
-                self.reg.rip += insn.length as u64;
-            }
-
-            LEA => {
-                let dst = &insn.operands[0];
-                let src = &insn.operands[1];
-
-                let value = self.get_operand_address(src);
-                self.write_operand(dst, value)?;
-
-                self.reg.rip += insn.length as u64;
-            }
-
-            PUSH => {
-                // EXPLICIT/READ/IMMEDIATE|REGISTER
-                let src = &insn.operands[0];
-
-                // HIDDEN/WRITE/REG/$SP
-                let sp_op = &insn.operands[1];
-                assert!(sp_op.ty == zydis::enums::OperandType::REGISTER);
-
-                // HIDDEN/WRITE/MEM
-                let dst = &insn.operands[2];
-                assert!(dst.ty == zydis::enums::OperandType::MEMORY);
-
-                // > "The PUSH ESP instruction pushes the value of
-                // > the ESP register as it existed before the
-                // > instruction was executed."
-                //
-                // https://c9x.me/x86/html/file_module_x86_id_269.html
-                let value = self.read_operand(insn, src)?;
-
-                match sp_op.reg {
-                    RSP => self.reg.rsp -= 8,
-                    ESP => self.reg.rsp -= 4,
-                    _ => unimplemented!(),
-                }
-
-                if let Err(e) = self.write_operand(dst, value) {
-                    // roll back the stack changes
-                    match sp_op.reg {
-                        RSP => self.reg.rsp += 8,
-                        ESP => self.reg.rsp += 4,
-                        _ => unimplemented!(),
-                    }
-                    return Err(e.into());
-                }
-
-                self.reg.rip += insn.length as u64;
-            }
-
-            POP => {
-                // EXPLICIT/write/REGISTER
-                let dst = &insn.operands[0];
-
-                // HIDDEN/WRITE/REG/$SP
-                let sp_op = &insn.operands[1];
-                assert!(sp_op.ty == zydis::enums::OperandType::REGISTER);
-
-                // HIDDEN/READ/MEM
-                let src = &insn.operands[2];
-                assert!(src.ty == zydis::enums::OperandType::MEMORY);
-
-                let value = self.read_operand(insn, src)?;
-
-                // > "The POP ESP instruction increments the
-                // > stack pointer (ESP) before data at the
-                // > old top of stack is written into the destination."
-                //
-                // https://c9x.me/x86/html/file_module_x86_id_248.html
-                match sp_op.reg {
-                    RSP => self.reg.rsp += 8,
-                    ESP => self.reg.rsp += 4,
-                    _ => unimplemented!(),
-                }
-
-                if let Err(e) = self.write_operand(dst, value) {
-                    // roll back the stack changes
-                    match sp_op.reg {
-                        RSP => self.reg.rsp -= 8,
-                        ESP => self.reg.rsp -= 4,
-                        _ => unimplemented!(),
-                    }
-                    return Err(e.into());
-                }
-
-                self.reg.rip += insn.length as u64;
-            }
-
-            CALL => {
-                // EXPLICIT/READ/MEMORY|REGISTER call target
-                let target = &insn.operands[0];
-                // HIDDEN/READ-WRITE/REGISTER/PC program counter
-                let pc = &insn.operands[1];
-                assert!(pc.ty == zydis::enums::OperandType::REGISTER);
-                // HIDDEN/READ-WRITE/REGISTER/SP stack pointer
-                let sp = &insn.operands[2];
-                assert!(sp.ty == zydis::enums::OperandType::REGISTER);
-                // HIDDEN/READ-WRITE/MEMORY/SP stack contents
-                let stack = &insn.operands[3];
-                assert!(stack.ty == zydis::enums::OperandType::MEMORY);
-
-                match sp.reg {
-                    RSP => self.reg.rsp -= 8,
-                    ESP => self.reg.rsp -= 4,
-                    _ => unimplemented!(),
-                }
-
-                let return_address = self.reg.rip + insn.length as u64;
-                self.write_operand(stack, return_address)?;
-
-                if let Err(e) = self.write_operand(stack, return_address) {
-                    // roll back the stack changes
-                    match sp.reg {
-                        RSP => self.reg.rsp += 8,
-                        ESP => self.reg.rsp += 4,
-                        _ => unimplemented!(),
-                    }
-                    return Err(e.into());
-                }
-
-                // these read/writes shouldn't ever fail: address computation and PC register
-                // set.
-                let target_addr = self.read_operand(insn, target).expect("failed to read call target");
-                self.write_operand(pc, target_addr).expect("failed to set PC");
-            }
-
-            RET => {
-                // HIDDEN/WRITE/REGISTER/PC
-                let pc = &insn.operands[0];
-                assert!(pc.ty == zydis::enums::OperandType::REGISTER);
-                // HIDDEN/READ-WRITE/REGISTER/SP
-                let sp = &insn.operands[1];
-                assert!(sp.ty == zydis::enums::OperandType::REGISTER);
-                // HIDDEN/READ/MEMORY/SP stack contents
-                let stack = &insn.operands[2];
-                assert!(stack.ty == zydis::enums::OperandType::MEMORY);
-
-                let return_address = self.read_operand(insn, stack)?;
-
-                match sp.reg {
-                    RSP => self.reg.rsp += 8,
-                    ESP => self.reg.rsp += 4,
-                    _ => unimplemented!(),
-                }
-
-                // this write shouldn't ever fail: PC register set.
-                self.write_operand(pc, return_address).expect("failed to set PC");
-            }
-
-            SUB => {
-                // EXPLICIT/READ|WRITE
-                let dst = &insn.operands[0];
-                // EXPLICIT/READ
-                let src = &insn.operands[1];
-                // EXPLICIT/WRITE/RFLAGS
-                let flags = &insn.operands[2];
-                assert!(flags.ty == zydis::enums::OperandType::REGISTER);
-
-                let m = self.read_operand(insn, dst)?;
-                let n = self.read_operand(insn, src)?;
-
-                let (result, msb_index, cf) = match dst.size {
-                    64 => {
-                        let result = m.wrapping_sub(n);
-                        (result, 63, n as u64 > m as u64)
-                    }
-                    32 => {
-                        let result = (m as u32).wrapping_sub(n as u32) as u64;
-                        (result, 31, n as u32 > m as u32)
-                    }
-                    16 => {
-                        let result = (m as u16).wrapping_sub(n as u16) as u64;
-                        (result, 15, n as u16 > m as u16)
-                    }
-                    8 => {
-                        let result = (m as u8).wrapping_sub(n as u8) as u64;
-                        (result, 7, n as u8 > m as u8)
-                    }
-                    s => unimplemented!("sub size {:}", s),
-                };
-
-                let zf = result == 0;
-                let pf = (result as u8).count_ones() % 2 == 0;
-                let sf = (result & (1 << msb_index)) > 0;
-
-                // ```text
-                // > The rules for turning on the overflow flag in binary/integer math are two:
-                // >
-                // > 1. If the sum of two numbers with the sign bits off yields a result number
-                // >   with the sign bit on, the "overflow" flag is turned on.
-                // >
-                // >   0100 + 0100 = 1000 (overflow flag is turned on)
-                // >
-                // > 2. If the sum of two numbers with the sign bits on yields a result number
-                // >   with the sign bit off, the "overflow" flag is turned on.
-                // >
-                // >   1000 + 1000 = 0000 (overflow flag is turned on)
-                // ```
-                // http://teaching.idallen.com/dat2343/10f/notes/040_overflow.txt
-
-                let m_msb = (m & (1 << msb_index)) > 0;
-                let n_msb = (n & (1 << msb_index)) > 0;
-
-                // ```text
-                // >       SUBTRACTION SIGN BITS
-                // >     num1sign num2sign sumsign
-                // >    ---------------------------
-                // >         0 0 0
-                // >         0 0 1
-                // >         0 1 0
-                // >  *OVER* 0 1 1 (subtracting a negative is the same as adding a positive)
-                // >  *OVER* 1 0 0 (subtracting a positive is the same as adding a negative)
-                // >         1 0 1
-                // >         1 1 0
-                // >         1 1 1
-                // ```
-                // http://teaching.idallen.com/dat2343/10f/notes/040_overflow.txt
-                let of = matches!((m_msb, n_msb, sf), (false, true, true) | (true, false, false));
-
-                // https://stackoverflow.com/a/4513781/87207
-                let af = (n & 0x0F) > (m & 0x0F);
-
-                self.write_operand(dst, result)?;
-                self.reg.set_cf(cf);
-                self.reg.set_of(of);
-                self.reg.set_sf(sf);
-                self.reg.set_af(af);
-                self.reg.set_pf(pf);
-                self.reg.set_zf(zf);
-
-                self.reg.rip += insn.length as u64;
-            }
-
-            ADD => {
-                // EXPLICIT/READ|WRITE
-                let dst = &insn.operands[0];
-                // EXPLICIT/READ
-                let src = &insn.operands[1];
-                // HIDDEN/WRITE/RFLAGS
-                let flags = &insn.operands[2];
-                assert!(flags.ty == zydis::enums::OperandType::REGISTER);
-
-                let m = self.read_operand(insn, dst)?;
-                let n = self.read_operand(insn, src)?;
-
-                let (result, msb_index, cf) = match dst.size {
-                    64 => {
-                        let result = (n as u64) as u128 + (m as u64) as u128;
-                        (result as u64 & u64::MAX as u64, 63, result > u64::MAX as u128)
-                    }
-                    32 => {
-                        let result = (n as u32) as u64 + (m as u32) as u64;
-                        (result as u64 & u32::MAX as u64, 31, result > u32::MAX as u64)
-                    }
-                    16 => {
-                        let result = (n as u16) as u32 + (m as u16) as u32;
-                        (result as u64 & u16::MAX as u64, 15, result > u16::MAX as u32)
-                    }
-                    8 => {
-                        let result = (n as u8) as u16 + (m as u8) as u16;
-                        (result as u64 & u8::MAX as u64, 7, result > u8::MAX as u16)
-                    }
-                    s => unimplemented!("sub size {:}", s),
-                };
-
-                let zf = result == 0;
-                let pf = (result as u8).count_ones() % 2 == 0;
-                let sf = (result & (1 << msb_index)) > 0;
-
-                let m_msb = (m & (1 << msb_index)) > 0;
-                let n_msb = (n & (1 << msb_index)) > 0;
-
-                // ```text
-                // >        ADDITION SIGN BITS
-                // >     num1sign num2sign sumsign
-                // >    ---------------------------
-                // >         0 0 0
-                // >  *OVER* 0 0 1 (adding two positives should be positive)
-                // >         0 1 0
-                // >         0 1 1
-                // >         1 0 0
-                // >         1 0 1
-                // >  *OVER* 1 1 0 (adding two negatives should be negative)
-                // >         1 1 1
-                // ```
-                // http://teaching.idallen.com/dat2343/10f/notes/040_overflow.txt
-                let of = matches!((m_msb, n_msb, sf), (false, false, true) | (true, true, false));
-
-                // https://stackoverflow.com/a/4513781/87207
-                let af = (((n & 0x0F) + (m & 0x0F)) & 0xF0) > 0;
-
-                self.write_operand(dst, result)?;
-                self.reg.set_cf(cf);
-                self.reg.set_of(of);
-                self.reg.set_sf(sf);
-                self.reg.set_af(af);
-                self.reg.set_pf(pf);
-                self.reg.set_zf(zf);
-
-                self.reg.rip += insn.length as u64;
-            }
-
-            CMP => {
-                // EXPLICIT/READ
-                let dst = &insn.operands[0];
-                // EXPLICIT/READ
-                let src = &insn.operands[1];
-                // HIDDEN/WRITE/RFLAGS
-                let flags = &insn.operands[2];
-                assert!(flags.ty == zydis::enums::OperandType::REGISTER);
-
-                let m = self.read_operand(insn, dst)?;
-                let n = self.read_operand(insn, src)?;
-
-                // this is a copy-pasta of SUB,
-                // with the exception that the destination is not written to.
-                let (result, msb_index, cf) = match dst.size {
-                    64 => {
-                        let result = m.wrapping_sub(n);
-                        (result, 63, n as u64 > m as u64)
-                    }
-                    32 => {
-                        let result = (m as u32).wrapping_sub(n as u32) as u64;
-                        (result, 31, n as u32 > m as u32)
-                    }
-                    16 => {
-                        let result = (m as u16).wrapping_sub(n as u16) as u64;
-                        (result, 15, n as u16 > m as u16)
-                    }
-                    8 => {
-                        let result = (m as u8).wrapping_sub(n as u8) as u64;
-                        (result, 7, n as u8 > m as u8)
-                    }
-                    s => unimplemented!("cmp size {:}", s),
-                };
-
-                let zf = result == 0;
-                let pf = (result as u8).count_ones() % 2 == 0;
-                let sf = (result & (1 << msb_index)) > 0;
-                let m_msb = (m & (1 << msb_index)) > 0;
-                let n_msb = (n & (1 << msb_index)) > 0;
-                let of = matches!((m_msb, n_msb, sf), (false, true, true) | (true, false, false));
-                let af = (n & 0x0F) > (m & 0x0F);
-
-                self.reg.set_cf(cf);
-                self.reg.set_of(of);
-                self.reg.set_sf(sf);
-                self.reg.set_af(af);
-                self.reg.set_pf(pf);
-                self.reg.set_zf(zf);
-
-                self.reg.rip += insn.length as u64;
-            }
-
-            JNB => {
-                // EXPLICIT/READ/IMMEDIATE target
-                let target = &insn.operands[0];
-                // HIDDEN/READ-WRITE/REGISTER/PC
-                let pc = &insn.operands[1];
-                assert!(pc.ty == zydis::enums::OperandType::REGISTER);
-                // HIDDEN/READ/REGISTER/FLAGS
-                let flags = &insn.operands[2];
-                assert!(flags.ty == zydis::enums::OperandType::REGISTER);
-
-                if !self.reg.cf() {
-                    self.reg.rip = self.read_operand(insn, target)?;
-                } else {
-                    self.reg.rip += insn.length as u64;
-                }
-            }
-
-            NEG => {
-                // EXPLICIT/READ-WRITE dst
-                let dst = &insn.operands[0];
-                // HIDDEN/WRITE/RFLAGS
-                let flags = &insn.operands[1];
-                assert!(flags.ty == zydis::enums::OperandType::REGISTER);
-
-                let m = 0u64;
-                let n = self.read_operand(insn, dst)?;
-
-                // this is a copy-pasta of SUB,
-                // with the exception that the destination is not written to.
-                let (result, msb_index, cf) = match dst.size {
-                    64 => {
-                        let result = m.wrapping_sub(n);
-                        (result, 63, n as u64 > m as u64)
-                    }
-                    32 => {
-                        let result = (m as u32).wrapping_sub(n as u32) as u64;
-                        (result, 31, n as u32 > m as u32)
-                    }
-                    16 => {
-                        let result = (m as u16).wrapping_sub(n as u16) as u64;
-                        (result, 15, n as u16 > m as u16)
-                    }
-                    8 => {
-                        let result = (m as u8).wrapping_sub(n as u8) as u64;
-                        (result, 7, n as u8 > m as u8)
-                    }
-                    s => unimplemented!("cmp size {:}", s),
-                };
-
-                self.write_operand(dst, result)?;
-                let zf = result == 0;
-                let pf = (result as u8).count_ones() % 2 == 0;
-                let sf = (result & (1 << msb_index)) > 0;
-                let m_msb = (m & (1 << msb_index)) > 0;
-                let n_msb = (n & (1 << msb_index)) > 0;
-                let of = matches!((m_msb, n_msb, sf), (false, true, true) | (true, false, false));
-                let af = (n & 0x0F) > (m & 0x0F);
-
-                self.reg.set_cf(cf);
-                self.reg.set_of(of);
-                self.reg.set_sf(sf);
-                self.reg.set_af(af);
-                self.reg.set_pf(pf);
-                self.reg.set_zf(zf);
-
-                self.reg.rip += insn.length as u64;
-            }
-
-            TEST => {
-                // EXPLICIT/READ
-                let m = &insn.operands[0];
-                let size = m.size;
-                // EXPLICIT/READ
-                let n = &insn.operands[1];
-                // HIDDEN/WRITE/RFLAGS
-                let flags = &insn.operands[2];
-                assert!(flags.ty == zydis::enums::OperandType::REGISTER);
-
-                let m = self.read_operand(insn, m)?;
-                let n = self.read_operand(insn, n)?;
-
-                let (result, msb_index) = match size {
-                    64 => {
-                        let result = m & n;
-                        (result, 63)
-                    }
-                    32 => {
-                        let result = ((m as u32) & (n as u32)) as u64;
-                        (result, 31)
-                    }
-                    16 => {
-                        let result = ((m as u16) & (n as u16)) as u64;
-                        (result, 15)
-                    }
-                    8 => {
-                        let result = ((m as u8) & (n as u8)) as u64;
-                        (result, 7)
-                    }
-                    s => unimplemented!("cmp size {:}", s),
-                };
-
-                let zf = result == 0;
-                let pf = (result as u8).count_ones() % 2 == 0;
-                let sf = (result & (1 << msb_index)) > 0;
-
-                self.reg.set_sf(sf);
-                self.reg.set_pf(pf);
-                self.reg.set_zf(zf);
-
-                self.reg.set_cf(false);
-                self.reg.set_of(false);
-                // AF is undefined
-                // self.reg.set_af(af);
-
-                self.reg.rip += insn.length as u64;
-            }
+        let arch = match insn.machine_mode {
+            zydis::MachineMode::LONG_64 => Arch::X64,
+            _ => Arch::X32,
+        };
 
-            m => {
-                unimplemented!("mnemonic: {:?}", m);
-                //self.reg.rip += insn.length as u64;
-            }
-        }
+        let insn = lift::lift_insn(arch, self.reg.rip, insn)?;
+        self.reg.rip = eval::evaluate(self, &insn)?;
 
         Ok(())
     }
@@ -1079,6 +464,9 @@ impl Emulator {
     ///     mapped.
     ///   - FetchError::AccessViolation when the instruction address is not
     ///     executable.
+    ///   - LiftError::UnsupportedInstruction for instructions without IR
+    ///     semantics.
+    ///   - EvalError::DivideByZero when dividing by zero.
     ///   - WriteError::AddressNotMapped when a memory address is not mapped.
     ///   - WriteError::AccessViolation when a memory address is not executable.
     ///   - ReadError::AddressNotMapped when a memory address is not mapped.
@@ -1098,12 +486,109 @@ impl Emulator {
     }
 }
 
+impl Machine for Emulator {
+    fn read_var(&self, var: crate::analysis::ir::Var) -> Result<u64> {
+        use crate::analysis::ir::{Flag, Var};
+
+        Ok(match var {
+            Var::Reg(reg) => self.read_register(reg),
+            Var::SegmentBase(reg) => self.get_segment_address(reg),
+            Var::Flag(Flag::CF) => self.reg.cf() as u64,
+            Var::Flag(Flag::PF) => self.reg.pf() as u64,
+            Var::Flag(Flag::AF) => self.reg.af() as u64,
+            Var::Flag(Flag::ZF) => self.reg.zf() as u64,
+            Var::Flag(Flag::SF) => self.reg.sf() as u64,
+            Var::Flag(Flag::OF) => self.reg.of() as u64,
+            Var::Flag(Flag::DF) => self.reg.df() as u64,
+            // temporaries are handled by the evaluator.
+            Var::Temp(_) => return Err(EvalError::UnsupportedVariable(var).into()),
+        })
+    }
+
+    fn write_var(&mut self, var: crate::analysis::ir::Var, value: u64) -> Result<()> {
+        use crate::analysis::ir::{Flag, Var};
+
+        match var {
+            Var::Reg(reg) => self.write_register(reg, value),
+            Var::SegmentBase(Register::FS) => self.fsbase = value,
+            Var::SegmentBase(Register::GS) => self.gsbase = value,
+            // we don't support other segments right now.
+            Var::SegmentBase(_) => {}
+            Var::Flag(Flag::CF) => self.reg.set_cf(value != 0),
+            Var::Flag(Flag::PF) => self.reg.set_pf(value != 0),
+            Var::Flag(Flag::AF) => self.reg.set_af(value != 0),
+            Var::Flag(Flag::ZF) => self.reg.set_zf(value != 0),
+            Var::Flag(Flag::SF) => self.reg.set_sf(value != 0),
+            Var::Flag(Flag::OF) => self.reg.set_of(value != 0),
+            Var::Flag(Flag::DF) => self.reg.set_df(value != 0),
+            // temporaries are handled by the evaluator.
+            Var::Temp(_) => return Err(EvalError::UnsupportedVariable(var).into()),
+        }
+
+        Ok(())
+    }
+
+    fn load(&self, addr: VA, size: u16) -> Result<u64> {
+        let ret = match size {
+            64 => self.mem.read_u64(addr),
+            32 => self.mem.read_u32(addr).map(|v| v as u64),
+            16 => self.mem.read_u16(addr).map(|v| v as u64),
+            8 => self.mem.read_u8(addr).map(|v| v as u64),
+            s => return Err(EvalError::UnsupportedSize(s).into()),
+        };
+
+        match ret {
+            Ok(v) => Ok(v),
+            Err(e @ mmu::MMUError::AddressNotMapped(..)) => Err(ReadError::AddressNotMapped {
+                va:     addr,
+                size:   size / 8,
+                source: e,
+            }
+            .into()),
+            Err(e @ mmu::MMUError::AccessViolation(..)) => Err(ReadError::AccessViolation {
+                va:     addr,
+                size:   size / 8,
+                source: e,
+            }
+            .into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn store(&mut self, addr: VA, size: u16, value: u64) -> Result<()> {
+        let ret = match size {
+            64 => self.mem.write_u64(addr, value),
+            32 => self.mem.write_u32(addr, value as u32),
+            16 => self.mem.write_u16(addr, value as u16),
+            8 => self.mem.write_u8(addr, value as u8),
+            s => return Err(EvalError::UnsupportedSize(s).into()),
+        };
+
+        match ret {
+            Ok(()) => Ok(()),
+            Err(e @ mmu::MMUError::AddressNotMapped(..)) => Err(WriteError::AddressNotMapped {
+                va:     addr,
+                size:   size / 8,
+                source: e,
+            }
+            .into()),
+            Err(e @ mmu::MMUError::AccessViolation(..)) => Err(WriteError::AccessViolation {
+                va:     addr,
+                size:   size / 8,
+                source: e,
+            }
+            .into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{arch::Arch, emu::*, test::*};
 
     use anyhow::Result;
-    use dynasmrt::{dynasm, DynasmApi};
+    use dynasmrt::{dynasm, DynasmApi, DynasmLabelApi};
 
     const BASE_ADDRESS: u64 = 0x1000;
 
@@ -1686,6 +1171,139 @@ mod tests {
         emu_check(&b"\x48\xC7\xC0\x01\x00\x00\x00\x48\x83\xF8\x00\x73\x01\x90\x48\xC7\xC0\x01\x00\x00\x00\x48\x83\xF8\x01\x73\x01\x90\x48\xC7\xC0\x01\x00\x00\x00\x48\x83\xF8\x02\x73\x01\x90"[..]);
     }
 
+    #[test]
+    fn insn_logic() -> Result<()> {
+        for &i in INTERESTING_NUMBERS.iter() {
+            for &j in INTERESTING_NUMBERS.iter() {
+                emu_check_with_asm(|ops| {
+                    dynasm!(ops
+                        ; .arch x64
+                        ; mov rax, QWORD i
+                        ; xor al, j as i8
+                        ; mov rax, QWORD i
+                        ; and ax, j as i16
+                        ; mov rax, QWORD i
+                        ; or eax, j as i32
+
+                        ; mov rax, QWORD i
+                        ; mov rbx, QWORD j
+                        ; xor rax, rbx
+                        ; mov rax, QWORD i
+                        ; and rax, rbx
+                        ; mov rax, QWORD i
+                        ; or rax, rbx
+                    );
+                });
+            }
+        }
+
+        emu_check_with_asm(|ops| {
+            dynasm!(ops
+                ; .arch x64
+                ; mov rax, 0x41
+                ; mov [rbp - 4], rax
+                ; xor BYTE [rbp - 4], 0x20
+                ; mov rbx, [rbp - 4]
+            );
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn insn_inc_dec() -> Result<()> {
+        for &i in INTERESTING_NUMBERS.iter() {
+            emu_check_with_asm(|ops| {
+                dynasm!(ops
+                    ; .arch x64
+                    ; mov rax, QWORD i
+                    ; inc al
+                    ; mov rax, QWORD i
+                    ; inc ax
+                    ; mov rax, QWORD i
+                    ; inc eax
+                    ; mov rax, QWORD i
+                    ; inc rax
+
+                    ; mov rax, QWORD i
+                    ; dec al
+                    ; mov rax, QWORD i
+                    ; dec ax
+                    ; mov rax, QWORD i
+                    ; dec eax
+                    ; mov rax, QWORD i
+                    ; dec rax
+                );
+            });
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn insn_jmp() {
+        emu_check_with_asm(|ops| {
+            dynasm!(ops
+                ; .arch x64
+                ; jmp >next
+                ; nop
+                ; next:
+                ; mov rax, 0x1
+            );
+        });
+    }
+
+    #[test]
+    fn insn_jcc() -> Result<()> {
+        for &i in INTERESTING_NUMBERS.iter() {
+            for &j in [-1i64, 0, 1].iter() {
+                emu_check_with_asm(|ops| {
+                    dynasm!(ops
+                        ; .arch x64
+                        ; mov rax, QWORD i
+                        ; cmp rax, j as i32
+                        ; jz >a
+                        ; nop
+                        ; a:
+                        ; jnz >b
+                        ; nop
+                        ; b:
+                        ; jb >c
+                        ; nop
+                        ; c:
+                        ; jbe >d
+                        ; nop
+                        ; d:
+                        ; ja >e
+                        ; nop
+                        ; e:
+                        ; jl >f
+                        ; nop
+                        ; f:
+                        ; jle >g
+                        ; nop
+                        ; g:
+                        ; jge >h
+                        ; nop
+                        ; h:
+                        ; jg >k
+                        ; nop
+                        ; k:
+                        ; js >l
+                        ; nop
+                        ; l:
+                        ; jns >m
+                        ; nop
+                        ; m:
+                        ; mov rax, 0x1
+                    );
+                });
+            }
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn insn_neg() -> Result<()> {
         emu_check_with_asm(|ops| {
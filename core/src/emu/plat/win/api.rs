@@ -23,7 +23,71 @@ pub struct FunctionDescriptor {
 
 type Hook = Box<dyn Fn(&mut dyn WindowsEmulator, &FunctionDescriptor) -> Result<()> + Send + Sync>;
 
+impl FunctionDescriptor {
+    fn new(calling_convention: CallingConvention, return_type: &str, arguments: &[(&str, &str)]) -> FunctionDescriptor {
+        FunctionDescriptor {
+            calling_convention,
+            return_type: String::from(return_type),
+            arguments: arguments
+                .iter()
+                .map(|&(ty, name)| ArgumentDescriptor {
+                    ty:   String::from(ty),
+                    name: String::from(name),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// structures with ANSI and wide variants, like `WIN32_FIND_DATAA` and
+/// `WIN32_FIND_DATAW`.
+const AW_TYPES: &[&str] = &["LPOSVERSIONINFO", "LPSTARTUPINFO", "LPWIN32_FIND_DATA"];
+
+/// register the ANSI and wide variants of the given API, like `CreateFileA`
+/// and `CreateFileW`. argument and return types like `LPCTSTR` are expanded to
+/// `LPCSTR` and `LPCWSTR`, respectively, and types like `LPWIN32_FIND_DATA` to
+/// `LPWIN32_FIND_DATAA` and `LPWIN32_FIND_DATAW`.
+fn insert_aw(m: &mut BTreeMap<String, FunctionDescriptor>, name: &str, return_type: &str, arguments: &[(&str, &str)]) {
+    for &(suffix, string) in [("A", "STR"), ("W", "WSTR")].iter() {
+        let expand = |ty: &str| {
+            if AW_TYPES.contains(&ty) {
+                format!("{}{}", ty, suffix)
+            } else {
+                ty.replace("TSTR", string)
+            }
+        };
+
+        let arguments: Vec<(String, &str)> = arguments.iter().map(|&(ty, name)| (expand(ty), name)).collect();
+        let arguments: Vec<(&str, &str)> = arguments.iter().map(|(ty, name)| (ty.as_str(), *name)).collect();
+
+        m.insert(
+            format!("{}{}", name, suffix),
+            FunctionDescriptor::new(CallingConvention::Stdcall, &expand(return_type), &arguments),
+        );
+    }
+}
+
+fn insert(m: &mut BTreeMap<String, FunctionDescriptor>, name: &str, return_type: &str, arguments: &[(&str, &str)]) {
+    m.insert(
+        String::from(name),
+        FunctionDescriptor::new(CallingConvention::Stdcall, return_type, arguments),
+    );
+}
+
+fn insert_cdecl(
+    m: &mut BTreeMap<String, FunctionDescriptor>,
+    name: &str,
+    return_type: &str,
+    arguments: &[(&str, &str)],
+) {
+    m.insert(
+        String::from(name),
+        FunctionDescriptor::new(CallingConvention::Cdecl, return_type, arguments),
+    );
+}
+
 lazy_static! {
+    /// descriptors of well-known APIs, keyed by `dll!name`, with the DLL name in lowercase.
     pub static ref API: BTreeMap<String, FunctionDescriptor> = {
         let mut m = BTreeMap::new();
 
@@ -31,19 +95,501 @@ lazy_static! {
         // alternative source: https://github.com/vivisect/vivisect/blob/master/vivisect/impapi/windows/i386.py
         // alternative source: https://github.com/fireeye/speakeasy/blob/88502c6eb99dd21ca6ebdcba3edff42c9c2c1bf8/speakeasy/winenv/api/usermode/kernel32.py#L1192
 
-        m.insert(
-            String::from("kernel32.dll!GetVersionExA"),
-            FunctionDescriptor {
-                calling_convention: CallingConvention::Stdcall,
-                return_type: String::from("bool"),
-                arguments: vec![
-                    ArgumentDescriptor {
-                        ty: String::from("LPOSVERSIONINFOA"),
-                        name: String::from("lpVersionInformation"),
-                    }
-                ]
-            }
-        );
+        //
+        // kernel32.dll
+        //
+        insert_aw(&mut m, "kernel32.dll!GetVersionEx", "BOOL", &[("LPOSVERSIONINFO", "lpVersionInformation")]);
+
+        // files
+        insert_aw(&mut m, "kernel32.dll!CreateFile", "HANDLE", &[
+            ("LPCTSTR", "lpFileName"),
+            ("DWORD", "dwDesiredAccess"),
+            ("DWORD", "dwShareMode"),
+            ("LPSECURITY_ATTRIBUTES", "lpSecurityAttributes"),
+            ("DWORD", "dwCreationDisposition"),
+            ("DWORD", "dwFlagsAndAttributes"),
+            ("HANDLE", "hTemplateFile"),
+        ]);
+        insert(&mut m, "kernel32.dll!ReadFile", "BOOL", &[
+            ("HANDLE", "hFile"),
+            ("LPVOID", "lpBuffer"),
+            ("DWORD", "nNumberOfBytesToRead"),
+            ("LPDWORD", "lpNumberOfBytesRead"),
+            ("LPOVERLAPPED", "lpOverlapped"),
+        ]);
+        insert(&mut m, "kernel32.dll!WriteFile", "BOOL", &[
+            ("HANDLE", "hFile"),
+            ("LPCVOID", "lpBuffer"),
+            ("DWORD", "nNumberOfBytesToWrite"),
+            ("LPDWORD", "lpNumberOfBytesWritten"),
+            ("LPOVERLAPPED", "lpOverlapped"),
+        ]);
+        insert(&mut m, "kernel32.dll!CloseHandle", "BOOL", &[("HANDLE", "hObject")]);
+        insert_aw(&mut m, "kernel32.dll!DeleteFile", "BOOL", &[("LPCTSTR", "lpFileName")]);
+        insert_aw(&mut m, "kernel32.dll!CopyFile", "BOOL", &[
+            ("LPCTSTR", "lpExistingFileName"),
+            ("LPCTSTR", "lpNewFileName"),
+            ("BOOL", "bFailIfExists"),
+        ]);
+        insert_aw(&mut m, "kernel32.dll!MoveFile", "BOOL", &[
+            ("LPCTSTR", "lpExistingFileName"),
+            ("LPCTSTR", "lpNewFileName"),
+        ]);
+        insert_aw(&mut m, "kernel32.dll!CreateDirectory", "BOOL", &[
+            ("LPCTSTR", "lpPathName"),
+            ("LPSECURITY_ATTRIBUTES", "lpSecurityAttributes"),
+        ]);
+        insert_aw(&mut m, "kernel32.dll!GetFileAttributes", "DWORD", &[("LPCTSTR", "lpFileName")]);
+        insert_aw(&mut m, "kernel32.dll!SetFileAttributes", "BOOL", &[
+            ("LPCTSTR", "lpFileName"),
+            ("DWORD", "dwFileAttributes"),
+        ]);
+        insert_aw(&mut m, "kernel32.dll!FindFirstFile", "HANDLE", &[
+            ("LPCTSTR", "lpFileName"),
+            ("LPWIN32_FIND_DATA", "lpFindFileData"),
+        ]);
+        insert_aw(&mut m, "kernel32.dll!FindNextFile", "BOOL", &[
+            ("HANDLE", "hFindFile"),
+            ("LPWIN32_FIND_DATA", "lpFindFileData"),
+        ]);
+        insert(&mut m, "kernel32.dll!FindClose", "BOOL", &[("HANDLE", "hFindFile")]);
+        insert_aw(&mut m, "kernel32.dll!GetTempPath", "DWORD", &[
+            ("DWORD", "nBufferLength"),
+            ("LPTSTR", "lpBuffer"),
+        ]);
+        insert(&mut m, "kernel32.dll!DeviceIoControl", "BOOL", &[
+            ("HANDLE", "hDevice"),
+            ("DWORD", "dwIoControlCode"),
+            ("LPVOID", "lpInBuffer"),
+            ("DWORD", "nInBufferSize"),
+            ("LPVOID", "lpOutBuffer"),
+            ("DWORD", "nOutBufferSize"),
+            ("LPDWORD", "lpBytesReturned"),
+            ("LPOVERLAPPED", "lpOverlapped"),
+        ]);
+
+        // modules
+        insert_aw(&mut m, "kernel32.dll!LoadLibrary", "HMODULE", &[("LPCTSTR", "lpLibFileName")]);
+        insert_aw(&mut m, "kernel32.dll!LoadLibraryEx", "HMODULE", &[
+            ("LPCTSTR", "lpLibFileName"),
+            ("HANDLE", "hFile"),
+            ("DWORD", "dwFlags"),
+        ]);
+        insert_aw(&mut m, "kernel32.dll!GetModuleHandle", "HMODULE", &[("LPCTSTR", "lpModuleName")]);
+        insert_aw(&mut m, "kernel32.dll!GetModuleFileName", "DWORD", &[
+            ("HMODULE", "hModule"),
+            ("LPTSTR", "lpFilename"),
+            ("DWORD", "nSize"),
+        ]);
+        insert(&mut m, "kernel32.dll!GetProcAddress", "FARPROC", &[
+            ("HMODULE", "hModule"),
+            ("LPCSTR", "lpProcName"),
+        ]);
+        insert(&mut m, "kernel32.dll!FreeLibrary", "BOOL", &[("HMODULE", "hLibModule")]);
+
+        // memory
+        insert(&mut m, "kernel32.dll!VirtualAlloc", "LPVOID", &[
+            ("LPVOID", "lpAddress"),
+            ("SIZE_T", "dwSize"),
+            ("DWORD", "flAllocationType"),
+            ("DWORD", "flProtect"),
+        ]);
+        insert(&mut m, "kernel32.dll!VirtualAllocEx", "LPVOID", &[
+            ("HANDLE", "hProcess"),
+            ("LPVOID", "lpAddress"),
+            ("SIZE_T", "dwSize"),
+            ("DWORD", "flAllocationType"),
+            ("DWORD", "flProtect"),
+        ]);
+        insert(&mut m, "kernel32.dll!VirtualProtect", "BOOL", &[
+            ("LPVOID", "lpAddress"),
+            ("SIZE_T", "dwSize"),
+            ("DWORD", "flNewProtect"),
+            ("PDWORD", "lpflOldProtect"),
+        ]);
+        insert(&mut m, "kernel32.dll!VirtualFree", "BOOL", &[
+            ("LPVOID", "lpAddress"),
+            ("SIZE_T", "dwSize"),
+            ("DWORD", "dwFreeType"),
+        ]);
+        insert(&mut m, "kernel32.dll!ReadProcessMemory", "BOOL", &[
+            ("HANDLE", "hProcess"),
+            ("LPCVOID", "lpBaseAddress"),
+            ("LPVOID", "lpBuffer"),
+            ("SIZE_T", "nSize"),
+            ("SIZE_T*", "lpNumberOfBytesRead"),
+        ]);
+        insert(&mut m, "kernel32.dll!WriteProcessMemory", "BOOL", &[
+            ("HANDLE", "hProcess"),
+            ("LPVOID", "lpBaseAddress"),
+            ("LPCVOID", "lpBuffer"),
+            ("SIZE_T", "nSize"),
+            ("SIZE_T*", "lpNumberOfBytesWritten"),
+        ]);
+        insert(&mut m, "kernel32.dll!GetProcessHeap", "HANDLE", &[]);
+        insert(&mut m, "kernel32.dll!HeapAlloc", "LPVOID", &[
+            ("HANDLE", "hHeap"),
+            ("DWORD", "dwFlags"),
+            ("SIZE_T", "dwBytes"),
+        ]);
+        insert(&mut m, "kernel32.dll!HeapFree", "BOOL", &[
+            ("HANDLE", "hHeap"),
+            ("DWORD", "dwFlags"),
+            ("LPVOID", "lpMem"),
+        ]);
+        insert(&mut m, "kernel32.dll!LocalAlloc", "HLOCAL", &[("UINT", "uFlags"), ("SIZE_T", "uBytes")]);
+        insert(&mut m, "kernel32.dll!LocalFree", "HLOCAL", &[("HLOCAL", "hMem")]);
+
+        // processes and threads
+        insert_aw(&mut m, "kernel32.dll!CreateProcess", "BOOL", &[
+            ("LPCTSTR", "lpApplicationName"),
+            ("LPTSTR", "lpCommandLine"),
+            ("LPSECURITY_ATTRIBUTES", "lpProcessAttributes"),
+            ("LPSECURITY_ATTRIBUTES", "lpThreadAttributes"),
+            ("BOOL", "bInheritHandles"),
+            ("DWORD", "dwCreationFlags"),
+            ("LPVOID", "lpEnvironment"),
+            ("LPCTSTR", "lpCurrentDirectory"),
+            ("LPSTARTUPINFO", "lpStartupInfo"),
+            ("LPPROCESS_INFORMATION", "lpProcessInformation"),
+        ]);
+        insert(&mut m, "kernel32.dll!WinExec", "UINT", &[("LPCSTR", "lpCmdLine"), ("UINT", "uCmdShow")]);
+        insert(&mut m, "kernel32.dll!OpenProcess", "HANDLE", &[
+            ("DWORD", "dwDesiredAccess"),
+            ("BOOL", "bInheritHandle"),
+            ("DWORD", "dwProcessId"),
+        ]);
+        insert(&mut m, "kernel32.dll!TerminateProcess", "BOOL", &[("HANDLE", "hProcess"), ("UINT", "uExitCode")]);
+        insert(&mut m, "kernel32.dll!ExitProcess", "void", &[("UINT", "uExitCode")]);
+        insert(&mut m, "kernel32.dll!GetCurrentProcess", "HANDLE", &[]);
+        insert(&mut m, "kernel32.dll!GetCurrentProcessId", "DWORD", &[]);
+        insert(&mut m, "kernel32.dll!CreateThread", "HANDLE", &[
+            ("LPSECURITY_ATTRIBUTES", "lpThreadAttributes"),
+            ("SIZE_T", "dwStackSize"),
+            ("LPTHREAD_START_ROUTINE", "lpStartAddress"),
+            ("LPVOID", "lpParameter"),
+            ("DWORD", "dwCreationFlags"),
+            ("LPDWORD", "lpThreadId"),
+        ]);
+        insert(&mut m, "kernel32.dll!CreateRemoteThread", "HANDLE", &[
+            ("HANDLE", "hProcess"),
+            ("LPSECURITY_ATTRIBUTES", "lpThreadAttributes"),
+            ("SIZE_T", "dwStackSize"),
+            ("LPTHREAD_START_ROUTINE", "lpStartAddress"),
+            ("LPVOID", "lpParameter"),
+            ("DWORD", "dwCreationFlags"),
+            ("LPDWORD", "lpThreadId"),
+        ]);
+        insert(&mut m, "kernel32.dll!Sleep", "void", &[("DWORD", "dwMilliseconds")]);
+        insert(&mut m, "kernel32.dll!WaitForSingleObject", "DWORD", &[
+            ("HANDLE", "hHandle"),
+            ("DWORD", "dwMilliseconds"),
+        ]);
+
+        // synchronization
+        insert_aw(&mut m, "kernel32.dll!CreateMutex", "HANDLE", &[
+            ("LPSECURITY_ATTRIBUTES", "lpMutexAttributes"),
+            ("BOOL", "bInitialOwner"),
+            ("LPCTSTR", "lpName"),
+        ]);
+        insert_aw(&mut m, "kernel32.dll!OpenMutex", "HANDLE", &[
+            ("DWORD", "dwDesiredAccess"),
+            ("BOOL", "bInheritHandle"),
+            ("LPCTSTR", "lpName"),
+        ]);
+        insert_aw(&mut m, "kernel32.dll!CreateEvent", "HANDLE", &[
+            ("LPSECURITY_ATTRIBUTES", "lpEventAttributes"),
+            ("BOOL", "bManualReset"),
+            ("BOOL", "bInitialState"),
+            ("LPCTSTR", "lpName"),
+        ]);
+
+        // miscellaneous
+        insert(&mut m, "kernel32.dll!GetLastError", "DWORD", &[]);
+        insert(&mut m, "kernel32.dll!SetLastError", "void", &[("DWORD", "dwErrCode")]);
+        insert(&mut m, "kernel32.dll!GetTickCount", "DWORD", &[]);
+        insert_aw(&mut m, "kernel32.dll!OutputDebugString", "void", &[("LPCTSTR", "lpOutputString")]);
+        insert_aw(&mut m, "kernel32.dll!GetEnvironmentVariable", "DWORD", &[
+            ("LPCTSTR", "lpName"),
+            ("LPTSTR", "lpBuffer"),
+            ("DWORD", "nSize"),
+        ]);
+        insert_aw(&mut m, "kernel32.dll!lstrlen", "int", &[("LPCTSTR", "lpString")]);
+        insert_aw(&mut m, "kernel32.dll!lstrcpy", "LPTSTR", &[("LPTSTR", "lpString1"), ("LPCTSTR", "lpString2")]);
+        insert_aw(&mut m, "kernel32.dll!lstrcmpi", "int", &[("LPCTSTR", "lpString1"), ("LPCTSTR", "lpString2")]);
+        insert(&mut m, "kernel32.dll!MultiByteToWideChar", "int", &[
+            ("UINT", "CodePage"),
+            ("DWORD", "dwFlags"),
+            ("LPCSTR", "lpMultiByteStr"),
+            ("int", "cbMultiByte"),
+            ("LPWSTR", "lpWideCharStr"),
+            ("int", "cchWideChar"),
+        ]);
+        insert(&mut m, "kernel32.dll!WideCharToMultiByte", "int", &[
+            ("UINT", "CodePage"),
+            ("DWORD", "dwFlags"),
+            ("LPCWSTR", "lpWideCharStr"),
+            ("int", "cchWideChar"),
+            ("LPSTR", "lpMultiByteStr"),
+            ("int", "cbMultiByte"),
+            ("LPCSTR", "lpDefaultChar"),
+            ("LPBOOL", "lpUsedDefaultChar"),
+        ]);
+
+        //
+        // advapi32.dll
+        //
+        insert_aw(&mut m, "advapi32.dll!RegOpenKeyEx", "LSTATUS", &[
+            ("HKEY", "hKey"),
+            ("LPCTSTR", "lpSubKey"),
+            ("DWORD", "ulOptions"),
+            ("REGSAM", "samDesired"),
+            ("PHKEY", "phkResult"),
+        ]);
+        insert_aw(&mut m, "advapi32.dll!RegCreateKeyEx", "LSTATUS", &[
+            ("HKEY", "hKey"),
+            ("LPCTSTR", "lpSubKey"),
+            ("DWORD", "Reserved"),
+            ("LPTSTR", "lpClass"),
+            ("DWORD", "dwOptions"),
+            ("REGSAM", "samDesired"),
+            ("LPSECURITY_ATTRIBUTES", "lpSecurityAttributes"),
+            ("PHKEY", "phkResult"),
+            ("LPDWORD", "lpdwDisposition"),
+        ]);
+        insert_aw(&mut m, "advapi32.dll!RegQueryValueEx", "LSTATUS", &[
+            ("HKEY", "hKey"),
+            ("LPCTSTR", "lpValueName"),
+            ("LPDWORD", "lpReserved"),
+            ("LPDWORD", "lpType"),
+            ("LPBYTE", "lpData"),
+            ("LPDWORD", "lpcbData"),
+        ]);
+        insert_aw(&mut m, "advapi32.dll!RegSetValueEx", "LSTATUS", &[
+            ("HKEY", "hKey"),
+            ("LPCTSTR", "lpValueName"),
+            ("DWORD", "Reserved"),
+            ("DWORD", "dwType"),
+            ("const BYTE*", "lpData"),
+            ("DWORD", "cbData"),
+        ]);
+        insert_aw(&mut m, "advapi32.dll!RegDeleteValue", "LSTATUS", &[
+            ("HKEY", "hKey"),
+            ("LPCTSTR", "lpValueName"),
+        ]);
+        insert(&mut m, "advapi32.dll!RegCloseKey", "LSTATUS", &[("HKEY", "hKey")]);
+        insert_aw(&mut m, "advapi32.dll!OpenSCManager", "SC_HANDLE", &[
+            ("LPCTSTR", "lpMachineName"),
+            ("LPCTSTR", "lpDatabaseName"),
+            ("DWORD", "dwDesiredAccess"),
+        ]);
+        insert_aw(&mut m, "advapi32.dll!OpenService", "SC_HANDLE", &[
+            ("SC_HANDLE", "hSCManager"),
+            ("LPCTSTR", "lpServiceName"),
+            ("DWORD", "dwDesiredAccess"),
+        ]);
+        insert_aw(&mut m, "advapi32.dll!CreateService", "SC_HANDLE", &[
+            ("SC_HANDLE", "hSCManager"),
+            ("LPCTSTR", "lpServiceName"),
+            ("LPCTSTR", "lpDisplayName"),
+            ("DWORD", "dwDesiredAccess"),
+            ("DWORD", "dwServiceType"),
+            ("DWORD", "dwStartType"),
+            ("DWORD", "dwErrorControl"),
+            ("LPCTSTR", "lpBinaryPathName"),
+            ("LPCTSTR", "lpLoadOrderGroup"),
+            ("LPDWORD", "lpdwTagId"),
+            ("LPCTSTR", "lpDependencies"),
+            ("LPCTSTR", "lpServiceStartName"),
+            ("LPCTSTR", "lpPassword"),
+        ]);
+        insert_aw(&mut m, "advapi32.dll!StartService", "BOOL", &[
+            ("SC_HANDLE", "hService"),
+            ("DWORD", "dwNumServiceArgs"),
+            ("LPCTSTR*", "lpServiceArgVectors"),
+        ]);
+        insert(&mut m, "advapi32.dll!CloseServiceHandle", "BOOL", &[("SC_HANDLE", "hSCObject")]);
+        insert(&mut m, "advapi32.dll!OpenProcessToken", "BOOL", &[
+            ("HANDLE", "ProcessHandle"),
+            ("DWORD", "DesiredAccess"),
+            ("PHANDLE", "TokenHandle"),
+        ]);
+        insert_aw(&mut m, "advapi32.dll!LookupPrivilegeValue", "BOOL", &[
+            ("LPCTSTR", "lpSystemName"),
+            ("LPCTSTR", "lpName"),
+            ("PLUID", "lpLuid"),
+        ]);
+        insert(&mut m, "advapi32.dll!AdjustTokenPrivileges", "BOOL", &[
+            ("HANDLE", "TokenHandle"),
+            ("BOOL", "DisableAllPrivileges"),
+            ("PTOKEN_PRIVILEGES", "NewState"),
+            ("DWORD", "BufferLength"),
+            ("PTOKEN_PRIVILEGES", "PreviousState"),
+            ("PDWORD", "ReturnLength"),
+        ]);
+        insert_aw(&mut m, "advapi32.dll!CryptAcquireContext", "BOOL", &[
+            ("HCRYPTPROV*", "phProv"),
+            ("LPCTSTR", "szContainer"),
+            ("LPCTSTR", "szProvider"),
+            ("DWORD", "dwProvType"),
+            ("DWORD", "dwFlags"),
+        ]);
+
+        //
+        // user32.dll
+        //
+        insert_aw(&mut m, "user32.dll!MessageBox", "int", &[
+            ("HWND", "hWnd"),
+            ("LPCTSTR", "lpText"),
+            ("LPCTSTR", "lpCaption"),
+            ("UINT", "uType"),
+        ]);
+        insert_aw(&mut m, "user32.dll!FindWindow", "HWND", &[
+            ("LPCTSTR", "lpClassName"),
+            ("LPCTSTR", "lpWindowName"),
+        ]);
+        insert_aw(&mut m, "user32.dll!SetWindowsHookEx", "HHOOK", &[
+            ("int", "idHook"),
+            ("HOOKPROC", "lpfn"),
+            ("HINSTANCE", "hmod"),
+            ("DWORD", "dwThreadId"),
+        ]);
+        insert(&mut m, "user32.dll!GetAsyncKeyState", "SHORT", &[("int", "vKey")]);
+        // varargs, so only the fixed arguments are described.
+        insert_cdecl(&mut m, "user32.dll!wsprintfA", "int", &[("LPSTR", "lpOut"), ("LPCSTR", "lpFmt")]);
+        insert_cdecl(&mut m, "user32.dll!wsprintfW", "int", &[("LPWSTR", "lpOut"), ("LPCWSTR", "lpFmt")]);
+
+        //
+        // shell32.dll
+        //
+        insert_aw(&mut m, "shell32.dll!ShellExecute", "HINSTANCE", &[
+            ("HWND", "hwnd"),
+            ("LPCTSTR", "lpOperation"),
+            ("LPCTSTR", "lpFile"),
+            ("LPCTSTR", "lpParameters"),
+            ("LPCTSTR", "lpDirectory"),
+            ("INT", "nShowCmd"),
+        ]);
+
+        //
+        // ws2_32.dll
+        //
+        insert(&mut m, "ws2_32.dll!WSAStartup", "int", &[
+            ("WORD", "wVersionRequested"),
+            ("LPWSADATA", "lpWSAData"),
+        ]);
+        insert(&mut m, "ws2_32.dll!socket", "SOCKET", &[("int", "af"), ("int", "type"), ("int", "protocol")]);
+        insert(&mut m, "ws2_32.dll!connect", "int", &[
+            ("SOCKET", "s"),
+            ("const sockaddr*", "name"),
+            ("int", "namelen"),
+        ]);
+        insert(&mut m, "ws2_32.dll!send", "int", &[
+            ("SOCKET", "s"),
+            ("const char*", "buf"),
+            ("int", "len"),
+            ("int", "flags"),
+        ]);
+        insert(&mut m, "ws2_32.dll!recv", "int", &[
+            ("SOCKET", "s"),
+            ("char*", "buf"),
+            ("int", "len"),
+            ("int", "flags"),
+        ]);
+        insert(&mut m, "ws2_32.dll!closesocket", "int", &[("SOCKET", "s")]);
+        insert(&mut m, "ws2_32.dll!gethostbyname", "hostent*", &[("LPCSTR", "name")]);
+        insert(&mut m, "ws2_32.dll!inet_addr", "unsigned long", &[("LPCSTR", "cp")]);
+        insert(&mut m, "ws2_32.dll!htons", "u_short", &[("u_short", "hostshort")]);
+
+        //
+        // wininet.dll
+        //
+        insert_aw(&mut m, "wininet.dll!InternetOpen", "HINTERNET", &[
+            ("LPCTSTR", "lpszAgent"),
+            ("DWORD", "dwAccessType"),
+            ("LPCTSTR", "lpszProxy"),
+            ("LPCTSTR", "lpszProxyBypass"),
+            ("DWORD", "dwFlags"),
+        ]);
+        insert_aw(&mut m, "wininet.dll!InternetConnect", "HINTERNET", &[
+            ("HINTERNET", "hInternet"),
+            ("LPCTSTR", "lpszServerName"),
+            ("INTERNET_PORT", "nServerPort"),
+            ("LPCTSTR", "lpszUserName"),
+            ("LPCTSTR", "lpszPassword"),
+            ("DWORD", "dwService"),
+            ("DWORD", "dwFlags"),
+            ("DWORD_PTR", "dwContext"),
+        ]);
+        insert_aw(&mut m, "wininet.dll!InternetOpenUrl", "HINTERNET", &[
+            ("HINTERNET", "hInternet"),
+            ("LPCTSTR", "lpszUrl"),
+            ("LPCTSTR", "lpszHeaders"),
+            ("DWORD", "dwHeadersLength"),
+            ("DWORD", "dwFlags"),
+            ("DWORD_PTR", "dwContext"),
+        ]);
+        insert_aw(&mut m, "wininet.dll!HttpOpenRequest", "HINTERNET", &[
+            ("HINTERNET", "hConnect"),
+            ("LPCTSTR", "lpszVerb"),
+            ("LPCTSTR", "lpszObjectName"),
+            ("LPCTSTR", "lpszVersion"),
+            ("LPCTSTR", "lpszReferrer"),
+            ("LPCTSTR*", "lplpszAcceptTypes"),
+            ("DWORD", "dwFlags"),
+            ("DWORD_PTR", "dwContext"),
+        ]);
+        insert(&mut m, "wininet.dll!InternetReadFile", "BOOL", &[
+            ("HINTERNET", "hFile"),
+            ("LPVOID", "lpBuffer"),
+            ("DWORD", "dwNumberOfBytesToRead"),
+            ("LPDWORD", "lpdwNumberOfBytesRead"),
+        ]);
+        insert(&mut m, "wininet.dll!InternetCloseHandle", "BOOL", &[("HINTERNET", "hInternet")]);
+
+        //
+        // urlmon.dll
+        //
+        insert_aw(&mut m, "urlmon.dll!URLDownloadToFile", "HRESULT", &[
+            ("LPUNKNOWN", "pCaller"),
+            ("LPCTSTR", "szURL"),
+            ("LPCTSTR", "szFileName"),
+            ("DWORD", "dwReserved"),
+            ("LPBINDSTATUSCALLBACK", "lpfnCB"),
+        ]);
+
+        //
+        // ntdll.dll
+        //
+        insert(&mut m, "ntdll.dll!RtlInitUnicodeString", "void", &[
+            ("PUNICODE_STRING", "DestinationString"),
+            ("PCWSTR", "SourceString"),
+        ]);
+
+        //
+        // msvcrt.dll
+        //
+        // varargs, so only the fixed arguments are described.
+        insert_cdecl(&mut m, "msvcrt.dll!printf", "int", &[("LPCSTR", "format")]);
+        insert_cdecl(&mut m, "msvcrt.dll!wprintf", "int", &[("LPCWSTR", "format")]);
+        insert_cdecl(&mut m, "msvcrt.dll!sprintf", "int", &[("LPSTR", "buffer"), ("LPCSTR", "format")]);
+        insert_cdecl(&mut m, "msvcrt.dll!swprintf", "int", &[("LPWSTR", "buffer"), ("LPCWSTR", "format")]);
+        insert_cdecl(&mut m, "msvcrt.dll!strlen", "size_t", &[("LPCSTR", "str")]);
+        insert_cdecl(&mut m, "msvcrt.dll!wcslen", "size_t", &[("LPCWSTR", "str")]);
+        insert_cdecl(&mut m, "msvcrt.dll!strcpy", "char*", &[("LPSTR", "strDestination"), ("LPCSTR", "strSource")]);
+        insert_cdecl(&mut m, "msvcrt.dll!strcmp", "int", &[("LPCSTR", "string1"), ("LPCSTR", "string2")]);
+        insert_cdecl(&mut m, "msvcrt.dll!_wcsicmp", "int", &[("LPCWSTR", "string1"), ("LPCWSTR", "string2")]);
+        insert_cdecl(&mut m, "msvcrt.dll!memcpy", "void*", &[
+            ("void*", "dest"),
+            ("const void*", "src"),
+            ("size_t", "count"),
+        ]);
+        insert_cdecl(&mut m, "msvcrt.dll!memset", "void*", &[("void*", "dest"), ("int", "c"), ("size_t", "count")]);
+        insert_cdecl(&mut m, "msvcrt.dll!malloc", "void*", &[("size_t", "size")]);
+        insert_cdecl(&mut m, "msvcrt.dll!free", "void", &[("void*", "memblock")]);
+        insert_cdecl(&mut m, "msvcrt.dll!fopen", "FILE*", &[("LPCSTR", "filename"), ("LPCSTR", "mode")]);
+        insert_cdecl(&mut m, "msvcrt.dll!_wfopen", "FILE*", &[("LPCWSTR", "filename"), ("LPCWSTR", "mode")]);
+        insert_cdecl(&mut m, "msvcrt.dll!system", "int", &[("LPCSTR", "command")]);
 
         m
     };
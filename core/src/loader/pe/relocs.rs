@@ -0,0 +1,132 @@
+// we use identifier names from the C headers for PE structures,
+// which don't match the Rust style guide.
+// example: `IMAGE_REL_BASED_HIGHLOW`
+// don't show compiler warnings when encountering these names.
+#![allow(non_upper_case_globals)]
+
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    aspace::AddressSpace,
+    loader::pe::{PEError, IMAGE_DIRECTORY_ENTRY_BASERELOC, PE},
+    RVA, VA,
+};
+
+// ref: https://docs.microsoft.com/en-us/windows/win32/debug/pe-format#base-relocation-types
+pub const IMAGE_REL_BASED_ABSOLUTE: u16 = 0;
+pub const IMAGE_REL_BASED_HIGH: u16 = 1;
+pub const IMAGE_REL_BASED_LOW: u16 = 2;
+pub const IMAGE_REL_BASED_HIGHLOW: u16 = 3;
+pub const IMAGE_REL_BASED_HIGHADJ: u16 = 4;
+pub const IMAGE_REL_BASED_DIR64: u16 = 10;
+
+const sizeof_IMAGE_BASE_RELOCATION: RVA = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    /// the address of the relocated data.
+    pub address: VA,
+    /// like `IMAGE_REL_BASED_DIR64`.
+    pub kind:    u16,
+}
+
+impl Relocation {
+    /// the number of bytes patched by the relocation.
+    pub fn size(&self) -> usize {
+        match self.kind {
+            IMAGE_REL_BASED_HIGH | IMAGE_REL_BASED_LOW | IMAGE_REL_BASED_HIGHADJ => 2,
+            IMAGE_REL_BASED_HIGHLOW => 4,
+            IMAGE_REL_BASED_DIR64 => 8,
+            _ => 0,
+        }
+    }
+}
+
+// ```
+//  0x0         0x4          0x8
+//  +-----------+------------+----------+----------+-----+
+//  | page RVA  | block size | u16 entry| u16 entry| ... |    entry: type << 12 | page offset
+//  +-----------+------------+----------+----------+-----+
+//  | page RVA  | block size | ...
+//  +-----------+------------+
+// ```
+/// read the entries of the base relocation directory (.reloc), sorted by
+/// address. padding entries (`IMAGE_REL_BASED_ABSOLUTE`) are skipped.
+pub fn read_base_relocations(pe: &PE) -> Result<Vec<Relocation>> {
+    let mut relocs = vec![];
+
+    let directory = match pe.get_data_directory(IMAGE_DIRECTORY_ENTRY_BASERELOC)? {
+        Some(directory) if directory.size > 0 => directory,
+        _ => return Ok(relocs),
+    };
+
+    let base_address = pe.module.address_space.base_address;
+    let end = directory.address + directory.size;
+    let mut block = directory.address;
+    while block + sizeof_IMAGE_BASE_RELOCATION <= end {
+        let page = pe.module.address_space.read_u32(block)? as RVA;
+        let size = pe.module.address_space.read_u32(block + 4)? as RVA;
+
+        if size == 0 {
+            break;
+        }
+
+        if size < sizeof_IMAGE_BASE_RELOCATION || !size.is_multiple_of(2) {
+            return Err(PEError::MalformedPEFile(format!("invalid base relocation block at {:#x}", block)).into());
+        }
+
+        for entry in (block + sizeof_IMAGE_BASE_RELOCATION..std::cmp::min(block + size, end)).step_by(2) {
+            let entry = pe.module.address_space.read_u16(entry)?;
+            let kind = entry >> 12;
+            if kind == IMAGE_REL_BASED_ABSOLUTE {
+                continue;
+            }
+
+            relocs.push(Relocation {
+                address: base_address + page + (entry & 0xFFF) as RVA,
+                kind,
+            });
+        }
+
+        block += size;
+    }
+
+    relocs.sort_by_key(|reloc| reloc.address);
+
+    debug!("relocs: found {} base relocations", relocs.len());
+    Ok(relocs)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{aspace::AddressSpace, loader::pe::relocs::*, rsrc::*};
+    use anyhow::Result;
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        let relocs = read_base_relocations(&pe)?;
+
+        assert!(!relocs.is_empty());
+        for reloc in relocs.iter() {
+            assert_eq!(reloc.kind, IMAGE_REL_BASED_DIR64);
+
+            // relocated pointers refer to the module itself.
+            let ptr = pe.module.address_space.read_u64(reloc.address)?;
+            assert!(pe.module.probe_va(ptr, crate::module::Permissions::R));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn tiny() -> Result<()> {
+        let buf = get_buf(Rsrc::TINY);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert!(read_base_relocations(&pe)?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::Result;
+extern crate chrono;
+extern crate clap;
+extern crate log;
+
+fn run(pat_path: &str, sig_path: &str, library_name: &str) -> Result<()> {
+    let pat = String::from_utf8(std::fs::read(pat_path)?)?;
+    let sigs = lancelot_flirt::pat::parse(&pat)?;
+
+    std::fs::write(sig_path, lancelot_flirt::sig::write(&sigs, library_name)?)?;
+
+    Ok(())
+}
+
+fn main() {
+    better_panic::install();
+
+    // while the macro form of clap is more readable,
+    // it doesn't seem to allow us to use dynamically-generated values,
+    // such as the defaults pulled from env vars, etc.
+    let matches = clap::App::new("pat2sig")
+        .author("Willi Ballenthin <willi.ballenthin@gmail.com>")
+        .about("compile a FLIRT .pat file into a .sig file")
+        .arg(
+            clap::Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("log verbose messages"),
+        )
+        .arg(
+            clap::Arg::with_name("name")
+                .short("n")
+                .long("name")
+                .takes_value(true)
+                .default_value("Unnamed sample library")
+                .help("name of the library"),
+        )
+        .arg(
+            clap::Arg::with_name("pat")
+                .required(true)
+                .index(1)
+                .help("path to .pat file"),
+        )
+        .arg(
+            clap::Arg::with_name("sig")
+                .required(true)
+                .index(2)
+                .help("path to .sig file to write"),
+        )
+        .get_matches();
+
+    let log_level = match matches.occurrences_of("verbose") {
+        0 => log::LevelFilter::Info,
+        1 => log::LevelFilter::Debug,
+        2 => log::LevelFilter::Trace,
+        _ => log::LevelFilter::Trace,
+    };
+
+    fern::Dispatch::new()
+        .format(move |out, message, record| {
+            out.finish(format_args!(
+                "{} [{:5}] {} {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                if log_level == log::LevelFilter::Trace {
+                    record.target()
+                } else {
+                    ""
+                },
+                message
+            ))
+        })
+        .level(log_level)
+        .chain(std::io::stderr())
+        .apply()
+        .expect("failed to configure logging");
+
+    if let Err(e) = run(
+        matches.value_of("pat").unwrap(),
+        matches.value_of("sig").unwrap(),
+        matches.value_of("name").unwrap(),
+    ) {
+        eprintln!("error: {:}", e);
+    }
+}
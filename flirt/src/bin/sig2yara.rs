@@ -0,0 +1,86 @@
+use anyhow::Result;
+extern crate chrono;
+extern crate clap;
+extern crate log;
+
+fn run(sig_paths: &[&str], library_name: Option<&str>) -> Result<()> {
+    let mut sigs = vec![];
+
+    for &sig_path in sig_paths.iter() {
+        let buf = std::fs::read(sig_path)?;
+
+        if sig_path.ends_with(".pat") {
+            sigs.extend(lancelot_flirt::pat::parse(&String::from_utf8(buf)?)?);
+        } else {
+            sigs.extend(lancelot_flirt::sig::parse(&buf)?);
+        }
+    }
+
+    print!("{}", lancelot_flirt::yara::render(&sigs, library_name));
+
+    Ok(())
+}
+
+fn main() {
+    better_panic::install();
+
+    // while the macro form of clap is more readable,
+    // it doesn't seem to allow us to use dynamically-generated values,
+    // such as the defaults pulled from env vars, etc.
+    let matches = clap::App::new("sig2yara")
+        .author("Willi Ballenthin <willi.ballenthin@gmail.com>")
+        .about("translate FLIRT .sig and .pat files into YARA rules")
+        .arg(
+            clap::Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("log verbose messages"),
+        )
+        .arg(
+            clap::Arg::with_name("name")
+                .short("n")
+                .long("name")
+                .takes_value(true)
+                .help("name of the library, recorded in the rule metadata"),
+        )
+        .arg(
+            clap::Arg::with_name("sig")
+                .required(true)
+                .multiple(true)
+                .index(1)
+                .help("path to .sig or .pat files"),
+        )
+        .get_matches();
+
+    let log_level = match matches.occurrences_of("verbose") {
+        0 => log::LevelFilter::Info,
+        1 => log::LevelFilter::Debug,
+        2 => log::LevelFilter::Trace,
+        _ => log::LevelFilter::Trace,
+    };
+
+    fern::Dispatch::new()
+        .format(move |out, message, record| {
+            out.finish(format_args!(
+                "{} [{:5}] {} {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                if log_level == log::LevelFilter::Trace {
+                    record.target()
+                } else {
+                    ""
+                },
+                message
+            ))
+        })
+        .level(log_level)
+        .chain(std::io::stderr())
+        .apply()
+        .expect("failed to configure logging");
+
+    let sig_paths: Vec<&str> = matches.values_of("sig").unwrap().collect();
+    if let Err(e) = run(&sig_paths, matches.value_of("name")) {
+        eprintln!("error: {:}", e);
+    }
+}
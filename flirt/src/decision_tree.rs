@@ -388,6 +388,32 @@ pub struct DecisionTree {
     // each bucket contains only the patterns of that size.
     // during matching, need to do a match against each bucket with the haystack size and smaller.
     buckets:  BTreeMap<usize, (Vec<PatternId>, Node)>,
+    // the two-byte prefixes that may start a pattern, indexed by `b0 << 8 | b1`.
+    // used to quickly skip most offsets while scanning.
+    prefixes: BitVec,
+}
+
+/// compute the set of two-byte prefixes that may start one of the given
+/// patterns. a wildcard allows any byte value.
+fn get_prefixes(patterns: &[Pattern]) -> BitVec {
+    let mut prefixes = bitvec![0; 0x10000];
+
+    for pattern in patterns.iter() {
+        let values = |i: usize| -> std::ops::RangeInclusive<usize> {
+            match pattern.0.get(i) {
+                Some(Symbol::Byte(b)) => (*b as usize)..=(*b as usize),
+                Some(Symbol::Wildcard) | None => 0..=0xFF,
+            }
+        };
+
+        for b0 in values(0) {
+            for b1 in values(1) {
+                prefixes.set(b0 << 8 | b1, true);
+            }
+        }
+    }
+
+    prefixes
 }
 
 impl DecisionTree {
@@ -415,7 +441,13 @@ impl DecisionTree {
         let buckets: BTreeMap<usize, (Vec<PatternId>, Node)> =
             buckets.into_iter().map(|(k, (a, b))| (k, (a, Node::new(&b)))).collect();
 
-        DecisionTree { patterns, buckets }
+        let prefixes = get_prefixes(&patterns);
+
+        DecisionTree {
+            patterns,
+            buckets,
+            prefixes,
+        }
     }
 
     pub fn matches(&self, haystack: &[u8]) -> Vec<PatternId> {
@@ -441,6 +473,36 @@ impl DecisionTree {
 
         patterns
     }
+
+    /// find the patterns that match at each offset of the haystack,
+    /// not just at the start.
+    /// returns pairs of (offset, pattern id), sorted by offset.
+    ///
+    /// this walks the haystack once, and uses the prefixes of the patterns to
+    /// skip offsets where nothing can match, so its much faster than calling
+    /// `matches` at each offset.
+    pub fn scan(&self, haystack: &[u8]) -> Vec<(usize, PatternId)> {
+        let mut matches = vec![];
+
+        for offset in 0..haystack.len() {
+            // there's only one byte at the end of the haystack,
+            // which is too rare to bother filtering.
+            if let Some(&b1) = haystack.get(offset + 1) {
+                let prefix = (haystack[offset] as usize) << 8 | b1 as usize;
+                if !self.prefixes[prefix] {
+                    continue;
+                }
+            }
+
+            matches.extend(
+                self.matches(&haystack[offset..])
+                    .into_iter()
+                    .map(|pattern_id| (offset, pattern_id)),
+            );
+        }
+
+        matches
+    }
 }
 
 impl std::fmt::Debug for DecisionTree {
@@ -589,6 +651,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_scan() {
+        let dt = DecisionTree::new(PATTERNS);
+
+        assert_eq!(dt.scan(b""), vec![]);
+        assert_eq!(dt.scan(b"\x55\x8B\xEC\x33\xC0\x5D\xC3"), vec![(0, 7)]);
+
+        // matches anywhere in the haystack, not just at the start.
+        assert_eq!(
+            dt.scan(b"\xCC\xCC\x55\x8B\xEC\x33\xC0\x5D\xC3\xCC\x55\x8B\xEC\x33\xC0\x5D\xC2\x08\x00"),
+            vec![(2, 7), (10, 6)]
+        );
+
+        // with wildcards, too.
+        assert_eq!(
+            dt.scan(b"\x00\x55\x8B\xEC\x33\xC0\x66\xA1!!!!\x25\x00\x01\x00\x00\xF7\xD8\x1B\xC0\x40\x5D\xC3"),
+            vec![(1, 8)]
+        );
+
+        // a pattern that starts with a wildcard matches at each offset.
+        let dt = DecisionTree::new(&["..C3"]);
+        assert_eq!(dt.scan(b"\xC3\xC3\xC3"), vec![(0, 0), (1, 0)]);
+    }
+
     #[test]
     fn test_perf() {
         init_logging();
@@ -40,8 +40,9 @@ pub mod decision_tree;
 pub mod pat;
 pub mod pattern_set;
 pub mod sig;
+pub mod yara;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SigElement {
     Byte(u8),
     Wildcard,
@@ -56,7 +57,7 @@ impl std::fmt::Display for SigElement {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ByteSignature(pub Vec<SigElement>);
 
 impl std::fmt::Display for ByteSignature {
@@ -98,7 +99,7 @@ impl std::fmt::Display for Symbol {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct TailByte {
     offset: u64,
     value:  u8,
@@ -280,7 +281,76 @@ impl std::fmt::Debug for FlirtSignature {
     }
 }
 
+/// the number of bytes at the start of a function that sigmake uses for the
+/// byte signature.
+const MAX_BYTE_SIG_SIZE: usize = 32;
+
 impl FlirtSignature {
+    /// create a signature for the function in the given buffer,
+    /// like sigmake does for the functions in a .pat file.
+    ///
+    /// `variable` marks the bytes that may differ across instances of the
+    /// function, such as relocations, and has the same length as `buf`.
+    /// these bytes are wildcarded, and the CRC16 covers the bytes following
+    /// the byte signature up to the first variable byte.
+    ///
+    /// ```
+    /// use lancelot_flirt::*;
+    /// let sig = FlirtSignature::from_function(
+    ///     b"\x55\x8B\xEC\xE8\x00\x00\x00\x00\x5D\xC3",
+    ///     &[false, false, false, false, true, true, true, true, false, false],
+    ///     vec![Symbol::Public(Name { offset: 0, name: "foo".to_string() })],
+    /// );
+    /// assert_eq!(
+    ///     sig.render_pat(),
+    ///     "558bece8........5dc3............................................ 00 0000 000a :0000 foo"
+    /// );
+    /// ```
+    pub fn from_function(buf: &[u8], variable: &[bool], names: Vec<Symbol>) -> FlirtSignature {
+        assert_eq!(buf.len(), variable.len());
+
+        let elements: Vec<SigElement> = buf
+            .iter()
+            .zip(variable.iter())
+            .map(|(&b, &v)| if v { SigElement::Wildcard } else { SigElement::Byte(b) })
+            .collect();
+
+        let mut byte_sig: Vec<SigElement> = elements.iter().take(MAX_BYTE_SIG_SIZE).cloned().collect();
+        byte_sig.resize(MAX_BYTE_SIG_SIZE, SigElement::Wildcard);
+
+        let mut size_of_bytes_crc16 = 0usize;
+        if buf.len() > MAX_BYTE_SIG_SIZE {
+            size_of_bytes_crc16 = variable[MAX_BYTE_SIG_SIZE..]
+                .iter()
+                .take(0xFF)
+                .take_while(|&&v| !v)
+                .count();
+        }
+        let crc_end = MAX_BYTE_SIG_SIZE + size_of_bytes_crc16;
+        let crc16 = if size_of_bytes_crc16 > 0 {
+            FlirtSignature::crc16(&buf[MAX_BYTE_SIG_SIZE..crc_end])
+        } else {
+            0
+        };
+
+        let footer = if buf.len() > crc_end {
+            Some(ByteSignature(elements[crc_end..].to_vec()))
+        } else {
+            None
+        };
+
+        FlirtSignature {
+            byte_sig_size: MAX_BYTE_SIG_SIZE as u16,
+            byte_sig: ByteSignature(byte_sig),
+            size_of_bytes_crc16: size_of_bytes_crc16 as u8,
+            crc16,
+            size_of_function: buf.len() as u64,
+            names,
+            footer,
+            tail_bytes: vec![],
+        }
+    }
+
     pub fn create_matcher(&self) -> FlirtSignatureMatcher {
         FlirtSignatureMatcher::new(self)
     }
@@ -405,19 +475,102 @@ impl FlirtSignature {
             write!(f, " {}", name).unwrap();
         }
 
-        for tail_byte in self.tail_bytes.iter() {
-            write!(f, " ({:04X}: {:02X})", tail_byte.offset, tail_byte.value).unwrap();
-        }
-
+        // the footer comes before the tail bytes, which is what `pat::parse`
+        // expects.
         if let Some(footer) = &self.footer {
             write!(f, " {}", footer).unwrap();
         }
 
+        for tail_byte in self.tail_bytes.iter() {
+            write!(f, " ({:04X}: {:02X})", tail_byte.offset, tail_byte.value).unwrap();
+        }
+
         // we're writing utf8 above, so no reason for this to fail.
         String::from_utf8(f).unwrap()
     }
 }
 
+/// distinguish the signatures that share a byte signature and CRC16,
+/// like sigmake does, by adding tail bytes at the first offsets at which their
+/// footers differ.
+/// signatures that can't be distinguished, such as duplicates, are left as-is,
+/// as are those already distinguished by their tail bytes, so this is
+/// idempotent.
+pub fn add_tail_bytes(sigs: &mut [FlirtSignature]) {
+    let mut groups: HashMap<(ByteSignature, u8, u16), Vec<usize>> = Default::default();
+    for (i, sig) in sigs.iter().enumerate() {
+        groups
+            .entry((sig.byte_sig.clone(), sig.size_of_bytes_crc16, sig.crc16))
+            .or_default()
+            .push(i);
+    }
+
+    for group in groups.into_values() {
+        distinguish_signatures(sigs, group, 0);
+    }
+}
+
+/// do the tail bytes of the two signatures disagree at some offset?
+fn are_distinguished(a: &FlirtSignature, b: &FlirtSignature) -> bool {
+    a.tail_bytes.iter().any(|ta| {
+        b.tail_bytes
+            .iter()
+            .any(|tb| ta.offset == tb.offset && ta.value != tb.value)
+    })
+}
+
+fn distinguish_signatures(sigs: &mut [FlirtSignature], group: Vec<usize>, start: usize) {
+    if group.len() < 2 {
+        return;
+    }
+
+    // such as tail bytes from sigmake, or from a previous pass.
+    if group
+        .iter()
+        .enumerate()
+        .all(|(k, &i)| group[k + 1..].iter().all(|&j| are_distinguished(&sigs[i], &sigs[j])))
+    {
+        return;
+    }
+
+    let footer_byte = |sig: &FlirtSignature, offset: usize| match sig.footer.as_ref().and_then(|f| f.0.get(offset)) {
+        Some(SigElement::Byte(v)) => Some(*v),
+        _ => None,
+    };
+
+    let max_offset = group
+        .iter()
+        .filter_map(|&i| sigs[i].footer.as_ref().map(|f| f.0.len()))
+        .max()
+        .unwrap_or(0);
+
+    for offset in start..max_offset {
+        // all the footers must have a concrete byte here, and some must differ.
+        let values: Option<Vec<u8>> = group.iter().map(|&i| footer_byte(&sigs[i], offset)).collect();
+        let values = match values {
+            Some(values) if values.iter().any(|&v| v != values[0]) => values,
+            _ => continue,
+        };
+
+        let mut subgroups: std::collections::BTreeMap<u8, Vec<usize>> = Default::default();
+        for (&i, value) in group.iter().zip(values) {
+            if !sigs[i].tail_bytes.iter().any(|t| t.offset == offset as u64) {
+                sigs[i].tail_bytes.push(TailByte {
+                    offset: offset as u64,
+                    value,
+                });
+            }
+            subgroups.entry(value).or_default().push(i);
+        }
+
+        for subgroup in subgroups.into_values() {
+            distinguish_signatures(sigs, subgroup, offset + 1);
+        }
+
+        return;
+    }
+}
+
 pub struct FlirtSignatureMatcher<'a> {
     re:  Regex,
     sig: &'a FlirtSignature,
@@ -567,6 +720,30 @@ impl FlirtSignatureSet {
             .filter(|&sig| sig.match_footer(buf))
             .collect()
     }
+
+    /// find the signatures that match at each offset of the buffer,
+    /// not just at the start, such as across an entire section of code.
+    /// returns pairs of (offset, signature), sorted by offset.
+    ///
+    /// like `match`, a signature matches if its pattern, CRC16, tail bytes,
+    /// and footer all match. references to other functions aren't checked.
+    pub fn scan(&self, buf: &[u8]) -> Vec<(usize, &FlirtSignature)> {
+        self.matcher
+            .scan(buf)
+            .into_iter()
+            .flat_map(|(offset, pattern)| {
+                let buf = &buf[offset..];
+                self.sigs_by_pattern
+                    .get(pattern)
+                    .unwrap()
+                    .iter()
+                    .filter(move |&sig| sig.match_crc16(buf))
+                    .filter(move |&sig| sig.match_tail_bytes(buf))
+                    .filter(move |&sig| sig.match_footer(buf))
+                    .map(move |sig| (offset, sig))
+            })
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -776,4 +953,133 @@ mod tests {
         assert_eq!(matches.len(), 1);
         assert_eq!(matches[0].get_name().unwrap(), "__fseek_lk");
     }
+
+    #[test]
+    fn test_scan() {
+        let name = |name: &str| {
+            vec![Symbol::Public(Name {
+                offset: 0,
+                name:   name.to_string(),
+            })]
+        };
+
+        // identical except for the tail byte at 0x22, which follows a relocation.
+        let mut foo = vec![0x90; 0x30];
+        foo[0x0] = 0x55;
+        foo[0x20] = 0xE8;
+        foo[0x2F] = 0xC3;
+        let mut bar = foo.clone();
+        bar[0x22] = 0xC3;
+        let mut variable = vec![false; foo.len()];
+        variable[0x21] = true;
+
+        let mut sigs = vec![
+            FlirtSignature::from_function(&foo, &variable, name("foo")),
+            FlirtSignature::from_function(&bar, &variable, name("bar")),
+        ];
+        add_tail_bytes(&mut sigs);
+        let sigs = FlirtSignatureSet::with_signatures(sigs);
+
+        // and a copy of foo that fails the CRC16.
+        let mut baz = foo.clone();
+        baz[0x10] = 0xCC;
+
+        let mut buf = vec![0xCC; 0x3];
+        buf.extend(&foo);
+        buf.extend(&[0xCC; 0x5]);
+        buf.extend(&baz);
+        buf.extend(&bar);
+
+        let matches: Vec<(usize, &str)> = sigs
+            .scan(&buf)
+            .into_iter()
+            .map(|(offset, sig)| (offset, sig.get_name().unwrap()))
+            .collect();
+        assert_eq!(matches, vec![(0x3, "foo"), (0x68, "bar")]);
+    }
+
+    #[test]
+    fn test_from_function() {
+        // push ebp; mov ebp, esp; call $+5 (relocated); ...; pop ebp; ret
+        let mut buf = vec![0x55, 0x8B, 0xEC, 0xE8, 0x00, 0x00, 0x00, 0x00];
+        buf.extend(&[0x90; 0x30]);
+        buf.extend(&[0xA1, 0x00, 0x10, 0x40, 0x00, 0x5D, 0xC3]);
+
+        let mut variable = vec![false; buf.len()];
+        variable[4..8].fill(true);
+        variable[0x39..0x3D].fill(true);
+
+        let sig = FlirtSignature::from_function(
+            &buf,
+            &variable,
+            vec![Symbol::Public(Name {
+                offset: 0,
+                name:   "foo".to_string(),
+            })],
+        );
+        assert_eq!(sig.size_of_bytes_crc16, 0x39 - 0x20);
+        assert_eq!(sig.size_of_function, buf.len() as u64);
+
+        // the rendered signature parses back to the same thing.
+        let pats = pat::parse(&pat::render(std::slice::from_ref(&sig))).unwrap();
+        assert_eq!(pats.len(), 1);
+        assert_eq!(pats[0].render_pat(), sig.render_pat());
+
+        // and matches the function, even when relocated.
+        buf[5] = 0x11;
+        buf[0x3A] = 0x20;
+        let sigs = FlirtSignatureSet::with_signatures(pats);
+        assert_eq!(sigs.r#match(&buf).len(), 1);
+
+        // but not when the code differs after the CRC16 block.
+        buf[0x3D] = 0x90;
+        assert_eq!(sigs.r#match(&buf).len(), 0);
+    }
+
+    #[test]
+    fn test_add_tail_bytes() {
+        let name = |name: &str| {
+            vec![Symbol::Public(Name {
+                offset: 0,
+                name:   name.to_string(),
+            })]
+        };
+
+        // identical except for the byte at 0x22, which follows a relocation.
+        let mut foo = vec![0x90; 0x24];
+        foo[0x20] = 0xE8;
+        let mut bar = foo.clone();
+        bar[0x22] = 0xC3;
+        let mut variable = vec![false; foo.len()];
+        variable[0x21] = true;
+
+        let mut sigs = vec![
+            FlirtSignature::from_function(&foo, &variable, name("foo")),
+            FlirtSignature::from_function(&bar, &variable, name("bar")),
+            FlirtSignature::from_function(&foo, &variable, name("foo2")),
+        ];
+        add_tail_bytes(&mut sigs);
+
+        // the footer starts at 0x21, after the single CRC16 byte.
+        assert_eq!(sigs[0].tail_bytes.len(), 1);
+        assert_eq!(sigs[0].tail_bytes[0].offset, 1);
+        assert_eq!(sigs[0].tail_bytes[0].value, 0x90);
+        assert_eq!(sigs[1].tail_bytes[0].value, 0xC3);
+        assert!(sigs[1].render_pat().ends_with("(0001: C3)"));
+
+        // `pat::parse` expects the footer before the tail bytes,
+        // like in the .pat files emitted by IDA, so the rendered signatures
+        // parse back with both.
+        let pats = pat::parse(&pat::render(&sigs)).unwrap();
+        assert_eq!(pats[1].footer, sigs[1].footer);
+        assert_eq!(pats[1].tail_bytes, sigs[1].tail_bytes);
+
+        let sigs = FlirtSignatureSet::with_signatures(sigs);
+        let matches = sigs.r#match(&bar);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].get_name().unwrap(), "bar");
+
+        // duplicates can't be distinguished.
+        assert_eq!(sigs.r#match(&foo).len(), 2);
+    }
 }
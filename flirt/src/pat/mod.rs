@@ -336,6 +336,26 @@ pub fn parse(buf: &str) -> Result<Vec<FlirtSignature>> {
     }
 }
 
+/// render FLIRT signatures as a .pat file.
+///
+/// ```
+/// use lancelot_flirt::pat;
+/// let pat_buf = "3B0D........F27502F2C3F2E9...................................... 00 0000 0011 :0000 @__security_check_cookie@4 :000B@ $failure$4 ^0002 ___security_cookie ^000D ___report_gsfailure\n---";
+/// let sigs = pat::parse(pat_buf).unwrap();
+/// assert_eq!(pat::parse(&pat::render(&sigs)).unwrap().len(), 1);
+/// ```
+pub fn render(sigs: &[FlirtSignature]) -> String {
+    let mut pat = String::new();
+
+    for sig in sigs.iter() {
+        pat.push_str(&sig.render_pat());
+        pat.push('\n');
+    }
+
+    pat.push_str("---\n");
+    pat
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
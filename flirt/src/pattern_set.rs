@@ -120,6 +120,16 @@ impl PatternSet {
             .collect()
     }
 
+    /// find the patterns that match at each offset of the buffer,
+    /// not just at the start, as pairs of (offset, pattern).
+    pub fn scan(&self, buf: &[u8]) -> Vec<(usize, &Pattern)> {
+        self.dt
+            .scan(buf)
+            .into_iter()
+            .map(|(offset, i)| (offset, &self.patterns[i as usize]))
+            .collect()
+    }
+
     pub fn builder() -> PatternSetBuilder {
         PatternSetBuilder { patterns: vec![] }
     }
@@ -1,15 +1,14 @@
 use anyhow::Result;
 use log::trace;
 use nom::{
-    branch::alt,
     bytes::complete::{tag, take, take_while},
-    combinator::peek,
+    combinator::{peek, verify},
     number::complete::{be_u16, be_u8, le_u16, le_u32, le_u8},
     IResult,
 };
 use thiserror::Error;
 
-use super::{FlirtSignature, TailByte};
+use super::{FlirtSignature, Symbol, TailByte};
 use crate::{ByteSignature, SigElement};
 
 #[derive(Debug, Error)]
@@ -20,6 +19,8 @@ pub enum SigError {
     CompressionNotSupported(String),
     #[error("The .sig file is corrupt (or unsupported)")]
     CorruptSigFile,
+    #[error("The signature cannot be written to a .sig file: {0}")]
+    UnsupportedSignature(String),
 }
 
 bitflags! {
@@ -33,6 +34,10 @@ bitflags! {
     }
 }
 
+/// the .sig file versions that we can parse.
+const MIN_VERSION: u8 = 5;
+const MAX_VERSION: u8 = 10;
+
 #[derive(Debug)]
 enum HeaderExtra {
     V5,
@@ -54,7 +59,8 @@ impl HeaderExtra {
     /// get the size of this structure in bytes.
     fn get_size(&self) -> usize {
         match self {
-            HeaderExtra::V5 | HeaderExtra::V6_7 { .. } => 4,
+            HeaderExtra::V5 => 0,
+            HeaderExtra::V6_7 { .. } => 4,
             HeaderExtra::V8_9 { .. } => 6,
             HeaderExtra::V10 { .. } => 8,
         }
@@ -101,22 +107,15 @@ impl Header {
 
 fn utf8(input: &[u8], size: u16) -> IResult<&[u8], String> {
     let (input, s) = take(size)(input)?;
-    let s = String::from_utf8(s.to_vec()).expect("invalid string");
+    // library names are in the local codepage, which may not be UTF-8.
+    let s = String::from_utf8_lossy(s).into_owned();
     Ok((input, s))
 }
 
 fn header(input: &[u8]) -> IResult<&[u8], Header> {
     let (input, _) = tag(b"IDASGN")(input)?;
 
-    let (input, version) = alt((
-        tag(b"\x0A"),
-        tag(b"\x09"),
-        tag(b"\x08"),
-        tag(b"\x07"),
-        tag(b"\x06"),
-        tag(b"\x05"),
-    ))(input)?;
-    let version = version[0];
+    let (input, version) = verify(le_u8, |version| (MIN_VERSION..=MAX_VERSION).contains(version))(input)?;
 
     let (input, arch) = le_u8(input)?;
     let (input, file_types) = le_u32(input)?;
@@ -159,7 +158,7 @@ fn header(input: &[u8]) -> IResult<&[u8], Header> {
                 },
             )
         }
-        _ => unimplemented!(),
+        _ => unreachable!("version already verified"),
     };
 
     let (input, library_name) = utf8(input, library_name_length as u16)?;
@@ -172,7 +171,8 @@ fn header(input: &[u8]) -> IResult<&[u8], Header> {
             file_types,
             os_types,
             app_types,
-            features: Features::from_bits(features).expect("invalid features"),
+            // ignore feature flags we don't know about, such as from newer versions of IDA.
+            features: Features::from_bits_truncate(features),
             crc16,
             ctypes_crc16,
             extra,
@@ -426,7 +426,7 @@ fn name<'a>(input: &'a [u8], header: &Header, base_offset: i64) -> IResult<&'a [
     };
 
     let (input, s) = take_while(|b| b >= 0x20)(input)?;
-    let pname = String::from_utf8(s.to_vec()).expect("invalid name");
+    let pname = String::from_utf8_lossy(s).into_owned();
 
     let (input, pflags) = parsing_flags(input)?;
 
@@ -616,10 +616,7 @@ fn node<'a>(input: &'a [u8], header: &Header, prefix: Vec<SigElement>) -> IResul
 /// see `unpack_sig`.
 fn sig(input: &[u8]) -> Result<Vec<FlirtSignature>> {
     //nom::util::dbg_dmp(...);
-    let (input, header) = match header(input) {
-        Err(_) => return Err(SigError::CorruptSigFile.into()),
-        Ok((input, header)) => (input, header),
-    };
+    let (input, header) = parse_header(input)?;
 
     trace!("header: {:#?}", header);
 
@@ -631,40 +628,844 @@ fn sig(input: &[u8]) -> Result<Vec<FlirtSignature>> {
     Ok(sigs)
 }
 
-pub fn unpack_sig(input: &[u8]) -> Result<Vec<u8>> {
+/// parse the header of a .sig file,
+/// distinguishing versions we don't support from corrupt files.
+fn parse_header(input: &[u8]) -> Result<(&[u8], Header)> {
     match header(input) {
-        Ok((compressed, header)) => {
-            /*
-            println!("{:02x?}", &input[..0x50]);
-            println!("{:02x?}", &compressed[..0x50]);
-            */
-
-            if header.features.intersects(Features::COMPRESSED) {
-                let header_buf = &input[..header.get_size()];
-                match inflate::inflate_bytes_zlib(compressed) {
-                    Ok(decompressed) => {
-                        // stitch together the header with the decompressed payload
-                        let mut buf = vec![];
-                        buf.extend(header_buf);
-                        buf.extend(decompressed);
-                        Ok(buf)
-                    }
-                    Err(e) => {
-                        // I've see possible CMF values:
-                        //  0xC4 - IDA Pro 7.4/sig/pc/bc31cls.sig
-                        //  0x0C - IDA Pro 7.4/sig/pc/bc15owl
-                        //  0x05 - IDA Pro 7.4/sig/pc/bc15c2.sig
-                        Err(SigError::CompressionNotSupported(e).into())
-                    }
-                }
-            } else {
-                Ok(input.to_vec())
+        Ok((input, header)) => Ok((input, header)),
+        Err(_) => match input.get(6) {
+            Some(version) if input.starts_with(b"IDASGN") && !(MIN_VERSION..=MAX_VERSION).contains(version) => {
+                Err(SigError::NotSupported.into())
             }
-        }
-        Err(_) => Err(SigError::CorruptSigFile.into()),
+            _ => Err(SigError::CorruptSigFile.into()),
+        },
+    }
+}
+
+type Inflate = fn(&[u8]) -> std::result::Result<Vec<u8>, String>;
+
+/// decompress the payload of a .sig file.
+///
+/// versions 5 and 6 use the legacy compression scheme: a raw deflate stream,
+/// without the zlib header. newer versions use a zlib stream.
+/// some files don't follow the convention for their version,
+/// so fall back to the other scheme before giving up.
+///
+/// for example, these files aren't zlib streams, as seen by their CMF values:
+///   0xC4 - IDA Pro 7.4/sig/pc/bc31cls.sig
+///   0x0C - IDA Pro 7.4/sig/pc/bc15owl
+///   0x05 - IDA Pro 7.4/sig/pc/bc15c2.sig
+fn decompress(header: &Header, compressed: &[u8]) -> Result<Vec<u8>> {
+    let (preferred, fallback): (Inflate, Inflate) = if header.version < 7 {
+        (inflate::inflate_bytes, inflate::inflate_bytes_zlib)
+    } else {
+        (inflate::inflate_bytes_zlib, inflate::inflate_bytes)
+    };
+
+    match preferred(compressed) {
+        Ok(decompressed) => Ok(decompressed),
+        Err(e) => match fallback(compressed) {
+            Ok(decompressed) => {
+                trace!("sig: decompressed v{} payload with fallback scheme", header.version);
+                Ok(decompressed)
+            }
+            Err(_) => Err(SigError::CompressionNotSupported(e).into()),
+        },
+    }
+}
+
+pub fn unpack_sig(input: &[u8]) -> Result<Vec<u8>> {
+    let (compressed, header) = parse_header(input)?;
+
+    if header.features.intersects(Features::COMPRESSED) {
+        // stitch together the header with the decompressed payload
+        let mut buf = input[..header.get_size()].to_vec();
+        buf.extend(decompress(&header, compressed)?);
+        Ok(buf)
+    } else {
+        Ok(input.to_vec())
     }
 }
 
 pub fn parse(buf: &[u8]) -> Result<Vec<FlirtSignature>> {
     sig(&unpack_sig(buf)?)
 }
+
+/// the version of the .sig files that we write, as emitted by IDA 7.
+const WRITE_VERSION: u8 = 10;
+
+/// pack a variable-length integer with max range 16 bits, see `vint16`.
+fn write_vint16(buf: &mut Vec<u8>, v: u16) {
+    if v < 0x80 {
+        buf.push(v as u8);
+    } else {
+        assert!(v < 0x8000, "vint16 out of range");
+        buf.push(0x80 | (v >> 8) as u8);
+        buf.push(v as u8);
+    }
+}
+
+/// pack a variable-length integer with max range 32 bits, see `vint32`.
+fn write_vint32(buf: &mut Vec<u8>, v: u32) {
+    if v < 0x80 {
+        buf.push(v as u8);
+    } else if v < 0x4000 {
+        buf.push(0x80 | (v >> 8) as u8);
+        buf.push(v as u8);
+    } else if v < 0x2000_0000 {
+        buf.push(0xC0 | (v >> 24) as u8);
+        buf.push((v >> 16) as u8);
+        buf.extend_from_slice(&(v as u16).to_be_bytes());
+    } else {
+        buf.push(0xFF);
+        buf.extend_from_slice(&v.to_be_bytes());
+    }
+}
+
+/// pack the wildcard mask for a subpattern with the given length,
+/// see `wildcard_mask`.
+fn write_wildcard_mask(buf: &mut Vec<u8>, length: usize, mask: u64) {
+    if length == 0 {
+    } else if length < 0x10 {
+        write_vint16(buf, mask as u16);
+    } else if length <= 0x20 {
+        write_vint32(buf, mask as u32);
+    } else {
+        write_vint32(buf, (mask >> 32) as u32);
+        write_vint32(buf, mask as u32);
+    }
+}
+
+/// the most pattern elements in a single edge of the tree,
+/// limited by the size of the wildcard mask.
+const MAX_EDGE_LENGTH: usize = 0x40;
+
+/// a node in the prefix tree of byte signatures.
+/// each edge is a run of pattern elements shared by all the signatures below
+/// it, and only leaves have signatures.
+struct TreeNode<'a> {
+    children: Vec<(Vec<SigElement>, TreeNode<'a>)>,
+    sigs:     Vec<&'a FlirtSignature>,
+}
+
+/// a (trimmed) byte pattern and the signature it belongs to.
+type TreeItem<'a> = (Vec<SigElement>, &'a FlirtSignature);
+
+fn build_tree(items: Vec<TreeItem>, depth: usize) -> TreeNode {
+    if items.iter().all(|(pattern, _)| pattern.len() == depth) {
+        return TreeNode {
+            children: vec![],
+            sigs:     items.into_iter().map(|(_, sig)| sig).collect(),
+        };
+    }
+
+    // a node can't have both children and signatures,
+    // so extend the patterns that end here with the wildcards trimmed from them.
+    let items: Vec<_> = items
+        .into_iter()
+        .map(|(mut pattern, sig)| {
+            if pattern.len() == depth {
+                pattern.push(SigElement::Wildcard);
+            }
+            (pattern, sig)
+        })
+        .collect();
+
+    // group by the next pattern element, in the order they're first seen.
+    let mut groups: Vec<(SigElement, Vec<TreeItem>)> = vec![];
+    for item in items.into_iter() {
+        let element = item.0[depth];
+        match groups.iter_mut().find(|(e, _)| *e == element) {
+            Some((_, group)) => group.push(item),
+            None => groups.push((element, vec![item])),
+        }
+    }
+
+    let children = groups
+        .into_iter()
+        .map(|(_, group)| {
+            // extend the edge while all the patterns agree.
+            let first = group[0].0.clone();
+            let mut end = depth + 1;
+            while end - depth < MAX_EDGE_LENGTH
+                && group
+                    .iter()
+                    .all(|(pattern, _)| pattern.len() > end && first.len() > end && pattern[end] == first[end])
+            {
+                end += 1;
+            }
+
+            (first[depth..end].to_vec(), build_tree(group, end))
+        })
+        .collect();
+
+    TreeNode { children, sigs: vec![] }
+}
+
+fn write_name(buf: &mut Vec<u8>, name: &str) -> Result<()> {
+    if name.is_empty() || name.bytes().any(|b| b < 0x20) {
+        return Err(SigError::UnsupportedSignature(format!("invalid name: {:?}", name)).into());
+    }
+
+    buf.extend_from_slice(name.as_bytes());
+    Ok(())
+}
+
+fn write_module(buf: &mut Vec<u8>, sig: &FlirtSignature, flags: ParsingFlags, is_collision: bool) -> Result<()> {
+    if sig.size_of_function > u32::MAX as u64 {
+        return Err(SigError::UnsupportedSignature(format!("function too large: {:#x}", sig.size_of_function)).into());
+    }
+    write_vint32(buf, sig.size_of_function as u32);
+
+    let names: Vec<(&super::Name, bool)> = sig
+        .names
+        .iter()
+        .filter_map(|symbol| match symbol {
+            Symbol::Public(name) => Some((name, false)),
+            Symbol::Local(name) => Some((name, true)),
+            Symbol::Reference(_) => None,
+        })
+        .collect();
+    let references: Vec<&super::Name> = sig
+        .names
+        .iter()
+        .filter_map(|symbol| match symbol {
+            Symbol::Reference(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+
+    if names.is_empty() {
+        return Err(SigError::UnsupportedSignature("signature has no public names".to_string()).into());
+    }
+
+    // name offsets are relative to the prior name.
+    let mut offset = 0i64;
+    for (i, (name, is_local)) in names.iter().enumerate() {
+        let relative_offset = name.offset - offset;
+        offset = name.offset;
+        write_vint32(buf, relative_offset.unsigned_abs() as u32);
+
+        let mut name_flags = NameFlags::empty();
+        if *is_local {
+            name_flags |= NameFlags::LOCAL;
+        }
+        if relative_offset < 0 {
+            name_flags |= NameFlags::NEGATIVE_OFFSET;
+        }
+        if is_collision {
+            name_flags |= NameFlags::UNRESOLVED_COLLISION;
+        }
+        if !name_flags.is_empty() {
+            buf.push(name_flags.bits());
+        }
+
+        write_name(buf, &name.name)?;
+
+        if i + 1 < names.len() {
+            buf.push(ParsingFlags::MORE_PUBLIC_NAMES.bits());
+        } else {
+            let mut flags = flags;
+            if !sig.tail_bytes.is_empty() {
+                flags |= ParsingFlags::TAIL_BYTES;
+            }
+            if !references.is_empty() {
+                flags |= ParsingFlags::REFERENCED_FUNCTIONS;
+            }
+            buf.push(flags.bits());
+        }
+    }
+
+    if !sig.tail_bytes.is_empty() {
+        write_vint32(buf, sig.tail_bytes.len() as u32);
+        for tail_byte in sig.tail_bytes.iter() {
+            write_vint32(buf, tail_byte.offset as u32);
+            buf.push(tail_byte.value);
+        }
+    }
+
+    if !references.is_empty() {
+        write_vint32(buf, references.len() as u32);
+        for reference in references.iter() {
+            if reference.offset < 0 {
+                return Err(
+                    SigError::UnsupportedSignature(format!("negative reference offset: {}", reference.name)).into(),
+                );
+            }
+            write_vint32(buf, reference.offset as u32);
+
+            let length = reference.name.len();
+            if length == 0 || length >= 0x8000 {
+                return Err(SigError::UnsupportedSignature(format!("invalid name: {:?}", reference.name)).into());
+            } else if length <= 0xFF {
+                buf.push(length as u8);
+            } else {
+                buf.push(0x0);
+                write_vint16(buf, length as u16);
+            }
+            buf.extend_from_slice(reference.name.as_bytes());
+        }
+    }
+
+    Ok(())
+}
+
+/// can the given modules, which share a pattern and CRC16, be told apart?
+fn is_collision(a: &FlirtSignature, b: &FlirtSignature) -> bool {
+    let references = |sig: &FlirtSignature| -> Vec<Symbol> {
+        sig.names
+            .iter()
+            .filter(|symbol| matches!(symbol, Symbol::Reference(_)))
+            .cloned()
+            .collect()
+    };
+
+    a.get_name() != b.get_name() && a.tail_bytes == b.tail_bytes && references(a) == references(b)
+}
+
+fn write_leaf(buf: &mut Vec<u8>, sigs: &[&FlirtSignature]) -> Result<()> {
+    // group the modules by CRC16, in the order they're first seen.
+    let mut groups: Vec<((u8, u16), Vec<&FlirtSignature>)> = vec![];
+    for &sig in sigs.iter() {
+        let key = (sig.size_of_bytes_crc16, sig.crc16);
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(sig),
+            None => groups.push((key, vec![sig])),
+        }
+    }
+
+    for (i, ((crc_len, crc), modules)) in groups.iter().enumerate() {
+        buf.push(*crc_len);
+        buf.extend_from_slice(&crc.to_be_bytes());
+
+        for (j, &sig) in modules.iter().enumerate() {
+            let mut flags = ParsingFlags::empty();
+            if j + 1 < modules.len() {
+                flags |= ParsingFlags::MORE_MODULES_WITH_SAME_CRC;
+            }
+            if i + 1 < groups.len() {
+                flags |= ParsingFlags::MORE_MODULES;
+            }
+
+            let is_collision = modules
+                .iter()
+                .enumerate()
+                .any(|(k, &other)| k != j && is_collision(sig, other));
+            if is_collision {
+                trace!("unresolved collision: {:?}", sig.get_name());
+            }
+
+            write_module(buf, sig, flags, is_collision)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_node(buf: &mut Vec<u8>, node: &TreeNode) -> Result<()> {
+    write_vint16(buf, node.children.len() as u16);
+
+    if node.children.is_empty() {
+        return write_leaf(buf, &node.sigs);
+    }
+
+    for (edge, child) in node.children.iter() {
+        write_vint16(buf, edge.len() as u16);
+
+        // the first element is the most significant bit.
+        let mut mask = 0u64;
+        for (i, element) in edge.iter().enumerate() {
+            if let SigElement::Wildcard = element {
+                mask |= 1 << (edge.len() - 1 - i);
+            }
+        }
+        write_wildcard_mask(buf, edge.len(), mask);
+
+        for element in edge.iter() {
+            if let SigElement::Byte(v) = element {
+                buf.push(*v);
+            }
+        }
+
+        write_node(buf, child)?;
+    }
+
+    Ok(())
+}
+
+/// render FLIRT signatures into an (unpacked) .sig file.
+fn render_sig(sigs: &[FlirtSignature], library_name: &str, features: Features) -> Result<Vec<u8>> {
+    if sigs.is_empty() {
+        return Err(SigError::UnsupportedSignature("no signatures".to_string()).into());
+    }
+
+    if library_name.len() > 0xFF {
+        return Err(SigError::UnsupportedSignature("library name too long".to_string()).into());
+    }
+
+    // .sig files have no footers, so distinguish the signatures with tail
+    // bytes instead.
+    let mut sigs = sigs.to_vec();
+    super::add_tail_bytes(&mut sigs);
+
+    let pattern_size = sigs
+        .iter()
+        .map(|sig| std::cmp::max(sig.byte_sig_size as usize, sig.byte_sig.0.len()))
+        .max()
+        .unwrap_or(0);
+    if pattern_size > 0xFFFF {
+        return Err(SigError::UnsupportedSignature("byte signature too large".to_string()).into());
+    }
+
+    // like sigmake, don't store the trailing wildcards of the patterns,
+    // such as for functions shorter than the pattern size.
+    let items = sigs
+        .iter()
+        .map(|sig| {
+            let mut pattern = sig.byte_sig.0.clone();
+            while let Some(SigElement::Wildcard) = pattern.last() {
+                pattern.pop();
+            }
+            (pattern, sig)
+        })
+        .collect();
+    let tree = build_tree(items, 0);
+
+    let mut buf = vec![];
+    buf.extend_from_slice(b"IDASGN");
+    buf.push(WRITE_VERSION);
+    // arch: x86
+    buf.push(0x0);
+    // file, OS, and application types: any
+    buf.extend_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+    buf.extend_from_slice(&0xFFFFu16.to_le_bytes());
+    buf.extend_from_slice(&0xFFFFu16.to_le_bytes());
+    buf.extend_from_slice(&features.bits().to_le_bytes());
+    // old number of functions
+    buf.extend_from_slice(&0u16.to_le_bytes());
+    // crc16
+    buf.extend_from_slice(&0u16.to_le_bytes());
+    // ctype
+    buf.extend_from_slice(&[0u8; 12]);
+    buf.push(library_name.len() as u8);
+    // ctypes crc16
+    buf.extend_from_slice(&0u16.to_le_bytes());
+    buf.extend_from_slice(&(sigs.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&(pattern_size as u16).to_le_bytes());
+    // unknown
+    buf.extend_from_slice(&0u16.to_le_bytes());
+    buf.extend_from_slice(library_name.as_bytes());
+
+    write_node(&mut buf, &tree)?;
+
+    Ok(buf)
+}
+
+/// write FLIRT signatures into a compressed .sig file, as IDA does.
+///
+/// signatures with the same byte signature and CRC16 are distinguished by
+/// tail bytes, when their footers differ. otherwise, their names are marked
+/// as unresolved collisions.
+///
+/// ```
+/// use lancelot_flirt::{pat, sig};
+/// let pat_buf = "3B0D........F27502F2C3F2E9...................................... 00 0000 0011 :0000 @__security_check_cookie@4 :000B@ $failure$4 ^0002 ___security_cookie ^000D ___report_gsfailure\n---";
+/// let sig_buf = sig::write(&pat::parse(pat_buf).unwrap(), "example").unwrap();
+/// assert_eq!(sig::parse(&sig_buf).unwrap()[0].get_name(), Some("@__security_check_cookie@4"));
+/// ```
+pub fn write(sigs: &[FlirtSignature], library_name: &str) -> Result<Vec<u8>> {
+    let buf = render_sig(sigs, library_name, Features::COMPRESSED)?;
+
+    // the header is not compressed, only the tree.
+    let (payload, header) = match header(&buf) {
+        Ok((payload, header)) => (payload, header),
+        Err(_) => return Err(SigError::CorruptSigFile.into()),
+    };
+
+    let mut packed = buf[..header.get_size()].to_vec();
+    packed.extend(miniz_oxide::deflate::compress_to_vec_zlib(payload, 9));
+
+    Ok(packed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pat, Name};
+
+    /// the signature fields stored in a .sig file, for comparison.
+    fn summarize(sig: &FlirtSignature) -> String {
+        let mut pattern = sig.byte_sig.0.clone();
+        while let Some(SigElement::Wildcard) = pattern.last() {
+            pattern.pop();
+        }
+
+        format!(
+            "{} {:02x} {:04x} {:04x} {:?} {:?}",
+            ByteSignature(pattern),
+            sig.size_of_bytes_crc16,
+            sig.crc16,
+            sig.size_of_function,
+            sig.names,
+            sig.tail_bytes.iter().map(|t| (t.offset, t.value)).collect::<Vec<_>>()
+        )
+    }
+
+    fn summarize_all(sigs: &[FlirtSignature]) -> Vec<String> {
+        let mut summaries: Vec<String> = sigs.iter().map(summarize).collect();
+        summaries.sort();
+        summaries
+    }
+
+    /// rebuild the given .sig file with the header of another version,
+    /// keeping its tree.
+    ///
+    /// this only works when both versions encode the tree the same way:
+    /// versions 5 to 7 do, as do versions 8 to 10 when all the numbers are
+    /// small, such as for `__EH_prolog3`.
+    fn with_version(buf: &[u8], version: u8, features: Features, ctypes_crc16: u16) -> Vec<u8> {
+        let unpacked = unpack_sig(buf).unwrap();
+        let count = sig(&unpacked).unwrap().len();
+        let (tree, header) = parse_header(&unpacked).unwrap();
+
+        let mut out = b"IDASGN".to_vec();
+        out.push(version);
+        out.push(header.arch);
+        out.extend_from_slice(&header.file_types.to_le_bytes());
+        out.extend_from_slice(&header.os_types.to_le_bytes());
+        out.extend_from_slice(&header.app_types.to_le_bytes());
+        out.extend_from_slice(&features.bits().to_le_bytes());
+        // old number of functions
+        let old_count = if version < 6 { count as u16 } else { 0 };
+        out.extend_from_slice(&old_count.to_le_bytes());
+        out.extend_from_slice(&header.crc16.to_le_bytes());
+        // ctype
+        out.extend_from_slice(&[0u8; 12]);
+        out.push(header.library_name.len() as u8);
+        out.extend_from_slice(&ctypes_crc16.to_le_bytes());
+        if version >= 6 {
+            out.extend_from_slice(&(count as u32).to_le_bytes());
+        }
+        if version >= 8 {
+            out.extend_from_slice(&header.get_pattern_size().to_le_bytes());
+        }
+        if version >= 10 {
+            // unknown
+            out.extend_from_slice(&0u16.to_le_bytes());
+        }
+        out.extend_from_slice(header.library_name.as_bytes());
+
+        if !features.intersects(Features::COMPRESSED) {
+            out.extend_from_slice(tree);
+        } else if version < 7 {
+            out.extend(miniz_oxide::deflate::compress_to_vec(tree, 9));
+        } else {
+            out.extend(miniz_oxide::deflate::compress_to_vec_zlib(tree, 9));
+        }
+
+        out
+    }
+
+    fn eh_prolog3_pats() -> Vec<FlirtSignature> {
+        pat::parse(include_str!("../../sigs/pat/__EH_prolog3.pat")).unwrap()
+    }
+
+    /// `__EH_prolog3` as a version 8 file, which isn't compressed.
+    fn eh_prolog3_v8() -> Vec<u8> {
+        with_version(
+            &write(&eh_prolog3_pats(), "EH prolog3").unwrap(),
+            8,
+            Features::empty(),
+            0,
+        )
+    }
+
+    /// `__EH_prolog3` as a version 10 file, with CTYPE_CRC_3V.
+    fn eh_prolog3_v10() -> Vec<u8> {
+        with_version(
+            &write(&eh_prolog3_pats(), "EH prolog3").unwrap(),
+            10,
+            Features::COMPRESSED | Features::CTYPE_CRC_3V,
+            0x1234,
+        )
+    }
+
+    #[test]
+    fn test_vint() {
+        for &v in [0u32, 0x7F, 0x80, 0x3FFF, 0x4000, 0x1FFF_FFFF, 0x2000_0000, u32::MAX].iter() {
+            let mut buf = vec![];
+            write_vint32(&mut buf, v);
+            assert_eq!(vint32(&buf).unwrap(), (&[][..], v));
+        }
+
+        for &v in [0u16, 0x7F, 0x80, 0x7FFF].iter() {
+            let mut buf = vec![];
+            write_vint16(&mut buf, v);
+            assert_eq!(vint16(&buf).unwrap(), (&[][..], v));
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_pat() {
+        let pats = pat::parse(
+            "\
+518B4C240C895C240C8D5C240C508D442408F7D923C18D60F88B43F08904248B 21 B4FE 006E :0000 __EH_prolog3_GS_align ^0041 ___security_cookie ........33C5508941FC8B4DF0895DF08B4304894504FF75F464A1000000008945F48D45F464A300000000F2C3
+518B4C240C895C240C8D5C240C508D442408F7D923C18D60F88B43F08904248B 1F E4CF 0063 :0000 __EH_prolog3_align ^003F ___security_cookie ........33C5508B4304894504FF75F464A1000000008945F48D45F464A300000000F2C3
+3B0D........F27502F2C3F2E9...................................... 00 0000 0011 :0000 @__security_check_cookie@4 :000B@ $failure$4 ^0002 ___security_cookie ^000D ___report_gsfailure
+e9.............................................................. 00 0000 0005 :0000 ___vdecl_acos2 ^0001 ___sse2_acos2
+e9.............................................................. 00 0000 0005 :0000 ___vdecl_acos4 ^0001 ___avx_acos4
+---",
+        )
+        .unwrap();
+
+        let unpacked = render_sig(&pats, "test", Features::empty()).unwrap();
+        let sigs = sig(&unpacked).unwrap();
+        assert_eq!(summarize_all(&sigs), summarize_all(&pats));
+
+        let sigs = parse(&write(&pats, "test").unwrap()).unwrap();
+        assert_eq!(summarize_all(&sigs), summarize_all(&pats));
+
+        // the patterns are matched as before.
+        let buf = [
+            0x3B, 0x0D, 0x00, 0x00, 0x00, 0x00, 0xF2, 0x75, 0x02, 0xF2, 0xC3, 0xF2, 0xE9, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut buf = buf.to_vec();
+        buf.resize(0x20, 0xCC);
+        let set = crate::FlirtSignatureSet::with_signatures(sigs);
+        let matches = set.r#match(&buf);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].get_name(), Some("@__security_check_cookie@4"));
+    }
+
+    #[test]
+    fn test_tail_bytes() {
+        let name = |name: &str| {
+            vec![Symbol::Public(Name {
+                offset: 0,
+                name:   name.to_string(),
+            })]
+        };
+
+        // identical except for the byte at 0x22, which follows a relocation.
+        let mut foo = vec![0x90; 0x24];
+        foo[0x20] = 0xE8;
+        let mut bar = foo.clone();
+        bar[0x22] = 0xC3;
+        let mut variable = vec![false; foo.len()];
+        variable[0x21] = true;
+
+        let pats = vec![
+            FlirtSignature::from_function(&foo, &variable, name("foo")),
+            FlirtSignature::from_function(&bar, &variable, name("bar")),
+        ];
+
+        let sigs = parse(&write(&pats, "test").unwrap()).unwrap();
+        assert_eq!(sigs.len(), 2);
+        assert!(sigs.iter().all(|sig| sig.tail_bytes.len() == 1));
+
+        let set = crate::FlirtSignatureSet::with_signatures(sigs);
+        let matches = set.r#match(&bar);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].get_name(), Some("bar"));
+    }
+
+    #[test]
+    fn test_existing_tail_bytes() {
+        // the footers differ at 0x2 and 0x5, but the tail bytes at 0x5
+        // already distinguish the signatures, as sigmake emits them.
+        let pats = crate::pat::parse(
+            "\
+9090909090909090909090909090909090909090909090909090909090909090 00 0000 0026 :0000 foo 9090C39090C3 (0005: C3)
+9090909090909090909090909090909090909090909090909090909090909090 00 0000 0026 :0000 bar 9090CC9090CC (0005: CC)
+---
+",
+        )
+        .unwrap();
+
+        let sigs = parse(&write(&pats, "test").unwrap()).unwrap();
+        assert_eq!(sigs.len(), 2);
+        for sig in sigs.iter() {
+            assert_eq!(sig.tail_bytes.len(), 1);
+            assert_eq!(sig.tail_bytes[0].offset, 5);
+        }
+
+        // and writing the signatures again doesn't add any more.
+        let sigs = parse(&write(&sigs, "test").unwrap()).unwrap();
+        assert!(sigs.iter().all(|sig| sig.tail_bytes.len() == 1));
+    }
+
+    #[test]
+    fn test_roundtrip_sig() {
+        let buf = include_bytes!("../../sigs/sig/libcmt_15_msvc_x86.sig");
+        let expected = parse(&buf[..]).unwrap();
+        let sigs = parse(&write(&expected, "MSVC C Standard Library").unwrap()).unwrap();
+        assert_eq!(sigs.len(), expected.len());
+        assert_eq!(summarize_all(&sigs), summarize_all(&expected));
+    }
+
+    /// parse each .sig file under `flirt/sigs/sig`.
+    /// when there's a .pat file with the same base name under `flirt/sigs/pat`,
+    /// such as `__EH_prolog3.v5.sig` and `__EH_prolog3.pat`,
+    /// the signatures must match it.
+    /// see `flirt/sigs/sig/README.md` for how the fixtures were made.
+    #[test]
+    fn test_corpus() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("sigs");
+
+        for entry in std::fs::read_dir(root.join("sig")).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension() != Some("sig".as_ref()) {
+                continue;
+            }
+
+            let buf = std::fs::read(&path).unwrap();
+            let sigs = parse(&buf).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            assert!(!sigs.is_empty(), "{}", path.display());
+
+            let name = path.file_name().unwrap().to_str().unwrap();
+            let pat_path = root
+                .join("pat")
+                .join(format!("{}.pat", name.split('.').next().unwrap()));
+            if pat_path.exists() {
+                let pats = pat::parse(&std::fs::read_to_string(&pat_path).unwrap()).unwrap();
+                assert_eq!(summarize_all(&sigs), summarize_all(&pats), "{}", path.display());
+            }
+        }
+    }
+
+    /// parse each .sig file under the directory named by `LANCELOT_SIG_CORPUS`,
+    /// recursively, such as the `sig` directory of an IDA installation.
+    /// unlike most of the fixtures, these are written by IDA itself,
+    /// so they check the parser against the real legacy and current versions.
+    /// skipped when the variable isn't set.
+    #[test]
+    fn test_external_corpus() {
+        let root = match std::env::var_os("LANCELOT_SIG_CORPUS") {
+            Some(root) => std::path::PathBuf::from(root),
+            None => return,
+        };
+
+        let mut versions: std::collections::BTreeMap<u8, usize> = Default::default();
+        let mut dirs = vec![root];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                if path.extension() != Some("sig".as_ref()) {
+                    continue;
+                }
+
+                let buf = std::fs::read(&path).unwrap();
+                let sigs = parse(&buf).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+                assert!(!sigs.is_empty(), "{}", path.display());
+
+                let unpacked = unpack_sig(&buf).unwrap();
+                let (_, header) = parse_header(&unpacked).unwrap();
+                *versions.entry(header.version).or_default() += 1;
+            }
+        }
+
+        assert!(!versions.is_empty(), "no .sig files found");
+        println!("sig versions: {:?}", versions);
+    }
+
+    #[test]
+    fn test_versions() {
+        let pats = summarize_all(&eh_prolog3_pats());
+
+        let v6 = include_bytes!("../../sigs/sig/__EH_prolog3.v6.sig");
+        let v5 = with_version(v6, 5, Features::COMPRESSED, 0);
+        // the compressed streams may differ, but not their contents.
+        assert_eq!(
+            unpack_sig(&v5).unwrap(),
+            unpack_sig(include_bytes!("../../sigs/sig/__EH_prolog3.v5.sig")).unwrap()
+        );
+
+        // v7 uses zlib, unlike v6.
+        let v7 = with_version(v6, 7, Features::COMPRESSED, 0);
+        assert_eq!(&v7[0x25 + 4 + "EH prolog3".len()..][..1], &[0x78]);
+
+        for (version, buf) in [(7, v7), (8, eh_prolog3_v8()), (10, eh_prolog3_v10())].iter() {
+            let sigs = parse(buf).unwrap_or_else(|e| panic!("v{}: {}", version, e));
+            assert_eq!(summarize_all(&sigs), pats, "v{}", version);
+        }
+
+        let v10 = unpack_sig(&eh_prolog3_v10()).unwrap();
+        let (_, header) = parse_header(&v10).unwrap();
+        assert!(header.features.contains(Features::CTYPE_CRC_3V));
+        assert_eq!(header.ctypes_crc16, 0x1234);
+    }
+
+    #[test]
+    fn test_unknown_features() {
+        // such as from a newer version of IDA.
+        let mut buf = eh_prolog3_v10();
+        buf[0x10..0x12].copy_from_slice(&(Features::COMPRESSED.bits() | 0x8000).to_le_bytes());
+
+        let sigs = parse(&buf).unwrap();
+        assert_eq!(summarize_all(&sigs), summarize_all(&eh_prolog3_pats()));
+    }
+
+    #[test]
+    fn test_non_utf8_names() {
+        // names are in the local codepage, like `é` in cp1252.
+        let mut buf = eh_prolog3_v8();
+        let library = buf
+            .windows(b"EH prolog3".len())
+            .position(|w| w == b"EH prolog3")
+            .unwrap();
+        buf[library + 2] = 0xE9;
+        let module = buf
+            .windows(b"__EH_prolog3_align".len())
+            .position(|w| w == b"__EH_prolog3_align")
+            .unwrap();
+        buf[module + 2] = 0xE9;
+
+        let (_, header) = parse_header(&buf).unwrap();
+        assert_eq!(header.library_name, "EH\u{FFFD}prolog3");
+
+        let sigs = parse(&buf).unwrap();
+        assert_eq!(sigs.len(), 4);
+        assert!(sigs
+            .iter()
+            .any(|sig| sig.get_name() == Some("__\u{FFFD}H_prolog3_align")));
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let buf = eh_prolog3_v8();
+
+        let mut unsupported = buf.to_vec();
+        unsupported[6] = 11;
+        let e = parse(&unsupported).unwrap_err();
+        assert!(matches!(e.downcast_ref::<SigError>(), Some(SigError::NotSupported)));
+
+        let e = parse(&buf[..0x20]).unwrap_err();
+        assert!(matches!(e.downcast_ref::<SigError>(), Some(SigError::CorruptSigFile)));
+
+        // the compressed payload is neither a zlib nor a raw deflate stream.
+        let mut corrupt = eh_prolog3_v10();
+        corrupt[0x25 + 8 + "EH prolog3".len()..].fill(0xFF);
+        let e = parse(&corrupt).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<SigError>(),
+            Some(SigError::CompressionNotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(write(&[], "test").is_err());
+
+        let pats = pat::parse(
+            "\
+3B0D........F27502F2C3F2E9...................................... 00 0000 0011 ^0002 ___security_cookie
+---",
+        )
+        .unwrap();
+        assert!(write(&pats, "test").is_err());
+    }
+}
@@ -0,0 +1,437 @@
+//! render FLIRT signatures as YARA rules.
+//!
+//! each signature becomes a rule named after its public name, with a single
+//! hex string that covers:
+//!
+//!   - the byte pattern,
+//!   - the CRC16 range, as wildcards, since YARA can't compute a CRC16, and
+//!   - the tail bytes (from .sig files) or footer (from .pat files).
+//!
+//! when the trailing wildcards are trimmed from the hex string,
+//! the condition still requires that the CRC16 range fits in the file.
+//! references to other functions can't be expressed in YARA,
+//! so they're only recorded in the metadata.
+//!
+//! since YARA doesn't check CRC16s or references,
+//! expect more false positives than from FLIRT matching.
+//! signatures with too few fixed bytes to be distinctive, like for tiny
+//! functions, are skipped.
+use std::collections::HashSet;
+
+use crate::{FlirtSignature, SigElement, Symbol};
+
+/// runs of more wildcards than this are rendered as a jump, like `[8]`.
+const MAX_WILDCARD_RUN: usize = 4;
+
+/// YARA identifiers can't be longer than this.
+const MAX_IDENTIFIER_LENGTH: usize = 128;
+
+/// signatures with fewer fixed bytes than this, such as for a lone `jmp`,
+/// would match all over the place, so they're not rendered.
+const MIN_FIXED_BYTES: usize = 0x10;
+
+// ref: https://yara.readthedocs.io/en/stable/writingrules.html#yara-keywords
+const KEYWORDS: &[&str] = &[
+    "all",
+    "and",
+    "any",
+    "ascii",
+    "at",
+    "base64",
+    "base64wide",
+    "condition",
+    "contains",
+    "defined",
+    "endswith",
+    "entrypoint",
+    "false",
+    "filesize",
+    "for",
+    "fullword",
+    "global",
+    "import",
+    "icontains",
+    "iendswith",
+    "iequals",
+    "in",
+    "include",
+    "int16",
+    "int16be",
+    "int32",
+    "int32be",
+    "int8",
+    "int8be",
+    "istartswith",
+    "matches",
+    "meta",
+    "nocase",
+    "none",
+    "not",
+    "of",
+    "or",
+    "private",
+    "rule",
+    "startswith",
+    "strings",
+    "them",
+    "true",
+    "uint16",
+    "uint16be",
+    "uint32",
+    "uint32be",
+    "uint8",
+    "uint8be",
+    "wide",
+    "xor",
+];
+
+/// translate the given symbol name into a valid YARA rule identifier.
+///
+/// ```
+/// use lancelot_flirt::yara::to_identifier;
+///
+/// assert_eq!(to_identifier("__EH_prolog3"), "__EH_prolog3");
+/// assert_eq!(to_identifier("@__security_check_cookie@4"), "___security_check_cookie_4");
+/// assert_eq!(to_identifier("??2@YAPAXI@Z"), "__2_YAPAXI_Z");
+/// assert_eq!(to_identifier("7zip"), "_7zip");
+/// assert_eq!(to_identifier("rule"), "rule_");
+/// ```
+pub fn to_identifier(name: &str) -> String {
+    let mut identifier: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+
+    match identifier.chars().next() {
+        None => identifier.push('_'),
+        Some(c) if c.is_ascii_digit() => identifier.insert(0, '_'),
+        _ => (),
+    }
+
+    if KEYWORDS.contains(&identifier.as_str()) {
+        identifier.push('_');
+    }
+
+    identifier.truncate(MAX_IDENTIFIER_LENGTH);
+    identifier
+}
+
+/// escape the given string for use in a YARA text string, like metadata.
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for b in s.bytes() {
+        match b {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7E => escaped.push(b as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    escaped
+}
+
+/// the elements that a match must have, from the start of the function,
+/// with trailing wildcards trimmed.
+fn get_elements(sig: &FlirtSignature) -> Vec<SigElement> {
+    let mut elements = sig.byte_sig.0.clone();
+    elements.resize(sig.byte_sig_size as usize, SigElement::Wildcard);
+
+    // YARA can't check the CRC16, but we can keep the alignment of what follows.
+    let footer_offset = elements.len() + sig.size_of_bytes_crc16 as usize;
+    elements.resize(footer_offset, SigElement::Wildcard);
+
+    let mut set = |offset: usize, element: SigElement| {
+        if let SigElement::Byte(_) = element {
+            if elements.len() <= offset {
+                elements.resize(offset + 1, SigElement::Wildcard);
+            }
+            elements[offset] = element;
+        }
+    };
+
+    if let Some(footer) = &sig.footer {
+        for (i, &element) in footer.0.iter().enumerate() {
+            set(footer_offset + i, element);
+        }
+    }
+
+    for tail_byte in sig.tail_bytes.iter() {
+        set(
+            footer_offset + tail_byte.offset as usize,
+            SigElement::Byte(tail_byte.value),
+        );
+    }
+
+    while let Some(SigElement::Wildcard) = elements.last() {
+        elements.pop();
+    }
+
+    elements
+}
+
+/// render the given elements as the body of a YARA hex string,
+/// like `55 8B EC ?? ?? [8] C3`.
+///
+/// the elements must not end with a wildcard, since YARA hex strings can't end
+/// with a jump.
+fn render_hex_string(elements: &[SigElement]) -> String {
+    let mut parts: Vec<String> = vec![];
+
+    let mut i = 0;
+    while i < elements.len() {
+        match elements[i] {
+            SigElement::Byte(b) => {
+                parts.push(format!("{:02X}", b));
+                i += 1;
+            }
+            SigElement::Wildcard => {
+                let run = elements[i..]
+                    .iter()
+                    .take_while(|element| matches!(element, SigElement::Wildcard))
+                    .count();
+
+                // hex strings can't start with a jump, either.
+                if run > MAX_WILDCARD_RUN && i > 0 {
+                    parts.push(format!("[{}]", run));
+                } else {
+                    parts.extend((0..run).map(|_| "??".to_string()));
+                }
+                i += run;
+            }
+        }
+    }
+
+    parts.join(" ")
+}
+
+/// the names of the given kind of symbol, like the references.
+fn get_names<F: Fn(&Symbol) -> Option<&str>>(sig: &FlirtSignature, f: F) -> Vec<&str> {
+    sig.names.iter().filter_map(f).collect()
+}
+
+/// render the given signature as a YARA rule with the given name.
+/// `library_name` is recorded in the metadata, if provided.
+///
+/// returns `None` if the signature has too few fixed bytes to search for.
+pub fn render_rule(sig: &FlirtSignature, rule_name: &str, library_name: Option<&str>) -> Option<String> {
+    let elements = get_elements(sig);
+    let fixed = elements
+        .iter()
+        .filter(|element| matches!(element, SigElement::Byte(_)))
+        .count();
+    if fixed < MIN_FIXED_BYTES {
+        return None;
+    }
+
+    let mut rule = format!("rule {} : flirt\n{{\n    meta:\n", rule_name);
+
+    if let Some(name) = sig.get_name() {
+        rule.push_str(&format!("        name = \"{}\"\n", escape(name)));
+    }
+    let locals = get_names(sig, |name| match name {
+        Symbol::Local(name) => Some(&name.name),
+        _ => None,
+    });
+    if !locals.is_empty() {
+        rule.push_str(&format!("        locals = \"{}\"\n", escape(&locals.join(", "))));
+    }
+    let references = get_names(sig, |name| match name {
+        Symbol::Reference(name) => Some(&name.name),
+        _ => None,
+    });
+    if !references.is_empty() {
+        rule.push_str(&format!(
+            "        references = \"{}\"\n",
+            escape(&references.join(", "))
+        ));
+    }
+    if let Some(library_name) = library_name {
+        rule.push_str(&format!("        library = \"{}\"\n", escape(library_name)));
+    }
+    rule.push_str(&format!("        size = {}\n", sig.size_of_function));
+    rule.push_str(&format!("        crc16_length = {}\n", sig.size_of_bytes_crc16));
+    rule.push_str(&format!("        crc16 = \"{:04x}\"\n", sig.crc16));
+    rule.push_str(&format!("        pattern = \"{}\"\n", sig.render_pat()));
+
+    rule.push_str("\n    strings:\n");
+    rule.push_str(&format!("        $pattern = {{ {} }}\n", render_hex_string(&elements)));
+
+    rule.push_str("\n    condition:\n");
+    // like FLIRT, the function must be large enough to cover the CRC16 range,
+    // even though YARA can't check it.
+    let size = if sig.size_of_bytes_crc16 > 0 {
+        sig.byte_sig_size as usize + sig.size_of_bytes_crc16 as usize
+    } else {
+        sig.byte_sig.0.len()
+    };
+    if elements.len() < size {
+        rule.push_str(&format!(
+            "        for any i in (1..#pattern) : (@pattern[i] + {} <= filesize)\n",
+            size
+        ));
+    } else {
+        rule.push_str("        $pattern\n");
+    }
+
+    rule.push_str("}\n");
+    Some(rule)
+}
+
+/// render the given signatures as YARA rules, one per signature.
+///
+/// rules are named after the public name of each signature,
+/// with a suffix to keep them unique, like `_memcpy_2`.
+/// signatures with too few fixed bytes are skipped.
+///
+/// ```
+/// use lancelot_flirt::{pat, yara};
+///
+/// let sigs = pat::parse("\
+/// 518B4C240C895C240C8D5C240C508D442408F7D923C18D60F88B43F08904248B 20 6562 0067 :0000 __EH_prolog3_catch_align ^0040 ___security_cookie ........33C5508965F08B4304894504FF75F464A1000000008945F48D45F464A300000000F2C3
+/// ---").unwrap();
+///
+/// let rules = yara::render(&sigs, Some("libcmt"));
+/// assert!(rules.starts_with("rule __EH_prolog3_catch_align : flirt\n"));
+/// assert!(rules.contains("        references = \"___security_cookie\"\n"));
+/// assert!(rules.contains("$pattern = { 51 8B 4C 24 0C 89 5C 24 0C 8D 5C 24 0C 50 8D 44 24 08 F7 D9 23 C1 8D 60 F8 8B 43 F0 89 04 24 8B [36] 33 C5 50 89 65 F0"));
+/// ```
+pub fn render(sigs: &[FlirtSignature], library_name: Option<&str>) -> String {
+    let mut rule_names: HashSet<String> = Default::default();
+    let mut rules = vec![];
+
+    for sig in sigs.iter() {
+        let identifier = to_identifier(sig.get_name().unwrap_or("flirt"));
+
+        let mut rule_name = identifier.clone();
+        let mut i = 2;
+        while rule_names.contains(&rule_name) {
+            let suffix = format!("_{}", i);
+            rule_name = identifier.clone();
+            rule_name.truncate(MAX_IDENTIFIER_LENGTH - suffix.len());
+            rule_name.push_str(&suffix);
+            i += 1;
+        }
+        rule_names.insert(rule_name.clone());
+
+        if let Some(rule) = render_rule(sig, &rule_name, library_name) {
+            rules.push(rule);
+        }
+    }
+
+    rules.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pat, sig};
+
+    #[test]
+    fn test_render_rule() {
+        let sigs = pat::parse(
+            "\
+568B742408578B460CA883746F8B7C241485FF740A83FF01740583FF02755D24 0B 2EC8 008C :0000 _fseek :0061@ $fail ^0081 __lock_file
+e9.............................................................. 00 0000 0005 :0000 ___vdecl_acos2 ^0001 ___sse2_acos2
+---",
+        )
+        .unwrap();
+
+        assert_eq!(
+            render_rule(&sigs[0], "_fseek", None).unwrap(),
+            r#"rule _fseek : flirt
+{
+    meta:
+        name = "_fseek"
+        locals = "$fail"
+        references = "__lock_file"
+        size = 140
+        crc16_length = 11
+        crc16 = "2ec8"
+        pattern = "568b742408578b460ca883746f8b7c241485ff740a83ff01740583ff02755d24 0b 2ec8 008c :0000 _fseek :0061@ $fail ^0081 __lock_file"
+
+    strings:
+        $pattern = { 56 8B 74 24 08 57 8B 46 0C A8 83 74 6F 8B 7C 24 14 85 FF 74 0A 83 FF 01 74 05 83 FF 02 75 5D 24 }
+
+    condition:
+        for any i in (1..#pattern) : (@pattern[i] + 43 <= filesize)
+}
+"#
+        );
+
+        // a single byte would match all over the place.
+        assert!(render_rule(&sigs[1], "___vdecl_acos2", Some("libm")).is_none());
+    }
+
+    #[test]
+    fn test_tail_bytes() {
+        let sigs = pat::parse(
+            "\
+568b742408578b460ca883746f8b7c241485ff740a83ff01740583ff02755d24 0b 2ec8 008c :0000 _fseek (0050: 0D)
+568b742408578b460ca883746f8b7c241485ff740a83ff01740583ff02755d24 0b 2ec8 008d :0000 __fseek_lk (0050: 0E)
+---",
+        )
+        .unwrap();
+
+        let rules = render(&sigs, None);
+        // the CRC16 range is wildcarded, followed by the tail byte at 0x20 + 0x0B +
+        // 0x50.
+        assert!(rules.contains("02 75 5D 24 [91] 0D }"));
+        assert!(rules.contains("02 75 5D 24 [91] 0E }"));
+        assert!(rules.contains("        $pattern\n"));
+    }
+
+    #[test]
+    fn test_unique_names() {
+        let sigs = pat::parse(
+            "\
+558bec83ec105356578b7d0833c05f5e5b8be55dc3...................... 00 0000 0015 :0000 foo
+558bec83ec205356578b7d0833c05f5e5b8be55dc3...................... 00 0000 0015 :0000 foo
+558bec83ec305356578b7d0833c05f5e5b8be55dc3...................... 00 0000 0015 :0000 rule
+---",
+        )
+        .unwrap();
+
+        let rules = render(&sigs, None);
+        let names: Vec<&str> = rules.lines().filter_map(|line| line.strip_prefix("rule ")).collect();
+        assert_eq!(names, vec!["foo : flirt", "foo_2 : flirt", "rule_ : flirt"]);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("foo"), "foo");
+        assert_eq!(escape("a\"b\\c"), "a\\\"b\\\\c");
+        assert_eq!(escape("\n\u{e9}"), "\\x0a\\xc3\\xa9");
+    }
+
+    #[test]
+    fn test_sig() {
+        let buf = include_bytes!("../../sigs/sig/libcmt_15_msvc_x86.sig");
+        let sigs = sig::parse(&buf[..]).unwrap();
+
+        let rules = render(&sigs, Some("MSVC C Standard Library"));
+        let names: Vec<&str> = rules.lines().filter(|line| line.starts_with("rule ")).collect();
+        // signatures for tiny functions, like thunks, are skipped.
+        let expected = sigs
+            .iter()
+            .filter(|sig| render_rule(sig, "flirt", None).is_some())
+            .count();
+        assert_eq!(names.len(), expected);
+        assert!(names.len() < sigs.len());
+
+        let unique: std::collections::HashSet<&str> = names.iter().cloned().collect();
+        assert_eq!(unique.len(), names.len());
+
+        // hex strings never start or end with a jump,
+        // and have enough fixed bytes to be distinctive.
+        for line in rules.lines().filter(|line| line.contains("$pattern = {")) {
+            assert!(!line.contains("{ ["), "{}", line);
+            assert!(!line.contains("] }"), "{}", line);
+
+            let fixed = line
+                .split_whitespace()
+                .filter(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_hexdigit()))
+                .count();
+            assert!(fixed >= MIN_FIXED_BYTES, "{}", line);
+        }
+    }
+}
@@ -14,6 +14,7 @@ fn to_py_err(e: Error) -> PyErr {
         Some(sig::SigError::NotSupported) => return to_value_error(e),
         Some(sig::SigError::CompressionNotSupported(_)) => return to_value_error(e),
         Some(sig::SigError::CorruptSigFile) => return to_value_error(e),
+        Some(sig::SigError::UnsupportedSignature(_)) => return to_value_error(e),
         None => (),
     };
 
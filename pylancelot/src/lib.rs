@@ -1,9 +1,13 @@
 #![allow(clippy::upper_case_acronyms)]
 
+use std::{borrow::Cow, collections::BTreeMap};
+
 use anyhow::Error;
 use lancelot::{
+    analysis::pe::db::{Analysis, Database},
     arch::Arch,
     aspace::AddressSpace,
+    config::Config,
     loader::pe::{PEError, PE as lPE},
     module::{ModuleError, Permissions},
     pagemap::PageMapError,
@@ -57,8 +61,39 @@ pub fn from_bytes(buf: &PyBytes) -> PyResult<PE> {
     let pe = lPE::from_bytes(buf.as_bytes()).map_err(to_py_err)?;
     let dec = dis::get_disassembler(&pe.module).map_err(to_py_err)?;
     Ok(PE {
-        inner:   pe,
-        decoder: dec,
+        inner:    pe,
+        decoder:  dec,
+        analysis: None,
+    })
+}
+
+/// parse and construct a PE instance from the given bytes,
+/// reusing the analysis database in the given cache directory.
+/// if there isn't one, then analyze the PE and save its database there.
+/// the lancelot CLI shares the same databases via `--cache`.
+/// a database analyzed with another configuration is replaced
+/// (keeping the user's comments and names),
+/// so pass the same configuration as the CLI to share its analysis.
+///
+/// Args:
+///   buf (bytes): the raw bytes of a PE file.
+///   cache (str): the path to the directory of analysis databases.
+///   config (Optional[str]): the path to a TOML configuration file.
+///
+/// Returns: PE
+#[pyfunction]
+pub fn from_cache(buf: &PyBytes, cache: &str, config: Option<&str>) -> PyResult<PE> {
+    use lancelot::analysis::dis;
+    let config = match config {
+        Some(path) => Config::from_file(path).map_err(to_py_err)?,
+        None => Default::default(),
+    };
+    let db = Database::from_cache(cache, buf.as_bytes(), &config).map_err(to_py_err)?;
+    let dec = dis::get_disassembler(&db.pe.module).map_err(to_py_err)?;
+    Ok(PE {
+        inner:    db.pe,
+        decoder:  dec,
+        analysis: Some(db.analysis),
     })
 }
 
@@ -335,14 +370,245 @@ impl PyNumberProtocol for Instruction {
     }
 }
 
+/// The inferred calling convention and arguments of a function.
+#[pyclass]
+pub struct Prototype {
+    /// the address of the function.
+    #[pyo3(get)]
+    pub address: u64,
+
+    /// one of "cdecl", "stdcall", "fastcall", "thiscall", or "win64".
+    #[pyo3(get)]
+    pub calling_convention: String,
+
+    /// names of the registers used to pass arguments, in argument order.
+    /// type: List[str]
+    #[pyo3(get)]
+    pub register_arguments: Vec<String>,
+
+    /// number of arguments passed on the stack.
+    #[pyo3(get)]
+    pub stack_arguments: usize,
+
+    /// total number of arguments.
+    #[pyo3(get)]
+    pub argument_count: usize,
+
+    /// number of bytes popped from the stack by the function, via `ret N`.
+    #[pyo3(get)]
+    pub callee_cleanup: u64,
+}
+
+impl Prototype {
+    fn from_prototype(va: VA, p: &lancelot::analysis::calling_convention::Prototype) -> Prototype {
+        Prototype {
+            address:            va,
+            calling_convention: p.calling_convention.to_string(),
+            register_arguments: p
+                .register_arguments
+                .iter()
+                .map(|&reg| format!("{:?}", reg).to_lowercase())
+                .collect(),
+            stack_arguments:    p.stack_arguments,
+            argument_count:     p.argument_count(),
+            callee_cleanup:     p.callee_cleanup,
+        }
+    }
+}
+
+#[pyproto]
+impl PyNumberProtocol for Prototype {
+    fn __int__(&self) -> PyResult<u64> {
+        Ok(self.address)
+    }
+}
+
+const XREF_CALL: u8 = 0;
+const XREF_UNCONDITIONAL_JUMP: u8 = 1;
+const XREF_CONDITIONAL_JUMP: u8 = 2;
+const XREF_READ: u8 = 3;
+const XREF_WRITE: u8 = 4;
+const XREF_OFFSET: u8 = 5;
+const XREF_POINTER: u8 = 6;
+
+fn xref_to_tuple(py: Python, xref: &lancelot::analysis::xrefs::Xref) -> Py<PyTuple> {
+    // we use a tuple for performance.
+    use lancelot::analysis::xrefs::XrefType;
+    let ty = match xref.ty {
+        XrefType::Call => XREF_CALL,
+        XrefType::UnconditionalJump => XREF_UNCONDITIONAL_JUMP,
+        XrefType::ConditionalJump => XREF_CONDITIONAL_JUMP,
+        XrefType::Read => XREF_READ,
+        XrefType::Write => XREF_WRITE,
+        XrefType::Offset => XREF_OFFSET,
+        XrefType::Pointer => XREF_POINTER,
+    };
+    let triple: [u64; 3] = [xref.src, xref.dst, ty as u64];
+    let triple = PyTuple::new(py, triple.iter());
+    triple.into()
+}
+
+/// An index of the cross-references among code and data in a module.
+/// each reference is a tuple (source address, destination address, xref
+/// type). use the `XREF_(SRC|DST|TYPE)` constants to index into this tuple.
+/// the xref type is one of the `XREF_TYPE_*` constants, such as
+/// `XREF_TYPE_READ`.
+#[pyclass]
+pub struct Xrefs {
+    inner: lancelot::analysis::xrefs::Xrefs,
+}
+
+#[pymethods]
+impl Xrefs {
+    /// fetch the references to the given address.
+    ///
+    /// Args:
+    ///   va (int): the referenced address.
+    ///
+    /// Returns: List[Tuple[int, int, int]]
+    pub fn xrefs_to(&self, py: Python, va: VA) -> Vec<Py<PyTuple>> {
+        self.inner
+            .xrefs_to(va)
+            .iter()
+            .map(|xref| xref_to_tuple(py, xref))
+            .collect()
+    }
+
+    /// fetch the references made by the instruction or pointer at the given
+    /// address.
+    ///
+    /// Args:
+    ///   va (int): the address of the instruction or pointer.
+    ///
+    /// Returns: List[Tuple[int, int, int]]
+    pub fn xrefs_from(&self, py: Python, va: VA) -> Vec<Py<PyTuple>> {
+        self.inner
+            .xrefs_from(va)
+            .iter()
+            .map(|xref| xref_to_tuple(py, xref))
+            .collect()
+    }
+}
+
+/// A string recovered from a data section of the module.
+#[pyclass(name = "String")]
+pub struct String_ {
+    /// the address of the string.
+    #[pyo3(get)]
+    pub address: u64,
+
+    /// either "ascii" or "utf-16le".
+    #[pyo3(get)]
+    pub encoding: String,
+
+    /// name of the section that contains the string.
+    #[pyo3(get)]
+    pub section: String,
+
+    /// the decoded string.
+    #[pyo3(get)]
+    pub string: String,
+
+    /// addresses of the instructions that reference the string.
+    /// type: List[int]
+    #[pyo3(get)]
+    pub references: Vec<u64>,
+
+    /// starts of the functions that reference the string.
+    /// type: List[int]
+    #[pyo3(get)]
+    pub functions: Vec<u64>,
+}
+
+impl String_ {
+    fn from_module_string(s: &lancelot::analysis::strings::ModuleString) -> String_ {
+        String_ {
+            address:    s.address,
+            encoding:   s.encoding.to_string(),
+            section:    s.section.clone(),
+            string:     s.string.clone(),
+            references: s.references.clone(),
+            functions:  s.functions.clone(),
+        }
+    }
+}
+
+#[pyproto]
+impl PyObjectProtocol for String_ {
+    fn __str__(&self) -> PyResult<String> {
+        Ok(self.string.clone())
+    }
+}
+
+#[pyproto]
+impl PyNumberProtocol for String_ {
+    fn __int__(&self) -> PyResult<u64> {
+        Ok(self.address)
+    }
+}
+
 const PERMISSION_READ: u8 = 0b001;
 const PERMISSION_WRITE: u8 = 0b010;
 const PERMISSION_EXECUTE: u8 = 0b100;
 
 #[pyclass]
 pub struct PE {
-    inner:   lPE,
-    decoder: zydis::Decoder,
+    inner:    lPE,
+    decoder:  zydis::Decoder,
+    /// the analysis loaded from the cache, if any.
+    analysis: Option<Analysis>,
+}
+
+impl PE {
+    /// the CFGs loaded from the cache, otherwise build a CFG for each function.
+    fn cfgs(&self) -> anyhow::Result<Cow<'_, BTreeMap<VA, lancelot::analysis::cfg::CFG>>> {
+        use lancelot::analysis::{cfg, pe};
+
+        match &self.analysis {
+            Some(analysis) => Ok(Cow::Borrowed(&analysis.cfgs)),
+            None => {
+                let functions = pe::find_function_starts(&self.inner)?;
+                Ok(Cow::Owned(cfg::build_cfgs(
+                    &self.inner.module,
+                    &functions,
+                    &Default::default(),
+                )))
+            }
+        }
+    }
+
+    fn call_graph(
+        &self,
+        cfgs: &BTreeMap<VA, lancelot::analysis::cfg::CFG>,
+    ) -> anyhow::Result<Cow<'_, lancelot::analysis::call_graph::CallGraph>> {
+        match &self.analysis {
+            Some(analysis) => Ok(Cow::Borrowed(&analysis.call_graph)),
+            None => Ok(Cow::Owned(lancelot::analysis::call_graph::build_call_graph(
+                &self.inner.module,
+                cfgs,
+            )?)),
+        }
+    }
+
+    fn xrefs(
+        &self,
+        cfgs: &BTreeMap<VA, lancelot::analysis::cfg::CFG>,
+    ) -> anyhow::Result<Cow<'_, lancelot::analysis::xrefs::Xrefs>> {
+        match &self.analysis {
+            Some(analysis) => Ok(Cow::Borrowed(&analysis.xrefs)),
+            None => Ok(Cow::Owned(lancelot::analysis::xrefs::build_xrefs(
+                &self.inner.module,
+                cfgs,
+            )?)),
+        }
+    }
+
+    fn find_functions(&self) -> anyhow::Result<Vec<lancelot::analysis::pe::Function>> {
+        match &self.analysis {
+            Some(analysis) => Ok(analysis.functions.clone()),
+            None => lancelot::analysis::pe::find_functions(&self.inner),
+        }
+    }
 }
 
 #[pymethods]
@@ -380,19 +646,21 @@ impl PE {
     ///
     /// Returns: List[int]
     pub fn get_functions(&self) -> PyResult<Vec<u64>> {
-        Ok(lancelot::analysis::pe::find_functions(&self.inner)
+        Ok(self
+            .find_functions()
             .map_err(to_py_err)?
             .into_iter()
             .filter(|f| matches!(f, lancelot::analysis::pe::Function::Local(_)))
             .map(|f| match f {
-                lancelot::analysis::pe::Function::Local(va) => va,
+                lancelot::analysis::pe::Function::Local(f) => f.address,
                 _ => unreachable!(),
             })
             .collect())
     }
 
     pub fn get_thunks(&self) -> PyResult<Vec<u64>> {
-        Ok(lancelot::analysis::pe::find_functions(&self.inner)
+        Ok(self
+            .find_functions()
             .map_err(to_py_err)?
             .into_iter()
             .filter(|f| matches!(f, lancelot::analysis::pe::Function::Thunk(_)))
@@ -436,17 +704,8 @@ impl PE {
     ///
     /// Returns: CallGraph
     pub fn build_call_graph(&self, py: Python) -> PyResult<CallGraph> {
-        use lancelot::analysis::{call_graph, cfg, pe};
-        use std::collections::BTreeMap;
-
-        let mut cfgs: BTreeMap<VA, cfg::CFG> = Default::default();
-        for &function in pe::find_function_starts(&self.inner).map_err(to_py_err)?.iter() {
-            if let Ok(cfg) = cfg::build_cfg(&self.inner.module, function) {
-                cfgs.insert(function, cfg);
-            }
-        }
-
-        let cg = call_graph::build_call_graph(&self.inner.module, &cfgs).map_err(to_py_err)?;
+        let cfgs = self.cfgs().map_err(to_py_err)?;
+        let cg = self.call_graph(&cfgs).map_err(to_py_err)?.into_owned();
 
         let calls_to: PyObject = cg.calls_to.into_py(py);
         let calls_to: Py<PyDict> = calls_to.extract(py)?;
@@ -468,6 +727,56 @@ impl PE {
         })
     }
 
+    /// infer the calling convention and argument count of each function.
+    /// this routine will implicitly find all functions, build a CFG for each,
+    /// and construct the call graph.
+    ///
+    /// Returns: Dict[int, Prototype]
+    pub fn get_prototypes(&self, py: Python) -> PyResult<Py<PyDict>> {
+        use lancelot::analysis::calling_convention;
+
+        let cfgs = self.cfgs().map_err(to_py_err)?;
+        let cg = self.call_graph(&cfgs).map_err(to_py_err)?;
+        let prototypes = calling_convention::find_prototypes(&self.inner.module, &cfgs, &cg).map_err(to_py_err)?;
+
+        let ret = PyDict::new(py);
+        for (va, prototype) in prototypes.iter() {
+            let prototype: PyObject = Prototype::from_prototype(*va, prototype).into_py(py);
+            ret.set_item(va, prototype)?;
+        }
+
+        Ok(ret.into())
+    }
+
+    /// construct an index of the cross-references among code and data.
+    /// this routine will implicitly find all functions and build a CFG for
+    /// each.
+    ///
+    /// Returns: Xrefs
+    pub fn build_xrefs(&self) -> PyResult<Xrefs> {
+        let cfgs = self.cfgs().map_err(to_py_err)?;
+
+        Ok(Xrefs {
+            inner: self.xrefs(&cfgs).map_err(to_py_err)?.into_owned(),
+        })
+    }
+
+    /// recover the ASCII and UTF-16LE strings from the data sections,
+    /// along with the instructions and functions that reference each.
+    /// this routine will implicitly find all functions, build a CFG for each,
+    /// and index the cross-references.
+    ///
+    /// Returns: List[String]
+    pub fn get_strings(&self) -> PyResult<Vec<String_>> {
+        use lancelot::analysis::strings;
+
+        let cfgs = self.cfgs().map_err(to_py_err)?;
+        let xrefs = self.xrefs(&cfgs).map_err(to_py_err)?;
+        let strings = strings::find_strings(&self.inner.module, &cfgs, &xrefs).map_err(to_py_err)?;
+
+        Ok(strings.values().map(String_::from_module_string).collect())
+    }
+
     /// read a sequence of bytes at the given virtual address.
     ///
     /// Args:
@@ -569,6 +878,7 @@ impl PE {
 #[pymodule]
 fn lancelot(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(from_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(from_cache, m)?)?;
     m.add_class::<PE>()?;
 
     // indices into a flow tuple
@@ -583,6 +893,21 @@ fn lancelot(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add("FLOW_TYPE_CONDITIONAL_JUMP", FLOW_CONDITIONAL_JUMP)?;
     m.add("FLOW_TYPE_CONDITIONAL_MOVE", FLOW_CONDITIONAL_MOVE)?;
 
+    // indices into an xref tuple
+    m.add("XREF_SRC", 0)?;
+    m.add("XREF_DST", 1)?;
+    m.add("XREF_TYPE", 2)?;
+
+    // xref types
+    // we use int constants for performance
+    m.add("XREF_TYPE_CALL", XREF_CALL)?;
+    m.add("XREF_TYPE_UNCONDITIONAL_JUMP", XREF_UNCONDITIONAL_JUMP)?;
+    m.add("XREF_TYPE_CONDITIONAL_JUMP", XREF_CONDITIONAL_JUMP)?;
+    m.add("XREF_TYPE_READ", XREF_READ)?;
+    m.add("XREF_TYPE_WRITE", XREF_WRITE)?;
+    m.add("XREF_TYPE_OFFSET", XREF_OFFSET)?;
+    m.add("XREF_TYPE_POINTER", XREF_POINTER)?;
+
     // indices into an operand tuple
     m.add("OPERAND_TYPE", OPERAND_TYPE)?;
     m.add("OPERAND_SIZE", OPERAND_SIZE)?;
@@ -22,6 +22,26 @@ def test_load_pe(k32):
     lancelot.from_bytes(k32)
 
 
+def test_from_cache(k32, tmp_path):
+    cache = tmp_path / "cache"
+    ws = lancelot.from_cache(k32, str(cache))
+    assert len(list(cache.iterdir())) == 1
+
+    # the second time, the analysis is loaded from the database.
+    ws2 = lancelot.from_cache(k32, str(cache))
+    assert ws2.get_functions() == ws.get_functions()
+    # the database also has the functions found recursively.
+    assert set(lancelot.from_bytes(k32).get_functions()) <= set(ws2.get_functions())
+    assert len(ws2.build_call_graph().calls_to[0x180001068]) == 2
+
+    # with another configuration, the sample is re-analyzed.
+    config = tmp_path / "config.toml"
+    config.write_text("[functions]\nprologues = false\n")
+    ws3 = lancelot.from_cache(k32, str(cache), str(config))
+    assert len(list(cache.iterdir())) == 1
+    assert len(ws3.get_functions()) > 0
+
+
 def test_arch(k32):
     ws = lancelot.from_bytes(k32)
     assert "Returns: str" in lancelot.PE.arch.__doc__
@@ -88,6 +108,55 @@ def test_call_graph(k32):
     assert 0x180060504 in cg.function_call_instructions[0x1800602C0]
 
 
+def test_prototypes(k32):
+    ws = lancelot.from_bytes(k32)
+
+    assert "Returns: Dict[int, Prototype]" in ws.get_prototypes.__doc__
+
+    prototypes = ws.get_prototypes()
+
+    # this is _report_gsfailure
+    # it spills rcx to its home space
+    p = prototypes[0x1800202B0]
+    assert p.calling_convention == "win64"
+    assert p.register_arguments == ["rcx"]
+    assert p.argument_count == 1
+
+
+def test_xrefs(k32):
+    ws = lancelot.from_bytes(k32)
+
+    assert "Returns: Xrefs" in ws.build_xrefs.__doc__
+
+    xrefs = ws.build_xrefs()
+
+    # .text:0000000180051F9C  lea r8, [0x00000001800803C0]
+    xref = xrefs.xrefs_to(0x1800803C0)[0]
+    assert xref[lancelot.XREF_SRC] == 0x180051F9C
+    assert xref[lancelot.XREF_DST] == 0x1800803C0
+    assert xref[lancelot.XREF_TYPE] == lancelot.XREF_TYPE_OFFSET
+
+    # .text:000000018005E9B2  call [0x0000000180079800]
+    assert (0x18005E9B2, 0x180079800, lancelot.XREF_TYPE_READ) in xrefs.xrefs_from(0x18005E9B2)
+
+    assert xrefs.xrefs_to(0x0) == []
+
+
+def test_strings(k32):
+    ws = lancelot.from_bytes(k32)
+
+    assert "Returns: List[String]" in ws.get_strings.__doc__
+
+    strings = {int(s): s for s in ws.get_strings()}
+
+    s = strings[0x180083CD0]
+    assert str(s) == "FindFirstFile failed for %ws [GLE: %x]"
+    assert s.encoding == "ascii"
+    assert s.section == ".rdata"
+    assert s.references == [0x18006863E]
+    assert s.functions == [0x180068554]
+
+
 def test_read_insn(k32):
     ws = lancelot.from_bytes(k32)
 